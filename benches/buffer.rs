@@ -0,0 +1,80 @@
+//! Times filling and copying the buffers of a 4K output, as done on full
+//! redraws, against filling them a pixel at a time.
+
+// Only part of what these modules offer is used here.
+#[allow(dead_code)]
+#[path = "../src/buffer.rs"]
+mod buffer;
+#[allow(dead_code)]
+#[path = "../src/color.rs"]
+mod color;
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use memmap2::MmapMut;
+
+use buffer::Buffer;
+use color::Color;
+
+const SIZE: (u32, u32) = (3840, 2160);
+
+// The login box, roughly, on a 4K output at a scale of 2.
+const BOX: (u32, u32, u32, u32) = (1320, 780, 1200, 600);
+
+fn map() -> MmapMut {
+    MmapMut::map_anon((4 * SIZE.0 * SIZE.1) as usize).unwrap()
+}
+
+// Filling a pixel at a time, as memset used to.
+fn fill_per_pixel(mmap: &mut MmapMut, (x, y, width, height): (u32, u32, u32, u32), c: &Color) {
+    let ptr = mmap.as_mut_ptr() as *mut u32;
+    for y in y..y + height {
+        for x in x..x + width {
+            unsafe {
+                *ptr.offset((x + y * SIZE.0) as isize) = c.as_argb8888();
+            }
+        }
+    }
+}
+
+fn memset(c: &mut Criterion) {
+    let color = Color::new(0.2, 0.4, 0.6, 1.0);
+    let mut mmap = map();
+    let mut group = c.benchmark_group("memset");
+    group.bench_function("full", |b| {
+        b.iter(|| Buffer::new(&mut mmap, SIZE).memset(&color))
+    });
+    group.bench_function("full per pixel", |b| {
+        b.iter(|| fill_per_pixel(&mut mmap, (0, 0, SIZE.0, SIZE.1), &color))
+    });
+    group.bench_function("box", |b| {
+        b.iter(|| {
+            let mut buf = Buffer::new(&mut mmap, SIZE);
+            buf.subdimensions(BOX).unwrap().memset(&color);
+        })
+    });
+    group.bench_function("box per pixel", |b| {
+        b.iter(|| fill_per_pixel(&mut mmap, BOX, &color))
+    });
+    group.finish();
+}
+
+fn copy_to(c: &mut Criterion) {
+    let (mut from, mut to) = (map(), map());
+    let mut group = c.benchmark_group("copy_to");
+    group.bench_function("full", |b| {
+        b.iter(|| {
+            let damage = (0, 0, SIZE.0 as i32, SIZE.1 as i32);
+            Buffer::new(&mut from, SIZE).copy_to(&mut Buffer::new(&mut to, SIZE), damage)
+        })
+    });
+    group.bench_function("box", |b| {
+        b.iter(|| {
+            let damage = (BOX.0 as i32, BOX.1 as i32, BOX.2 as i32, BOX.3 as i32);
+            Buffer::new(&mut from, SIZE).copy_to(&mut Buffer::new(&mut to, SIZE), damage)
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, memset, copy_to);
+criterion_main!(benches);
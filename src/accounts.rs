@@ -0,0 +1,54 @@
+//! Users as known to AccountsService, for the user list, with their full
+//! names and pictures. System and locked accounts are left out, as display
+//! managers using AccountsService do.
+
+use std::path::PathBuf;
+use std::time::Duration;
+
+use dbus::arg::prop_cast;
+use dbus::blocking::stdintf::org_freedesktop_dbus::Properties;
+use dbus::blocking::Connection;
+
+use crate::widgets::login::Account;
+
+const NAME: &str = "org.freedesktop.Accounts";
+const PATH: &str = "/org/freedesktop/Accounts";
+const USER_INTERFACE: &str = "org.freedesktop.Accounts.User";
+
+// How long to wait for AccountsService before falling back to /etc/passwd.
+const TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Returns the users to offer, sorted by login name.
+pub fn list() -> Result<Vec<Account>, dbus::Error> {
+    let conn = Connection::new_system()?;
+    let (paths,): (Vec<dbus::Path<'static>>,) =
+        conn.with_proxy(NAME, PATH, TIMEOUT)
+            .method_call(NAME, "ListCachedUsers", ())?;
+    let mut accounts = Vec::new();
+    for path in paths {
+        let props = conn
+            .with_proxy(NAME, path, TIMEOUT)
+            .get_all(USER_INTERFACE)?;
+        let flag = |key: &str| prop_cast::<bool>(&props, key).copied().unwrap_or(false);
+        if flag("SystemAccount") || flag("Locked") {
+            continue;
+        }
+        let text = |key: &str| {
+            prop_cast::<String>(&props, key)
+                .cloned()
+                .unwrap_or_default()
+        };
+        let name = text("UserName");
+        if name.is_empty() {
+            continue;
+        }
+        let icon = Some(PathBuf::from(text("IconFile"))).filter(|path| path.is_file());
+        accounts.push(Account {
+            name,
+            real_name: text("RealName"),
+            icon,
+        });
+    }
+    accounts.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(accounts)
+}
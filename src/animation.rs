@@ -0,0 +1,109 @@
+//! Time-based animations. Whatever draws an animation asks the main loop to
+//! draw again with Cmd::Wake until the animation is done.
+
+use std::time::{Duration, Instant};
+
+/// Time between frames of an animation.
+pub const FRAME: Duration = Duration::from_millis(16);
+
+// Time the caret is shown or hidden for while blinking, and how long it
+// blinks for after it was last moved.
+const BLINK_PERIOD: Duration = Duration::from_millis(530);
+const BLINK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Shapes the progress of an animation.
+#[derive(Clone, Copy)]
+pub enum Easing {
+    Linear,
+    /// Starts slow and speeds up towards the end.
+    EaseIn,
+    /// Starts fast and slows down towards the end.
+    EaseOut,
+}
+
+impl Easing {
+    fn apply(self, t: f32) -> f32 {
+        match self {
+            Easing::Linear => t,
+            Easing::EaseIn => t.powi(3),
+            Easing::EaseOut => 1.0 - (1.0 - t).powi(3),
+        }
+    }
+}
+
+/// An animation running for a fixed time from when it was created.
+pub struct Animation {
+    start: Instant,
+    duration: Duration,
+    easing: Easing,
+}
+
+impl Animation {
+    pub fn new(duration: Duration, easing: Easing) -> Animation {
+        Animation {
+            start: Instant::now(),
+            duration,
+            easing,
+        }
+    }
+
+    /// Returns the time the animation is done at.
+    pub fn end(&self) -> Instant {
+        self.start + self.duration
+    }
+
+    /// Returns true once the animation has run its course.
+    pub fn done(&self, now: Instant) -> bool {
+        now >= self.end()
+    }
+
+    /// Returns the eased progress of the animation at now, from 0 to 1.
+    pub fn value(&self, now: Instant) -> f32 {
+        let elapsed = now.saturating_duration_since(self.start).as_secs_f32();
+        self.easing
+            .apply((elapsed / self.duration.as_secs_f32()).min(1.0))
+    }
+
+    /// Returns the time to draw the next frame at, unless the animation is
+    /// done.
+    pub fn next_frame(&self, now: Instant) -> Option<Instant> {
+        if self.done(now) {
+            None
+        } else {
+            Some(now + FRAME)
+        }
+    }
+}
+
+/// A caret that blinks for a while after it was last moved, and then stays
+/// on so that an idle greeter does not keep redrawing.
+pub struct Blink {
+    since: Instant,
+    enabled: bool,
+}
+
+impl Blink {
+    pub fn new(enabled: bool) -> Blink {
+        Blink {
+            since: Instant::now(),
+            enabled,
+        }
+    }
+
+    /// Shows the caret and starts blinking over.
+    pub fn reset(&mut self) {
+        self.since = Instant::now();
+    }
+
+    /// Returns whether the caret is shown at now, and when that changes
+    /// next, if ever.
+    pub fn state(&self, now: Instant) -> (bool, Option<Instant>) {
+        let elapsed = now.saturating_duration_since(self.since);
+        if !self.enabled || elapsed >= BLINK_TIMEOUT {
+            return (true, None);
+        }
+        let phase = (elapsed.as_millis() / BLINK_PERIOD.as_millis()) as u32;
+        let next = (self.since + BLINK_PERIOD * (phase + 1)).min(self.since + BLINK_TIMEOUT);
+        ((phase & 1) == 0, Some(next))
+    }
+}
@@ -1,553 +1,2276 @@
-use std::collections::VecDeque;
-use std::sync::mpsc::Sender;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::env;
+use std::io::{self, Read};
+use std::os::unix::io::{AsFd, BorrowedFd};
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
+use calloop::channel::Sender;
 use chrono::Local;
+use log::{debug, warn};
+use xkbcommon::xkb::{self, compose};
 
-use smithay_client_toolkit::environment::MultiGlobalHandler;
-use smithay_client_toolkit::seat::{
-    keyboard::{keysyms, map_keyboard, Event as KbEvent, KeyState, ModifiersState},
-    SeatHandler,
+use smithay_client_toolkit::compositor::{CompositorHandler, CompositorState, SurfaceData};
+use smithay_client_toolkit::data_device_manager::data_device::{DataDevice, DataDeviceHandler};
+use smithay_client_toolkit::data_device_manager::data_offer::{DataOfferHandler, DragOffer};
+use smithay_client_toolkit::data_device_manager::data_source::DataSourceHandler;
+use smithay_client_toolkit::data_device_manager::{DataDeviceManagerState, WritePipe};
+use smithay_client_toolkit::output::{OutputHandler, OutputInfo, OutputState};
+use smithay_client_toolkit::registry::{ProvidesRegistryState, RegistryState};
+use smithay_client_toolkit::seat::keyboard::{KeyEvent, KeyboardHandler, Keysym, Modifiers, RMLVO};
+use smithay_client_toolkit::seat::pointer::{
+    CursorIcon, PointerEvent, PointerEventKind, PointerHandler, ThemeSpec, ThemedPointer,
+};
+use smithay_client_toolkit::seat::touch::TouchHandler;
+use smithay_client_toolkit::seat::{Capability, SeatHandler, SeatState};
+use smithay_client_toolkit::session_lock::{
+    SessionLock, SessionLockHandler, SessionLockState, SessionLockSurface,
+    SessionLockSurfaceConfigure,
+};
+use smithay_client_toolkit::shell::wlr_layer::{
+    self, KeyboardInteractivity, Layer, LayerShell, LayerShellHandler, LayerSurface,
+    LayerSurfaceConfigure,
+};
+use smithay_client_toolkit::shell::WaylandSurface;
+use smithay_client_toolkit::shm::{Shm, ShmHandler};
+use smithay_client_toolkit::subcompositor::SubcompositorState;
+use smithay_client_toolkit::{
+    delegate_compositor, delegate_data_device, delegate_keyboard, delegate_layer, delegate_output,
+    delegate_pointer, delegate_registry, delegate_seat, delegate_session_lock, delegate_shm,
+    delegate_subcompositor, delegate_touch, registry_handlers,
 };
 
-use wayland_client::protocol::{wl_compositor, wl_output, wl_pointer, wl_shm, wl_surface};
+use wayland_client::backend::{Backend, ObjectId, WaylandError};
+use wayland_client::globals::registry_queue_init;
+use wayland_client::protocol::wl_data_device::WlDataDevice;
+use wayland_client::protocol::wl_data_device_manager::DndAction;
+use wayland_client::protocol::wl_data_source::WlDataSource;
+use wayland_client::protocol::{
+    wl_buffer, wl_keyboard, wl_output, wl_pointer, wl_seat, wl_shm, wl_subsurface, wl_surface,
+    wl_touch,
+};
 use wayland_client::{
-    Attached, DispatchData, Display, EventQueue, GlobalEvent, GlobalManager, Main,
+    delegate_dispatch, delegate_noop, Connection, Dispatch, EventQueue, Proxy, QueueHandle,
+};
+use wayland_protocols::ext::idle_notify::v1::client::{
+    ext_idle_notification_v1, ext_idle_notifier_v1,
 };
-use wayland_protocols::wlr::unstable::layer_shell::v1::client::{
-    zwlr_layer_shell_v1, zwlr_layer_surface_v1,
+use wayland_protocols::wp::fractional_scale::v1::client::{
+    wp_fractional_scale_manager_v1, wp_fractional_scale_v1,
 };
+use wayland_protocols::wp::viewporter::client::{wp_viewport, wp_viewporter};
 
+use crate::animation::{Animation, Easing};
+use crate::backdrop::Backdrop;
 use crate::buffer::Buffer;
 use crate::color::Color;
-use crate::config::{Config, OutputMode};
-use crate::widget::{DrawContext, Widget};
+use crate::config::{Anchor, Config, Keymap, LayoutToggle, Margin, OutputMode, OutputSettings};
+use crate::widget::{scaled, DrawContext, KeyState, Widget, DEFAULT_WIDTH};
 
-use crate::cmd::Cmd;
-use crate::doublemempool::DoubleMemPool;
+use crate::cmd::{Cmd, SeatId};
+use crate::doublemempool::{BufferData, DoubleMemPool, MemPool};
+use crate::draw;
+use crate::error::Error;
+use crate::keybindings::{Action, Keybindings};
+use crate::secret::{SecretBytes, SecretString, Zeroize};
 
-struct AppInner {
-    compositor: Option<Main<wl_compositor::WlCompositor>>,
+// A lock surface covering one output, with the widget surface placed
+// centered on top of it as a subsurface.
+struct LockSurface {
+    lock_surface: SessionLockSurface,
+    subsurface: wl_subsurface::WlSubsurface,
+    child: wl_surface::WlSurface,
+    // Size of the last configure event.
+    size: (u32, u32),
+    background: Color,
+    pool: MemPool,
+}
+
+impl LockSurface {
+    // Fills the lock surface with the background color, as it must be
+    // covered entirely.
+    fn fill(&mut self, qh: &QueueHandle<AppInner>, idle: Idle) -> io::Result<()> {
+        let (w, h) = self.size;
+        self.pool.resize((4 * w * h) as usize)?;
+        Buffer::new(self.pool.mmap(), (w, h)).memset(&idle.color(&self.background));
+        self.pool.mmap().flush()?;
+        let buffer = self.pool.buffer(
+            0,
+            w as i32,
+            h as i32,
+            4 * w as i32,
+            wl_shm::Format::Argb8888,
+            qh,
+        );
+        let surface = self.lock_surface.wl_surface();
+        surface.attach(Some(&buffer), 0, 0);
+        surface.damage_buffer(0, 0, w as i32, h as i32);
+        surface.commit();
+        Ok(())
+    }
+
+    fn destroy(self) {
+        self.subsurface.destroy();
+        // Destroys the lock surface along with its surface.
+        drop(self.lock_surface);
+        self.child.destroy();
+    }
+}
+
+// Fraction of the brightness kept while dimmed.
+const DIM_LEVEL: f32 = 0.4;
+
+// Time the greeter takes to fade in on startup.
+const FADE_IN: Duration = Duration::from_millis(300);
+
+// Time a level, such as the brightness, stays shown after it changed.
+const LEVEL_SHOWN: Duration = Duration::from_millis(1500);
+
+// How far the greeter has faded for lack of input.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Idle {
+    Active,
+    Dimmed,
+    Blanked,
+}
+
+impl Idle {
+    // The color to show in place of color.
+    fn color(self, color: &Color) -> Color {
+        match self {
+            Idle::Active => *color,
+            Idle::Dimmed => color.dim(DIM_LEVEL),
+            Idle::Blanked => Color::new(0.0, 0.0, 0.0, 1.0),
+        }
+    }
+
+    // Dims or blanks what was drawn into buf.
+    fn apply(self, buf: &mut Buffer) {
+        match self {
+            Idle::Active => (),
+            Idle::Dimmed => buf.dim(DIM_LEVEL),
+            Idle::Blanked => buf.memset(&Idle::Blanked.color(&Color::default())),
+        }
+    }
+}
+
+// An output, as last described by the compositor.
+#[derive(PartialEq)]
+struct Output {
+    id: u32,
+    output: wl_output::WlOutput,
+    name: Option<String>,
+    scale: u32,
+    // Size of the current mode, in physical pixels.
+    mode: (u32, u32),
+    transform: wl_output::Transform,
+}
+
+impl Output {
+    fn new(output: wl_output::WlOutput, info: &OutputInfo) -> Output {
+        let mode = info
+            .modes
+            .iter()
+            .find(|mode| mode.current)
+            .map_or((0, 0), |mode| {
+                (
+                    mode.dimensions.0.max(0) as u32,
+                    mode.dimensions.1.max(0) as u32,
+                )
+            });
+        Output {
+            id: info.id,
+            output,
+            name: info.name.clone(),
+            scale: info.scale_factor.max(1) as u32,
+            mode,
+            transform: info.transform,
+        }
+    }
+
+    // Returns the width of the output in surface coordinates at the given
+    // scale, taking rotation into account.
+    fn logical_width(&self, scale: f32) -> Option<u32> {
+        use wl_output::Transform;
+        let width = match self.transform {
+            Transform::_90 | Transform::_270 | Transform::Flipped90 | Transform::Flipped270 => {
+                self.mode.1
+            }
+            _ => self.mode.0,
+        };
+        if width == 0 {
+            return None;
+        }
+        Some((width as f32 / scale).round() as u32)
+    }
+}
+
+// The objects used to show a surface at a fractional scale, and to learn
+// the scale the compositor prefers for it.
+struct SurfaceScale {
+    viewport: Option<wp_viewport::WpViewport>,
+    fractional_scale: Option<wp_fractional_scale_v1::WpFractionalScaleV1>,
+}
+
+impl SurfaceScale {
+    fn destroy(&self) {
+        if let Some(ref viewport) = self.viewport {
+            viewport.destroy();
+        }
+        if let Some(ref fractional_scale) = self.fractional_scale {
+            fractional_scale.destroy();
+        }
+    }
+}
+
+// The surface a fractional scale object was created for: the id of its
+// output, or None for the surface placed by the compositor in active output
+// mode, and the scale it was created with.
+struct FractionalScaleData {
+    key: Option<u32>,
+    scale: f32,
+}
+
+// The surfaces on outputs with the same settings, which all show the same
+// buffer.
+struct View {
+    settings: OutputSettings,
     surfaces: Vec<wl_surface::WlSurface>,
-    shell_surfaces: Vec<zwlr_layer_surface_v1::ZwlrLayerSurfaceV1>,
-    configured_surfaces: Arc<Mutex<usize>>,
-    outputs: Vec<(u32, Attached<wl_output::WlOutput>)>,
-    shell: Option<Main<zwlr_layer_shell_v1::ZwlrLayerShellV1>>,
-    seats: SeatHandler,
+    scales: Vec<SurfaceScale>,
+    shell_surfaces: Vec<LayerSurface>,
+    lock_surfaces: Vec<LockSurface>,
+    pools: DoubleMemPool,
+    // Size of the last layer surface configure event.
+    configured_size: (u32, u32),
+    // Size of the buffer last attached to the surfaces.
+    buffer_size: (u32, u32),
+    // Width of the narrowest output showing the surfaces, in surface
+    // coordinates, if known.
+    output_width: Option<u32>,
+    last_damage: Option<Vec<(i32, i32, i32, i32)>>,
+    // Whether the compositor has yet to show the last frame.
+    frame_pending: bool,
+    // A draw asked for while waiting for the frame, and whether it was
+    // forced.
+    queued_draw: Option<bool>,
+}
+
+impl View {
+    fn new(shm: &Shm, settings: OutputSettings) -> io::Result<View> {
+        Ok(View {
+            settings,
+            surfaces: Vec::new(),
+            scales: Vec::new(),
+            shell_surfaces: Vec::new(),
+            lock_surfaces: Vec::new(),
+            pools: DoubleMemPool::new(shm)?,
+            configured_size: (0, 0),
+            buffer_size: (1, 1),
+            output_width: None,
+            last_damage: None,
+            frame_pending: false,
+            queued_draw: None,
+        })
+    }
+
+    // The size in surface coordinates of a buffer of the given size.
+    fn logical_size(&self, size: (u32, u32)) -> (u32, u32) {
+        let scale = self.settings.scale;
+        (
+            (size.0 as f32 / scale).round() as u32,
+            (size.1 as f32 / scale).round() as u32,
+        )
+    }
+
+    // The size to request for a surface showing a buffer of the given size.
+    fn requested_size(&self, stretched: (bool, bool), size: (u32, u32)) -> (u32, u32) {
+        let logical = self.logical_size(size);
+        (
+            if stretched.0 { 0 } else { logical.0 },
+            if stretched.1 { 0 } else { logical.1 },
+        )
+    }
+
+    // Limits the width of the surfaces to fit on an output of the given
+    // width.
+    fn fit_output(&mut self, width: Option<u32>) {
+        if let Some(width) = width {
+            self.output_width = Some(self.output_width.map_or(width, |w| w.min(width)));
+        }
+    }
+
+    // The space given to us by the compositor in buffer pixels, with 0 for
+    // dimensions where we pick the size ourselves. Outputs too narrow for
+    // the default width, such as rotated ones, limit the width.
+    fn available_size(&self, stretched: (bool, bool)) -> (u32, u32) {
+        let scale = self.settings.scale;
+        let configured = self.configured_size;
+        (
+            if stretched.0 {
+                scaled(configured.0, scale)
+            } else {
+                match self.output_width {
+                    Some(width) if width < DEFAULT_WIDTH => scaled(width, scale),
+                    _ => 0,
+                }
+            },
+            if stretched.1 {
+                scaled(configured.1, scale)
+            } else {
+                0
+            },
+        )
+    }
+
+    fn destroy(&mut self) {
+        for scale in self.scales.drain(..) {
+            scale.destroy();
+        }
+        // Layer surfaces are destroyed along with their surface when
+        // dropped.
+        self.shell_surfaces.clear();
+        for lock_surface in self.lock_surfaces.drain(..) {
+            lock_surface.destroy();
+        }
+        self.surfaces.clear();
+    }
+}
+
+// The input devices we have mapped for one seat.
+struct SeatDevices {
+    seat: wl_seat::WlSeat,
+    keyboard: Option<wl_keyboard::WlKeyboard>,
+    pointer: Option<ThemedPointer>,
+    touch: Option<wl_touch::WlTouch>,
+    // The touch points down, with the scale of the surface each went down
+    // on, as later events do not say.
+    touch_points: Vec<(i32, f32)>,
+    data_device: Option<DataDevice>,
+    // The modifiers last reported for the keyboard.
+    modifiers: Modifiers,
+    // Combines dead keys and compose sequences typed on the keyboard.
+    compose: Option<compose::State>,
+    idle_notifications: Vec<ext_idle_notification_v1::ExtIdleNotificationV1>,
+}
+
+impl SeatDevices {
+    fn stop_idle_notifications(&mut self) {
+        for notification in self.idle_notifications.drain(..) {
+            notification.destroy();
+        }
+    }
+
+    // Lets widgets know that the touch points down are gone.
+    fn cancel_touch(&mut self, cmd_queue: &Mutex<VecDeque<Cmd>>) {
+        if !self.touch_points.is_empty() {
+            self.touch_points.clear();
+            cmd_queue.lock().unwrap().push_back(Cmd::TouchCancel {
+                seat: self.seat.id().protocol_id(),
+            });
+        }
+    }
+
+    fn release_touch(&mut self, cmd_queue: &Mutex<VecDeque<Cmd>>) {
+        self.cancel_touch(cmd_queue);
+        if let Some(touch) = self.touch.take() {
+            if touch.version() >= 3 {
+                touch.release();
+            }
+        }
+    }
+
+    fn release_keyboard(&mut self, cmd_queue: &Mutex<VecDeque<Cmd>>) {
+        if let Some(keyboard) = self.keyboard.take() {
+            if keyboard.version() >= 3 {
+                keyboard.release();
+            }
+            cmd_queue.lock().unwrap().push_back(Cmd::KeyboardLeave {
+                seat: self.seat.id().protocol_id(),
+            });
+        }
+    }
+}
+
+const PASTE_MIME_TYPES: [&str; 3] = ["text/plain;charset=utf-8", "UTF8_STRING", "text/plain"];
+
+// Requests the clipboard contents as text, and reads them on a separate
+// thread so that a slow source cannot block us.
+fn paste_selection(data_device: &DataDevice, seat: SeatId, tx: Sender<Cmd>) {
+    let pipe = data_device.data().selection_offer().and_then(|offer| {
+        let mime = offer.with_mime_types(|types| {
+            PASTE_MIME_TYPES
+                .iter()
+                .find(|m| types.iter().any(|t| t == *m))
+                .map(|m| m.to_string())
+        })?;
+        offer.receive(mime).ok()
+    });
+    if let Some(mut pipe) = pipe {
+        std::thread::spawn(move || {
+            // What is pasted may well be a password.
+            let mut text = SecretBytes::new();
+            let mut chunk = [0u8; 256];
+            let res = loop {
+                match pipe.read(&mut chunk) {
+                    Ok(0) => break Ok(()),
+                    Ok(n) => text.extend_from_slice(&chunk[..n]),
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => (),
+                    Err(e) => break Err(e),
+                }
+            };
+            chunk.zeroize();
+            match res {
+                Ok(()) => {
+                    if let Ok(text) = SecretString::from_utf8(text) {
+                        let _ = tx.send(Cmd::Paste(seat, text));
+                    }
+                }
+                Err(e) => warn!("Failed to read clipboard: {}", e),
+            }
+        });
+    }
+}
+
+// Whether pressing key with the given modifiers held switches layouts.
+fn is_layout_toggle(toggle: LayoutToggle, key: Keysym, modifiers: &Modifiers) -> bool {
+    let shift = matches!(key, Keysym::Shift_L | Keysym::Shift_R);
+    match toggle {
+        LayoutToggle::AltShift => {
+            (shift && modifiers.alt)
+                || (modifiers.shift
+                    && matches!(
+                        key,
+                        Keysym::Alt_L | Keysym::Alt_R | Keysym::Meta_L | Keysym::Meta_R
+                    ))
+        }
+        LayoutToggle::CtrlShift => {
+            (shift && modifiers.ctrl)
+                || (modifiers.shift && matches!(key, Keysym::Control_L | Keysym::Control_R))
+        }
+        LayoutToggle::SuperSpace => key == Keysym::space && modifiers.logo,
+    }
+}
+
+// Returns a compose state using the compose table of the locale, if there is
+// one.
+fn compose_state() -> Option<compose::State> {
+    let locale = ["LC_ALL", "LC_CTYPE", "LANG"]
+        .iter()
+        .filter_map(env::var_os)
+        .find(|l| !l.is_empty())
+        .unwrap_or_else(|| "C".into());
+    let context = xkb::Context::new(xkb::CONTEXT_NO_FLAGS);
+    match compose::Table::new_from_locale(&context, &locale, compose::COMPILE_NO_FLAGS) {
+        Ok(table) => Some(compose::State::new(&table, compose::STATE_NO_FLAGS)),
+        Err(()) => {
+            debug!("No compose table for locale {:?}", locale);
+            None
+        }
+    }
+}
+
+// Runs a key press through a compose state, returning the keysym and text it
+// makes, or None while in the middle of a sequence or if it was cancelled.
+fn compose_key(state: &mut compose::State, event: &KeyEvent) -> Option<(Keysym, Option<String>)> {
+    if state.feed(event.keysym) == compose::FeedResult::Ignored {
+        return Some((event.keysym, event.utf8.clone()));
+    }
+    match state.status() {
+        compose::Status::Composing => None,
+        compose::Status::Composed => {
+            let composed = (state.keysym().unwrap_or(event.keysym), state.utf8());
+            state.reset();
+            Some(composed)
+        }
+        compose::Status::Cancelled => {
+            state.reset();
+            None
+        }
+        compose::Status::Nothing => Some((event.keysym, event.utf8.clone())),
+    }
+}
+
+// The keys switching layouts, if there is more than one to switch between.
+fn layout_toggle(config: &Config) -> Option<LayoutToggle> {
+    match config.keyboard_layouts.len() {
+        0 | 1 => None,
+        _ => Some(config.layout_toggle),
+    }
+}
+
+fn layer_anchor(config: &Config) -> wlr_layer::Anchor {
+    let mut anchor = wlr_layer::Anchor::empty();
+    for edge in config.anchor.iter() {
+        anchor |= match edge {
+            Anchor::Top => wlr_layer::Anchor::TOP,
+            Anchor::Bottom => wlr_layer::Anchor::BOTTOM,
+            Anchor::Left => wlr_layer::Anchor::LEFT,
+            Anchor::Right => wlr_layer::Anchor::RIGHT,
+        };
+    }
+    anchor
+}
+
+struct AppInner {
+    registry_state: RegistryState,
+    seat_state: SeatState,
+    output_state: OutputState,
+    compositor: CompositorState,
+    subcompositor: Option<SubcompositorState>,
+    shm: Shm,
+    data_device_manager: Option<DataDeviceManagerState>,
+    qh: QueueHandle<AppInner>,
+    views: Vec<View>,
+    // Lock surfaces on outputs the greeter is hidden on.
+    hidden_lock_surfaces: Vec<LockSurface>,
+    lock: Option<SessionLock>,
+    // The surfaces that have received their first configure event.
+    configured_surfaces: HashSet<ObjectId>,
+    outputs: Vec<Output>,
+    viewporter: Option<wp_viewporter::WpViewporter>,
+    fractional_scale_manager: Option<wp_fractional_scale_manager_v1::WpFractionalScaleManagerV1>,
+    // Scales preferred by the compositor, by output id, or None for the
+    // surface placed by the compositor in active output mode.
+    preferred_scales: HashMap<Option<u32>, f32>,
+    // Set when a preferred scale differs from the one the surfaces use.
+    rescale: bool,
+    shell: Option<LayerShell>,
+    seats: Vec<SeatDevices>,
+    idle_notifier: Option<ext_idle_notifier_v1::ExtIdleNotifierV1>,
+    idle: Idle,
+    cmd_queue: Arc<Mutex<VecDeque<Cmd>>>,
+    // The keymap to use instead of the one of the compositor, and the keys
+    // switching to the next layout.
+    keymap: Option<Keymap>,
+    toggle: Option<LayoutToggle>,
+    keybindings: Keybindings,
     draw_tx: Sender<Cmd>,
+    // An error from an event handler, for the main loop to pick up.
+    error: Option<Error>,
     output_mode: OutputMode,
+    anchor: wlr_layer::Anchor,
+    margin: Margin,
+    exclusive_zone: i32,
     visible: bool,
-    scale: u32,
+    // Name of the output showing the surface with keyboard focus.
+    focused_output: Option<String>,
+    config: Config,
 }
 
 impl AppInner {
-    fn new(tx: Sender<Cmd>, output_mode: OutputMode, scale: u32) -> AppInner {
-        AppInner {
-            compositor: None,
-            surfaces: Vec::new(),
-            shell_surfaces: Vec::new(),
-            configured_surfaces: Arc::new(Mutex::new(0)),
-            outputs: Vec::new(),
-            shell: None,
-            seats: SeatHandler::new(),
-            draw_tx: tx,
-            output_mode: output_mode,
-            visible: true,
-            scale: scale,
+    fn apply_config(&mut self, config: &Config) {
+        self.output_mode = config.output_mode;
+        self.anchor = layer_anchor(config);
+        self.margin = config.margin;
+        self.exclusive_zone = config.exclusive_zone;
+        self.keybindings = Keybindings::new(&config.keybindings);
+        let idle_changed = (config.idle_dim_timeout, config.idle_blank_timeout)
+            != (self.config.idle_dim_timeout, self.config.idle_blank_timeout);
+        self.config = config.clone();
+        if idle_changed {
+            self.restart_idle_notifications();
+        }
+    }
+
+    // Asks the compositor to tell when the seat has been idle for long
+    // enough to dim or blank the greeter.
+    fn idle_notifications(
+        &self,
+        seat: &wl_seat::WlSeat,
+    ) -> Vec<ext_idle_notification_v1::ExtIdleNotificationV1> {
+        let notifier = match self.idle_notifier {
+            Some(ref notifier) => notifier,
+            None => return Vec::new(),
+        };
+        [
+            (self.config.idle_dim_timeout, Idle::Dimmed),
+            (self.config.idle_blank_timeout, Idle::Blanked),
+        ]
+        .iter()
+        .filter(|(timeout, _)| *timeout > 0)
+        .map(|&(timeout, idle)| {
+            notifier.get_idle_notification(timeout.saturating_mul(1000), seat, &self.qh, idle)
+        })
+        .collect()
+    }
+
+    fn restart_idle_notifications(&mut self) {
+        let notifications: Vec<_> = self
+            .seats
+            .iter()
+            .map(|dev| self.idle_notifications(&dev.seat))
+            .collect();
+        for (dev, notifications) in self.seats.iter_mut().zip(notifications) {
+            dev.stop_idle_notifications();
+            dev.idle_notifications = notifications;
+        }
+        self.set_idle(Idle::Active);
+    }
+
+    // Redraws everything dimmed, blanked or back to normal.
+    fn set_idle(&mut self, idle: Idle) {
+        if self.idle == idle {
+            return;
+        }
+        debug!("Idle state changed");
+        self.idle = idle;
+        let qh = &self.qh;
+        let result = self
+            .views
+            .iter_mut()
+            .flat_map(|view| view.lock_surfaces.iter_mut())
+            .chain(self.hidden_lock_surfaces.iter_mut())
+            .filter(|l| l.size != (0, 0))
+            .try_for_each(|l| l.fill(qh, idle));
+        if let Err(e) = result {
+            self.fail(e.into());
+            return;
+        }
+        let _ = self.draw_tx.send(Cmd::ForceDraw);
+    }
+
+    // Whether the surface is anchored to both opposite edges horizontally
+    // and vertically, in which case the compositor picks its size.
+    fn stretched(&self) -> (bool, bool) {
+        use wlr_layer::Anchor;
+        if self.lock.is_some() {
+            return (false, false);
+        }
+        (
+            self.anchor.contains(Anchor::LEFT | Anchor::RIGHT),
+            self.anchor.contains(Anchor::TOP | Anchor::BOTTOM),
+        )
+    }
+
+    // Whether every surface has received its first configure event.
+    fn configured(&self) -> bool {
+        let surfaces: usize = self
+            .views
+            .iter()
+            .map(|view| view.shell_surfaces.len() + view.lock_surfaces.len())
+            .sum();
+        surfaces + self.hidden_lock_surfaces.len() == self.configured_surfaces.len()
+    }
+
+    // Returns the index of the view showing surfaces with the given
+    // settings, creating it if needed.
+    fn view_index(&mut self, settings: OutputSettings) -> Result<usize, Error> {
+        match self.views.iter().position(|v| v.settings == settings) {
+            Some(idx) => Ok(idx),
+            None => {
+                self.views.push(View::new(&self.shm, settings)?);
+                Ok(self.views.len() - 1)
+            }
+        }
+    }
+
+    // Returns the scale of the view showing the surface, if it is one of
+    // ours.
+    fn surface_scale(&self, surface: &wl_surface::WlSurface) -> f32 {
+        self.views
+            .iter()
+            .find(|view| view.surfaces.contains(surface))
+            .map_or(1.0, |view| view.settings.scale)
+    }
+
+    // Whether the scale of the surface for the given output follows the
+    // compositor, rather than being set in the config.
+    fn auto_scale(&self, key: Option<u32>) -> bool {
+        let name = key.and_then(|id| self.outputs.iter().find(|o| o.id == id)?.name.as_deref());
+        !self.config.scale_configured(name)
+    }
+
+    // Returns the scale preferred by the compositor for the given output, or
+    // the integer scale of the output if it has not told us.
+    fn preferred_scale(&self, key: Option<u32>, scale: u32) -> f32 {
+        match self.preferred_scales.get(&key) {
+            Some(preferred) => *preferred,
+            None => scale as f32,
+        }
+    }
+
+    // Rounds fractional scales up if the compositor cannot show them.
+    fn supported_settings(&self, mut settings: OutputSettings) -> OutputSettings {
+        if self.viewporter.is_none() {
+            settings.scale = settings.scale.ceil();
+        }
+        settings
+    }
+
+    // Sets up a surface to show buffers at the scale of the settings, and
+    // to tell us when the compositor prefers another scale for it.
+    fn scale_surface(
+        &self,
+        surface: &wl_surface::WlSurface,
+        settings: OutputSettings,
+        key: Option<u32>,
+    ) -> SurfaceScale {
+        let scale = settings.scale;
+        let viewport = match self.viewporter {
+            Some(ref viewporter) if scale.fract() != 0.0 => {
+                Some(viewporter.get_viewport(surface, &self.qh, ()))
+            }
+            _ => {
+                surface.set_buffer_scale(scale as i32);
+                None
+            }
+        };
+        let fractional_scale = match (&self.viewporter, &self.fractional_scale_manager) {
+            (Some(_), Some(manager)) if self.auto_scale(key) => Some(manager.get_fractional_scale(
+                surface,
+                &self.qh,
+                FractionalScaleData { key, scale },
+            )),
+            _ => None,
+        };
+        SurfaceScale {
+            viewport,
+            fractional_scale,
+        }
+    }
+
+    fn add_shell_surface(
+        &self,
+        output: Option<(u32, &wl_output::WlOutput)>,
+        view: &View,
+    ) -> Option<(wl_surface::WlSurface, SurfaceScale, LayerSurface)> {
+        let shell = self.shell.as_ref()?;
+        let surface = self.compositor.create_surface(&self.qh);
+        let shell_surface = shell.create_layer_surface(
+            &self.qh,
+            surface.clone(),
+            Layer::Overlay,
+            Some(""),
+            output.map(|(_, output)| output),
+        );
+
+        shell_surface.set_keyboard_interactivity(KeyboardInteractivity::Exclusive);
+        let size = view.requested_size(self.stretched(), view.buffer_size);
+        shell_surface.set_size(size.0, size.1);
+        shell_surface.set_anchor(self.anchor);
+        shell_surface.set_margin(
+            self.margin.top,
+            self.margin.right,
+            self.margin.bottom,
+            self.margin.left,
+        );
+        shell_surface.set_exclusive_zone(self.exclusive_zone);
+        let scale = self.scale_surface(&surface, view.settings, output.map(|(id, _)| id));
+        surface.commit();
+        Some((surface, scale, shell_surface))
+    }
+
+    fn add_lock_surface(
+        &self,
+        lock: &SessionLock,
+        settings: OutputSettings,
+        output: &wl_output::WlOutput,
+    ) -> Result<LockSurface, Error> {
+        let subcompositor = match self.subcompositor {
+            Some(ref subcompositor) => subcompositor,
+            None => return Err(Error::MissingGlobal("wl_subcompositor")),
+        };
+        let surface = self.compositor.create_surface(&self.qh);
+        let (subsurface, child) = subcompositor.create_subsurface(surface.clone(), &self.qh);
+        subsurface.set_desync();
+
+        Ok(LockSurface {
+            lock_surface: lock.create_lock_surface(surface, output, &self.qh),
+            subsurface,
+            child,
+            size: (0, 0),
+            background: settings.background,
+            pool: MemPool::new(&self.shm)?,
+        })
+    }
+
+    fn destroy_surfaces(&mut self) {
+        for view in self.views.iter_mut() {
+            view.destroy();
+        }
+        for lock_surface in self.hidden_lock_surfaces.drain(..) {
+            lock_surface.destroy();
+        }
+        self.views = Vec::new();
+        self.configured_surfaces.clear();
+    }
+
+    // Returns the index of the configured target output, if it is present.
+    fn target_output(&self) -> Option<usize> {
+        let target = self.config.target_output.as_ref()?;
+        self.outputs
+            .iter()
+            .position(|o| o.name.as_ref() == Some(target))
+    }
+
+    // Returns the width of the output with the given id in surface
+    // coordinates, or of the narrowest output if None.
+    fn output_width(&self, key: Option<u32>) -> Option<u32> {
+        self.outputs
+            .iter()
+            .filter(|o| key.unwrap_or(o.id) == o.id)
+            .filter_map(|o| o.logical_width(self.preferred_scale(Some(o.id), o.scale)))
+            .min()
+    }
+
+    // Returns the id and settings of every output. If the target output is
+    // present, the greeter is hidden on all others.
+    fn output_settings(&self) -> Vec<(u32, wl_output::WlOutput, OutputSettings)> {
+        let target = self.target_output();
+        self.outputs
+            .iter()
+            .enumerate()
+            .map(|(idx, o)| {
+                let scale = self.preferred_scale(Some(o.id), o.scale);
+                let mut settings = self.config.output_settings(o.name.as_deref(), scale);
+                settings.visible &= target.map(|t| t == idx).unwrap_or(true);
+                (o.id, o.output.clone(), self.supported_settings(settings))
+            })
+            .collect()
+    }
+
+    // Records an error for the main loop, and wakes it up to notice.
+    fn fail(&mut self, error: Error) {
+        self.error = Some(error);
+        let _ = self.draw_tx.send(Cmd::Draw);
+    }
+
+    fn outputs_changed(&mut self) {
+        if let Err(e) = self.create_surfaces() {
+            self.fail(e);
+        }
+    }
+
+    // Creates the surfaces for the current outputs.
+    fn create_surfaces(&mut self) -> Result<(), Error> {
+        if let Some(ref lock) = self.lock {
+            let lock = lock.clone();
+            self.destroy_surfaces();
+            debug!(
+                "Creating lock surfaces for {} output(s)",
+                self.output_settings().len()
+            );
+
+            // A lock surface is required on every output, even those the
+            // greeter is hidden on.
+            for (id, output, settings) in self.output_settings() {
+                let lock_surface = self.add_lock_surface(&lock, settings, &output)?;
+                if settings.visible {
+                    let surface = lock_surface.child.clone();
+                    let scale = self.scale_surface(&surface, settings, Some(id));
+                    let width = self.output_width(Some(id));
+                    let idx = self.view_index(settings)?;
+                    self.views[idx].fit_output(width);
+                    self.views[idx].surfaces.push(surface);
+                    self.views[idx].scales.push(scale);
+                    self.views[idx].lock_surfaces.push(lock_surface);
+                } else {
+                    self.hidden_lock_surfaces.push(lock_surface);
+                }
+            }
+            let _ = self.draw_tx.send(Cmd::ForceDraw);
+            return Ok(());
+        }
+
+        if self.shell.is_none() {
+            return Ok(());
+        }
+
+        let target = self.target_output();
+        let margin = (self.margin.left + self.margin.right).max(0) as u32;
+        if let (OutputMode::Active, None) = (self.output_mode, target) {
+            if !self.views.is_empty() {
+                // The surface stays, but may have to fit on another output.
+                let width = self.output_width(None).map(|w| w.saturating_sub(margin));
+                for view in self.views.iter_mut() {
+                    view.output_width = None;
+                    view.fit_output(width);
+                }
+                let _ = self.draw_tx.send(Cmd::ForceDraw);
+                return Ok(());
+            }
+        }
+        self.destroy_surfaces();
+
+        if !self.visible {
+            return Ok(());
+        }
+        let outputs: Vec<(Option<(u32, wl_output::WlOutput)>, OutputSettings)> =
+            match (self.output_mode, target) {
+                (OutputMode::Active, None) => {
+                    // We do not know which output the compositor picks, so
+                    // use the highest scale to look sharp on any of them
+                    // until it tells us.
+                    let scale = self.outputs.iter().map(|o| o.scale).max().unwrap_or(1);
+                    let scale = self.preferred_scale(None, scale);
+                    let settings = self.config.output_settings(None, scale);
+                    vec![(None, self.supported_settings(settings))]
+                }
+                _ => self
+                    .output_settings()
+                    .into_iter()
+                    .filter(|(_, _, settings)| settings.visible)
+                    .map(|(id, output, settings)| (Some((id, output)), settings))
+                    .collect(),
+            };
+        debug!("Creating {} surface(s)", outputs.len());
+        for (output, settings) in outputs {
+            let width = self
+                .output_width(output.as_ref().map(|(id, _)| *id))
+                .map(|width| width.saturating_sub(margin));
+            let idx = self.view_index(settings)?;
+            self.views[idx].fit_output(width);
+            let created = self.add_shell_surface(
+                output.as_ref().map(|(id, output)| (*id, output)),
+                &self.views[idx],
+            );
+            if let Some((surface, scale, shell_surface)) = created {
+                self.views[idx].surfaces.push(surface);
+                self.views[idx].scales.push(scale);
+                self.views[idx].shell_surfaces.push(shell_surface);
+            }
+        }
+        let _ = self.draw_tx.send(Cmd::ForceDraw);
+        Ok(())
+    }
+
+    // Records the current description of an output, recreating the
+    // surfaces if anything changed.
+    fn update_output(&mut self, output: wl_output::WlOutput) {
+        let info = match self.output_state.info(&output) {
+            Some(info) => info,
+            None => return,
+        };
+        let updated = Output::new(output, &info);
+        match self.outputs.iter_mut().find(|o| o.output == updated.output) {
+            Some(output) if *output == updated => return,
+            Some(output) => *output = updated,
+            None => self.outputs.push(updated),
+        }
+        self.outputs_changed();
+    }
+
+    // Returns whether the surfaces need to be recreated at a new scale.
+    fn take_rescale(&mut self) -> bool {
+        std::mem::replace(&mut self.rescale, false)
+    }
+
+    fn map_keyboard(&mut self, seat: &wl_seat::WlSeat) -> Option<wl_keyboard::WlKeyboard> {
+        let keymap = self.keymap.clone();
+        let mut map = |keymap: Option<Keymap>| {
+            let rmlvo = keymap.map(|keymap| RMLVO {
+                rules: None,
+                model: keymap.model,
+                layout: keymap.layout,
+                variant: keymap.variant,
+                options: keymap.options,
+            });
+            match self.seat_state.get_keyboard(&self.qh, seat, rmlvo) {
+                Ok(keyboard) => Some(keyboard),
+                Err(e) => {
+                    warn!("Failed to map keyboard: {:?}", e);
+                    None
+                }
+            }
+        };
+        match keymap {
+            // Fall back to the keymap of the compositor rather than leaving
+            // the keyboard unusable.
+            Some(keymap) => map(Some(keymap)).or_else(|| map(None)),
+            None => map(None),
+        }
+    }
+
+    fn map_pointer(&mut self, seat: &wl_seat::WlSeat) -> Option<ThemedPointer> {
+        // Pointers get the system cursor theme, from $XCURSOR_THEME and
+        // $XCURSOR_SIZE, unless the compositor draws cursors itself.
+        let surface = self.compositor.create_surface(&self.qh);
+        match self.seat_state.get_pointer_with_theme(
+            &self.qh,
+            seat,
+            self.shm.wl_shm(),
+            surface,
+            ThemeSpec::System,
+        ) {
+            Ok(pointer) => Some(pointer),
+            Err(e) => {
+                warn!("Failed to map pointer: {:?}", e);
+                None
+            }
+        }
+    }
+
+    // Switches to another keymap, mapping the keyboards again to load it.
+    fn set_keymap(&mut self, keymap: Option<Keymap>, toggle: Option<LayoutToggle>) {
+        self.keymap = keymap;
+        self.toggle = toggle;
+        for idx in 0..self.seats.len() {
+            if self.seats[idx].keyboard.is_some() {
+                self.seats[idx].release_keyboard(&self.cmd_queue);
+                let seat = self.seats[idx].seat.clone();
+                self.seats[idx].keyboard = self.map_keyboard(&seat);
+            }
+        }
+    }
+
+    // The devices of the seat the touch device belongs to.
+    fn touch_devices(&mut self, touch: &wl_touch::WlTouch) -> Option<&mut SeatDevices> {
+        self.seats
+            .iter_mut()
+            .find(|d| d.touch.as_ref() == Some(touch))
+    }
+
+    // Returns the devices of a seat, starting with none for a seat we have
+    // not seen yet.
+    fn seat_devices(&mut self, seat: &wl_seat::WlSeat) -> &mut SeatDevices {
+        let idx = match self.seats.iter().position(|d| &d.seat == seat) {
+            Some(idx) => idx,
+            None => {
+                let data_device = self
+                    .data_device_manager
+                    .as_ref()
+                    .map(|manager| manager.get_data_device(&self.qh, seat));
+                let idle_notifications = self.idle_notifications(seat);
+                self.seats.push(SeatDevices {
+                    seat: seat.clone(),
+                    keyboard: None,
+                    pointer: None,
+                    touch: None,
+                    touch_points: Vec::new(),
+                    data_device,
+                    modifiers: Modifiers::default(),
+                    compose: compose_state(),
+                    idle_notifications,
+                });
+                self.seats.len() - 1
+            }
+        };
+        &mut self.seats[idx]
+    }
+}
+
+impl CompositorHandler for AppInner {
+    fn scale_factor_changed(
+        &mut self,
+        _: &Connection,
+        _: &QueueHandle<Self>,
+        _: &wl_surface::WlSurface,
+        _: i32,
+    ) {
+    }
+
+    fn transform_changed(
+        &mut self,
+        _: &Connection,
+        _: &QueueHandle<Self>,
+        _: &wl_surface::WlSurface,
+        _: wl_output::Transform,
+    ) {
+    }
+
+    fn frame(
+        &mut self,
+        _: &Connection,
+        _: &QueueHandle<Self>,
+        surface: &wl_surface::WlSurface,
+        _: u32,
+    ) {
+        // Any surface of the view will do, as the others may be on outputs
+        // that are turned off.
+        let view = match self.views.iter_mut().find(|v| v.surfaces.contains(surface)) {
+            Some(view) => view,
+            None => return,
+        };
+        view.frame_pending = false;
+        match view.queued_draw.take() {
+            Some(true) => self.cmd_queue.lock().unwrap().push_back(Cmd::ForceDraw),
+            Some(false) => self.cmd_queue.lock().unwrap().push_back(Cmd::Draw),
+            None => (),
+        }
+    }
+}
+
+impl OutputHandler for AppInner {
+    fn output_state(&mut self) -> &mut OutputState {
+        &mut self.output_state
+    }
+
+    fn new_output(&mut self, _: &Connection, _: &QueueHandle<Self>, output: wl_output::WlOutput) {
+        self.update_output(output);
+    }
+
+    fn update_output(
+        &mut self,
+        _: &Connection,
+        _: &QueueHandle<Self>,
+        output: wl_output::WlOutput,
+    ) {
+        AppInner::update_output(self, output);
+    }
+
+    fn output_destroyed(
+        &mut self,
+        _: &Connection,
+        _: &QueueHandle<Self>,
+        output: wl_output::WlOutput,
+    ) {
+        if let Some(idx) = self.outputs.iter().position(|o| o.output == output) {
+            self.outputs.remove(idx);
+            self.outputs_changed();
+        }
+    }
+}
+
+impl LayerShellHandler for AppInner {
+    fn closed(&mut self, _: &Connection, _: &QueueHandle<Self>, _: &LayerSurface) {
+        debug!("Surface closed by the compositor");
+        self.destroy_surfaces();
+        self.outputs_changed();
+    }
+
+    fn configure(
+        &mut self,
+        _: &Connection,
+        _: &QueueHandle<Self>,
+        layer: &LayerSurface,
+        configure: LayerSurfaceConfigure,
+        _: u32,
+    ) {
+        let (width, height) = configure.new_size;
+        debug!("Surface configured to {}x{}", width, height);
+        let view = match self
+            .views
+            .iter_mut()
+            .find(|view| view.shell_surfaces.contains(layer))
+        {
+            Some(view) => view,
+            None => return,
+        };
+        let resized = view.configured_size != (width, height);
+        view.configured_size = (width, height);
+        let first = self.configured_surfaces.insert(layer.wl_surface().id());
+        if first || resized {
+            let scale = view.settings.scale;
+            let size = (scaled(width, scale), scaled(height, scale));
+            let _ = self.draw_tx.send(Cmd::Configure(size));
+        }
+    }
+}
+
+impl SessionLockHandler for AppInner {
+    fn locked(&mut self, _: &Connection, _: &QueueHandle<Self>, _: SessionLock) {
+        debug!("Session locked");
+    }
+
+    fn finished(&mut self, _: &Connection, _: &QueueHandle<Self>, _: SessionLock) {
+        self.fail(Error::LockRefused);
+    }
+
+    fn configure(
+        &mut self,
+        _: &Connection,
+        qh: &QueueHandle<Self>,
+        surface: SessionLockSurface,
+        configure: SessionLockSurfaceConfigure,
+        _: u32,
+    ) {
+        let lock_surface = match self
+            .views
+            .iter_mut()
+            .flat_map(|view| view.lock_surfaces.iter_mut())
+            .chain(self.hidden_lock_surfaces.iter_mut())
+            .find(|l| l.lock_surface.wl_surface() == surface.wl_surface())
+        {
+            Some(lock_surface) => lock_surface,
+            None => return,
+        };
+        lock_surface.size = configure.new_size;
+        if let Err(e) = lock_surface.fill(qh, self.idle) {
+            self.fail(e.into());
+            return;
+        }
+        self.configured_surfaces.insert(surface.wl_surface().id());
+        // Surfaces on outputs the greeter is hidden on do not show it.
+        let scale = self
+            .views
+            .iter()
+            .find(|view| {
+                view.lock_surfaces
+                    .iter()
+                    .any(|l| l.lock_surface.wl_surface() == surface.wl_surface())
+            })
+            .map(|view| view.settings.scale);
+        let cmd = match scale {
+            Some(scale) => {
+                let (width, height) = configure.new_size;
+                Cmd::Configure((scaled(width, scale), scaled(height, scale)))
+            }
+            None => Cmd::ForceDraw,
+        };
+        let _ = self.draw_tx.send(cmd);
+    }
+}
+
+impl SeatHandler for AppInner {
+    fn seat_state(&mut self) -> &mut SeatState {
+        &mut self.seat_state
+    }
+
+    fn new_seat(&mut self, _: &Connection, _: &QueueHandle<Self>, seat: wl_seat::WlSeat) {
+        self.seat_devices(&seat);
+    }
+
+    // Maps and unmaps keyboards and pointers as seats change their
+    // capabilities.
+    fn new_capability(
+        &mut self,
+        _: &Connection,
+        _: &QueueHandle<Self>,
+        seat: wl_seat::WlSeat,
+        capability: Capability,
+    ) {
+        let dev = self.seat_devices(&seat);
+        match capability {
+            Capability::Keyboard if dev.keyboard.is_none() => {
+                let keyboard = self.map_keyboard(&seat);
+                self.seat_devices(&seat).keyboard = keyboard;
+            }
+            Capability::Pointer if dev.pointer.is_none() => {
+                let pointer = self.map_pointer(&seat);
+                self.seat_devices(&seat).pointer = pointer;
+            }
+            Capability::Touch if dev.touch.is_none() => {
+                match self.seat_state.get_touch(&self.qh, &seat) {
+                    Ok(touch) => self.seat_devices(&seat).touch = Some(touch),
+                    Err(e) => warn!("Failed to map touch: {:?}", e),
+                }
+            }
+            _ => (),
         }
     }
 
-    fn add_shell_surface(
-        compositor: &wl_compositor::WlCompositor,
-        shell: &zwlr_layer_shell_v1::ZwlrLayerShellV1,
-        scale: u32,
-        configured_surfaces: Arc<Mutex<usize>>,
-        tx: Sender<Cmd>,
-        output: Option<&wl_output::WlOutput>,
-    ) -> (
-        wl_surface::WlSurface,
-        zwlr_layer_surface_v1::ZwlrLayerSurfaceV1,
+    fn remove_capability(
+        &mut self,
+        _: &Connection,
+        _: &QueueHandle<Self>,
+        seat: wl_seat::WlSeat,
+        capability: Capability,
     ) {
-        let surface = compositor.create_surface();
+        let cmd_queue = self.cmd_queue.clone();
+        let dev = self.seat_devices(&seat);
+        match capability {
+            Capability::Keyboard => dev.release_keyboard(&cmd_queue),
+            // Themed pointers are released when dropped.
+            Capability::Pointer => dev.pointer = None,
+            Capability::Touch => dev.release_touch(&cmd_queue),
+            _ => (),
+        }
+    }
 
-        let this_is_stupid = Arc::new(Mutex::new(false));
+    fn remove_seat(&mut self, _: &Connection, _: &QueueHandle<Self>, seat: wl_seat::WlSeat) {
+        if let Some(idx) = self.seats.iter().position(|d| d.seat == seat) {
+            let mut dev = self.seats.remove(idx);
+            dev.release_keyboard(&self.cmd_queue);
+            dev.release_touch(&self.cmd_queue);
+            dev.stop_idle_notifications();
+        }
+    }
+}
 
-        let shell_surface = shell.get_layer_surface(
-            &surface,
-            output,
-            zwlr_layer_shell_v1::Layer::Overlay,
-            "".to_string(),
-        );
-        shell_surface.quick_assign(move |layer, evt, _| match evt {
-            zwlr_layer_surface_v1::Event::Configure { serial, .. } => {
-                let mut x = this_is_stupid.lock().unwrap();
-                if !*x {
-                    *x = true;
-                    *(configured_surfaces.lock().unwrap()) += 1;
-                    layer.ack_configure(serial);
-                    tx.send(Cmd::ForceDraw).unwrap();
-                }
-            }
-            _ => unreachable!(),
+impl KeyboardHandler for AppInner {
+    fn enter(
+        &mut self,
+        _: &Connection,
+        _: &QueueHandle<Self>,
+        _: &wl_keyboard::WlKeyboard,
+        surface: &wl_surface::WlSurface,
+        _: u32,
+        _: &[u32],
+        _: &[Keysym],
+    ) {
+        let output = surface
+            .data::<SurfaceData>()
+            .and_then(|data| data.outputs().next());
+        self.focused_output = output.and_then(|output| {
+            self.outputs
+                .iter()
+                .find(|o| o.output == output)
+                .and_then(|o| o.name.clone())
         });
+    }
 
-        shell_surface
-            .set_keyboard_interactivity(zwlr_layer_surface_v1::KeyboardInteractivity::Exclusive);
-        shell_surface.set_size(1, 1);
-        surface.set_buffer_scale(scale as i32);
-        surface.commit();
-        (surface.detach(), shell_surface.detach())
+    fn leave(
+        &mut self,
+        _: &Connection,
+        _: &QueueHandle<Self>,
+        keyboard: &wl_keyboard::WlKeyboard,
+        _: &wl_surface::WlSurface,
+        _: u32,
+    ) {
+        // A sequence is not to be finished after coming back.
+        let dev = self
+            .seats
+            .iter_mut()
+            .find(|d| d.keyboard.as_ref() == Some(keyboard));
+        if let Some(dev) = dev {
+            if let Some(ref mut state) = dev.compose {
+                state.reset();
+            }
+            let seat = dev.seat.id().protocol_id();
+            self.cmd_queue
+                .lock()
+                .unwrap()
+                .push_back(Cmd::KeyboardLeave { seat });
+        }
     }
 
-    fn outputs_changed(&mut self) {
-        let shell = match self.shell {
-            Some(ref shell) => shell.to_owned(),
+    fn press_key(
+        &mut self,
+        _: &Connection,
+        _: &QueueHandle<Self>,
+        keyboard: &wl_keyboard::WlKeyboard,
+        _: u32,
+        event: KeyEvent,
+    ) {
+        let dev = match self
+            .seats
+            .iter_mut()
+            .find(|d| d.keyboard.as_ref() == Some(keyboard))
+        {
+            Some(dev) => dev,
             None => return,
         };
-        let compositor = match self.compositor {
-            Some(ref c) => c.to_owned(),
-            None => return,
+        let modifiers = dev.modifiers;
+        let seat = dev.seat.id().protocol_id();
+        let (key, utf8) = match dev.compose {
+            Some(ref mut state) => match compose_key(state, &event) {
+                Some(composed) => composed,
+                None => return,
+            },
+            None => (event.keysym, event.utf8),
         };
+        let cmd = match self.keybindings.lookup(key, &modifiers) {
+            Some(Action::Exit) if !self.config.lock => Cmd::Exit,
+            Some(Action::Paste) => {
+                if let Some(data_device) = &dev.data_device {
+                    paste_selection(data_device, seat, self.draw_tx.clone());
+                }
+                return;
+            }
+            Some(Action::HighContrast) => Cmd::ToggleHighContrast,
+            Some(Action::TextLarger) => Cmd::ScaleText(1),
+            Some(Action::TextSmaller) => Cmd::ScaleText(-1),
+            Some(Action::TextReset) => Cmd::ScaleText(0),
+            Some(Action::BrightnessUp) => Cmd::Brightness(1),
+            Some(Action::BrightnessDown) => Cmd::Brightness(-1),
+            Some(Action::VolumeUp) => Cmd::Volume(1),
+            Some(Action::VolumeDown) => Cmd::Volume(-1),
+            Some(Action::Mute) => Cmd::Volume(0),
+            Some(action) => Cmd::Action(seat, action),
+            None if matches!(self.toggle, Some(t) if is_layout_toggle(t, key, &modifiers)) => {
+                Cmd::SwitchLayout
+            }
+            None => Cmd::Keyboard {
+                seat,
+                key,
+                key_state: KeyState::Pressed,
+                modifiers_state: modifiers,
+                interpreted: utf8,
+            },
+        };
+        self.cmd_queue.lock().unwrap().push_back(cmd);
+    }
 
-        for shell_surface in self.shell_surfaces.iter() {
-            shell_surface.destroy();
-        }
-        for surface in self.surfaces.iter() {
-            surface.destroy();
+    fn release_key(
+        &mut self,
+        _: &Connection,
+        _: &QueueHandle<Self>,
+        keyboard: &wl_keyboard::WlKeyboard,
+        _: u32,
+        event: KeyEvent,
+    ) {
+        if let Some(dev) = self
+            .seats
+            .iter()
+            .find(|d| d.keyboard.as_ref() == Some(keyboard))
+        {
+            self.cmd_queue.lock().unwrap().push_back(Cmd::KeyRelease {
+                seat: dev.seat.id().protocol_id(),
+                key: event.keysym,
+            });
         }
+    }
 
-        self.configured_surfaces = Arc::new(Mutex::new(0));
+    fn update_modifiers(
+        &mut self,
+        _: &Connection,
+        _: &QueueHandle<Self>,
+        keyboard: &wl_keyboard::WlKeyboard,
+        _: u32,
+        modifiers: Modifiers,
+    ) {
+        if let Some(dev) = self
+            .seats
+            .iter_mut()
+            .find(|d| d.keyboard.as_ref() == Some(keyboard))
+        {
+            dev.modifiers = modifiers;
+            self.cmd_queue.lock().unwrap().push_back(Cmd::Modifiers {
+                seat: dev.seat.id().protocol_id(),
+                modifiers_state: modifiers,
+            });
+        }
+    }
+}
 
-        if self.visible {
-            match self.output_mode {
-                OutputMode::Active => {
-                    if self.shell_surfaces.len() > 0 {
-                        return;
+impl PointerHandler for AppInner {
+    fn pointer_frame(
+        &mut self,
+        conn: &Connection,
+        _: &QueueHandle<Self>,
+        pointer: &wl_pointer::WlPointer,
+        events: &[PointerEvent],
+    ) {
+        let seat = match self
+            .seats
+            .iter()
+            .find(|d| matches!(d.pointer, Some(ref p) if p.pointer() == pointer))
+        {
+            Some(dev) => dev.seat.id().protocol_id(),
+            None => return,
+        };
+        let mut pos: (u32, u32) = (0, 0);
+        let mut vert_scroll: f64 = 0.0;
+        let mut click = None;
+        let mut moved = false;
+        let mut left = false;
+        for event in events {
+            let scale = self.surface_scale(&event.surface) as f64;
+            pos = (
+                (event.position.0 * scale) as u32,
+                (event.position.1 * scale) as u32,
+            );
+            match event.kind {
+                PointerEventKind::Enter { .. } => {
+                    let themed = self
+                        .seats
+                        .iter()
+                        .filter_map(|d| d.pointer.as_ref())
+                        .find(|p| p.pointer() == pointer);
+                    if let Some(themed) = themed {
+                        let _ = themed.set_cursor(conn, CursorIcon::Default);
                     }
-                    let (surface, shell_surface) = AppInner::add_shell_surface(
-                        &compositor,
-                        &shell,
-                        self.scale,
-                        self.configured_surfaces.clone(),
-                        self.draw_tx.clone(),
-                        None,
-                    );
-                    self.surfaces = vec![surface];
-                    self.shell_surfaces = vec![shell_surface];
+                    moved = true;
+                    left = false;
                 }
-                OutputMode::All => {
-                    let mut surfaces = Vec::new();
-                    let mut shell_surfaces = Vec::new();
-                    for output in self.outputs.iter() {
-                        let (surface, shell_surface) = AppInner::add_shell_surface(
-                            &compositor,
-                            &shell,
-                            self.scale,
-                            self.configured_surfaces.clone(),
-                            self.draw_tx.clone(),
-                            Some(&output.1),
-                        );
-                        surfaces.push(surface);
-                        shell_surfaces.push(shell_surface);
-                    }
-                    self.surfaces = surfaces;
-                    self.shell_surfaces = shell_surfaces;
+                PointerEventKind::Motion { .. } => {
+                    moved = true;
+                    left = false;
                 }
+                PointerEventKind::Leave { .. } => {
+                    pos = (0, 0);
+                    moved = false;
+                    left = true;
+                }
+                PointerEventKind::Axis { ref vertical, .. } => vert_scroll += vertical.absolute,
+                PointerEventKind::Release { button, .. } => click = Some(button),
+                _ => (),
             }
-            self.draw_tx.send(Cmd::ForceDraw).unwrap();
-        } else {
-            self.surfaces = Vec::new();
-            self.shell_surfaces = Vec::new();
+        }
+        let mut cmd_queue = self.cmd_queue.lock().unwrap();
+        if moved {
+            cmd_queue.push_back(Cmd::MouseMove { seat, pos });
+        } else if left {
+            cmd_queue.push_back(Cmd::MouseLeave { seat });
+        }
+        if vert_scroll != 0.0 {
+            cmd_queue.push_back(Cmd::MouseScroll {
+                seat,
+                scroll: (0.0, vert_scroll),
+                pos,
+            });
+        }
+        if let Some(btn) = click {
+            cmd_queue.push_back(Cmd::MouseClick { seat, btn, pos });
         }
     }
+}
 
-    fn add_output(&mut self, id: u32, output: Attached<wl_output::WlOutput>) {
-        self.outputs.push((id, output));
-        self.outputs_changed();
+impl TouchHandler for AppInner {
+    fn down(
+        &mut self,
+        _: &Connection,
+        _: &QueueHandle<Self>,
+        touch: &wl_touch::WlTouch,
+        _: u32,
+        _: u32,
+        surface: wl_surface::WlSurface,
+        id: i32,
+        position: (f64, f64),
+    ) {
+        let scale = self.surface_scale(&surface);
+        let seat = match self.touch_devices(touch) {
+            Some(dev) => {
+                dev.touch_points.push((id, scale));
+                dev.seat.id().protocol_id()
+            }
+            None => return,
+        };
+        self.cmd_queue.lock().unwrap().push_back(Cmd::TouchDown {
+            seat,
+            id,
+            pos: touch_position(position, scale),
+        });
+    }
+
+    fn up(
+        &mut self,
+        _: &Connection,
+        _: &QueueHandle<Self>,
+        touch: &wl_touch::WlTouch,
+        _: u32,
+        _: u32,
+        id: i32,
+    ) {
+        let seat = match self.touch_devices(touch) {
+            Some(dev) => {
+                dev.touch_points.retain(|(point, _)| *point != id);
+                dev.seat.id().protocol_id()
+            }
+            None => return,
+        };
+        self.cmd_queue
+            .lock()
+            .unwrap()
+            .push_back(Cmd::TouchUp { seat, id });
     }
 
-    fn remove_output(&mut self, id: u32) {
-        let old_output = self.outputs.iter().find(|(output_id, _)| *output_id == id);
-        if let Some(output) = old_output {
-            let new_outputs = self
-                .outputs
+    fn motion(
+        &mut self,
+        _: &Connection,
+        _: &QueueHandle<Self>,
+        touch: &wl_touch::WlTouch,
+        _: u32,
+        id: i32,
+        position: (f64, f64),
+    ) {
+        let point = self.touch_devices(touch).and_then(|dev| {
+            let seat = dev.seat.id().protocol_id();
+            dev.touch_points
                 .iter()
-                .filter(|(output_id, _)| *output_id != id)
-                .map(|(x, y)| (x.clone(), y.clone()))
-                .collect();
-            if output.1.as_ref().version() >= 3 {
-                output.1.release()
+                .find(|(point, _)| *point == id)
+                .map(|(_, scale)| (seat, *scale))
+        });
+        if let Some((seat, scale)) = point {
+            self.cmd_queue.lock().unwrap().push_back(Cmd::TouchMotion {
+                seat,
+                id,
+                pos: touch_position(position, scale),
+            });
+        }
+    }
+
+    fn shape(
+        &mut self,
+        _: &Connection,
+        _: &QueueHandle<Self>,
+        _: &wl_touch::WlTouch,
+        _: i32,
+        _: f64,
+        _: f64,
+    ) {
+    }
+
+    fn orientation(
+        &mut self,
+        _: &Connection,
+        _: &QueueHandle<Self>,
+        _: &wl_touch::WlTouch,
+        _: i32,
+        _: f64,
+    ) {
+    }
+
+    fn cancel(&mut self, _: &Connection, _: &QueueHandle<Self>, touch: &wl_touch::WlTouch) {
+        let cmd_queue = self.cmd_queue.clone();
+        if let Some(dev) = self.touch_devices(touch) {
+            dev.cancel_touch(&cmd_queue);
+        }
+    }
+}
+
+// A position in surface coordinates in buffer pixels.
+fn touch_position(position: (f64, f64), scale: f32) -> (u32, u32) {
+    let scale = scale as f64;
+    ((position.0 * scale) as u32, (position.1 * scale) as u32)
+}
+
+impl ShmHandler for AppInner {
+    fn shm_state(&mut self) -> &mut Shm {
+        &mut self.shm
+    }
+}
+
+// The clipboard is only read when pasting, so none of its events matter.
+impl DataDeviceHandler for AppInner {
+    fn enter(&mut self, _: &Connection, _: &QueueHandle<Self>, _: &WlDataDevice) {}
+
+    fn leave(&mut self, _: &Connection, _: &QueueHandle<Self>, _: &WlDataDevice) {}
+
+    fn motion(&mut self, _: &Connection, _: &QueueHandle<Self>, _: &WlDataDevice) {}
+
+    fn selection(&mut self, _: &Connection, _: &QueueHandle<Self>, _: &WlDataDevice) {}
+
+    fn drop_performed(&mut self, _: &Connection, _: &QueueHandle<Self>, _: &WlDataDevice) {}
+}
+
+impl DataOfferHandler for AppInner {
+    fn source_actions(
+        &mut self,
+        _: &Connection,
+        _: &QueueHandle<Self>,
+        _: &mut DragOffer,
+        _: DndAction,
+    ) {
+    }
+
+    fn selected_action(
+        &mut self,
+        _: &Connection,
+        _: &QueueHandle<Self>,
+        _: &mut DragOffer,
+        _: DndAction,
+    ) {
+    }
+}
+
+impl DataSourceHandler for AppInner {
+    fn accept_mime(
+        &mut self,
+        _: &Connection,
+        _: &QueueHandle<Self>,
+        _: &WlDataSource,
+        _: Option<String>,
+    ) {
+    }
+
+    fn send_request(
+        &mut self,
+        _: &Connection,
+        _: &QueueHandle<Self>,
+        _: &WlDataSource,
+        _: String,
+        _: WritePipe,
+    ) {
+    }
+
+    fn cancelled(&mut self, _: &Connection, _: &QueueHandle<Self>, _: &WlDataSource) {}
+
+    fn dnd_dropped(&mut self, _: &Connection, _: &QueueHandle<Self>, _: &WlDataSource) {}
+
+    fn dnd_finished(&mut self, _: &Connection, _: &QueueHandle<Self>, _: &WlDataSource) {}
+
+    fn action(&mut self, _: &Connection, _: &QueueHandle<Self>, _: &WlDataSource, _: DndAction) {}
+}
+
+impl Dispatch<wp_fractional_scale_v1::WpFractionalScaleV1, FractionalScaleData> for AppInner {
+    fn event(
+        state: &mut AppInner,
+        _: &wp_fractional_scale_v1::WpFractionalScaleV1,
+        event: wp_fractional_scale_v1::Event,
+        data: &FractionalScaleData,
+        _: &Connection,
+        _: &QueueHandle<AppInner>,
+    ) {
+        if let wp_fractional_scale_v1::Event::PreferredScale { scale } = event {
+            // The scale is sent in 120ths.
+            let preferred = scale as f32 / 120.0;
+            state.preferred_scales.insert(data.key, preferred);
+            if preferred != data.scale {
+                state.rescale = true;
+                let _ = state.draw_tx.send(Cmd::ForceDraw);
             }
-            self.outputs = new_outputs;
-            self.outputs_changed();
         }
     }
+}
+
+impl ProvidesRegistryState for AppInner {
+    fn registry(&mut self) -> &mut RegistryState {
+        &mut self.registry_state
+    }
+
+    registry_handlers![OutputState, SeatState];
+}
+
+delegate_compositor!(AppInner);
+delegate_subcompositor!(AppInner);
+delegate_output!(AppInner);
+delegate_shm!(AppInner);
+delegate_seat!(AppInner);
+delegate_keyboard!(AppInner);
+delegate_pointer!(AppInner);
+delegate_touch!(AppInner);
+delegate_data_device!(AppInner);
+delegate_layer!(AppInner);
+delegate_session_lock!(AppInner);
+delegate_registry!(AppInner);
+delegate_dispatch!(AppInner: [wl_buffer::WlBuffer: BufferData] => MemPool);
+delegate_noop!(AppInner: wp_viewporter::WpViewporter);
+delegate_noop!(AppInner: wp_viewport::WpViewport);
+delegate_noop!(AppInner: wp_fractional_scale_manager_v1::WpFractionalScaleManagerV1);
+delegate_noop!(AppInner: ext_idle_notifier_v1::ExtIdleNotifierV1);
 
-    fn set_compositor(&mut self, compositor: Option<Main<wl_compositor::WlCompositor>>) {
-        self.compositor = compositor
+impl Dispatch<ext_idle_notification_v1::ExtIdleNotificationV1, Idle> for AppInner {
+    fn event(
+        state: &mut AppInner,
+        _: &ext_idle_notification_v1::ExtIdleNotificationV1,
+        event: ext_idle_notification_v1::Event,
+        idle: &Idle,
+        _: &Connection,
+        _: &QueueHandle<AppInner>,
+    ) {
+        match event {
+            ext_idle_notification_v1::Event::Idled => state.set_idle(state.idle.max(*idle)),
+            ext_idle_notification_v1::Event::Resumed => state.set_idle(Idle::Active),
+            _ => (),
+        }
     }
+}
+
+/// The socket of the compositor connection, to be polled for events.
+pub struct ConnectionFd(Backend);
 
-    fn set_shell(&mut self, shell: Option<Main<zwlr_layer_shell_v1::ZwlrLayerShellV1>>) {
-        self.shell = shell
+impl AsFd for ConnectionFd {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.0.poll_fd()
     }
 }
 
 pub struct App {
     config: Config,
-    pools: DoubleMemPool,
-    display: Display,
-    event_queue: EventQueue,
+    conn: Connection,
+    event_queue: EventQueue<AppInner>,
     cmd_queue: Arc<Mutex<VecDeque<Cmd>>>,
     widget: Option<Box<dyn Widget + Send>>,
-    inner: Arc<Mutex<AppInner>>,
-    last_damage: Option<Vec<(i32, i32, i32, i32)>>,
-    last_dim: (u32, u32),
+    inner: AppInner,
+    // The modifiers held on each seat, and on the seat last typed on, which
+    // are the ones shown.
+    seat_modifiers: HashMap<SeatId, Modifiers>,
+    seat: Option<SeatId>,
+    modifiers: Modifiers,
+    // Index of the current layout in the keyboardLayouts list.
+    layout: usize,
+    // Whether a frame has been committed yet.
+    committed: bool,
+    // Fades in the first frames.
+    fade_in: Option<Animation>,
+    // A level shown over the widgets, and until when.
+    level: Option<(f32, Instant)>,
+    // Animates the background around the widgets.
+    backdrop: Option<Backdrop>,
 }
 
 impl App {
-    pub fn redraw(&mut self, mut force: bool) -> Result<(), ::std::io::Error> {
+    pub fn redraw(&mut self, force: bool) -> Result<(), Error> {
         let widget = match self.widget {
             Some(ref mut widget) => widget,
             None => return Ok(()),
         };
 
-        let inner = self.inner.lock().unwrap();
+        let inner = &mut self.inner;
         let time = Local::now();
 
-        if inner.shell_surfaces.len() != *inner.configured_surfaces.lock().unwrap() {
+        if inner.take_rescale() {
+            // The compositor prefers another scale, so start over with new
+            // surfaces. This queues a new draw.
+            inner.destroy_surfaces();
+            inner.outputs_changed();
+            return Ok(());
+        }
+
+        if !inner.configured() {
             // Not ready yet
             return Ok(());
         }
 
-        let (last, pool) = match self.pools.pool() {
-            Some((last, pool)) => (last, pool),
-            None => return Ok(()),
-        };
+        let now = Instant::now();
+        if !self.committed && self.fade_in.is_none() && self.config.animations {
+            self.fade_in = Some(Animation::new(FADE_IN, Easing::EaseOut));
+        }
+        // The frame after the fade-in is drawn in full at full opacity.
+        let fade = self.fade_in.as_ref().map(|a| a.value(now));
+        if matches!(self.fade_in, Some(ref a) if a.done(now)) {
+            self.fade_in = None;
+        }
+
+        // Widgets only draw what changed since they were last drawn, so with
+        // more than one view every view has to be drawn in full. The same
+        // goes for dimming, which darkens everything, and for wallpapers
+        // changing behind the widgets.
+        let idle = inner.idle;
+        // There is no point animating a screen that is dimmed or blanked.
+        let animate = idle == Idle::Active;
+        let mut backdrop = self.backdrop.as_mut();
+        let backdrop_changed = backdrop.as_mut().is_some_and(|b| b.advance(now, animate));
+        let behind_changed = backdrop.as_ref().is_some_and(|b| b.behind_changed());
+        let force = force
+            || inner.views.len() > 1
+            || idle != Idle::Active
+            || fade.is_some()
+            || behind_changed;
+        let stretched = inner.stretched();
+        let qh = &inner.qh;
+        for view in inner.views.iter_mut() {
+            if view.frame_pending {
+                // Drawing faster than the compositor shows the frames is
+                // wasted work, so wait for the frame callback.
+                view.queued_draw = Some(view.queued_draw.unwrap_or(false) || force);
+                continue;
+            }
+            let mut force = force;
+            let available = view.available_size(stretched);
+            widget.resize(available, view.settings.scale);
+            let widget_size = widget.size();
+            let size = (
+                widget_size.0.max(available.0),
+                widget_size.1.max(available.1),
+            );
+            let size_changed = view.buffer_size != size;
+            let requested = view.requested_size(stretched, size);
+            let logical = view.logical_size(size);
+            let configured = view.configured_size;
+            let destination = (
+                if stretched.0 { configured.0 } else { logical.0 },
+                if stretched.1 { configured.1 } else { logical.1 },
+            );
 
-        let size = widget.size();
-        let size_changed = self.last_dim != size;
+            let (last, pool) = match view.pools.pool() {
+                Some((last, pool)) => (last, pool),
+                None => continue,
+            };
 
-        // resize the pool if relevant
-        pool.resize((4 * size.0 * size.1) as usize)
-            .expect("Failed to resize the memory pool.");
-        let mmap = pool.mmap();
-        let mut buf = Buffer::new(mmap, size);
+            // resize the pool if relevant
+            pool.resize((4 * size.0 * size.1) as usize)?;
+            let mmap = pool.mmap();
+            let mut buf = Buffer::new(mmap, size);
 
-        // Copy old damage
-        if let Some(d) = &self.last_damage {
-            if !size_changed {
-                let lastmmap = last.mmap();
-                let last = Buffer::new(lastmmap, size);
+            // Copy old damage
+            if let Some(d) = &view.last_damage {
+                if !size_changed {
+                    let lastmmap = last.mmap();
+                    let last = Buffer::new(lastmmap, size);
 
-                if cfg!(feature = "damage_debug") {
-                    buf.memset(&Color::new(0.5, 0.75, 0.75, 1.0));
-                }
-                for d in d {
-                    last.copy_to(&mut buf, d.clone());
+                    if cfg!(feature = "damage_debug") {
+                        buf.memset(&Color::new(0.5, 0.75, 0.75, 1.0));
+                    }
+                    for d in d {
+                        last.copy_to(&mut buf, d.clone());
+                    }
+                } else {
+                    force = true;
                 }
             } else {
                 force = true;
             }
-        } else {
-            force = true;
-        }
 
-        if force {
-            buf.memset(&self.config.background);
-        }
-        let report = widget.draw(
-            &mut DrawContext {
-                buf: &mut buf,
-                bg: &self.config.background,
-                time: &time,
-                force,
-                config: &self.config,
-            },
-            (0, 0),
-        )?;
+            let background = view.settings.background;
+            if force {
+                buf.memset(&background);
+            }
+            let origin = ((size.0 - widget_size.0) / 2, (size.1 - widget_size.1) / 2);
+            let mut report = widget.draw(
+                &mut DrawContext {
+                    buf: &mut buf,
+                    bg: &background,
+                    time: &time,
+                    force,
+                    config: &self.config,
+                    modifiers: &self.modifiers,
+                    layout: self
+                        .config
+                        .keyboard_layouts
+                        .get(self.layout)
+                        .map(|l| l.as_str()),
+                    output: inner.focused_output.as_deref(),
+                    focused: true,
+                },
+                origin,
+            )?;
+            let widgets = (origin.0, origin.1, widget_size.0, widget_size.1);
+            if let Some(backdrop) = backdrop.as_mut() {
+                backdrop.draw_behind(
+                    &mut Buffer::new(mmap, size),
+                    &background,
+                    widgets,
+                    Some(report.damage.as_slice()).filter(|_| !force),
+                    view.settings.scale,
+                    now,
+                )?;
+            }
+            if let Some(backdrop) = backdrop.as_mut().filter(|_| force || backdrop_changed) {
+                report.damage.extend(backdrop.draw(
+                    &mut Buffer::new(mmap, size),
+                    &background,
+                    Some(&self.config.headline).filter(|_| animate),
+                    widgets,
+                    view.settings.scale,
+                    now,
+                )?);
+            }
+            if let Some((level, _)) = self.level {
+                // Drawn over the widgets every time, as they may have drawn
+                // over it.
+                let s = view.settings.scale;
+                let dim = (scaled(240, s).min(size.0), scaled(16, s).min(size.1));
+                let pos = (
+                    (size.0 - dim.0) / 2,
+                    size.1 - dim.1 - scaled(24, s).min(size.1 - dim.1),
+                );
+                let mut buf = Buffer::new(mmap, size);
+                let mut bar = buf.subdimensions((pos.0, pos.1, dim.0, dim.1))?;
+                draw::draw_meter(
+                    &mut bar,
+                    &background,
+                    &self.config.prompt,
+                    dim,
+                    scaled(1, s).max(1),
+                    level,
+                )?;
+                report.damage.push(bar.get_signed_bounds());
+            }
+            idle.apply(&mut Buffer::new(mmap, size));
+            if let Some(level) = fade.filter(|level| *level < 1.0) {
+                Buffer::new(mmap, size).fade(level);
+            }
 
-        mmap.flush().unwrap();
+            mmap.flush()?;
 
-        if !size_changed && !report.full_damage && report.damage.len() == 0 {
-            // Nothing to do
-            return Ok(());
-        }
+            if !force && !size_changed && !report.full_damage && report.damage.len() == 0 {
+                // Nothing to do
+                continue;
+            }
 
-        // get a buffer and attach it
-        let new_buffer = pool.buffer(
-            0,
-            report.width as i32,
-            report.height as i32,
-            4 * size.0 as i32,
-            wl_shm::Format::Argb8888,
-        );
-        if size_changed {
-            for shell_surface in inner.shell_surfaces.iter() {
-                shell_surface.set_size(size.0 / inner.scale, size.1 / inner.scale);
+            // get a buffer and attach it
+            let new_buffer = pool.buffer(
+                0,
+                size.0 as i32,
+                size.1 as i32,
+                4 * size.0 as i32,
+                wl_shm::Format::Argb8888,
+                qh,
+            );
+            if size_changed {
+                for shell_surface in view.shell_surfaces.iter() {
+                    shell_surface.set_size(requested.0, requested.1);
+                }
+                for scale in view.scales.iter() {
+                    if let Some(ref viewport) = scale.viewport {
+                        viewport.set_destination(destination.0 as i32, destination.1 as i32);
+                    }
+                }
+                for lock_surface in view.lock_surfaces.iter() {
+                    let (width, height) = lock_surface.size;
+                    lock_surface.subsurface.set_position(
+                        (width as i32 - logical.0 as i32) / 2,
+                        (height as i32 - logical.1 as i32) / 2,
+                    );
+                    lock_surface.lock_surface.wl_surface().commit();
+                }
             }
-        }
-        for surface in inner.surfaces.iter() {
-            surface.attach(Some(&new_buffer), 0, 0);
-            if cfg!(feature = "damage_debug") || force || report.full_damage {
-                surface.damage_buffer(0, 0, size.0 as i32, size.1 as i32);
-            } else {
-                for d in report.damage.iter() {
-                    surface.damage_buffer(d.0, d.1, d.2, d.3);
+            for surface in view.surfaces.iter() {
+                surface.attach(Some(&new_buffer), 0, 0);
+                if cfg!(feature = "damage_debug") || force || report.full_damage {
+                    surface.damage_buffer(0, 0, size.0 as i32, size.1 as i32);
+                } else {
+                    for d in report.damage.iter() {
+                        surface.damage_buffer(d.0, d.1, d.2, d.3);
+                    }
                 }
+                surface.frame(qh, surface.clone());
+                surface.commit();
             }
-            surface.commit();
+            self.committed = true;
+            view.frame_pending = true;
+            view.last_damage = if force || report.full_damage {
+                Some(vec![(0, 0, size.0 as i32, size.1 as i32)])
+            } else {
+                Some(report.damage)
+            };
+            view.buffer_size = size;
+        }
+        if let Some(at) = self.fade_in.as_ref().and_then(|a| a.next_frame(now)) {
+            self.cmd_queue.lock().unwrap().push_back(Cmd::Wake(at));
+        }
+        if let Some(at) = backdrop.and_then(|b| b.next_frame(now, animate)) {
+            self.cmd_queue.lock().unwrap().push_back(Cmd::Wake(at));
         }
-        self.last_damage = if force || report.full_damage {
-            Some(vec![(0, 0, size.0 as i32, size.1 as i32)])
-        } else {
-            Some(report.damage)
-        };
-        self.last_dim = size;
         Ok(())
     }
 
-    pub fn cmd_queue(&self) -> Arc<Mutex<VecDeque<Cmd>>> {
-        self.cmd_queue.clone()
+    /// Unlocks the session when running as a screen locker.
+    pub fn unlock(&mut self) -> Result<(), Error> {
+        // Dropping the lock unlocks the session.
+        self.inner.lock = None;
+        self.inner.destroy_surfaces();
+        self.roundtrip()
     }
 
-    pub fn display(&mut self) -> &mut Display {
-        &mut self.display
+    /// Hides or shows the greeter. Lock surfaces stay up regardless, as the
+    /// session would be left without them.
+    pub fn set_visible(&mut self, visible: bool) {
+        if self.inner.visible != visible {
+            self.inner.visible = visible;
+            self.inner.destroy_surfaces();
+            self.inner.outputs_changed();
+            if let Some(widget) = self.widget.as_mut() {
+                if visible {
+                    widget.on_show();
+                } else {
+                    widget.on_hide();
+                }
+            }
+        }
     }
 
-    pub fn flush_display(&mut self) {
-        self.display.flush().expect("unable to flush display");
+    /// Destroys the surfaces before exiting. When running as a screen
+    /// locker, the session stays locked.
+    pub fn close(&mut self) -> Result<(), Error> {
+        // Dropping the lock would unlock the session, so leave it to go
+        // away with the connection instead.
+        if let Some(lock) = self.inner.lock.take() {
+            std::mem::forget(lock);
+        }
+        self.inner.destroy_surfaces();
+        self.roundtrip()
     }
 
-    pub fn event_queue(&mut self) -> &mut EventQueue {
-        &mut self.event_queue
+    /// Applies a reloaded configuration, recreating the surfaces.
+    pub fn set_config(&mut self, config: Config) {
+        self.inner.apply_config(&config);
+        self.inner.destroy_surfaces();
+        self.inner.outputs_changed();
+        let relayout = config.keyboard_layouts != self.config.keyboard_layouts
+            || config.layout_toggle != self.config.layout_toggle
+            || config.xkb_layout != self.config.xkb_layout
+            || config.xkb_variant != self.config.xkb_variant
+            || config.xkb_model != self.config.xkb_model
+            || config.xkb_options != self.config.xkb_options;
+        self.backdrop = Backdrop::new(&config);
+        self.config = config;
+        if relayout {
+            self.layout = 0;
+            self.apply_layout();
+        }
     }
 
-    pub fn get_widget(&mut self) -> &mut Box<dyn Widget + Send> {
-        self.widget.as_mut().unwrap()
+    // Maps the keyboards with the keymap of the current layout.
+    fn apply_layout(&mut self) {
+        let keymap = self.config.keymap(self.layout);
+        self.inner.set_keymap(keymap, layout_toggle(&self.config));
     }
 
-    pub fn set_widget(&mut self, w: Box<dyn Widget + Send>) -> Result<(), ::std::io::Error> {
-        self.widget = Some(w);
-        self.redraw(true)
+    /// Switches to the next layout of the keyboardLayouts list.
+    pub fn next_layout(&mut self) {
+        let count = self.config.keyboard_layouts.len();
+        if count > 1 {
+            self.layout = (self.layout + 1) % count;
+            self.apply_layout();
+        }
     }
 
-    pub fn new(tx: Sender<Cmd>, config: Config) -> App {
-        let inner = Arc::new(Mutex::new(AppInner::new(
-            tx.clone(),
-            config.output_mode,
-            config.scale,
-        )));
+    /// Records the modifiers held on seat, showing them if it is the seat
+    /// last typed on.
+    pub fn set_modifiers(&mut self, seat: SeatId, modifiers: Modifiers) {
+        self.seat_modifiers.insert(seat, modifiers);
+        if self.seat.map_or(true, |s| s == seat) {
+            self.seat = Some(seat);
+            self.modifiers = modifiers;
+        }
+    }
 
-        //
-        // Set up modules
-        //
+    /// Shows the modifiers of seat, as it is being typed on.
+    pub fn set_seat(&mut self, seat: SeatId) {
+        self.seat = Some(seat);
+        self.modifiers = self.seat_modifiers.get(&seat).copied().unwrap_or_default();
+    }
 
-        let cmd_queue = Arc::new(Mutex::new(VecDeque::new()));
+    /// Shows level, from 0 to 1, as a bar over the widgets for a moment.
+    pub fn show_level(&mut self, level: f32) {
+        self.level = Some((level, Instant::now() + LEVEL_SHOWN));
+    }
 
-        let display = Display::connect_to_env().unwrap();
+    /// Stops showing the level once its time is up, returning true if it
+    /// was, and everything has to be drawn again.
+    pub fn expire_level(&mut self, now: Instant) -> bool {
+        if matches!(self.level, Some((_, until)) if until <= now) {
+            self.level = None;
+            return true;
+        }
+        false
+    }
 
-        let mut event_queue = display.create_event_queue();
+    pub fn cmd_queue(&self) -> Arc<Mutex<VecDeque<Cmd>>> {
+        self.cmd_queue.clone()
+    }
 
-        //
-        // Set up global manager and get seats
-        //
-        let inner_global = inner.clone();
-        let manager = GlobalManager::new_with_cb(
-            &display.attach(event_queue.token()),
-            move |evt, registry, ddata: DispatchData| match evt {
-                GlobalEvent::New {
-                    id,
-                    ref interface,
-                    version,
-                } => {
-                    if let "wl_output" = &interface[..] {
-                        let output =
-                            registry.bind::<wl_output::WlOutput>(std::cmp::min(version, 3), id);
-                        output.quick_assign(move |_, _, _| {});
-                        inner_global
-                            .lock()
-                            .unwrap()
-                            .add_output(id, (*output).clone());
-                    } else if let "wl_seat" = &interface[..] {
-                        inner_global
-                            .lock()
-                            .unwrap()
-                            .seats
-                            .created(registry, id, version, ddata);
-                    }
-                }
-                GlobalEvent::Removed { id, ref interface } => {
-                    if let "wl_output" = &interface[..] {
-                        inner_global.lock().unwrap().remove_output(id);
-                    } else if let "wl_seat" = &interface[..] {
-                        inner_global.lock().unwrap().seats.removed(id, ddata);
-                    }
-                }
-            },
-        );
+    /// The file descriptor of the connection, to poll for events.
+    pub fn connection_fd(&self) -> ConnectionFd {
+        ConnectionFd(self.conn.backend())
+    }
+
+    /// Returns true once the first frame has been committed.
+    pub fn committed(&self) -> bool {
+        self.committed
+    }
 
-        // double sync to retrieve the global list
-        // and the globals metadata
-        event_queue
-            .sync_roundtrip(&mut (), |_, _, _| unreachable!())
-            .unwrap();
-        event_queue
-            .sync_roundtrip(&mut (), |_, _, _| unreachable!())
-            .unwrap();
-
-        // wl_compositor
-        let compositor: Main<wl_compositor::WlCompositor> = manager
-            .instantiate_range(1, 4)
-            .expect("server didn't advertise `wl_compositor`");
-
-        inner.lock().unwrap().set_compositor(Some(compositor));
-
-        // wl_shm
-        let shm_formats = Arc::new(Mutex::new(Vec::new()));
-        let shm_formats2 = shm_formats.clone();
-        let shm = manager
-            .instantiate_range::<wl_shm::WlShm>(1, 1)
-            .expect("server didn't advertise `wl_shm`");
-        shm.quick_assign(move |_, evt, _| {
-            if let wl_shm::Event::Format { format } = evt {
-                shm_formats2.lock().unwrap().push(format);
+    pub fn flush_display(&mut self) -> Result<(), Error> {
+        Ok(self.conn.flush()?)
+    }
+
+    /// Reads the events waiting on the connection, and handles them.
+    pub fn dispatch_events(&mut self) -> Result<(), Error> {
+        if let Some(guard) = self.event_queue.prepare_read() {
+            match guard.read() {
+                Ok(_) => (),
+                Err(WaylandError::Io(ref e)) if e.kind() == io::ErrorKind::WouldBlock => (),
+                Err(e) => return Err(e.into()),
             }
-        });
+        }
+        self.event_queue.dispatch_pending(&mut self.inner)?;
+        Ok(())
+    }
+
+    /// Waits for the compositor to handle everything sent so far, handling
+    /// the events it sends meanwhile.
+    pub fn roundtrip(&mut self) -> Result<(), Error> {
+        self.event_queue.roundtrip(&mut self.inner)?;
+        Ok(())
+    }
 
-        let pools = DoubleMemPool::new(shm).expect("Failed to create a memory pool !");
+    /// Stops the greeter with error once the current command is handled.
+    pub fn fail(&mut self, error: Error) {
+        self.inner.fail(error);
+    }
 
-        //
-        // Keyboard processing
-        //
-        for seat in inner.lock().unwrap().seats.get_all() {
-            let kbd_clone = cmd_queue.clone();
-            let modifiers_state = Arc::new(Mutex::new(ModifiersState {
-                ctrl: false,
-                alt: false,
-                shift: false,
-                caps_lock: false,
-                logo: false,
-                num_lock: false,
-            }));
-            map_keyboard(&seat, None, move |event: KbEvent, _, _| match event {
-                KbEvent::Key {
-                    keysym,
-                    utf8,
-                    state,
-                    ..
-                } => match state {
-                    KeyState::Pressed => match keysym {
-                        keysyms::XKB_KEY_c if modifiers_state.lock().unwrap().ctrl => {
-                            kbd_clone.lock().unwrap().push_back(Cmd::Exit)
-                        }
-                        v => kbd_clone.lock().unwrap().push_back(Cmd::Keyboard {
-                            key: v,
-                            key_state: state,
-                            modifiers_state: modifiers_state.lock().unwrap().clone(),
-                            interpreted: utf8,
-                        }),
-                    },
-                    _ => (),
-                },
-                KbEvent::Modifiers { modifiers } => *modifiers_state.lock().unwrap() = modifiers,
-                _ => (),
-            })
-            .expect("Failed to map keyboard");
+    /// Returns the error that came up while handling events, if any.
+    pub fn take_error(&mut self) -> Result<(), Error> {
+        match self.inner.error.take() {
+            Some(e) => Err(e),
+            None => Ok(()),
         }
+    }
 
-        //
-        // Prepare shell so that we can create our shell surface
-        //
-        inner.lock().unwrap().set_shell(Some(
-            if let Ok(layer) = manager.instantiate_exact::<zwlr_layer_shell_v1::ZwlrLayerShellV1>(1)
-            {
-                layer.quick_assign(move |_, _, _| {});
-                layer
-            } else {
-                panic!("server didn't advertise `zwlr_layer_shell_v1`");
-            },
-        ));
+    pub fn get_widget(&mut self) -> &mut Box<dyn Widget + Send> {
+        self.widget.as_mut().unwrap()
+    }
+
+    pub fn set_widget(&mut self, mut w: Box<dyn Widget + Send>) -> Result<(), Error> {
+        if self.inner.visible {
+            w.on_show();
+        }
+        self.widget = Some(w);
+        self.redraw(true)
+    }
+
+    pub fn new(tx: Sender<Cmd>, config: Config) -> Result<App, Error> {
+        let cmd_queue = Arc::new(Mutex::new(VecDeque::new()));
+
+        let conn = Connection::connect_to_env()?;
+        let (globals, mut event_queue) = registry_queue_init::<AppInner>(&conn)?;
+        let qh = event_queue.handle();
+
+        let compositor = CompositorState::bind(&globals, &qh)
+            .map_err(|_| Error::MissingGlobal("wl_compositor"))?;
+        let shm = Shm::bind(&globals, &qh).map_err(|_| Error::MissingGlobal("wl_shm"))?;
 
-        inner.lock().unwrap().outputs_changed();
-        event_queue
-            .sync_roundtrip(&mut (), |_, _, _| () )
-            .unwrap();
+        let (lock, subcompositor, shell) = if config.lock {
+            //
+            // Lock the session, and create lock surfaces instead of shell surfaces
+            //
+            let subcompositor =
+                SubcompositorState::bind(compositor.wl_compositor().clone(), &globals, &qh)
+                    .map_err(|_| Error::MissingGlobal("wl_subcompositor"))?;
+            let lock = SessionLockState::new(&globals, &qh)
+                .lock(&qh)
+                .map_err(|_| Error::MissingGlobal("ext_session_lock_manager_v1"))?;
+            (Some(lock), Some(subcompositor), None)
+        } else {
+            let shell = LayerShell::bind(&globals, &qh)
+                .map_err(|_| Error::MissingGlobal("zwlr_layer_shell_v1"))?;
+            (None, None, Some(shell))
+        };
 
         //
-        // Cursor processing
+        // Fractional scales are shown through viewports, if supported
         //
-        for seat in inner.lock().unwrap().seats.get_all() {
-            let scale = config.scale;
-            let pointer_clone = cmd_queue.clone();
-            let mut pos: (u32, u32) = (0, 0);
-            let mut vert_scroll: f64 = 0.0;
-            let mut horiz_scroll: f64 = 0.0;
-            let mut btn: u32 = 0;
-            let mut btn_clicked = false;
-            let pointer = seat.get_pointer();
-            pointer.quick_assign(move |_, evt, _| match evt {
-                wl_pointer::Event::Enter {
-                    surface_x,
-                    surface_y,
-                    ..
-                } => {
-                    pos = (surface_x as u32, surface_y as u32);
-                }
-                wl_pointer::Event::Leave { .. } => {
-                    pos = (0, 0);
-                }
-                wl_pointer::Event::Motion {
-                    surface_x,
-                    surface_y,
-                    ..
-                } => {
-                    pos = (surface_x as u32 * scale, surface_y as u32 * scale);
-                }
-                wl_pointer::Event::Axis { axis, value, .. } => {
-                    if axis == wl_pointer::Axis::VerticalScroll {
-                        vert_scroll += value;
-                    }
-                }
-                wl_pointer::Event::Button { button, state, .. } => match state {
-                    wl_pointer::ButtonState::Released => {
-                        btn = button;
-                        btn_clicked = true;
-                    }
-                    _ => {}
-                },
-                wl_pointer::Event::Frame => {
-                    if vert_scroll != 0.0 || horiz_scroll != 0.0 {
-                        pointer_clone.lock().unwrap().push_back(Cmd::MouseScroll {
-                            scroll: (horiz_scroll, vert_scroll),
-                            pos: pos,
-                        });
-                        vert_scroll = 0.0;
-                        horiz_scroll = 0.0;
-                    }
-                    if btn_clicked {
-                        pointer_clone
-                            .lock()
-                            .unwrap()
-                            .push_back(Cmd::MouseClick { btn: btn, pos: pos });
-                        btn_clicked = false;
-                    }
-                }
-                _ => {}
-            });
-        }
+        let viewporter: Option<wp_viewporter::WpViewporter> = globals.bind(&qh, 1..=1, ()).ok();
+        let fractional_scale_manager = match viewporter {
+            Some(_) => globals.bind(&qh, 1..=1, ()).ok(),
+            None => None,
+        };
+        let idle_notifier = globals.bind(&qh, 1..=1, ()).ok();
 
-        display.flush().unwrap();
+        let mut inner = AppInner {
+            registry_state: RegistryState::new(&globals),
+            seat_state: SeatState::new(&globals, &qh),
+            output_state: OutputState::new(&globals, &qh),
+            compositor,
+            subcompositor,
+            shm,
+            data_device_manager: DataDeviceManagerState::bind(&globals, &qh).ok(),
+            qh,
+            views: Vec::new(),
+            hidden_lock_surfaces: Vec::new(),
+            lock,
+            configured_surfaces: HashSet::new(),
+            outputs: Vec::new(),
+            viewporter,
+            fractional_scale_manager,
+            preferred_scales: HashMap::new(),
+            rescale: false,
+            shell,
+            seats: Vec::new(),
+            idle_notifier,
+            idle: Idle::Active,
+            cmd_queue: cmd_queue.clone(),
+            keymap: config.keymap(0),
+            toggle: layout_toggle(&config),
+            keybindings: Keybindings::new(&config.keybindings),
+            draw_tx: tx,
+            error: None,
+            output_mode: config.output_mode,
+            anchor: layer_anchor(&config),
+            margin: config.margin,
+            exclusive_zone: config.exclusive_zone,
+            visible: true,
+            focused_output: None,
+            config: config.clone(),
+        };
 
-        App {
+        // Seats and outputs are described by the compositor as they are
+        // bound, creating surfaces and mapping keyboards and pointers.
+        event_queue.roundtrip(&mut inner)?;
+        inner.outputs_changed();
+        event_queue.roundtrip(&mut inner)?;
+
+        conn.flush()?;
+
+        let backdrop = Backdrop::new(&config);
+        Ok(App {
             config,
-            display: display,
-            event_queue: event_queue,
-            cmd_queue: cmd_queue,
-            pools: pools,
+            conn,
+            event_queue,
+            cmd_queue,
             widget: None,
-            inner: inner,
-            last_damage: None,
-            last_dim: (0, 0),
-        }
+            inner,
+            seat_modifiers: HashMap::new(),
+            seat: None,
+            modifiers: Modifiers::default(),
+            layout: 0,
+            committed: false,
+            fade_in: None,
+            level: None,
+            backdrop,
+        })
     }
 }
@@ -0,0 +1,97 @@
+//! Pictures of users, as set through AccountsService or left in ~/.face,
+//! shown in the login box once it is known who is logging in. Only PNG
+//! pictures are shown.
+
+use std::fs;
+use std::path::PathBuf;
+
+use log::debug;
+use nix::unistd::User;
+
+use crate::buffer::Buffer;
+use crate::color::Color;
+use crate::colorfont::{decode_png, scale};
+
+const ACCOUNTS_SERVICE: &str = "/var/lib/AccountsService/icons";
+
+/// The picture of a user, cut to a square, and as last scaled.
+pub struct Avatar {
+    size: u32,
+    pixels: Vec<[u8; 4]>,
+    scaled: Option<(u32, Vec<(Color, f32)>)>,
+}
+
+// Where the picture of user may be, in order of preference, starting with
+// icon if given. The home directory is often not readable by the greeter,
+// while AccountsService keeps its copies readable for that reason.
+fn paths(user: &str, icon: Option<PathBuf>) -> Vec<PathBuf> {
+    let mut paths: Vec<PathBuf> = icon.into_iter().collect();
+    paths.push(PathBuf::from(ACCOUNTS_SERVICE).join(user));
+    if let Ok(Some(user)) = User::from_name(user) {
+        paths.push(user.dir.join(".face"));
+    }
+    paths
+}
+
+impl Avatar {
+    /// Loads the picture of user, if there is one, from icon if given.
+    pub fn load(user: &str, icon: Option<PathBuf>) -> Option<Avatar> {
+        if user.is_empty() || user.contains('/') {
+            return None;
+        }
+        let (width, height, pixels) = paths(user, icon).iter().find_map(|path| {
+            let data = fs::read(path).ok()?;
+            let image = decode_png(&data);
+            if image.is_none() {
+                debug!("Unable to decode {} as PNG", path.display());
+            }
+            image
+        })?;
+        if width == 0 || height == 0 {
+            return None;
+        }
+        // Cut the middle square out of pictures that are not square.
+        let size = width.min(height);
+        let (left, top) = ((width - size) / 2, (height - size) / 2);
+        let pixels = (top..top + size)
+            .flat_map(|y| {
+                let row = (y * width + left) as usize;
+                pixels[row..row + size as usize].iter().copied()
+            })
+            .collect();
+        Some(Avatar {
+            size,
+            pixels,
+            scaled: None,
+        })
+    }
+
+    /// Draws the picture scaled to size, in a circle blended into bg.
+    pub fn draw(
+        &mut self,
+        buf: &mut Buffer,
+        bg: &Color,
+        size: u32,
+    ) -> Result<(), ::std::io::Error> {
+        if !matches!(self.scaled, Some((s, _)) if s == size) {
+            let pixels = scale(&self.pixels, (self.size, self.size), (size, size));
+            self.scaled = Some((size, pixels));
+        }
+        let pixels = match self.scaled {
+            Some((_, ref pixels)) => pixels,
+            None => return Ok(()),
+        };
+        let radius = size as f32 / 2.0;
+        for y in 0..size {
+            for x in 0..size {
+                let (dx, dy) = (x as f32 + 0.5 - radius, y as f32 + 0.5 - radius);
+                let edge = (radius - (dx * dx + dy * dy).sqrt() + 0.5).clamp(0.0, 1.0);
+                let (color, coverage) = &pixels[(y * size + x) as usize];
+                if edge * coverage > 0.0 {
+                    buf.put((x, y), &bg.blend(color, edge * coverage))?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,231 @@
+//! Wallpapers and animated backgrounds, drawn around the widgets, and the
+//! wallpapers blurred behind them. Each frame
+//! of an animation is worked out from the time since it started, so that it
+//! needs no state of its own and looks alike on every output.
+
+use std::io;
+use std::time::{Duration, Instant};
+
+use crate::animation::FRAME;
+use crate::buffer::Buffer;
+use crate::color::Color;
+use crate::config::{AnimatedBackground, Config};
+use crate::slideshow::{pictures, Slideshow};
+use crate::widget::scaled;
+
+/// What is drawn around the widgets in place of the background color.
+pub struct Backdrop {
+    // The animation and how often to draw it.
+    animation: Option<(AnimatedBackground, Duration)>,
+    slideshow: Option<Slideshow>,
+    // The radius the wallpapers are blurred over behind the widgets, if
+    // they are shown there at all.
+    blur: u32,
+    start: Instant,
+}
+
+// A number from 0 to 1 that looks random, the same for the same n.
+fn noise(n: u32) -> f32 {
+    let mut x = n.wrapping_mul(0x9e37_79b9) ^ 0x85eb_ca6b;
+    x ^= x >> 16;
+    x = x.wrapping_mul(0x7feb_352d);
+    x ^= x >> 15;
+    x = x.wrapping_mul(0x846c_a68b);
+    x ^= x >> 16;
+    x as f32 / u32::MAX as f32
+}
+
+impl Backdrop {
+    /// Returns the wallpapers and animated background set in config, if
+    /// any. Animations are left out when they are turned off.
+    pub fn new(config: &Config) -> Option<Backdrop> {
+        let animation = Some(config.animated_background)
+            .filter(|style| config.animations && *style != AnimatedBackground::None)
+            .map(|style| {
+                let fps = config.animated_background_fps.max(1);
+                (style, (Duration::from_secs(1) / fps).max(FRAME))
+            });
+        let slideshow = Slideshow::new(
+            pictures(&config.backgrounds),
+            Duration::from_secs(config.background_interval.max(1)),
+            config.animations,
+        );
+        if animation.is_none() && slideshow.is_none() {
+            return None;
+        }
+        Some(Backdrop {
+            animation,
+            slideshow,
+            blur: config.background_blur,
+            start: Instant::now(),
+        })
+    }
+
+    /// Returns the time to draw the next frame at, animating only if
+    /// animate is set.
+    pub fn next_frame(&self, now: Instant, animate: bool) -> Option<Instant> {
+        let animation = self
+            .animation
+            .filter(|_| animate)
+            .map(|(_, interval)| now + interval);
+        let slideshow = self.slideshow.as_ref().and_then(|s| s.next_frame(now));
+        animation.into_iter().chain(slideshow).min()
+    }
+
+    /// Moves the slideshow along to now, and returns true if there is
+    /// something new to draw, animating only if animate is set.
+    pub fn advance(&mut self, now: Instant, animate: bool) -> bool {
+        let changed = self.slideshow.as_mut().is_some_and(|s| s.advance(now));
+        changed || (animate && self.animation.is_some())
+    }
+
+    /// Returns true if the wallpapers blurred behind the widgets changed, so
+    /// that the widgets have to be drawn again over them.
+    pub fn behind_changed(&self) -> bool {
+        self.blur > 0 && self.slideshow.as_ref().is_some_and(|s| s.changed())
+    }
+
+    /// Puts the wallpapers, blurred, behind the widgets taking up the given
+    /// area of buf, where they were drawn over bg. Only the damage is done
+    /// if given, as the rest already has them behind it.
+    pub fn draw_behind(
+        &mut self,
+        buf: &mut Buffer,
+        bg: &Color,
+        widgets: (u32, u32, u32, u32),
+        damage: Option<&[(i32, i32, i32, i32)]>,
+        scale: f32,
+        now: Instant,
+    ) -> Result<(), io::Error> {
+        let slideshow = match self.slideshow {
+            Some(ref mut slideshow) if self.blur > 0 => slideshow,
+            _ => return Ok(()),
+        };
+        let (_, _, width, height) = buf.get_bounds();
+        let (left, top) = (widgets.0.min(width), widgets.1.min(height));
+        let area = (
+            left,
+            top,
+            (widgets.0 + widgets.2).min(width) - left,
+            (widgets.1 + widgets.3).min(height) - top,
+        );
+        if area.2 == 0 || area.3 == 0 {
+            return Ok(());
+        }
+
+        // Damage may overlap, but each pixel must only be done once.
+        let mut mask = vec![damage.is_none(); (area.2 * area.3) as usize];
+        for d in damage.unwrap_or_default() {
+            let (x0, y0) = (d.0.max(area.0 as i32), d.1.max(area.1 as i32));
+            let x1 = (d.0 + d.2).min((area.0 + area.2) as i32);
+            let y1 = (d.1 + d.3).min((area.1 + area.3) as i32);
+            for y in y0..y1 {
+                let row = ((y as u32 - area.1) * area.2) as usize;
+                for x in x0..x1 {
+                    mask[row + (x as u32 - area.0) as usize] = true;
+                }
+            }
+        }
+        let radius = scaled(self.blur, scale).max(1);
+        slideshow.draw_blurred(buf, bg, area, radius, &mask, now)
+    }
+
+    /// Draws the frame at now around the widgets taking up the given area
+    /// of buf, with the animation in c if given, and returns the damage.
+    pub fn draw(
+        &mut self,
+        buf: &mut Buffer,
+        bg: &Color,
+        c: Option<&Color>,
+        widgets: (u32, u32, u32, u32),
+        scale: f32,
+        now: Instant,
+    ) -> Result<Vec<(i32, i32, i32, i32)>, io::Error> {
+        let animation = c.and_then(|c| self.animation.map(|(style, _)| (style, c)));
+        let (_, _, width, height) = buf.get_bounds();
+        let (left, top, right, bottom) = (
+            widgets.0.min(width),
+            widgets.1.min(height),
+            (widgets.0 + widgets.2).min(width),
+            (widgets.1 + widgets.3).min(height),
+        );
+        let strips: Vec<(u32, u32, u32, u32)> = [
+            (0, 0, width, top),
+            (0, bottom, width, height - bottom),
+            (0, top, left, bottom - top),
+            (right, top, width - right, bottom - top),
+        ]
+        .iter()
+        .copied()
+        .filter(|strip| strip.2 > 0 && strip.3 > 0)
+        .collect();
+        for strip in strips.iter() {
+            match self.slideshow {
+                Some(ref mut slideshow) => slideshow.draw(buf, bg, *strip, now)?,
+                None => buf.subdimensions(*strip)?.memset(bg),
+            }
+        }
+
+        let t = now.saturating_duration_since(self.start).as_secs_f32();
+        let mut put = |x: f32, y: f32, c: &Color, alpha: f32| {
+            if x < 0.0 || y < 0.0 {
+                return;
+            }
+            let (x, y) = (x as u32, y as u32);
+            let covered = x >= left && x < right && y >= top && y < bottom;
+            if covered || alpha <= 0.0 {
+                return;
+            }
+            if let Ok(below) = buf.get((x, y)) {
+                let _ = buf.put((x, y), &below.blend(c, alpha));
+            }
+        };
+        let (w, h) = (width as f32, height as f32);
+        match animation {
+            None | Some((AnimatedBackground::None, _)) => (),
+            Some((AnimatedBackground::Particles, c)) => {
+                // Dots drifting slowly upwards, swaying as they go.
+                let count = (width * height) / scaled(110, scale).pow(2).max(1);
+                for i in 0..count {
+                    let speed = (10.0 + 30.0 * noise(4 * i)) * scale;
+                    let y = (noise(4 * i + 1) * h - speed * t).rem_euclid(h);
+                    let sway = (t * 0.5 + i as f32).sin() * 8.0 * scale;
+                    let x = (noise(4 * i + 2) * w + sway).rem_euclid(w);
+                    let radius = (1.0 + noise(4 * i + 3)) * 1.5 * scale;
+                    let alpha = 0.2 + 0.4 * noise(4 * i + 3);
+                    let reach = radius.ceil() as i32;
+                    for dy in -reach..=reach {
+                        for dx in -reach..=reach {
+                            let d = ((dx * dx + dy * dy) as f32).sqrt();
+                            let coverage = (radius - d + 0.5).clamp(0.0, 1.0);
+                            put(x + dx as f32, y + dy as f32, c, alpha * coverage);
+                        }
+                    }
+                }
+            }
+            Some((AnimatedBackground::Rain, c)) => {
+                // Streaks falling down columns, brightest at their head.
+                let spacing = scaled(12, scale).max(1);
+                let thickness = scaled(2, scale).max(1);
+                for column in 0..width / spacing {
+                    let speed = (80.0 + 160.0 * noise(3 * column)) * scale;
+                    let trail = (60.0 + 120.0 * noise(3 * column + 1)) * scale;
+                    let period = h + trail;
+                    let head = (noise(3 * column + 2) * period + speed * t) % period;
+                    let x = (column * spacing) as f32;
+                    for k in 0..trail as u32 {
+                        let alpha = 0.6 * (1.0 - k as f32 / trail);
+                        for dx in 0..thickness {
+                            put(x + dx as f32, head - k as f32, c, alpha);
+                        }
+                    }
+                }
+            }
+        }
+
+        Ok(strips
+            .iter()
+            .map(|s| (s.0 as i32, s.1 as i32, s.2 as i32, s.3 as i32))
+            .collect())
+    }
+}
@@ -0,0 +1,84 @@
+//! Changes the brightness of the screen for the brightness keys. The level
+//! is written to the backlight in sysfs, which the greeter user may not be
+//! allowed to do, in which case brightnessctl is asked to do it instead.
+
+use std::fs::{self, read_dir, read_to_string};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use log::debug;
+
+const BACKLIGHTS: &str = "/sys/class/backlight";
+
+// The fraction of the full brightness changed by each key press.
+const STEP: f32 = 0.05;
+
+fn read_value(path: &Path) -> io::Result<u32> {
+    read_to_string(path)?
+        .trim()
+        .parse()
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+pub struct Backlight {
+    name: String,
+    path: PathBuf,
+    max: u32,
+}
+
+impl Backlight {
+    /// Finds the backlight called name, or the first one there is if name
+    /// is empty.
+    pub fn find(name: &str) -> io::Result<Backlight> {
+        let name = if name.is_empty() {
+            let mut names: Vec<String> = read_dir(BACKLIGHTS)?
+                .filter_map(|entry| entry.ok())
+                .map(|entry| entry.file_name().to_string_lossy().into_owned())
+                .collect();
+            names.sort();
+            match names.into_iter().next() {
+                Some(name) => name,
+                None => return Err(io::Error::new(io::ErrorKind::NotFound, "no backlight")),
+            }
+        } else {
+            name.to_string()
+        };
+        let path = PathBuf::from(BACKLIGHTS).join(&name);
+        let max = read_value(&path.join("max_brightness"))?;
+        Ok(Backlight { name, path, max })
+    }
+
+    /// Returns the brightness as a fraction of the full brightness.
+    pub fn level(&self) -> io::Result<f32> {
+        Ok(read_value(&self.path.join("brightness"))? as f32 / self.max.max(1) as f32)
+    }
+
+    /// Makes the screen a step brighter, or dimmer if step is negative,
+    /// returning the new level. The screen is never turned off entirely.
+    pub fn step(&self, step: i32) -> io::Result<f32> {
+        let current = read_value(&self.path.join("brightness"))?;
+        let delta = ((self.max as f32 * STEP).round() as i64).max(1) * step as i64;
+        let value = (current as i64 + delta).clamp(1, self.max as i64) as u32;
+        if let Err(e) = fs::write(self.path.join("brightness"), value.to_string()) {
+            debug!(
+                "Unable to write backlight {}: {}, trying brightnessctl",
+                self.name, e
+            );
+            let status = Command::new("brightnessctl")
+                .arg("--quiet")
+                .arg("--class=backlight")
+                .arg(format!("--device={}", self.name))
+                .arg("set")
+                .arg(value.to_string())
+                .status()?;
+            if !status.success() {
+                return Err(io::Error::new(
+                    io::ErrorKind::Other,
+                    format!("brightnessctl failed: {}", status),
+                ));
+            }
+        }
+        self.level()
+    }
+}
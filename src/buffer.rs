@@ -9,10 +9,10 @@ pub struct Buffer<'a> {
 }
 
 impl<'a> Buffer<'a> {
-    pub fn new(buf: &'a mut MmapMut, dimensions: (u32, u32)) -> Buffer {
+    pub fn new(buf: &'a mut MmapMut, dimensions: (u32, u32)) -> Buffer<'a> {
         Buffer {
-            buf: buf,
-            dimensions: dimensions,
+            buf,
+            dimensions,
             subdimensions: None,
         }
     }
@@ -42,36 +42,36 @@ impl<'a> Buffer<'a> {
         debug_assert!(self.dimensions == other.dimensions);
         debug_assert!(self.subdimensions.is_none() && other.subdimensions.is_none());
 
-        if x == 0 && width as u32 == self.dimensions.0 {
+        let stride = 4 * self.dimensions.0 as usize;
+        let (x, width) = (4 * x as usize, 4 * width as usize);
+        let rows = y as usize * stride..(y + height) as usize * stride;
+        if width == stride {
             // Full-width copy
-            let offset = y as isize * self.dimensions.0 as isize;
-            let n = height as usize * self.dimensions.0 as usize;
-            unsafe {
-                std::ptr::copy(
-                    (self.buf.as_ptr() as *const u32).offset(offset),
-                    (other.buf.as_mut_ptr() as *mut u32).offset(offset),
-                    n,
-                );
-            }
+            other.buf[rows.clone()].copy_from_slice(&self.buf[rows]);
         } else {
             // Row-by-row copy
-            for cur_y in y as isize..(y + height) as isize {
-                let offset = x as isize + cur_y as isize * self.dimensions.0 as isize;
-                unsafe {
-                    std::ptr::copy(
-                        (self.buf.as_ptr() as *const u32).offset(offset),
-                        (other.buf.as_mut_ptr() as *mut u32).offset(offset),
-                        width as usize,
-                    );
-                }
+            for start in rows.step_by(stride) {
+                let row = start + x..start + x + width;
+                other.buf[row.clone()].copy_from_slice(&self.buf[row]);
             }
         }
     }
 
+    // The pixels of the whole buffer, regardless of subdimensions.
+    fn pixels_mut(&mut self) -> &mut [u32] {
+        let len = 4 * (self.dimensions.0 * self.dimensions.1) as usize;
+        // The memory is mapped whole pages at a time, so it is aligned for
+        // u32 and there is nothing before or after the pixels. Were it not,
+        // every pixel would be off by a few bytes.
+        let (before, pixels, _) = unsafe { self.buf[..len].align_to_mut::<u32>() };
+        assert!(before.is_empty(), "buffer is not aligned for pixels");
+        pixels
+    }
+
     pub fn subdimensions(
         &mut self,
         subdimensions: (u32, u32, u32, u32),
-    ) -> Result<Buffer, ::std::io::Error> {
+    ) -> Result<Buffer<'_>, ::std::io::Error> {
         let bounds = self.get_bounds();
         if subdimensions.0 + subdimensions.2 > bounds.2
             || subdimensions.1 + subdimensions.3 > bounds.3
@@ -97,7 +97,7 @@ impl<'a> Buffer<'a> {
         })
     }
 
-    pub fn offset(&mut self, offset: (u32, u32)) -> Result<Buffer, ::std::io::Error> {
+    pub fn offset(&mut self, offset: (u32, u32)) -> Result<Buffer<'_>, ::std::io::Error> {
         let bounds = self.get_bounds();
         if offset.0 > bounds.2 || offset.1 > bounds.3 {
             return Err(::std::io::Error::new(
@@ -122,26 +122,68 @@ impl<'a> Buffer<'a> {
     }
 
     pub fn memset(&mut self, c: &Color) {
-        if let Some(subdim) = self.subdimensions {
-            unsafe {
-                let ptr = self.buf.as_mut_ptr();
-                for y in subdim.1..(subdim.1 + subdim.3) {
-                    for x in subdim.0..(subdim.0 + subdim.2) {
-                        *((ptr as *mut u32).offset((x + y * self.dimensions.0) as isize)) =
-                            c.as_argb8888();
-                    }
-                }
-            }
+        let pixel = c.as_argb8888();
+        let (x, y, width, height) = self.get_bounds();
+        let stride = self.dimensions.0 as usize;
+        let (x, width) = (x as usize, width as usize);
+        let rows = &mut self.pixels_mut()[y as usize * stride..(y + height) as usize * stride];
+        if width == stride {
+            // Full-width fill
+            rows.fill(pixel);
         } else {
-            unsafe {
-                let ptr = self.buf.as_mut_ptr();
-                for p in 0..(self.dimensions.0 * self.dimensions.1) {
-                    *((ptr as *mut u32).offset(p as isize)) = c.as_argb8888();
-                }
+            // Row-by-row fill
+            for row in rows.chunks_exact_mut(stride) {
+                row[x..x + width].fill(pixel);
             }
         }
     }
 
+    /// Darkens every pixel to level of its brightness, keeping its opacity.
+    pub fn dim(&mut self, level: f32) {
+        self.scale(level, false);
+    }
+
+    /// Fades every pixel to level of its brightness and opacity.
+    pub fn fade(&mut self, level: f32) {
+        self.scale(level, true);
+    }
+
+    fn scale(&mut self, level: f32, opacity: bool) {
+        let level = (level * 256.0) as u32;
+        let len = 4 * (self.dimensions.0 * self.dimensions.1) as usize;
+        for p in self.buf[..len].chunks_exact_mut(4) {
+            let c = u32::from_ne_bytes([p[0], p[1], p[2], p[3]]);
+            let channel = |shift: u32| ((c >> shift & 0xFF) * level / 256) << shift;
+            let alpha = if opacity {
+                channel(24)
+            } else {
+                c & 0xFF00_0000
+            };
+            let scaled = alpha | channel(16) | channel(8) | channel(0);
+            p.copy_from_slice(&scaled.to_ne_bytes());
+        }
+    }
+
+    /// Returns the color of the pixel at pos, for drawing over it.
+    pub fn get(&self, pos: (u32, u32)) -> Result<Color, ::std::io::Error> {
+        let (x, y, width, height) = self.get_bounds();
+        if pos.0 >= width || pos.1 >= height {
+            return Err(::std::io::Error::new(
+                ::std::io::ErrorKind::Other,
+                format!(
+                    "get({:?}) is not within buffer ({:?})",
+                    pos,
+                    self.get_bounds()
+                ),
+            ));
+        }
+        let offset = 4 * (pos.0 + x + (pos.1 + y) * self.dimensions.0) as usize;
+        let p = &self.buf[offset..offset + 4];
+        let c = u32::from_ne_bytes([p[0], p[1], p[2], p[3]]);
+        let channel = |shift: u32| (c >> shift & 0xFF) as f32 / 255.0;
+        Ok(Color::new(channel(16), channel(8), channel(0), channel(24)))
+    }
+
     pub fn put(&mut self, pos: (u32, u32), c: &Color) -> Result<(), ::std::io::Error> {
         let true_pos = if let Some(subdim) = self.subdimensions {
             if pos.0 >= subdim.2 || pos.1 >= subdim.3 {
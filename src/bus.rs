@@ -0,0 +1,120 @@
+//! A D-Bus service reporting the state of the greeter and accepting Show,
+//! Hide and SetBanner calls. The greeter owns wtf.kl.wlgreet on the system
+//! bus, or on the session bus when locking the session.
+
+use calloop::channel::Sender;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use dbus::blocking::Connection;
+use dbus::channel::{MatchingReceiver, Sender as _};
+use dbus::message::MatchRule;
+use dbus_crossroads::{Crossroads, IfaceBuilder, MethodErr};
+use log::{debug, warn};
+
+use crate::cmd::Cmd;
+use crate::widgets::login::LoginState;
+
+const NAME: &str = "wtf.kl.wlgreet";
+const PATH: &str = "/wtf/kl/wlgreet";
+const INTERFACE: &str = "wtf.kl.wlgreet.Greeter";
+
+// The object served at PATH.
+struct Greeter {
+    state: Arc<Mutex<LoginState>>,
+    tx: Sender<Cmd>,
+}
+
+impl Greeter {
+    fn send(&self, cmd: Cmd) -> Result<(), MethodErr> {
+        self.tx
+            .send(cmd)
+            .map_err(|_| MethodErr::failed(&"greeter is exiting"))
+    }
+}
+
+fn serve(lock: bool, state: Arc<Mutex<LoginState>>, tx: Sender<Cmd>) -> Result<(), dbus::Error> {
+    let conn = if lock {
+        Connection::new_session()?
+    } else {
+        Connection::new_system()?
+    };
+    conn.request_name(NAME, false, true, true)?;
+
+    let mut cr = Crossroads::new();
+    let mut state_changed = None;
+    let token = cr.register(INTERFACE, |b: &mut IfaceBuilder<Greeter>| {
+        state_changed = Some(
+            b.property("State")
+                .get(|_, greeter| Ok(greeter.state.lock().unwrap().as_str().to_string()))
+                .changed_msg_fn(),
+        );
+        b.method("Show", (), (), |_, greeter, ()| greeter.send(Cmd::Show));
+        b.method("Hide", (), (), |_, greeter, ()| greeter.send(Cmd::Hide));
+        b.method(
+            "SetBanner",
+            ("text",),
+            (),
+            |_, greeter, (text,): (String,)| greeter.send(Cmd::Message(text)),
+        );
+    });
+    let state_changed = state_changed.unwrap();
+    cr.insert(
+        PATH,
+        &[token],
+        Greeter {
+            state: state.clone(),
+            tx,
+        },
+    );
+    conn.start_receive(
+        MatchRule::new_method_call(),
+        Box::new(move |msg, conn| {
+            let _ = cr.handle_message(msg, conn);
+            true
+        }),
+    );
+    debug!("Serving {} on D-Bus", NAME);
+
+    // State changes are picked up between messages, which is soon enough
+    // for anyone watching.
+    let path = PATH.into();
+    let mut last = *state.lock().unwrap();
+    loop {
+        conn.process(Duration::from_millis(250))?;
+        let current = *state.lock().unwrap();
+        if current != last {
+            last = current;
+            if let Some(msg) = state_changed(&path, &current.as_str().to_string()) {
+                let _ = conn.send(msg);
+            }
+        }
+    }
+}
+
+/// The state reported over D-Bus.
+pub struct Bus {
+    state: Arc<Mutex<LoginState>>,
+}
+
+impl Bus {
+    /// Starts a thread serving the greeter on D-Bus, passing method calls
+    /// on to tx as commands. The greeter carries on without it if the bus
+    /// cannot be reached.
+    pub fn spawn(lock: bool, tx: Sender<Cmd>) -> Bus {
+        let state = Arc::new(Mutex::new(LoginState::Idle));
+        let shared = state.clone();
+        let _ = std::thread::Builder::new()
+            .name("dbus".to_string())
+            .spawn(move || {
+                if let Err(e) = serve(lock, shared, tx) {
+                    warn!("D-Bus service unavailable: {}", e);
+                }
+            });
+        Bus { state }
+    }
+
+    pub fn set_state(&self, state: LoginState) {
+        *self.state.lock().unwrap() = state;
+    }
+}
@@ -0,0 +1,236 @@
+//! Validation of the configuration for `--check-config`, reporting problems
+//! that would otherwise only show up when the greeter starts.
+
+use std::env;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+
+use chrono::format::{Item, StrftimeItems};
+use nix::unistd::User;
+
+use crate::backlight::Backlight;
+#[cfg(not(feature = "dbus"))]
+use crate::config::UserSource;
+use crate::config::{env_name, is_option, session_argv, Config, WidgetKind};
+use crate::draw;
+use crate::keybindings::Binding;
+use crate::slideshow::pictures;
+use crate::widgets::logo::Logo;
+
+// Returns true if cmd names an executable, either by path or through $PATH.
+fn find_executable(cmd: &str) -> bool {
+    let is_executable = |path: &Path| {
+        path.metadata()
+            .map(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+            .unwrap_or(false)
+    };
+    if cmd.contains('/') {
+        return is_executable(Path::new(cmd));
+    }
+    match env::var_os("PATH") {
+        Some(paths) => env::split_paths(&paths).any(|dir| is_executable(&dir.join(cmd))),
+        None => false,
+    }
+}
+
+// Checks that a command line can be split into words, and the program it
+// starts, which is the shell if one is set.
+fn check_command(diagnostics: &mut Vec<String>, key: &str, cmd: &str, shell: &str) {
+    match session_argv(cmd, shell, &[]) {
+        Ok(argv) => {
+            if let Some(program) = argv.first().filter(|p| !find_executable(p)) {
+                diagnostics.push(format!("{}: {} not found or not executable", key, program))
+            }
+        }
+        Err(e) => diagnostics.push(format!("{}: {}", key, e)),
+    }
+}
+
+/// Checks a parsed configuration, returning a list of problems found. table
+/// is the raw configuration the config was parsed from, and is used to spot
+/// unknown options.
+pub fn check_config(config: &Config, table: &toml::value::Table) -> Vec<String> {
+    let mut diagnostics = Vec::new();
+
+    if !Path::new(&config.path).exists() {
+        diagnostics.push(format!(
+            "{}: file does not exist, defaults are used",
+            config.path
+        ));
+    }
+    for key in table.keys() {
+        if !is_option(key) {
+            diagnostics.push(format!("{}: unknown option", key));
+        }
+    }
+
+    if let Some(ref font) = config.font {
+        if let Err(e) = draw::load_font(font) {
+            diagnostics.push(format!("font: unable to load {}: {}", font, e));
+        }
+    }
+    if let Some(ref font) = config.emoji_font {
+        if let Err(e) = draw::load_font(font) {
+            diagnostics.push(format!("emojiFont: unable to load {}: {}", font, e));
+        }
+    }
+    if StrftimeItems::new(&config.clock_format).any(|i| i == Item::Error) {
+        diagnostics.push(format!(
+            "clockFormat: invalid format {:?}",
+            config.clock_format
+        ));
+    }
+
+    let invalid_scale = |scale: Option<f32>| match scale {
+        Some(scale) => !scale.is_finite() || scale <= 0.0,
+        None => false,
+    };
+    if invalid_scale(config.scale) {
+        diagnostics.push("scale: must be positive".to_string());
+    }
+    for (name, output) in config.outputs.iter() {
+        if invalid_scale(output.scale) {
+            diagnostics.push(format!("output.{:?}.scale: must be positive", name));
+        }
+    }
+    for (key, size) in [
+        ("clockSize", config.clock_size),
+        ("headlineSize", config.headline_size),
+        ("promptSize", config.prompt_size),
+    ]
+    .iter()
+    {
+        if !size.is_finite() || *size <= 0.0 {
+            diagnostics.push(format!("{}: must be positive", key));
+        }
+    }
+    #[cfg(not(feature = "dbus"))]
+    if config.user_source == UserSource::AccountsService {
+        diagnostics.push(
+            "userSource: accountsService needs wlgreet built with the dbus feature".to_string(),
+        );
+    }
+    if config.min_uid > config.max_uid {
+        diagnostics.push("minUid: must not be greater than maxUid".to_string());
+    }
+    for background in config.backgrounds.iter() {
+        let path = Path::new(background);
+        if !path.exists() {
+            diagnostics.push(format!("backgrounds: {} does not exist", background));
+        } else if path.is_dir() && pictures(std::slice::from_ref(background)).is_empty() {
+            diagnostics.push(format!("backgrounds: no PNG files in {}", background));
+        }
+    }
+    if !config.logo.is_empty() {
+        if let Err(e) = Logo::new(&config.logo, config.logo_size) {
+            diagnostics.push(format!("logo: unable to load {}: {}", config.logo, e));
+        }
+    }
+    if config.logo_size == 0 {
+        diagnostics.push("logoSize: must be at least 1".to_string());
+    }
+    if config.background_blur > 0 && config.backgrounds.is_empty() {
+        diagnostics.push("backgroundBlur: needs backgrounds to blur".to_string());
+    }
+    if config.background_interval == 0 {
+        diagnostics.push("backgroundInterval: must be at least 1".to_string());
+    }
+    if !(1..=60).contains(&config.animated_background_fps) {
+        diagnostics.push("animatedBackgroundFps: must be between 1 and 60".to_string());
+    }
+
+    if !config.command.is_empty() {
+        check_command(
+            &mut diagnostics,
+            "command",
+            &config.command,
+            &config.command_shell,
+        );
+    }
+    if !config.network_command.is_empty() {
+        check_command(
+            &mut diagnostics,
+            "networkCommand",
+            &config.network_command,
+            "",
+        );
+    }
+    if !config.qr_code.is_empty() {
+        if let Err(e) = qrcode::QrCode::new(config.qr_code.as_bytes()) {
+            diagnostics.push(format!("qrCode: unable to encode: {}", e));
+        }
+    }
+    if !config.volume_command.is_empty() {
+        check_command(
+            &mut diagnostics,
+            "volumeCommand",
+            &config.volume_command,
+            "",
+        );
+    }
+    if !config.backlight.is_empty() {
+        if let Err(e) = Backlight::find(&config.backlight) {
+            diagnostics.push(format!("backlight: {}: {}", config.backlight, e));
+        }
+    }
+    for (idx, widget) in config.widgets.iter().enumerate() {
+        if widget.kind != WidgetKind::Script {
+            continue;
+        }
+        let key = format!("widget[{}].command", idx);
+        if widget.command.is_empty() {
+            diagnostics.push(format!("{}: script widget without a command", key));
+        } else {
+            check_command(&mut diagnostics, &key, &widget.command, "");
+        }
+        if widget.interval == 0 {
+            diagnostics.push(format!("widget[{}].interval: must be at least 1", idx));
+        }
+        if widget.lines == 0 {
+            diagnostics.push(format!("widget[{}].lines: must be at least 1", idx));
+        }
+    }
+    if let Some(ref session) = config.autologin_session {
+        check_command(
+            &mut diagnostics,
+            "autologinSession",
+            session,
+            &config.command_shell,
+        );
+    }
+    for assignment in config.env.iter() {
+        if env_name(assignment).is_none() {
+            diagnostics.push(format!("env: {:?} is not NAME=value", assignment));
+        }
+    }
+    for (idx, session) in config.sessions.iter().enumerate() {
+        for assignment in session.env.iter() {
+            if env_name(assignment).is_none() {
+                diagnostics.push(format!(
+                    "session[{}].env: {:?} is not NAME=value",
+                    idx, assignment
+                ));
+            }
+        }
+    }
+    for key in config.keybindings.keys() {
+        if let Err(e) = Binding::parse(key) {
+            diagnostics.push(format!("keybindings.{:?}: {}", key, e));
+        }
+    }
+    if let Some(ref user) = config.autologin_user {
+        match User::from_name(user) {
+            Ok(Some(_)) => (),
+            Ok(None) => diagnostics.push(format!("autologinUser: no such user {}", user)),
+            Err(e) => diagnostics.push(format!("autologinUser: unable to look up {}: {}", user, e)),
+        }
+        if config.autologin_session.is_none()
+            && config.command.is_empty()
+            && !config.session_chooser
+        {
+            diagnostics.push("autologinUser: no session to start".to_string());
+        }
+    }
+
+    diagnostics
+}
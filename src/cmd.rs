@@ -1,21 +1,104 @@
-use smithay_client_toolkit::seat::keyboard::{KeyState, ModifiersState};
+use std::time::Instant;
 
+use crate::keybindings::Action;
+use crate::secret::SecretString;
+use crate::widget::{KeyState, Keysym, Modifiers};
+
+use crate::widgets::login::LoginState;
+
+/// Identifies a seat by the protocol id of its wl_seat, so that input from
+/// several seats is kept apart.
+pub type SeatId = u32;
+
+#[derive(Clone)]
 pub enum Cmd {
     Exit,
+    Unlock,
     Draw,
     ForceDraw,
+    // A surface was configured to the given size in buffer pixels, to be
+    // drawn in full.
+    Configure((u32, u32)),
+    // Draws again at the given time, for animations.
+    Wake(Instant),
     MouseClick {
+        seat: SeatId,
         btn: u32,
         pos: (u32, u32),
     },
     MouseScroll {
+        seat: SeatId,
         scroll: (f64, f64),
         pos: (u32, u32),
     },
+    MouseMove {
+        seat: SeatId,
+        pos: (u32, u32),
+    },
+    MouseLeave {
+        seat: SeatId,
+    },
+    // Touch points by id within their seat, with positions in buffer
+    // pixels.
+    TouchDown {
+        seat: SeatId,
+        id: i32,
+        pos: (u32, u32),
+    },
+    TouchMotion {
+        seat: SeatId,
+        id: i32,
+        pos: (u32, u32),
+    },
+    TouchUp {
+        seat: SeatId,
+        id: i32,
+    },
+    // The compositor took over the touch points down, such as for a
+    // gesture of its own.
+    TouchCancel {
+        seat: SeatId,
+    },
     Keyboard {
-        key: u32,
+        seat: SeatId,
+        key: Keysym,
         key_state: KeyState,
-        modifiers_state: ModifiersState,
+        modifiers_state: Modifiers,
         interpreted: Option<String>,
     },
+    KeyRelease {
+        seat: SeatId,
+        key: Keysym,
+    },
+    KeyboardLeave {
+        seat: SeatId,
+    },
+    // A key binding handled by the widgets.
+    Action(SeatId, Action),
+    Modifiers {
+        seat: SeatId,
+        modifiers_state: Modifiers,
+    },
+    Paste(SeatId, SecretString),
+    SwitchLayout,
+    // Makes text a step larger, or smaller if negative, or goes back to the
+    // configured size if 0.
+    ScaleText(i32),
+    ToggleHighContrast,
+    // Makes the screen a step brighter, or dimmer if negative.
+    Brightness(i32),
+    // Makes the sound a step louder, or quieter if negative, or turns
+    // muting on or off if 0.
+    Volume(i32),
+    // Shows the brightness or volume a change ended up at, from 0 to 1.
+    Level(f32),
+    Reload,
+    Hide,
+    Show,
+    Message(String),
+    // Text to announce through speech-dispatcher.
+    Speak(String),
+    State(LoginState),
+    // greetd failed for good, stopping the greeter.
+    GreetdFailed(String),
 }
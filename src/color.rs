@@ -1,6 +1,6 @@
-use serde::{Deserialize, Serialize};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
 
-#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default)]
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
 pub struct Color {
     red: f32,
     green: f32,
@@ -11,45 +11,15 @@ pub struct Color {
 impl Color {
     pub fn new(red: f32, green: f32, blue: f32, opacity: f32) -> Color {
         Color {
-            red: if red > 1.0 {
-                1.0
-            } else if red < 0.0 {
-                0.0
-            } else {
-                red
-            },
-            green: if green > 1.0 {
-                1.0
-            } else if green < 0.0 {
-                0.0
-            } else {
-                green
-            },
-            blue: if blue > 1.0 {
-                1.0
-            } else if blue < 0.0 {
-                0.0
-            } else {
-                blue
-            },
-            opacity: if opacity > 1.0 {
-                1.0
-            } else if opacity < 0.0 {
-                0.0
-            } else {
-                opacity
-            },
+            red: red.clamp(0.0, 1.0),
+            green: green.clamp(0.0, 1.0),
+            blue: blue.clamp(0.0, 1.0),
+            opacity: opacity.clamp(0.0, 1.0),
         }
     }
 
     pub fn blend(&self, other: &Color, ratio: f32) -> Color {
-        let ratio = if ratio > 1.0 {
-            1.0
-        } else if ratio < 0.0 {
-            0.0
-        } else {
-            ratio
-        };
+        let ratio = ratio.clamp(0.0, 1.0);
 
         Color {
             red: self.red + ((other.red - self.red) * ratio),
@@ -59,6 +29,48 @@ impl Color {
         }
     }
 
+    /// Returns the color darkened to level of its brightness.
+    pub fn dim(&self, level: f32) -> Color {
+        Color::new(
+            self.red * level,
+            self.green * level,
+            self.blue * level,
+            self.opacity,
+        )
+    }
+
+    /// Returns the color with its opacity scaled by level.
+    pub fn fade(&self, level: f32) -> Color {
+        Color::new(self.red, self.green, self.blue, self.opacity * level)
+    }
+
+    /// Returns the color of self drawn over other.
+    pub fn over(&self, other: &Color) -> Color {
+        let below = other.opacity * (1.0 - self.opacity);
+        let opacity = self.opacity + below;
+        if opacity <= 0.0 {
+            return Color::default();
+        }
+        let mix = |a: f32, b: f32| (a * self.opacity + b * below) / opacity;
+        Color::new(
+            mix(self.red, other.red),
+            mix(self.green, other.green),
+            mix(self.blue, other.blue),
+            opacity,
+        )
+    }
+
+    /// Returns the color as if drawn over to where it was drawn over from,
+    /// keeping its difference to what it was drawn over.
+    pub fn rebase(&self, from: &Color, to: &Color) -> Color {
+        Color::new(
+            self.red + to.red - from.red,
+            self.green + to.green - from.green,
+            self.blue + to.blue - from.blue,
+            self.opacity + to.opacity - from.opacity,
+        )
+    }
+
     pub fn as_argb8888(&self) -> u32 {
         ((255.0 * self.opacity) as u32 & 0xFF) << 24
             | ((255.0 * self.red) as u32 & 0xFF) << 16
@@ -66,3 +78,146 @@ impl Color {
             | ((255.0 * self.blue) as u32 & 0xFF)
     }
 }
+
+const NAMED_COLORS: [(&str, u32); 10] = [
+    ("black", 0x000000ff),
+    ("white", 0xffffffff),
+    ("red", 0xff0000ff),
+    ("green", 0x00ff00ff),
+    ("blue", 0x0000ffff),
+    ("yellow", 0xffff00ff),
+    ("cyan", 0x00ffffff),
+    ("magenta", 0xff00ffff),
+    ("gray", 0x808080ff),
+    ("transparent", 0x00000000),
+];
+
+impl Color {
+    fn from_rgba8888(rgba: u32) -> Color {
+        Color::new(
+            (rgba >> 24 & 0xff) as f32 / 255.0,
+            (rgba >> 16 & 0xff) as f32 / 255.0,
+            (rgba >> 8 & 0xff) as f32 / 255.0,
+            (rgba & 0xff) as f32 / 255.0,
+        )
+    }
+
+    /// Parses "#RRGGBB", "#RRGGBBAA" or a color name.
+    pub fn parse(s: &str) -> Result<Color, String> {
+        if let Some(hex) = s.strip_prefix('#') {
+            // from_str_radix would take a sign too.
+            if !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+                return Err(format!("invalid color {:?}", s));
+            }
+            let value =
+                u32::from_str_radix(hex, 16).map_err(|_| format!("invalid color {:?}", s))?;
+            return match hex.len() {
+                6 => Ok(Color::from_rgba8888(value << 8 | 0xff)),
+                8 => Ok(Color::from_rgba8888(value)),
+                _ => Err(format!(
+                    "invalid color {:?}, expected #RRGGBB or #RRGGBBAA",
+                    s
+                )),
+            };
+        }
+        let name = s.to_ascii_lowercase();
+        let name = if name == "grey" { "gray" } else { &name };
+        NAMED_COLORS
+            .iter()
+            .find(|(n, _)| *n == name)
+            .map(|(_, rgba)| Color::from_rgba8888(*rgba))
+            .ok_or_else(|| format!("unknown color {:?}", s))
+    }
+}
+
+// Colors are written as "#RRGGBBAA".
+impl Serialize for Color {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let c = |v: f32| (v * 255.0).round() as u8;
+        serializer.serialize_str(&format!(
+            "#{:02x}{:02x}{:02x}{:02x}",
+            c(self.red),
+            c(self.green),
+            c(self.blue),
+            c(self.opacity)
+        ))
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ColorRepr {
+    Text(String),
+    Components {
+        red: f32,
+        green: f32,
+        blue: f32,
+        opacity: f32,
+    },
+}
+
+impl<'de> Deserialize<'de> for Color {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Color, D::Error> {
+        match ColorRepr::deserialize(deserializer)? {
+            ColorRepr::Text(s) => Color::parse(&s).map_err(de::Error::custom),
+            ColorRepr::Components {
+                red,
+                green,
+                blue,
+                opacity,
+            } => Ok(Color::new(red, green, blue, opacity)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Color;
+
+    // Parses s, which has to be a valid color, into ARGB.
+    fn argb(s: &str) -> u32 {
+        Color::parse(s).unwrap().as_argb8888()
+    }
+
+    #[test]
+    fn hex_colors() {
+        assert_eq!(argb("#ff0000"), 0xffff0000);
+        assert_eq!(argb("#00FF00"), 0xff00ff00);
+        assert_eq!(argb("#0000ff00"), 0x000000ff);
+        assert_eq!(argb("#80808080"), 0x80808080);
+    }
+
+    #[test]
+    fn bad_hex_colors_are_refused() {
+        for s in [
+            "#",
+            "#fff",
+            "#fffff",
+            "#fffffff",
+            "#fffffffff",
+            "#gggggg",
+            "#+fffff",
+            "#-ffffff",
+            "# ffffff",
+            "ffffff",
+        ]
+        .iter()
+        {
+            assert!(Color::parse(s).is_err(), "{:?} was taken", s);
+        }
+    }
+
+    #[test]
+    fn named_colors() {
+        assert_eq!(argb("White"), 0xffffffff);
+        assert_eq!(argb("grey"), argb("gray"));
+        assert_eq!(argb("transparent"), 0);
+        assert!(Color::parse("mauve").is_err());
+    }
+
+    #[test]
+    fn colors_serialize_as_parsed() {
+        let written = toml::Value::try_from(Color::parse("#12345678").unwrap()).unwrap();
+        assert_eq!(written.as_str(), Some("#12345678"));
+    }
+}
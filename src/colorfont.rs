@@ -0,0 +1,176 @@
+//! Color glyphs as found in emoji fonts, either as embedded PNG bitmaps
+//! (CBDT and sbix) or as layers of outlines in palette colors (COLR v0).
+
+use crate::color::Color;
+
+use owned_ttf_parser::{Face, RasterImageFormat, Tag};
+use rusttype::GlyphId;
+
+/// A color image of a glyph, scaled to the requested size. Pixels are
+/// stored row by row along with their coverage.
+pub struct Bitmap {
+    /// The top left corner relative to the glyph origin on the baseline.
+    pub origin: (i32, i32),
+    pub dimensions: (u32, u32),
+    pub pixels: Vec<(Color, f32)>,
+}
+
+/// Decodes a PNG image to its width, height and RGBA pixels row by row.
+pub fn decode_png(data: &[u8]) -> Option<(u32, u32, Vec<[u8; 4]>)> {
+    let mut decoder = png::Decoder::new(data);
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+    let mut reader = decoder.read_info().ok()?;
+    let mut buf = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buf).ok()?;
+    let buf = &buf[..info.buffer_size()];
+    let pixels = match info.color_type {
+        png::ColorType::Rgba => buf.chunks(4).map(|p| [p[0], p[1], p[2], p[3]]).collect(),
+        png::ColorType::Rgb => buf.chunks(3).map(|p| [p[0], p[1], p[2], 255]).collect(),
+        png::ColorType::GrayscaleAlpha => buf.chunks(2).map(|p| [p[0], p[0], p[0], p[1]]).collect(),
+        png::ColorType::Grayscale => buf.iter().map(|&p| [p, p, p, 255]).collect(),
+        png::ColorType::Indexed => return None,
+    };
+    Some((info.width, info.height, pixels))
+}
+
+/// Returns the bitmap of a glyph at ppem pixels per em, scaled from the
+/// closest strike in the font.
+pub fn bitmap(face: &Face, id: GlyphId, ppem: f32) -> Option<Bitmap> {
+    let image = face.glyph_raster_image(owned_ttf_parser::GlyphId(id.0), ppem.ceil() as u16)?;
+    if image.format != RasterImageFormat::PNG {
+        return None;
+    }
+    let (width, height, pixels) = decode_png(image.data)?;
+    if width == 0 || height == 0 {
+        return None;
+    }
+
+    let factor = ppem / image.pixels_per_em as f32;
+    let dimensions = (
+        ((width as f32 * factor).round() as u32).max(1),
+        ((height as f32 * factor).round() as u32).max(1),
+    );
+
+    Some(Bitmap {
+        origin: (
+            (image.x as f32 * factor).round() as i32,
+            -((image.y as f32 + height as f32) * factor).round() as i32,
+        ),
+        dimensions,
+        pixels: scale(&pixels, (width, height), dimensions),
+    })
+}
+
+/// Scales RGBA pixels of the given size to dimensions, as colors along with
+/// their coverage.
+pub fn scale(
+    pixels: &[[u8; 4]],
+    (width, height): (u32, u32),
+    dimensions: (u32, u32),
+) -> Vec<(Color, f32)> {
+    // Average the source pixels covered by each target pixel, weighting
+    // colors by their alpha so that transparent edges don't darken.
+    let mut scaled = Vec::with_capacity((dimensions.0 * dimensions.1) as usize);
+    let span = |d: u32, target: u32, source: u32| {
+        let start = d * source / target;
+        let end = ((d + 1) * source / target).max(start + 1).min(source);
+        start..end
+    };
+    for dy in 0..dimensions.1 {
+        for dx in 0..dimensions.0 {
+            let (mut r, mut g, mut b, mut a, mut n) = (0.0, 0.0, 0.0, 0.0, 0.0);
+            for sy in span(dy, dimensions.1, height) {
+                for sx in span(dx, dimensions.0, width) {
+                    let p = pixels[(sy * width + sx) as usize];
+                    let alpha = p[3] as f32 / 255.0;
+                    r += p[0] as f32 / 255.0 * alpha;
+                    g += p[1] as f32 / 255.0 * alpha;
+                    b += p[2] as f32 / 255.0 * alpha;
+                    a += alpha;
+                    n += 1.0;
+                }
+            }
+            if a > 0.0 {
+                scaled.push((Color::new(r / a, g / a, b / a, 1.0), a / n));
+            } else {
+                scaled.push((Color::new(0.0, 0.0, 0.0, 1.0), 0.0));
+            }
+        }
+    }
+    scaled
+}
+
+fn read_u16(data: &[u8], offset: usize) -> Option<u16> {
+    let bytes = data.get(offset..offset + 2)?;
+    Some(u16::from_be_bytes([bytes[0], bytes[1]]))
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    let bytes = data.get(offset..offset + 4)?;
+    Some(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+// Returns the color of entry index in the first palette of a CPAL table.
+fn palette_color(cpal: &[u8], index: u16) -> Option<(Color, f32)> {
+    let entries = read_u16(cpal, 2)?;
+    if index >= entries {
+        return None;
+    }
+    let records = read_u32(cpal, 8)? as usize;
+    let first = read_u16(cpal, 12)? as usize;
+    let record = records + (first + index as usize) * 4;
+    let bgra = cpal.get(record..record + 4)?;
+    Some((
+        Color::new(
+            bgra[2] as f32 / 255.0,
+            bgra[1] as f32 / 255.0,
+            bgra[0] as f32 / 255.0,
+            1.0,
+        ),
+        bgra[3] as f32 / 255.0,
+    ))
+}
+
+/// Returns the layers of a COLR v0 glyph from bottom to top, as outline
+/// glyphs along with their color and opacity. Layers in the text color are
+/// drawn black, as glyphs are cached independently of the text color.
+pub fn layers(face: &Face, id: GlyphId) -> Option<Vec<(GlyphId, Color, f32)>> {
+    let colr = face.table_data(Tag::from_bytes(b"COLR"))?;
+    let cpal = face.table_data(Tag::from_bytes(b"CPAL"))?;
+
+    let base_count = read_u16(colr, 2)? as usize;
+    let base_offset = read_u32(colr, 4)? as usize;
+    let layer_offset = read_u32(colr, 8)? as usize;
+
+    // Base glyph records are sorted by glyph id.
+    let (mut low, mut high) = (0, base_count);
+    let record = loop {
+        if low >= high {
+            return None;
+        }
+        let mid = (low + high) / 2;
+        let record = base_offset + mid * 6;
+        let glyph = read_u16(colr, record)?;
+        if glyph == id.0 {
+            break record;
+        } else if glyph < id.0 {
+            low = mid + 1;
+        } else {
+            high = mid;
+        }
+    };
+
+    let first = read_u16(colr, record + 2)? as usize;
+    let count = read_u16(colr, record + 4)? as usize;
+    let mut layers = Vec::with_capacity(count);
+    for layer in first..first + count {
+        let layer = layer_offset + layer * 4;
+        let glyph = GlyphId(read_u16(colr, layer)?);
+        let (color, opacity) = match read_u16(colr, layer + 2)? {
+            0xFFFF => (Color::new(0.0, 0.0, 0.0, 1.0), 1.0),
+            index => palette_color(cpal, index)?,
+        };
+        layers.push((glyph, color, opacity));
+    }
+    Some(layers)
+}
@@ -1,9 +1,15 @@
+use crate::check;
 use crate::color::Color;
+use crate::error::Error;
+use crate::keybindings::Action;
 use getopts::Options;
+use log::LevelFilter;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::default::Default;
 use std::env;
 use std::fs::read_to_string;
+use std::path::PathBuf;
 
 #[derive(Serialize, Deserialize, Debug, Clone, Copy)]
 #[serde(rename_all = "camelCase")]
@@ -18,9 +24,197 @@ impl Default for OutputMode {
     }
 }
 
-fn default_scale() -> u32 {
-    1
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum Anchor {
+    Top,
+    Bottom,
+    Left,
+    Right,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default)]
+#[serde(default)]
+pub struct Margin {
+    pub top: i32,
+    pub right: i32,
+    pub bottom: i32,
+    pub left: i32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "camelCase")]
+pub enum LayoutToggle {
+    #[default]
+    AltShift,
+    CtrlShift,
+    SuperSpace,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "camelCase")]
+pub enum BorderStyle {
+    #[default]
+    Solid,
+    None,
+}
+
+/// How the answer to a secret prompt is shown.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "camelCase")]
+pub enum SecretEcho {
+    /// A dot for every character.
+    #[default]
+    Dots,
+    /// The same number of dots whatever the length.
+    Fixed,
+    /// Nothing at all.
+    None,
+}
+
+/// What moves in the background around the widgets.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "camelCase")]
+pub enum AnimatedBackground {
+    /// Nothing, only the background color.
+    #[default]
+    None,
+    /// Dots drifting upwards.
+    Particles,
+    /// Streaks falling down columns.
+    Rain,
+}
+
+/// Where the logo goes by default.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "camelCase")]
+pub enum LogoPosition {
+    /// Above the login box, below the clock.
+    #[default]
+    Above,
+    /// To the left of the login box.
+    Left,
+    /// To the right of the login box.
+    Right,
+}
+
+/// How the clock shows the time.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "camelCase")]
+pub enum ClockStyle {
+    /// Text, as formatted by clockFormat.
+    #[default]
+    Digital,
+    /// A clock face with hands.
+    Analog,
+}
+
+/// Where the users of the user list come from.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "camelCase")]
+pub enum UserSource {
+    /// /etc/passwd, within minUid and maxUid.
+    #[default]
+    Passwd,
+    /// AccountsService over D-Bus, with full names and pictures.
+    AccountsService,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum WidgetKind {
+    Clock,
+    Login,
+    Session,
+    Power,
+    HostInfo,
+    Network,
+    Script,
+    QrCode,
+    Logo,
+    Calendar,
+    SystemInfo,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "camelCase")]
+pub enum WidgetAnchor {
+    Top,
+    #[default]
+    Center,
+    Bottom,
 }
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct WidgetConfig {
+    #[serde(rename = "type")]
+    pub kind: WidgetKind,
+    #[serde(default)]
+    pub anchor: WidgetAnchor,
+    /// For script widgets, the command whose output is shown, run with
+    /// /bin/sh every interval seconds.
+    #[serde(default)]
+    pub command: String,
+    #[serde(default = "default_script_interval")]
+    pub interval: u64,
+    /// For script widgets, the number of lines of output shown.
+    #[serde(default = "default_script_lines")]
+    pub lines: u32,
+}
+
+/// An entry of the session list, picked with the F-key of its position.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct SessionConfig {
+    pub name: String,
+    pub command: String,
+    /// Environment assignments for this session, on top of env.
+    #[serde(default)]
+    pub env: Vec<String>,
+}
+
+/// The XKB names to compile a keymap from, instead of using the keymap of
+/// the compositor.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Keymap {
+    pub model: Option<String>,
+    pub layout: Option<String>,
+    pub variant: Option<String>,
+    pub options: Option<String>,
+}
+
+/// Overrides for a single output, from an [output."NAME"] section.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct OutputConfig {
+    #[serde(default)]
+    pub scale: Option<f32>,
+    #[serde(default)]
+    pub background: Option<Color>,
+    #[serde(default)]
+    pub visible: Option<bool>,
+}
+
+/// The settings a surface on a particular output is created with.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OutputSettings {
+    pub scale: f32,
+    pub background: Color,
+    pub visible: bool,
+}
+
+impl OutputConfig {
+    /// Returns settings with the overrides of this section applied.
+    pub fn apply(&self, settings: OutputSettings) -> OutputSettings {
+        OutputSettings {
+            scale: self.scale.unwrap_or(settings.scale),
+            background: self.background.unwrap_or(settings.background),
+            visible: self.visible.unwrap_or(settings.visible),
+        }
+    }
+}
+
 fn default_background() -> Color {
     Color::new(0.0, 0.0, 0.0, 0.9)
 }
@@ -34,19 +228,100 @@ fn default_prompt() -> Color {
     Color::new(1.0, 1.0, 1.0, 1.0)
 }
 fn default_prompt_err() -> Color {
-    Color::new(1.0, 1.0, 1.0, 1.0)
+    Color::new(1.0, 0.4, 0.4, 1.0)
 }
 fn default_border() -> Color {
     Color::new(1.0, 1.0, 1.0, 1.0)
 }
+fn default_border_width() -> u32 {
+    1
+}
+fn default_shadow() -> Color {
+    Color::new(0.0, 0.0, 0.0, 0.5)
+}
+fn default_clock_format() -> String {
+    "%H:%M".to_string()
+}
+fn default_clock_size() -> f32 {
+    48.0
+}
+fn default_power_menu() -> bool {
+    true
+}
+fn default_session_chooser() -> bool {
+    true
+}
+fn default_avatar() -> bool {
+    true
+}
+fn default_script_interval() -> u64 {
+    60
+}
+fn default_script_lines() -> u32 {
+    1
+}
+fn default_min_uid() -> u32 {
+    1000
+}
+fn default_max_uid() -> u32 {
+    60000
+}
+fn default_greeting() -> String {
+    "Login".to_string()
+}
+fn default_username_prompt() -> String {
+    "username:".to_string()
+}
+fn default_headline_size() -> f32 {
+    72.0
+}
+fn default_prompt_size() -> f32 {
+    32.0
+}
+fn default_secret_mask() -> char {
+    '*'
+}
+fn default_text_scale() -> f32 {
+    1.0
+}
+fn default_repeat_rate() -> u32 {
+    25
+}
+fn default_repeat_delay() -> u32 {
+    600
+}
+fn default_autologin_delay() -> u32 {
+    5
+}
+fn default_command_override() -> bool {
+    true
+}
+fn default_session_cache() -> String {
+    "/var/cache/wlgreet/sessions".to_string()
+}
+fn default_animations() -> bool {
+    true
+}
+fn default_animated_background_fps() -> u32 {
+    30
+}
+fn default_logo_size() -> u32 {
+    96
+}
+fn default_background_interval() -> u64 {
+    300
+}
+fn default_log_level() -> LevelFilter {
+    LevelFilter::Warn
+}
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct Config {
     #[serde(default)]
     pub output_mode: OutputMode,
-    #[serde(default = "default_scale")]
-    pub scale: u32,
+    #[serde(default)]
+    pub scale: Option<f32>,
     #[serde(default = "default_background")]
     pub background: Color,
     #[serde(default = "default_headline")]
@@ -57,63 +332,1002 @@ pub struct Config {
     pub prompt_err: Color,
     #[serde(default = "default_border")]
     pub border: Color,
+    #[serde(default = "default_shadow")]
+    pub shadow: Color,
     #[serde(default = "default_cmd")]
     pub command: String,
+    #[serde(default)]
+    pub anchor: Vec<Anchor>,
+    #[serde(default)]
+    pub margin: Margin,
+    #[serde(default)]
+    pub exclusive_zone: i32,
+    #[serde(default = "default_border_width")]
+    pub border_width: u32,
+    #[serde(default)]
+    pub border_style: BorderStyle,
+    #[serde(default)]
+    pub border_radius: u32,
+    #[serde(default)]
+    pub shadow_size: u32,
+    #[serde(default = "default_clock_format")]
+    pub clock_format: String,
+    #[serde(default = "default_clock_size")]
+    pub clock_size: f32,
+    #[serde(default)]
+    pub clock_style: ClockStyle,
+    #[serde(default = "default_power_menu")]
+    pub power_menu: bool,
+    #[serde(default = "default_session_chooser")]
+    pub session_chooser: bool,
+    #[serde(default)]
+    pub host_info: bool,
+    #[serde(default)]
+    pub network_status: bool,
+    #[serde(default)]
+    pub system_info: bool,
+    #[serde(default)]
+    pub network_command: String,
+    #[serde(default)]
+    pub qr_code: String,
+    #[serde(default)]
+    pub logo: String,
+    #[serde(default = "default_logo_size")]
+    pub logo_size: u32,
+    #[serde(default)]
+    pub logo_position: LogoPosition,
+    #[serde(default)]
+    pub backlight: String,
+    #[serde(default)]
+    pub volume_command: String,
+    #[serde(default)]
+    pub user_list: bool,
+    #[serde(default)]
+    pub user_source: UserSource,
+    #[serde(default = "default_avatar")]
+    pub avatar: bool,
+    #[serde(default = "default_min_uid")]
+    pub min_uid: u32,
+    #[serde(default = "default_max_uid")]
+    pub max_uid: u32,
+    #[serde(default)]
+    pub font: Option<String>,
+    #[serde(default)]
+    pub emoji_font: Option<String>,
+    #[serde(default = "default_greeting")]
+    pub greeting: String,
+    #[serde(default = "default_username_prompt")]
+    pub username_prompt: String,
+    #[serde(default = "default_headline_size")]
+    pub headline_size: f32,
+    #[serde(default = "default_prompt_size")]
+    pub prompt_size: f32,
+    #[serde(default = "default_text_scale")]
+    pub text_scale: f32,
+    #[serde(default = "default_repeat_rate")]
+    pub repeat_rate: u32,
+    #[serde(default = "default_repeat_delay")]
+    pub repeat_delay: u32,
+    #[serde(default)]
+    pub keyboard_layouts: Vec<String>,
+    #[serde(default)]
+    pub layout_toggle: LayoutToggle,
+    #[serde(default)]
+    pub xkb_layout: Option<String>,
+    #[serde(default)]
+    pub xkb_variant: Option<String>,
+    #[serde(default)]
+    pub xkb_model: Option<String>,
+    #[serde(default)]
+    pub xkb_options: Option<String>,
+    #[serde(default)]
+    pub autologin_user: Option<String>,
+    #[serde(default)]
+    pub autologin_session: Option<String>,
+    #[serde(default = "default_autologin_delay")]
+    pub autologin_delay: u32,
+    #[serde(default = "default_session_cache")]
+    pub session_cache: String,
+    #[serde(default)]
+    pub env: Vec<String>,
+    #[serde(default)]
+    pub command_shell: String,
+    #[serde(default = "default_command_override")]
+    pub command_override: bool,
+    #[serde(default)]
+    pub keybindings: BTreeMap<String, Action>,
+    #[serde(default)]
+    pub secret_echo: SecretEcho,
+    #[serde(default = "default_secret_mask")]
+    pub secret_mask: char,
+    #[serde(default)]
+    pub reveal_last: bool,
+    #[serde(default)]
+    pub input_timeout: u32,
+    #[serde(default)]
+    pub idle_dim_timeout: u32,
+    #[serde(default)]
+    pub idle_blank_timeout: u32,
+    #[serde(default = "default_animations")]
+    pub animations: bool,
+    #[serde(default)]
+    pub animated_background: AnimatedBackground,
+    #[serde(default = "default_animated_background_fps")]
+    pub animated_background_fps: u32,
+    /// Pictures, or directories of them, shown in turn around the widgets.
+    #[serde(default)]
+    pub backgrounds: Vec<String>,
+    #[serde(default = "default_background_interval")]
+    pub background_interval: u64,
+    #[serde(default)]
+    pub background_blur: u32,
+    #[serde(default)]
+    pub high_contrast: bool,
+    #[serde(default)]
+    pub speech: bool,
+    #[serde(default, rename = "widget")]
+    pub widgets: Vec<WidgetConfig>,
+    #[serde(default, rename = "session")]
+    pub sessions: Vec<SessionConfig>,
+    #[serde(default, rename = "output")]
+    pub outputs: BTreeMap<String, OutputConfig>,
+    #[serde(default)]
+    pub target_output: Option<String>,
+    #[serde(skip)]
+    pub lock: bool,
+    #[serde(skip, default = "default_log_level")]
+    pub log_level: LevelFilter,
+    #[serde(skip)]
+    pub journal: bool,
+    #[serde(skip)]
+    pub render_to: Option<String>,
+    #[serde(skip)]
+    pub render_size: (u32, u32),
+    #[serde(skip)]
+    pub path: String,
 }
 
 impl Default for Config {
     fn default() -> Self {
         Config {
             output_mode: Default::default(),
-            scale: 1,
+            scale: None,
             background: Color::new(0.0, 0.0, 0.0, 0.9),
             headline: Color::new(1.0, 1.0, 1.0, 1.0),
             prompt: Color::new(1.0, 1.0, 1.0, 1.0),
-            prompt_err: Color::new(1.0, 1.0, 1.0, 1.0),
+            prompt_err: Color::new(1.0, 0.4, 0.4, 1.0),
             border: Color::new(1.0, 1.0, 1.0, 1.0),
+            shadow: Color::new(0.0, 0.0, 0.0, 0.5),
             command: "".to_string(),
+            anchor: Vec::new(),
+            margin: Default::default(),
+            exclusive_zone: 0,
+            border_width: 1,
+            border_style: Default::default(),
+            border_radius: 0,
+            shadow_size: 0,
+            clock_format: "%H:%M".to_string(),
+            clock_size: 48.0,
+            clock_style: Default::default(),
+            power_menu: true,
+            session_chooser: true,
+            host_info: false,
+            network_status: false,
+            system_info: false,
+            network_command: String::new(),
+            qr_code: String::new(),
+            logo: String::new(),
+            logo_size: 96,
+            logo_position: Default::default(),
+            backlight: String::new(),
+            volume_command: String::new(),
+            user_list: false,
+            user_source: Default::default(),
+            avatar: true,
+            min_uid: 1000,
+            max_uid: 60000,
+            font: None,
+            emoji_font: None,
+            greeting: "Login".to_string(),
+            username_prompt: "username:".to_string(),
+            headline_size: 72.0,
+            prompt_size: 32.0,
+            text_scale: 1.0,
+            repeat_rate: 25,
+            repeat_delay: 600,
+            keyboard_layouts: Vec::new(),
+            layout_toggle: Default::default(),
+            xkb_layout: None,
+            xkb_variant: None,
+            xkb_model: None,
+            xkb_options: None,
+            autologin_user: None,
+            autologin_session: None,
+            autologin_delay: 5,
+            session_cache: "/var/cache/wlgreet/sessions".to_string(),
+            env: Vec::new(),
+            command_shell: String::new(),
+            command_override: true,
+            keybindings: BTreeMap::new(),
+            secret_echo: Default::default(),
+            secret_mask: '*',
+            reveal_last: false,
+            input_timeout: 0,
+            idle_dim_timeout: 0,
+            idle_blank_timeout: 0,
+            animations: true,
+            animated_background: Default::default(),
+            animated_background_fps: 30,
+            backgrounds: Vec::new(),
+            background_interval: 300,
+            background_blur: 0,
+            high_contrast: false,
+            speech: false,
+            widgets: Vec::new(),
+            sessions: Vec::new(),
+            outputs: BTreeMap::new(),
+            target_output: None,
+            lock: false,
+            log_level: default_log_level(),
+            journal: false,
+            render_to: None,
+            render_size: (1920, 1080),
+            path: String::new(),
         }
     }
 }
 
+// Least border width and text scale multiplier of the high contrast palette.
+const HIGH_CONTRAST_BORDER_WIDTH: u32 = 3;
+const HIGH_CONTRAST_TEXT_SCALE: f32 = 1.5;
+
+/// Returns the variable set by an environment assignment of NAME=value, or
+/// None if it is not one.
+pub fn env_name(assignment: &str) -> Option<&str> {
+    match assignment.split_once('=') {
+        Some((name, _)) if !name.is_empty() => Some(name),
+        _ => None,
+    }
+}
+
+/// Returns the environment to start cmd with: env, with the assignments of
+/// the entry of sessions for cmd, if any, replacing those of the same
+/// variables. Anything but NAME=value is left out.
+pub fn session_env(env: &[String], sessions: &[SessionConfig], cmd: &str) -> Vec<String> {
+    let session = sessions.iter().find(|s| s.command == cmd);
+    let mut out: Vec<String> = Vec::new();
+    for assignment in env.iter().chain(session.iter().flat_map(|s| s.env.iter())) {
+        if let Some(name) = env_name(assignment) {
+            out.retain(|a| env_name(a) != Some(name));
+            out.push(assignment.clone());
+        }
+    }
+    out
+}
+
+// Replaces the {NAME} placeholders of s with the values of vars, quoted for
+// a shell if quote is set. Unknown placeholders are left as they are.
+fn expand(s: &str, vars: &[(&str, &str)], quote: bool) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(start) = rest.find('{') {
+        out.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let var = rest[1..].find('}').and_then(|end| {
+            let name = &rest[1..end + 1];
+            let (_, value) = vars.iter().find(|(n, _)| *n == name)?;
+            Some((end + 2, value))
+        });
+        match var {
+            Some((len, value)) => {
+                if quote {
+                    out.push_str(&shell_words::quote(value));
+                } else {
+                    out.push_str(value);
+                }
+                rest = &rest[len..];
+            }
+            None => {
+                out.push('{');
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Returns the argument vector to start cmd with: cmd split into words as a
+/// shell would, or handed to shell as SHELL -c COMMAND if shell is set. The
+/// {NAME} placeholders of cmd are replaced with the values of vars, which
+/// cannot add words or shell syntax.
+pub fn session_argv(cmd: &str, shell: &str, vars: &[(&str, &str)]) -> Result<Vec<String>, String> {
+    if cmd.trim().is_empty() {
+        return Err("empty command".to_string());
+    }
+    if shell.is_empty() {
+        let words = shell_words::split(cmd).map_err(|e| e.to_string())?;
+        Ok(words.iter().map(|w| expand(w, vars, false)).collect())
+    } else {
+        Ok(vec![
+            shell.to_string(),
+            "-c".to_string(),
+            expand(cmd, vars, true),
+        ])
+    }
+}
+
+impl Config {
+    /// Returns the config with the high contrast palette applied if on:
+    /// white on opaque black, a thicker border without a shadow, and larger
+    /// text.
+    pub fn with_high_contrast(&self, on: bool) -> Config {
+        let mut config = self.clone();
+        if !on {
+            return config;
+        }
+        let white = Color::new(1.0, 1.0, 1.0, 1.0);
+        config.background = Color::new(0.0, 0.0, 0.0, 1.0);
+        for output in config.outputs.values_mut() {
+            output.background = None;
+        }
+        config.headline = white;
+        config.prompt = white;
+        config.prompt_err = white;
+        config.border = white;
+        config.border_style = BorderStyle::Solid;
+        config.border_width = config.border_width.max(HIGH_CONTRAST_BORDER_WIDTH);
+        config.shadow_size = 0;
+        config.text_scale *= HIGH_CONTRAST_TEXT_SCALE;
+        config
+    }
+
+    /// Returns true if the scale of the output with the given name is set
+    /// in the config rather than taken from the compositor.
+    pub fn scale_configured(&self, name: Option<&str>) -> bool {
+        self.scale.is_some()
+            || name
+                .and_then(|name| self.outputs.get(name))
+                .map(|output| output.scale.is_some())
+                .unwrap_or(false)
+    }
+
+    /// Returns the keymap to use with the entry of keyboardLayouts at index
+    /// layout, or None to use the keymap of the compositor. With
+    /// keyboardLayouts, xkbVariant lists one variant per layout.
+    pub fn keymap(&self, layout: usize) -> Option<Keymap> {
+        let (layout, variant) = match self.keyboard_layouts.get(layout) {
+            Some(name) => (
+                Some(name.clone()),
+                self.xkb_variant
+                    .as_ref()
+                    .and_then(|v| v.split(',').nth(layout))
+                    .filter(|v| !v.is_empty())
+                    .map(|v| v.to_string()),
+            ),
+            None => (self.xkb_layout.clone(), self.xkb_variant.clone()),
+        };
+        let keymap = Keymap {
+            model: self.xkb_model.clone(),
+            layout,
+            variant,
+            options: self.xkb_options.clone(),
+        };
+        match keymap {
+            Keymap {
+                model: None,
+                layout: None,
+                variant: None,
+                options: None,
+            } => None,
+            keymap => Some(keymap),
+        }
+    }
+
+    /// Returns the settings for the output with the given name, applying
+    /// its [output."NAME"] section if there is one. output_scale is the
+    /// scale the compositor reports for the output, used unless a scale is
+    /// configured.
+    pub fn output_settings(&self, name: Option<&str>, output_scale: f32) -> OutputSettings {
+        let settings = OutputSettings {
+            scale: self.scale.unwrap_or(output_scale),
+            background: self.background,
+            visible: true,
+        };
+        match name.and_then(|name| self.outputs.get(name)) {
+            Some(output) => output.apply(settings),
+            None => settings,
+        }
+    }
+}
+
+// Applies a KEY=VALUE override to the parsed config file. Dotted keys
+// address nested tables, and values that are not valid TOML are taken as
+// plain strings.
+fn apply_override(config: &mut toml::value::Table, arg: &str) -> Result<(), String> {
+    let idx = arg
+        .find('=')
+        .ok_or_else(|| format!("Invalid override {:?}, expected KEY=VALUE", arg))?;
+    let (key, value) = (&arg[..idx], &arg[idx + 1..]);
+    let value = match toml::from_str::<toml::value::Table>(&format!("v = {}", value)) {
+        Ok(mut t) => t.remove("v").unwrap(),
+        Err(_) => toml::Value::String(value.to_string()),
+    };
+
+    let mut path: Vec<&str> = key.split('.').collect();
+    let last = path.pop().unwrap();
+    let mut table = config;
+    for part in path {
+        let entry = table
+            .entry(part.to_string())
+            .or_insert_with(|| toml::Value::Table(Default::default()));
+        table = entry
+            .as_table_mut()
+            .ok_or_else(|| format!("Invalid override {:?}, {} is not a table", arg, part))?;
+    }
+    table.insert(last.to_string(), value);
+    Ok(())
+}
+
+// Descriptions of the options, written as comments by
+// --print-default-config.
+const OPTION_DOCS: [(&str, &str); 77] = [
+    (
+        "outputMode",
+        "Outputs to show the greeter on: \"all\" or \"active\"",
+    ),
+    (
+        "scale",
+        "Scale to render at, such as 2 or 1.5, taken from each output if not set",
+    ),
+    (
+        "background",
+        "Colors are \"#RRGGBB\", \"#RRGGBBAA\" or a name like \"white\"",
+    ),
+    ("headline", "Color of the headline and clock"),
+    ("prompt", "Color of prompts and answers"),
+    (
+        "promptErr",
+        "Color of error messages, and of the border after a failed login",
+    ),
+    ("border", "Color of the login box border"),
+    (
+        "shadow",
+        "Color of the shadow around the login box, or of a glow if light",
+    ),
+    ("command", "Command to start after logging in"),
+    (
+        "anchor",
+        "Edges to pin the greeter to: \"top\", \"bottom\", \"left\", \"right\"",
+    ),
+    ("margin", "Distance to the anchored edges"),
+    ("exclusiveZone", "Space reserved at the anchored edge"),
+    ("borderWidth", "Width of the login box border"),
+    (
+        "borderStyle",
+        "Style of the login box border: \"solid\" or \"none\"",
+    ),
+    (
+        "borderRadius",
+        "Radius of the corners of the login box, 0 for square corners",
+    ),
+    (
+        "shadowSize",
+        "Width of the shadow around the login box, 0 for none",
+    ),
+    (
+        "clockFormat",
+        "strftime format of the clock, empty to hide it",
+    ),
+    ("clockSize", "Font size of the clock"),
+    (
+        "clockStyle",
+        "How the clock shows the time, \"digital\" or \"analog\"",
+    ),
+    ("powerMenu", "Show the power menu"),
+    ("sessionChooser", "Show the session chooser"),
+    (
+        "hostInfo",
+        "Show the hostname, distribution and kernel version",
+    ),
+    (
+        "networkStatus",
+        "Show which network interfaces are up and their addresses",
+    ),
+    (
+        "systemInfo",
+        "Show the uptime, load averages and memory use",
+    ),
+    (
+        "networkCommand",
+        "Shell command whose first line of output the network status shows instead",
+    ),
+    (
+        "qrCode",
+        "URL shown as a QR code next to the login box, such as that of a helpdesk",
+    ),
+    ("logo", "PNG or SVG file of a logo shown by the login box"),
+    ("logoSize", "Height of the logo"),
+    (
+        "logoPosition",
+        "Where the logo goes, \"above\", \"left\" or \"right\" of the login box",
+    ),
+    (
+        "backlight",
+        "Backlight in /sys/class/backlight changed by the brightness keys, the first if empty",
+    ),
+    (
+        "volumeCommand",
+        "Command run by the volume keys with {action} set to up, down or mute, amixer if empty",
+    ),
+    ("userList", "Offer a list of users to log in as"),
+    (
+        "userSource",
+        "Where the user list comes from, \"passwd\" or \"accountsService\"",
+    ),
+    (
+        "avatar",
+        "Show the picture of the user logging in, from AccountsService or ~/.face",
+    ),
+    ("minUid", "Lowest UID shown in the user list"),
+    ("maxUid", "Highest UID shown in the user list"),
+    ("font", "Path of a TrueType font to use"),
+    (
+        "emojiFont",
+        "Path of a color font for emoji and characters missing from font",
+    ),
+    (
+        "greeting",
+        "Headline of the login box, empty to leave it out",
+    ),
+    ("usernamePrompt", "Prompt for the username"),
+    ("headlineSize", "Font size of the headline"),
+    ("promptSize", "Font size of prompts"),
+    (
+        "textScale",
+        "Multiplier for all font sizes, changed with Ctrl+Plus and Ctrl+Minus",
+    ),
+    ("repeatRate", "Key repeats per second, 0 to disable repeat"),
+    ("repeatDelay", "Milliseconds before a held key repeats"),
+    (
+        "keyboardLayouts",
+        "XKB layouts to switch between, such as [\"us\", \"de\"], empty to use the compositor's",
+    ),
+    (
+        "layoutToggle",
+        "Keys switching layouts: \"altShift\", \"ctrlShift\" or \"superSpace\"",
+    ),
+    (
+        "xkbLayout",
+        "XKB layout to use instead of the compositor's, unless keyboardLayouts is set",
+    ),
+    (
+        "xkbVariant",
+        "XKB variant, or one per entry of keyboardLayouts separated by commas",
+    ),
+    ("xkbModel", "XKB keyboard model, such as \"pc105\""),
+    ("xkbOptions", "XKB options, such as \"caps:escape\""),
+    (
+        "autologinUser",
+        "User to log in automatically after a countdown",
+    ),
+    ("autologinSession", "Command to start for auto-login"),
+    ("autologinDelay", "Seconds before logging in automatically"),
+    (
+        "sessionCache",
+        "File remembering the session each user last started, empty to not remember",
+    ),
+    (
+        "env",
+        "Environment of the started session, as NAME=value assignments",
+    ),
+    (
+        "commandShell",
+        "Shell to run session commands with as SHELL -c COMMAND, empty to split them into words",
+    ),
+    (
+        "commandOverride",
+        "Allow typing in the session command to start with Ctrl+Alt+E",
+    ),
+    (
+        "keybindings",
+        "Keys mapped to actions, replacing or adding to the built-in bindings",
+    ),
+    (
+        "secretEcho",
+        "How passwords show: \"dots\", \"fixed\" to hide the length or \"none\"",
+    ),
+    (
+        "secretMask",
+        "Character shown in place of those of passwords",
+    ),
+    (
+        "revealLast",
+        "Show the last character typed into a password for a moment",
+    ),
+    (
+        "inputTimeout",
+        "Seconds without input before clearing a half-entered login, 0 to never clear",
+    ),
+    (
+        "idleDimTimeout",
+        "Seconds without input before dimming the greeter, 0 to never dim",
+    ),
+    (
+        "idleBlankTimeout",
+        "Seconds without input before blanking the greeter, 0 to never blank",
+    ),
+    (
+        "animations",
+        "Blink the caret, fade in on startup and shake on failed logins",
+    ),
+    (
+        "animatedBackground",
+        "Animation around the widgets, \"none\", \"particles\" or \"rain\"",
+    ),
+    (
+        "animatedBackgroundFps",
+        "Frames per second the animated background is drawn at, at most 60",
+    ),
+    (
+        "backgrounds",
+        "PNG pictures, or directories of them, shown in turn around the widgets",
+    ),
+    (
+        "backgroundInterval",
+        "Seconds each of the backgrounds is shown for",
+    ),
+    (
+        "backgroundBlur",
+        "Radius in pixels of the backgrounds shown blurred behind the widgets, 0 for none",
+    ),
+    (
+        "highContrast",
+        "Start with the high contrast palette, toggled with Ctrl+Alt+H",
+    ),
+    (
+        "speech",
+        "Speak prompts and errors through speech-dispatcher",
+    ),
+    (
+        "widget",
+        "Widgets to show, as [[widget]] tables with type and anchor",
+    ),
+    (
+        "session",
+        "Sessions picked with F1 to F12, as [[session]] tables with name, command and env",
+    ),
+    (
+        "targetOutput",
+        "Name of the output to show the greeter on, if it is present",
+    ),
+    (
+        "output",
+        "Per-output overrides of scale, background and visible, as [output.\"NAME\"] tables",
+    ),
+];
+
+/// Returns true if key is a known top-level configuration option.
+pub fn is_option(key: &str) -> bool {
+    OPTION_DOCS.iter().any(|(k, _)| *k == key)
+}
+
+// Options without a default value, shown commented out.
+const OPTIONAL_EXAMPLES: [(&str, &str); 10] = [
+    ("scale", "2"),
+    ("font", "\"/usr/share/fonts/TTF/DejaVuSans.ttf\""),
+    ("emojiFont", "\"/usr/share/fonts/noto/NotoColorEmoji.ttf\""),
+    ("autologinUser", "\"alice\""),
+    ("autologinSession", "\"sway\""),
+    ("targetOutput", "\"eDP-1\""),
+    ("xkbLayout", "\"us\""),
+    ("xkbVariant", "\"dvorak\""),
+    ("xkbModel", "\"pc105\""),
+    ("xkbOptions", "\"caps:escape\""),
+];
+
+/// Returns the default configuration as a commented TOML document.
+pub fn default_config_toml() -> String {
+    let value = toml::Value::try_from(Config::default()).expect("unable to serialize config");
+    let doc = toml::to_string(&value).expect("unable to serialize config");
+
+    let mut out = String::from("# Default wlgreet configuration\n\n");
+    for (key, example) in OPTIONAL_EXAMPLES.iter() {
+        if let Some((_, docs)) = OPTION_DOCS.iter().find(|(k, _)| k == key) {
+            out += &format!("# {}\n", docs);
+        }
+        out += &format!("# {} = {}\n\n", key, example);
+    }
+    for line in doc.lines() {
+        let key = match line.strip_prefix('[') {
+            Some(table) => table.trim_end_matches(']'),
+            None => line.split(" = ").next().unwrap_or(""),
+        };
+        if let Some((_, docs)) = OPTION_DOCS.iter().find(|(k, _)| *k == key) {
+            out += &format!("# {}\n", docs);
+        }
+        out += line;
+        out += "\n";
+    }
+    out
+}
+
 fn print_usage(program: &str, opts: Options) {
     let brief = format!("Usage: {} [options]", program);
     print!("{}", opts.usage(&brief));
 }
 
+const SYSTEM_PATHS: [&str; 2] = ["/etc/greetd/wlgreet.toml", "/etc/wlgreet/config.toml"];
+
+// Returns $XDG_CONFIG_HOME/wlgreet/config.toml, falling back to ~/.config.
+fn user_path() -> Option<PathBuf> {
+    let dir = match env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(env::var_os("HOME")?).join(".config"),
+    };
+    Some(dir.join("wlgreet").join("config.toml"))
+}
+
+// Returns the first existing config file out of the user config and
+// SYSTEM_PATHS, in that order. If none exist, the first system path is
+// returned.
+fn default_path() -> String {
+    user_path()
+        .into_iter()
+        .chain(SYSTEM_PATHS.iter().map(PathBuf::from))
+        .find(|p| p.is_file())
+        .map(|p| p.to_string_lossy().into_owned())
+        .unwrap_or_else(|| SYSTEM_PATHS[0].to_string())
+}
+
+// Parses a size given as WIDTHxHEIGHT.
+fn parse_size(s: &str) -> Option<(u32, u32)> {
+    let (width, height) = s.split_once('x')?;
+    match (width.parse().ok()?, height.parse().ok()?) {
+        (0, _) | (_, 0) => None,
+        size => Some(size),
+    }
+}
+
+/// Reads the configuration, exiting on errors.
 pub fn read_config() -> Config {
+    match load_config() {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{}", e);
+            if let Error::Config(_) = e {
+                eprintln!("Please fix the configuration file and try again.");
+            }
+            std::process::exit(e.exit_code());
+        }
+    }
+}
+
+/// Reads the configuration file named on the command line, applying
+/// command line overrides.
+pub fn load_config() -> Result<Config, Error> {
     let args: Vec<String> = env::args().collect();
     let program = args[0].clone();
     let mut opts = Options::new();
     opts.optflag("h", "help", "print this help menu");
     opts.optopt("c", "config", "config file to use", "CONFIG_FILE");
     opts.optopt("e", "command", "command to run", "COMMAND");
+    opts.optmulti(
+        "s",
+        "set",
+        "override a config option, e.g. scale=2 or background.red=0.5",
+        "KEY=VALUE",
+    );
+    opts.optflag(
+        "",
+        "check-config",
+        "check the configuration for problems and exit",
+    );
+    opts.optflag(
+        "",
+        "print-default-config",
+        "print the default configuration and exit",
+    );
+    opts.optflag(
+        "",
+        "lock",
+        "lock the current session instead of acting as a greeter",
+    );
+    opts.optopt(
+        "",
+        "log-level",
+        "least severe messages to log: off, error, warn, info, debug or trace",
+        "LEVEL",
+    );
+    opts.optflag(
+        "",
+        "journal",
+        "log to the systemd journal instead of stderr",
+    );
+    opts.optopt(
+        "",
+        "render-to",
+        "draw one frame to a PNG file and exit, without a compositor",
+        "FILE",
+    );
+    opts.optopt(
+        "",
+        "render-size",
+        "size of the frame drawn by --render-to, 1920x1080 by default",
+        "WIDTHxHEIGHT",
+    );
     let matches = match opts.parse(&args[1..]) {
         Ok(m) => m,
-        Err(f) => panic!("{}", f.to_string()),
+        Err(f) => return Err(Error::Usage(format!("{}, see {} --help", f, program))),
     };
     if matches.opt_present("h") {
         print_usage(&program, opts);
         std::process::exit(0);
     }
+    if matches.opt_present("print-default-config") {
+        print!("{}", default_config_toml());
+        std::process::exit(0);
+    }
+
+    let log_level = match matches.opt_str("log-level") {
+        Some(level) => level
+            .parse()
+            .map_err(|_| Error::Usage(format!("Invalid log level: {}", level)))?,
+        None => default_log_level(),
+    };
+
+    let render_size = match matches.opt_str("render-size") {
+        Some(size) => {
+            parse_size(&size).ok_or_else(|| Error::Usage(format!("Invalid size: {}", size)))?
+        }
+        None => (1920, 1080),
+    };
+
+    let path = matches.opt_str("config").unwrap_or_else(default_path);
+    let parsed = parse_config(&path, &matches.opt_strs("set")).map(|(table, mut config)| {
+        config.command = matches.opt_get_default("command", config.command).unwrap();
+        config.lock = matches.opt_present("lock");
+        config.log_level = log_level;
+        config.journal = matches.opt_present("journal");
+        config.render_to = matches.opt_str("render-to");
+        config.render_size = render_size;
+        config.path = path.to_string();
+        (table, config)
+    });
+
+    if matches.opt_present("check-config") {
+        let diagnostics = match parsed {
+            Ok((table, config)) => check::check_config(&config, &table),
+            Err(e) => vec![e],
+        };
+        for d in diagnostics.iter() {
+            eprintln!("{}", d);
+        }
+        if !diagnostics.is_empty() {
+            eprintln!("{}: {} problem(s) found", path, diagnostics.len());
+            std::process::exit(1);
+        }
+        println!("{}: configuration OK", path);
+        std::process::exit(0);
+    }
+
+    parsed.map(|(_, config)| config).map_err(Error::Config)
+}
 
-    let mut config: Config = match read_to_string(
-        matches
-            .opt_str("config")
-            .unwrap_or_else(|| "/etc/greetd/wlgreet.toml".to_string()),
-    ) {
+// Reads the configuration file at path, if any, and applies overrides. The
+// raw table is returned alongside the configuration.
+fn parse_config(path: &str, overrides: &[String]) -> Result<(toml::value::Table, Config), String> {
+    let mut table: toml::value::Table = match read_to_string(path) {
         Ok(s) => match toml::from_str(&s) {
             Ok(v) => v,
-            Err(e) => {
-                eprintln!("Unable to parse configuration file: {:?}", e);
-                eprintln!("Please fix the configuration file and try again.");
-                std::process::exit(1);
-            }
+            Err(e) => return Err(format!("Unable to parse configuration file: {:?}", e)),
         },
         Err(_) => Default::default(),
     };
+    for arg in overrides {
+        apply_override(&mut table, arg)?;
+    }
+    match toml::Value::Table(table.clone()).try_into() {
+        Ok(config) => Ok((table, config)),
+        Err(e) => Err(format!("Invalid configuration: {:?}", e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SHELL: &str = "/bin/sh";
+
+    // Usernames that would add words or shell syntax if not quoted.
+    const NASTY: [&str; 4] = ["john doe", "o'brien", "a\"b", "x; rm -rf ~"];
+
+    #[test]
+    fn placeholders_stay_one_word_without_a_shell() {
+        for user in NASTY.iter() {
+            let argv = session_argv("run --user {username} now", "", &[("username", user)]);
+            assert_eq!(argv.unwrap(), ["run", "--user", user, "now"]);
+        }
+    }
+
+    #[test]
+    fn placeholders_are_quoted_for_a_shell() {
+        for user in NASTY.iter() {
+            let argv = session_argv("run --user {username}", SHELL, &[("username", user)]);
+            let argv = argv.unwrap();
+            assert_eq!(argv[..2], [SHELL, "-c"]);
+            // What the shell sees is still the same three words.
+            let words = shell_words::split(&argv[2]).unwrap();
+            assert_eq!(words, ["run", "--user", user]);
+        }
+    }
+
+    #[test]
+    fn values_are_not_expanded_again() {
+        let vars = [("username", "{session}"), ("session", "sway")];
+        let argv = session_argv("run {username}", "", &vars).unwrap();
+        assert_eq!(argv, ["run", "{session}"]);
+    }
+
+    #[test]
+    fn unknown_and_unterminated_placeholders_are_kept() {
+        let vars = [("username", "john")];
+        for word in ["{nope}", "{username", "{", "}", "{}"].iter() {
+            let cmd = format!("run {}", word);
+            assert_eq!(session_argv(&cmd, "", &vars).unwrap(), ["run", word]);
+            assert_eq!(session_argv(&cmd, SHELL, &vars).unwrap()[2], cmd);
+        }
+        assert_eq!(expand("{{username}}", &vars, false), "{john}");
+        assert_eq!(expand("{nope}{username}", &vars, true), "{nope}john");
+    }
+
+    #[test]
+    fn empty_commands_are_refused() {
+        for shell in ["", SHELL].iter() {
+            assert!(session_argv("", shell, &[]).is_err());
+            assert!(session_argv("  \t", shell, &[]).is_err());
+        }
+    }
 
-    config.command = matches.opt_get_default("command", config.command).unwrap();
+    #[test]
+    fn unbalanced_quotes_are_refused_without_a_shell() {
+        assert!(session_argv("run 'oops", "", &[]).is_err());
+    }
+
+    fn overridden(args: &[&str]) -> Result<toml::value::Table, String> {
+        let mut table = toml::value::Table::new();
+        for arg in args {
+            apply_override(&mut table, arg)?;
+        }
+        Ok(table)
+    }
 
-    config
+    #[test]
+    fn overrides_take_toml_values() {
+        let table = overridden(&["scale=2", "animations=false", "greeting=\"hi\""]).unwrap();
+        assert_eq!(table["scale"], toml::Value::Integer(2));
+        assert_eq!(table["animations"], toml::Value::Boolean(false));
+        assert_eq!(table["greeting"], toml::Value::String("hi".to_string()));
+    }
+
+    #[test]
+    fn overrides_take_anything_else_as_a_string() {
+        let table = overridden(&["greeting=hello world", "command=env A=1 sway"]).unwrap();
+        assert_eq!(table["greeting"].as_str(), Some("hello world"));
+        assert_eq!(table["command"].as_str(), Some("env A=1 sway"));
+    }
+
+    #[test]
+    fn dotted_overrides_make_nested_tables() {
+        let table = overridden(&["background.red=0.5", "background.green=1"]).unwrap();
+        let background = table["background"].as_table().unwrap();
+        assert_eq!(background["red"].as_float(), Some(0.5));
+        assert_eq!(background["green"].as_integer(), Some(1));
+    }
+
+    #[test]
+    fn bad_overrides_are_refused() {
+        assert!(overridden(&["scale"]).is_err());
+        assert!(overridden(&["greeting=hi", "greeting.size=2"]).is_err());
+    }
 }
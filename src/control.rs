@@ -0,0 +1,140 @@
+//! A control socket at $XDG_RUNTIME_DIR/wlgreet.sock, for scripts to hide
+//! and show the greeter, show a status message or reload the configuration.
+//! Every line sent is a command, answered with "ok" or "error: " and the
+//! reason.
+
+use calloop::channel::Sender;
+use std::env;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::PathBuf;
+
+use log::{debug, warn};
+
+use crate::cmd::Cmd;
+
+fn parse(line: &str) -> Result<Cmd, String> {
+    let (name, arg) = match line.split_once(' ') {
+        Some((name, arg)) => (name, arg.trim()),
+        None => (line, ""),
+    };
+    match (name, arg) {
+        ("hide", "") => Ok(Cmd::Hide),
+        ("show", "") => Ok(Cmd::Show),
+        ("reload", "") => Ok(Cmd::Reload),
+        ("message", text) => Ok(Cmd::Message(text.to_string())),
+        ("hide", _) | ("show", _) | ("reload", _) => Err(format!("{} takes no argument", name)),
+        _ => Err(format!("unknown command {:?}", name)),
+    }
+}
+
+fn serve(stream: UnixStream, tx: Sender<Cmd>) -> io::Result<()> {
+    let mut writer = stream.try_clone()?;
+    for line in BufReader::new(stream).lines() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let reply = match parse(line) {
+            Ok(cmd) => {
+                debug!("Control command: {}", line);
+                if tx.send(cmd).is_err() {
+                    return Ok(());
+                }
+                "ok".to_string()
+            }
+            Err(e) => format!("error: {}", e),
+        };
+        writeln!(writer, "{}", reply)?;
+    }
+    Ok(())
+}
+
+/// The control socket, which is removed when dropped.
+pub struct ControlSocket {
+    path: PathBuf,
+}
+
+impl ControlSocket {
+    /// Binds the control socket and starts a thread passing the commands
+    /// received on to tx. Returns None if XDG_RUNTIME_DIR is unset or the
+    /// socket is in use by another instance.
+    pub fn spawn(tx: Sender<Cmd>) -> Option<ControlSocket> {
+        let path = match env::var_os("XDG_RUNTIME_DIR") {
+            Some(dir) => PathBuf::from(dir).join("wlgreet.sock"),
+            None => {
+                debug!("XDG_RUNTIME_DIR is unset, not creating a control socket");
+                return None;
+            }
+        };
+        if UnixStream::connect(&path).is_ok() {
+            warn!("{} is in use by another instance", path.display());
+            return None;
+        }
+        // Left behind by an instance that did not exit cleanly.
+        let _ = fs::remove_file(&path);
+        let listener = match UnixListener::bind(&path) {
+            Ok(listener) => listener,
+            Err(e) => {
+                warn!("Unable to create control socket {}: {}", path.display(), e);
+                return None;
+            }
+        };
+
+        let _ = std::thread::Builder::new()
+            .name("control".to_string())
+            .spawn(move || {
+                for stream in listener.incoming() {
+                    let stream = match stream {
+                        Ok(stream) => stream,
+                        Err(e) => {
+                            warn!("Unable to accept control connection: {}", e);
+                            continue;
+                        }
+                    };
+                    let tx = tx.clone();
+                    std::thread::spawn(move || {
+                        if let Err(e) = serve(stream, tx) {
+                            debug!("Control connection failed: {}", e);
+                        }
+                    });
+                }
+            });
+        Some(ControlSocket { path })
+    }
+}
+
+impl Drop for ControlSocket {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn commands_without_arguments() {
+        assert!(matches!(parse("hide"), Ok(Cmd::Hide)));
+        assert!(matches!(parse("show"), Ok(Cmd::Show)));
+        assert!(matches!(parse("reload"), Ok(Cmd::Reload)));
+        assert!(parse("hide now").is_err());
+        assert!(parse("reload all").is_err());
+    }
+
+    #[test]
+    fn messages_keep_their_inner_spaces() {
+        assert!(matches!(parse("message back  soon "), Ok(Cmd::Message(m)) if m == "back  soon"));
+        assert!(matches!(parse("message"), Ok(Cmd::Message(m)) if m.is_empty()));
+    }
+
+    #[test]
+    fn unknown_commands_are_refused() {
+        assert!(parse("quit").is_err());
+        assert!(parse("HIDE").is_err());
+        assert!(parse("").is_err());
+    }
+}
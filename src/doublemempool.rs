@@ -1,6 +1,90 @@
-use smithay_client_toolkit::shm::MemPool;
-use wayland_client::protocol::wl_shm;
-use wayland_client::{Attached, Main};
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use memmap2::MmapMut;
+use smithay_client_toolkit::shm::raw::RawPool;
+use smithay_client_toolkit::shm::{CreatePoolError, Shm};
+use wayland_client::protocol::{wl_buffer, wl_shm};
+use wayland_client::{Connection, Dispatch, QueueHandle};
+
+/// Marks the pool of a buffer as free again once the compositor is done
+/// with the buffer.
+pub struct BufferData(Arc<AtomicBool>);
+
+/// A shared memory pool, which is in use while the compositor holds on to
+/// a buffer from it.
+pub struct MemPool {
+    pool: RawPool,
+    used: Arc<AtomicBool>,
+}
+
+impl MemPool {
+    pub fn new(shm: &Shm) -> io::Result<MemPool> {
+        let pool = match RawPool::new(4, shm) {
+            Ok(pool) => pool,
+            Err(CreatePoolError::Create(e)) => return Err(e),
+            Err(CreatePoolError::Global(e)) => {
+                return Err(io::Error::new(io::ErrorKind::NotFound, e))
+            }
+        };
+        Ok(MemPool {
+            pool,
+            used: Arc::new(AtomicBool::new(false)),
+        })
+    }
+
+    /// Grows the pool to at least size bytes.
+    pub fn resize(&mut self, size: usize) -> io::Result<()> {
+        self.pool.resize(size)
+    }
+
+    pub fn mmap(&mut self) -> &mut MmapMut {
+        self.pool.mmap()
+    }
+
+    pub fn is_used(&self) -> bool {
+        self.used.load(Ordering::SeqCst)
+    }
+
+    /// Creates a buffer from the pool, which is destroyed once released.
+    pub fn buffer<D>(
+        &mut self,
+        offset: i32,
+        width: i32,
+        height: i32,
+        stride: i32,
+        format: wl_shm::Format,
+        qh: &QueueHandle<D>,
+    ) -> wl_buffer::WlBuffer
+    where
+        D: Dispatch<wl_buffer::WlBuffer, BufferData> + 'static,
+    {
+        self.used.store(true, Ordering::SeqCst);
+        let data = BufferData(self.used.clone());
+        self.pool
+            .create_buffer(offset, width, height, stride, format, data, qh)
+    }
+}
+
+impl<D> Dispatch<wl_buffer::WlBuffer, BufferData, D> for MemPool
+where
+    D: Dispatch<wl_buffer::WlBuffer, BufferData>,
+{
+    fn event(
+        _: &mut D,
+        buffer: &wl_buffer::WlBuffer,
+        event: wl_buffer::Event,
+        data: &BufferData,
+        _: &Connection,
+        _: &QueueHandle<D>,
+    ) {
+        if let wl_buffer::Event::Release = event {
+            data.0.store(false, Ordering::SeqCst);
+            buffer.destroy();
+        }
+    }
+}
 
 pub struct DoubleMemPool {
     pool1: MemPool,
@@ -9,10 +93,10 @@ pub struct DoubleMemPool {
 }
 
 impl DoubleMemPool {
-    pub fn new(shm: Main<wl_shm::WlShm>) -> ::std::io::Result<DoubleMemPool> {
+    pub fn new(shm: &Shm) -> io::Result<DoubleMemPool> {
         Ok(DoubleMemPool {
-            pool1: MemPool::new(Attached::from(shm.clone()), move |_| {})?,
-            pool2: MemPool::new(Attached::from(shm), move |_| {})?,
+            pool1: MemPool::new(shm)?,
+            pool2: MemPool::new(shm)?,
             switch: false,
         })
     }
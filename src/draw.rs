@@ -1,10 +1,16 @@
 use crate::buffer::Buffer;
 use crate::color::Color;
+use crate::colorfont;
 
 use std::collections::HashMap;
+use std::sync::RwLock;
 
 use lazy_static::lazy_static;
-use rusttype::{point, Font as RustFont, Scale};
+use owned_ttf_parser::AsFaceRef;
+use rusttype::{point, Font as RustFont, GlyphId, Scale};
+use rustybuzz::{Direction, UnicodeBuffer};
+use unicode_bidi::BidiInfo;
+use unicode_segmentation::UnicodeSegmentation;
 
 pub static DEJAVUSANS_MONO_FONT_DATA: &'static [u8] =
     include_bytes!("../fonts/dejavu/DejaVuSansMono.ttf");
@@ -17,20 +23,106 @@ lazy_static! {
     pub static ref ROBOTO_REGULAR: RustFont<'static> =
         RustFont::try_from_bytes(ROBOTO_REGULAR_FONT_DATA as &[u8])
             .expect("error constructing Roboto-Regular");
+    // Font for characters missing from the text font, such as emoji.
+    static ref FALLBACK_FONT: RwLock<Option<RustFont<'static>>> = RwLock::new(None);
+    // Multiplier for the size of all text.
+    static ref TEXT_SCALE: RwLock<f32> = RwLock::new(1.0);
+}
+
+/// Sets the font used for characters missing from the text font. Only fonts
+/// created afterwards pick it up.
+pub fn set_fallback_font(font: Option<RustFont<'static>>) {
+    *FALLBACK_FONT.write().unwrap() = font;
+}
+
+/// Sets the multiplier for the size of all text. Fonts pick it up the next
+/// time their scale is set.
+pub fn set_text_scale(scale: f32) {
+    *TEXT_SCALE.write().unwrap() = scale;
 }
 
 struct CachedGlyph {
     dimensions: (u32, u32),
     origin: (i32, i32),
     render: Vec<f32>,
+    // The color of every pixel for color glyphs, which are drawn in their
+    // own colors rather than the text color.
+    colors: Option<Vec<Color>>,
 }
 
 impl CachedGlyph {
-    fn new(font: &RustFont, size: f32, ch: char) -> CachedGlyph {
+    fn new(font: &RustFont<'static>, size: f32, id: GlyphId) -> CachedGlyph {
+        let face = face_of(font);
+        let ppem = font.scale_for_pixel_height(size) * face.units_per_em() as f32;
+        if let Some(bitmap) = colorfont::bitmap(&face, id, ppem) {
+            let ascent = font.v_metrics(Scale::uniform(size)).ascent;
+            let (colors, render) = bitmap.pixels.into_iter().unzip();
+            return CachedGlyph {
+                origin: (bitmap.origin.0, bitmap.origin.1 + ascent.round() as i32),
+                dimensions: bitmap.dimensions,
+                render,
+                colors: Some(colors),
+            };
+        }
+        match colorfont::layers(&face, id) {
+            Some(layers) => CachedGlyph::layered(font, size, &layers),
+            None => CachedGlyph::outline(font, size, id),
+        }
+    }
+
+    // Composes the layers of a COLR glyph on top of each other.
+    fn layered(font: &RustFont, size: f32, layers: &[(GlyphId, Color, f32)]) -> CachedGlyph {
+        let layers: Vec<_> = layers
+            .iter()
+            .map(|(id, color, opacity)| (CachedGlyph::outline(font, size, *id), color, opacity))
+            .filter(|(glyph, _, _)| !glyph.render.is_empty())
+            .collect();
+        let min = (
+            layers.iter().map(|(g, _, _)| g.origin.0).min().unwrap_or(0),
+            layers.iter().map(|(g, _, _)| g.origin.1).min().unwrap_or(0),
+        );
+        let max = (
+            layers
+                .iter()
+                .map(|(g, _, _)| g.origin.0 + g.dimensions.0 as i32)
+                .max()
+                .unwrap_or(0),
+            layers
+                .iter()
+                .map(|(g, _, _)| g.origin.1 + g.dimensions.1 as i32)
+                .max()
+                .unwrap_or(0),
+        );
+        let dimensions = ((max.0 - min.0) as u32, (max.1 - min.1) as u32);
+        let len = (dimensions.0 * dimensions.1) as usize;
+        let mut render = vec![0.0; len];
+        let mut colors = vec![Color::new(0.0, 0.0, 0.0, 1.0); len];
+        for (glyph, color, opacity) in layers {
+            for (i, v) in glyph.render.iter().enumerate() {
+                let x = i as u32 % glyph.dimensions.0 + (glyph.origin.0 - min.0) as u32;
+                let y = i as u32 / glyph.dimensions.0 + (glyph.origin.1 - min.1) as u32;
+                let pos = (x + y * dimensions.0) as usize;
+                let alpha = v * opacity;
+                let total = alpha + render[pos] * (1.0 - alpha);
+                if total > 0.0 {
+                    colors[pos] = colors[pos].blend(color, alpha / total);
+                }
+                render[pos] = total;
+            }
+        }
+        CachedGlyph {
+            origin: min,
+            dimensions,
+            render,
+            colors: Some(colors),
+        }
+    }
+
+    fn outline(font: &RustFont, size: f32, id: GlyphId) -> CachedGlyph {
         let scale = Scale::uniform(size);
         let v_metrics = font.v_metrics(scale);
         let glyph = font
-            .glyph(ch)
+            .glyph(id)
             .scaled(scale)
             .positioned(point(0.0, v_metrics.ascent));
 
@@ -50,12 +142,14 @@ impl CachedGlyph {
                 origin: origin,
                 dimensions: dimensions,
                 render: render,
+                colors: None,
             }
         } else {
             CachedGlyph {
                 origin: (0, 0),
-                dimensions: ((size / 4.0) as u32, 0),
+                dimensions: (0, 0),
                 render: Vec::new(),
+                colors: None,
             }
         }
     }
@@ -63,13 +157,17 @@ impl CachedGlyph {
     fn draw(&self, buf: &mut Buffer, pos: (i32, i32), bg: &Color, c: &Color) {
         let mut x = 0;
         let mut y = 0;
-        for v in &self.render {
+        for (i, v) in self.render.iter().enumerate() {
+            let color = match self.colors {
+                Some(ref colors) => &colors[i],
+                None => c,
+            };
             let _ = buf.put(
                 (
                     (x + pos.0 + self.origin.0) as u32,
                     (y + pos.1 + self.origin.1) as u32,
                 ),
-                &bg.blend(&c, *v),
+                &bg.blend(color, *v),
             );
 
             if x == self.dimensions.0 as i32 - 1 {
@@ -82,61 +180,256 @@ impl CachedGlyph {
     }
 }
 
+// A glyph of shaped text, positioned in buffer pixels relative to the start
+// of the text.
+struct PlacedGlyph {
+    id: GlyphId,
+    pos: (i32, i32),
+    fallback: bool,
+}
+
+// Returns true for characters that modify the one before them, and so must
+// be shaped with the same font: joiners, variation selectors, the keycap
+// mark, skin tones and tag characters.
+fn is_modifier(ch: char) -> bool {
+    matches!(
+        ch,
+        '\u{200D}' | '\u{FE0E}' | '\u{FE0F}' | '\u{20E3}' | '\u{1F3FB}'..='\u{1F3FF}'
+            | '\u{E0020}'..='\u{E007F}'
+    )
+}
+
+// Returns the font tables parsed by rusttype, so that text can be shaped
+// without parsing the font again.
+fn face_of<'a>(font: &'a RustFont<'static>) -> owned_ttf_parser::Face<'a> {
+    match font {
+        RustFont::Ref(face) => (**face).clone(),
+        RustFont::Owned(face) => face.as_face_ref().clone(),
+    }
+}
+
+pub fn load_font(path: &str) -> Result<RustFont<'static>, ::std::io::Error> {
+    let data = std::fs::read(path)?;
+    RustFont::try_from_vec(data).ok_or_else(|| {
+        ::std::io::Error::new(
+            ::std::io::ErrorKind::InvalidData,
+            format!("unable to parse font {}", path),
+        )
+    })
+}
+
 pub struct Font {
-    glyphs: HashMap<char, CachedGlyph>,
-    font: &'static RustFont<'static>,
+    // Glyphs by scale in 120ths, whether they come from the fallback font,
+    // and glyph id.
+    glyphs: HashMap<(u32, bool, u16), CachedGlyph>,
+    font: RustFont<'static>,
+    fallback: Option<RustFont<'static>>,
     size: f32,
+    scale: f32,
 }
 
 impl Font {
-    pub fn new(font: &'static RustFont, size: f32) -> Font {
+    pub fn new(font: &RustFont<'static>, size: f32) -> Font {
         Font {
             glyphs: HashMap::new(),
-            font: font,
+            font: font.clone(),
+            fallback: FALLBACK_FONT.read().unwrap().clone(),
             size: size,
+            scale: 1.0,
         }
     }
 
-    pub fn add_str_to_cache(&mut self, s: &str) {
-        for ch in s.chars() {
-            if self.glyphs.get(&ch).is_none() {
-                let glyph = CachedGlyph::new(self.font, self.size, ch);
-                self.glyphs.insert(ch, glyph);
+    /// The size of the font in buffer pixels.
+    pub fn size(&self) -> f32 {
+        self.size * self.scale
+    }
+
+    /// Sets the buffer scale to render at, which the text scale applies on
+    /// top of. Glyphs are cached per scale, so switching back and forth is
+    /// cheap.
+    pub fn set_scale(&mut self, scale: f32) {
+        self.scale = scale * *TEXT_SCALE.read().unwrap();
+    }
+
+    fn key(&self, glyph: &PlacedGlyph) -> (u32, bool, u16) {
+        (
+            (self.scale * 120.0).round() as u32,
+            glyph.fallback,
+            glyph.id.0,
+        )
+    }
+
+    fn font_for(&self, fallback: bool) -> &RustFont<'static> {
+        match self.fallback {
+            Some(ref font) if fallback => font,
+            _ => &self.font,
+        }
+    }
+
+    // Splits s into runs of text drawn with the same font, using the
+    // fallback font for characters that the font lacks, and for those asking
+    // for emoji presentation.
+    fn runs<'s>(&self, s: &'s str) -> Vec<(bool, &'s str)> {
+        let fallback = match self.fallback {
+            Some(ref font) => face_of(font),
+            None => return vec![(false, s)],
+        };
+        let face = face_of(&self.font);
+
+        let mut runs = Vec::new();
+        let mut start = 0;
+        let mut current = false;
+        let mut chars = s.char_indices().peekable();
+        while let Some((idx, ch)) = chars.next() {
+            let use_fallback = if is_modifier(ch) {
+                current
+            } else {
+                let emoji = chars.peek().map(|&(_, next)| next) == Some('\u{FE0F}');
+                (emoji || face.glyph_index(ch).is_none()) && fallback.glyph_index(ch).is_some()
+            };
+            if use_fallback != current && idx > 0 {
+                runs.push((current, &s[start..idx]));
+                start = idx;
             }
+            current = use_fallback;
         }
+        runs.push((current, &s[start..]));
+        runs
     }
 
-    pub fn draw_text(
+    // Shapes a run of text in a single font and direction, appending its
+    // glyphs in visual order at x.
+    fn shape_run(
         &self,
-        buf: &mut Buffer,
-        bg: &Color,
-        c: &Color,
-        s: &str,
-    ) -> Result<(u32, u32), ::std::io::Error> {
-        let mut x_off = 0;
-        let mut off = 0;
-        let mut glyphs = Vec::with_capacity(s.len());
-        for ch in s.chars() {
-            let glyph = match self.glyphs.get(&ch) {
-                Some(glyph) => glyph,
-                None => {
-                    return Err(::std::io::Error::new(
-                        ::std::io::ErrorKind::Other,
-                        format!("glyph for {:} not in cache", ch),
-                    ))
+        run: &str,
+        fallback: bool,
+        rtl: bool,
+        x: &mut f32,
+        glyphs: &mut Vec<PlacedGlyph>,
+    ) {
+        let font = self.font_for(fallback);
+        let face = rustybuzz::Face::from_face(face_of(font)).expect("font without units per em");
+        let mut buffer = UnicodeBuffer::new();
+        buffer.push_str(run);
+        if rtl {
+            buffer.set_direction(Direction::RightToLeft);
+        }
+        let shaped = rustybuzz::shape(&face, &[], buffer);
+
+        let px = font.scale_for_pixel_height(self.size());
+        for (info, pos) in shaped.glyph_infos().iter().zip(shaped.glyph_positions()) {
+            glyphs.push(PlacedGlyph {
+                id: GlyphId(info.glyph_id as u16),
+                pos: (
+                    (*x + pos.x_offset as f32 * px).round() as i32,
+                    (-pos.y_offset as f32 * px).round() as i32,
+                ),
+                fallback,
+            });
+            *x += pos.x_advance as f32 * px;
+        }
+    }
+
+    // Shapes s with rustybuzz, so that ligatures and scripts that join or
+    // reorder characters come out right. Right-to-left text is reordered
+    // following the Unicode bidirectional algorithm. Returns the glyphs along
+    // with the width of the text.
+    fn shape(&self, s: &str) -> (Vec<PlacedGlyph>, u32) {
+        let mut x = 0.0;
+        let mut glyphs = Vec::new();
+        let bidi = BidiInfo::new(s, None);
+        for para in &bidi.paragraphs {
+            let (levels, runs) = bidi.visual_runs(para, para.range.clone());
+            for range in runs {
+                let rtl = levels[range.start].is_rtl();
+                let mut runs = self.runs(&s[range]);
+                if rtl {
+                    runs.reverse();
+                }
+                for (fallback, run) in runs {
+                    self.shape_run(run, fallback, rtl, &mut x, &mut glyphs);
                 }
-            };
-            glyphs.push(glyph);
-            if glyph.origin.1 < off {
-                off = glyph.origin.1
             }
         }
+        (glyphs, x.round() as u32)
+    }
+
+    fn add_glyphs_to_cache(&mut self, glyphs: &[PlacedGlyph]) {
         for glyph in glyphs {
-            glyph.draw(buf, (x_off, -off), bg, c);
-            x_off += glyph.dimensions.0 as i32 + glyph.origin.0;
+            let key = self.key(glyph);
+            if !self.glyphs.contains_key(&key) {
+                let font = self.font_for(glyph.fallback);
+                let cached = CachedGlyph::new(font, self.size(), glyph.id);
+                self.glyphs.insert(key, cached);
+            }
+        }
+    }
+
+    /// Returns the size s takes up when drawn, so that it can be laid out
+    /// before drawing it.
+    pub fn measure_text(&self, s: &str) -> (u32, u32) {
+        let (_, width) = self.shape(s);
+        (width, self.size() as u32)
+    }
+
+    /// Shortens s with an ellipsis if it is wider than width.
+    pub fn ellipsize(&self, s: &str, width: u32) -> String {
+        if self.measure_text(s).0 <= width {
+            return s.to_string();
+        }
+        for (idx, _) in s.grapheme_indices(true).rev() {
+            let text = format!("{}…", s[..idx].trim_end());
+            if self.measure_text(&text).0 <= width {
+                return text;
+            }
         }
+        "…".to_string()
+    }
 
-        Ok((x_off as u32, self.size as u32))
+    fn draw_glyphs(&self, buf: &mut Buffer, bg: &Color, c: &Color, glyphs: &[PlacedGlyph]) {
+        // Move the text down if any glyph reaches above the ascent.
+        let off = glyphs
+            .iter()
+            .map(|glyph| glyph.pos.1 + self.glyphs[&self.key(glyph)].origin.1)
+            .min()
+            .unwrap_or(0)
+            .min(0);
+        for glyph in glyphs {
+            self.glyphs[&self.key(glyph)].draw(buf, (glyph.pos.0, glyph.pos.1 - off), bg, c);
+        }
+    }
+
+    /// Breaks s into lines no wider than width, at whitespace where
+    /// possible.
+    pub fn wrap(&self, s: &str, width: u32) -> Vec<String> {
+        let mut lines = Vec::new();
+        let mut line = String::new();
+        for word in s.split_whitespace() {
+            let joined = if line.is_empty() {
+                word.to_string()
+            } else {
+                format!("{} {}", line, word)
+            };
+            if self.measure_text(&joined).0 <= width {
+                line = joined;
+                continue;
+            }
+            if !line.is_empty() {
+                lines.push(std::mem::take(&mut line));
+            }
+            // Break words that are too long for a line of their own.
+            for grapheme in word.graphemes(true) {
+                let joined = format!("{}{}", line, grapheme);
+                if !line.is_empty() && self.measure_text(&joined).0 > width {
+                    lines.push(std::mem::take(&mut line));
+                }
+                line.push_str(grapheme);
+            }
+        }
+        if !line.is_empty() || lines.is_empty() {
+            lines.push(line);
+        }
+        lines
     }
 
     pub fn auto_draw_text(
@@ -146,20 +439,255 @@ impl Font {
         c: &Color,
         s: &str,
     ) -> Result<(u32, u32), ::std::io::Error> {
-        self.add_str_to_cache(s);
-        self.draw_text(buf, bg, c, s)
+        let (glyphs, width) = self.shape(s);
+        self.add_glyphs_to_cache(&glyphs);
+        self.draw_glyphs(buf, bg, c, &glyphs);
+        Ok((width, self.size() as u32))
+    }
+}
+
+/// Draws a ring of eight dots in a square of the given size, with the dot
+/// at frame brightest and the ones before it fading out.
+pub fn draw_spinner(buf: &mut Buffer, bg: &Color, c: &Color, size: u32, frame: u32) {
+    let dot = (size / 8).max(1) as i32;
+    let center = size as f32 / 2.0;
+    let radius = center - dot as f32;
+    for i in 0..8 {
+        let angle = i as f32 * std::f32::consts::PI / 4.0 - std::f32::consts::FRAC_PI_2;
+        let x = (center + radius * angle.cos()).round() as i32;
+        let y = (center + radius * angle.sin()).round() as i32;
+        let age = (frame + 8 - i) % 8;
+        let color = bg.blend(c, 1.0 - age as f32 / 8.0);
+        for dy in -dot..=dot {
+            for dx in -dot..=dot {
+                if dx * dx + dy * dy <= dot * dot && x + dx >= 0 && y + dy >= 0 {
+                    let _ = buf.put(((x + dx) as u32, (y + dy) as u32), &color);
+                }
+            }
+        }
     }
 }
 
-pub fn draw_box(buf: &mut Buffer, c: &Color, dim: (u32, u32)) -> Result<(), ::std::io::Error> {
-    for x in 0..dim.0 {
-        let _ = buf.put((x, 0), c);
-        let _ = buf.put((x, dim.1 - 1), c);
+pub fn draw_box(
+    buf: &mut Buffer,
+    c: &Color,
+    dim: (u32, u32),
+    width: u32,
+) -> Result<(), ::std::io::Error> {
+    let width = width.min(dim.0 / 2).min(dim.1 / 2);
+    for i in 0..width {
+        for x in i..dim.0 - i {
+            buf.put((x, i), c)?;
+            buf.put((x, dim.1 - 1 - i), c)?;
+        }
+        for y in i..dim.1 - i {
+            buf.put((i, y), c)?;
+            buf.put((dim.0 - 1 - i, y), c)?;
+        }
     }
+
+    Ok(())
+}
+
+/// Draws a shadow around a box placed size pixels in from the edges of dim,
+/// with its corners rounded to radius. The shadow fades out over size
+/// pixels.
+pub fn draw_shadow(
+    buf: &mut Buffer,
+    bg: &Color,
+    c: &Color,
+    dim: (u32, u32),
+    size: u32,
+    radius: u32,
+) -> Result<(), ::std::io::Error> {
+    let half = (
+        dim.0.saturating_sub(2 * size) as f32 / 2.0,
+        dim.1.saturating_sub(2 * size) as f32 / 2.0,
+    );
+    let radius = (radius as f32).min(half.0).min(half.1);
+    let center = (dim.0 as f32 / 2.0, dim.1 as f32 / 2.0);
     for y in 0..dim.1 {
-        buf.put((0, y), c)?;
-        buf.put((dim.0 - 1, y), c)?;
+        for x in 0..dim.0 {
+            // Distance from the edge of the box, following its corners
+            let qx = ((x as f32 + 0.5 - center.0).abs() - half.0 + radius).max(0.0);
+            let qy = ((y as f32 + 0.5 - center.1).abs() - half.1 + radius).max(0.0);
+            let d = (qx * qx + qy * qy).sqrt() - radius;
+            if d <= 0.0 || d >= size as f32 {
+                continue;
+            }
+            let t = 1.0 - d / size as f32;
+            buf.put((x, y), &c.fade(t * t).over(bg))?;
+        }
+    }
+    Ok(())
+}
+
+/// Draws the outline of a box width pixels wide, with its corners rounded
+/// to radius, blending the curves into bg.
+pub fn draw_rounded_box(
+    buf: &mut Buffer,
+    bg: &Color,
+    c: &Color,
+    dim: (u32, u32),
+    width: u32,
+    radius: u32,
+) -> Result<(), ::std::io::Error> {
+    let radius = radius.min(dim.0 / 2).min(dim.1 / 2);
+    if radius == 0 {
+        return draw_box(buf, c, dim, width);
+    }
+    let width = width.min(radius);
+    for i in 0..width {
+        for x in radius..dim.0 - radius {
+            buf.put((x, i), c)?;
+            buf.put((x, dim.1 - 1 - i), c)?;
+        }
+        for y in radius..dim.1 - radius {
+            buf.put((i, y), c)?;
+            buf.put((dim.0 - 1 - i, y), c)?;
+        }
+    }
+
+    // Each corner is a quarter of a ring width pixels wide, with the pixels
+    // it covers in part blended by how much of them it covers.
+    let (r, w) = (radius as f32, width as f32);
+    for dy in 0..radius {
+        for dx in 0..radius {
+            let (x, y) = (r - dx as f32 - 0.5, r - dy as f32 - 0.5);
+            let d = (x * x + y * y).sqrt();
+            let coverage = (r - d + 0.5).clamp(0.0, 1.0) - (r - w - d + 0.5).clamp(0.0, 1.0);
+            if coverage <= 0.0 {
+                continue;
+            }
+            let color = bg.blend(c, coverage);
+            buf.put((dx, dy), &color)?;
+            buf.put((dim.0 - 1 - dx, dy), &color)?;
+            buf.put((dx, dim.1 - 1 - dy), &color)?;
+            buf.put((dim.0 - 1 - dx, dim.1 - 1 - dy), &color)?;
+        }
     }
+    Ok(())
+}
 
+/// Draws a bar filling dim, outlined width pixels wide and filled from the
+/// left up to level, from 0 for empty to 1 for full.
+pub fn draw_meter(
+    buf: &mut Buffer,
+    bg: &Color,
+    c: &Color,
+    dim: (u32, u32),
+    width: u32,
+    level: f32,
+) -> Result<(), ::std::io::Error> {
+    buf.memset(bg);
+    draw_box(buf, c, dim, width)?;
+    let gap = 2 * width;
+    let inner = (dim.0.saturating_sub(2 * gap), dim.1.saturating_sub(2 * gap));
+    let filled = (inner.0 as f32 * level.clamp(0.0, 1.0)).round() as u32;
+    for y in 0..inner.1 {
+        for x in 0..filled {
+            buf.put((gap + x, gap + y), c)?;
+        }
+    }
     Ok(())
 }
+
+// Blends c into the pixel at pos by coverage, leaving out pixels outside
+// the buffer.
+fn blend_pixel(buf: &mut Buffer, pos: (i32, i32), c: &Color, coverage: f32) {
+    if coverage <= 0.0 || pos.0 < 0 || pos.1 < 0 {
+        return;
+    }
+    let pos = (pos.0 as u32, pos.1 as u32);
+    if let Ok(below) = buf.get(pos) {
+        let _ = buf.put(pos, &below.blend(c, coverage));
+    }
+}
+
+/// Draws a line width pixels wide with round ends from one point to
+/// another, over what is already drawn. A line from a point to itself is a
+/// dot.
+pub fn draw_line(buf: &mut Buffer, c: &Color, from: (f32, f32), to: (f32, f32), width: f32) {
+    let half = width / 2.0;
+    let (dx, dy) = (to.0 - from.0, to.1 - from.1);
+    let length = dx * dx + dy * dy;
+    let x0 = (from.0.min(to.0) - half).floor() as i32;
+    let x1 = (from.0.max(to.0) + half).ceil() as i32;
+    let y0 = (from.1.min(to.1) - half).floor() as i32;
+    let y1 = (from.1.max(to.1) + half).ceil() as i32;
+    for y in y0..=y1 {
+        for x in x0..=x1 {
+            // Distance from the middle of the pixel to the closest point
+            // of the line.
+            let (px, py) = (x as f32 + 0.5 - from.0, y as f32 + 0.5 - from.1);
+            let t = if length > 0.0 {
+                ((px * dx + py * dy) / length).clamp(0.0, 1.0)
+            } else {
+                0.0
+            };
+            let (ex, ey) = (px - t * dx, py - t * dy);
+            let d = (ex * ex + ey * ey).sqrt();
+            blend_pixel(buf, (x, y), c, (half - d + 0.5).clamp(0.0, 1.0));
+        }
+    }
+}
+
+/// Draws a circle width pixels wide around center, over what is already
+/// drawn.
+pub fn draw_circle(buf: &mut Buffer, c: &Color, center: (f32, f32), radius: f32, width: f32) {
+    let half = width / 2.0;
+    let outer = radius + half + 1.0;
+    let (x0, x1) = (
+        (center.0 - outer).floor() as i32,
+        (center.0 + outer).ceil() as i32,
+    );
+    let (y0, y1) = (
+        (center.1 - outer).floor() as i32,
+        (center.1 + outer).ceil() as i32,
+    );
+    for y in y0..=y1 {
+        for x in x0..=x1 {
+            let (px, py) = (x as f32 + 0.5 - center.0, y as f32 + 0.5 - center.1);
+            let d = ((px * px + py * py).sqrt() - radius).abs();
+            blend_pixel(buf, (x, y), c, (half - d + 0.5).clamp(0.0, 1.0));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn font() -> Font {
+        Font::new(&DEJAVUSANS_MONO, 16.0)
+    }
+
+    #[test]
+    fn words_too_long_for_a_line_are_broken() {
+        let font = font();
+        let (width, _) = font.measure_text("abcd");
+        let lines = font.wrap("abcdefghij", width);
+        assert_eq!(lines, ["abcd", "efgh", "ij"]);
+    }
+
+    #[test]
+    fn words_go_on_the_next_line() {
+        let font = font();
+        let (width, _) = font.measure_text("one two");
+        assert_eq!(font.wrap("one two three", width), ["one two", "three"]);
+    }
+
+    #[test]
+    fn empty_text_is_one_empty_line() {
+        assert_eq!(font().wrap("", 100), [""]);
+        assert_eq!(font().wrap("  ", 100), [""]);
+    }
+
+    #[test]
+    fn newlines_break_like_spaces() {
+        let font = font();
+        let (width, _) = font.measure_text("one two");
+        assert_eq!(font.wrap("one\ntwo", width), ["one two"]);
+        assert_eq!(font.wrap("one\n\ntwo three", width), ["one two", "three"]);
+    }
+}
@@ -0,0 +1,116 @@
+//! Errors that stop the greeter.
+
+use std::fmt;
+use std::io;
+
+use wayland_client::backend::WaylandError;
+use wayland_client::globals::GlobalError;
+use wayland_client::{ConnectError, DispatchError};
+
+#[derive(Debug)]
+pub enum Error {
+    /// No Wayland compositor could be connected to.
+    Connect(ConnectError),
+    /// The compositor lacks a protocol the greeter needs.
+    MissingGlobal(&'static str),
+    /// The compositor refused to lock the session.
+    LockRefused,
+    /// greetd, or PAM when locking, failed in a way the login cannot
+    /// recover from.
+    Greetd(String),
+    /// The command line could not be parsed.
+    Usage(String),
+    /// The configuration file could not be read.
+    Config(String),
+    /// The compositor sent something the greeter could not handle, or
+    /// reported a protocol error.
+    Wayland(DispatchError),
+    Io(io::Error),
+}
+
+impl Error {
+    /// The exit status for the error, following sysexits.h.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::Connect(_) | Error::MissingGlobal(_) | Error::LockRefused => 69,
+            Error::Greetd(_) => 69,
+            Error::Usage(_) => 64,
+            Error::Config(_) => 78,
+            Error::Wayland(_) => 76,
+            Error::Io(_) => 74,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Connect(e) => write!(f, "unable to connect to the compositor: {}", e),
+            Error::MissingGlobal(name) => write!(f, "compositor does not support {}", name),
+            Error::LockRefused => write!(f, "compositor refused to lock the session"),
+            Error::Greetd(e) => write!(f, "greetd failed: {}", e),
+            Error::Usage(e) | Error::Config(e) => write!(f, "{}", e),
+            Error::Wayland(e) => write!(f, "compositor connection failed: {}", e),
+            Error::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        Error::Io(e)
+    }
+}
+
+impl From<calloop::Error> for Error {
+    fn from(e: calloop::Error) -> Error {
+        Error::Io(e.into())
+    }
+}
+
+impl<T> From<calloop::InsertError<T>> for Error {
+    fn from(e: calloop::InsertError<T>) -> Error {
+        e.error.into()
+    }
+}
+
+impl From<nix::Error> for Error {
+    fn from(e: nix::Error) -> Error {
+        Error::Io(e.into())
+    }
+}
+
+impl From<ConnectError> for Error {
+    fn from(e: ConnectError) -> Error {
+        Error::Connect(e)
+    }
+}
+
+impl From<WaylandError> for Error {
+    fn from(e: WaylandError) -> Error {
+        match e {
+            WaylandError::Io(e) => Error::Io(e),
+            e => Error::Wayland(DispatchError::Backend(e)),
+        }
+    }
+}
+
+impl From<DispatchError> for Error {
+    fn from(e: DispatchError) -> Error {
+        match e {
+            DispatchError::Backend(e) => e.into(),
+            e => Error::Wayland(e),
+        }
+    }
+}
+
+impl From<GlobalError> for Error {
+    fn from(e: GlobalError) -> Error {
+        match e {
+            GlobalError::Backend(e) => e.into(),
+            GlobalError::InvalidId(e) => Error::Io(io::Error::new(io::ErrorKind::InvalidData, e)),
+        }
+    }
+}
@@ -0,0 +1,263 @@
+//! Blurs pictures on the GPU through OpenGL ES, for large surfaces at high
+//! scale factors where blurring on the CPU holds up drawing. libEGL is
+//! loaded when first needed, with a context of its own that draws into
+//! textures only, so nothing changes in how surfaces are shown. Without a
+//! GPU to use, callers fall back to blurring on the CPU.
+
+use std::cell::RefCell;
+
+use glow::HasContext;
+use khronos_egl as egl;
+use log::{debug, warn};
+
+// EGL_PLATFORM_SURFACELESS_MESA, for a display with no window system.
+const PLATFORM_SURFACELESS: egl::Enum = 0x31DD;
+
+const VERTEX: &str = "#version 300 es
+void main() {
+    vec2 pos = vec2(float((gl_VertexID & 1) << 2), float((gl_VertexID & 2) << 1));
+    gl_Position = vec4(pos - 1.0, 0.0, 1.0);
+}
+";
+
+// Averages the texels up to reach away along step, taking the edges to go
+// on.
+const FRAGMENT: &str = "#version 300 es
+precision highp float;
+uniform sampler2D image;
+uniform ivec2 step;
+uniform int reach;
+out vec4 color;
+void main() {
+    ivec2 last = textureSize(image, 0) - 1;
+    ivec2 at = ivec2(gl_FragCoord.xy);
+    vec4 sum = vec4(0.0);
+    for (int i = -reach; i <= reach; i++) {
+        sum += texelFetch(image, clamp(at + i * step, ivec2(0), last), 0);
+    }
+    color = sum / float(2 * reach + 1);
+}
+";
+
+struct Gpu {
+    gl: glow::Context,
+    program: glow::Program,
+    vertex_array: glow::VertexArray,
+    framebuffer: glow::Framebuffer,
+    // Kept for the context to stay current.
+    _egl: egl::DynamicInstance<egl::EGL1_5>,
+}
+
+impl Gpu {
+    fn open() -> Result<Gpu, String> {
+        let egl = unsafe { egl::DynamicInstance::<egl::EGL1_5>::load_required() }
+            .map_err(|e| format!("unable to load libEGL: {}", e))?;
+        let display = unsafe {
+            egl.get_platform_display(
+                PLATFORM_SURFACELESS,
+                egl::DEFAULT_DISPLAY,
+                &[egl::ATTRIB_NONE],
+            )
+        }
+        .ok()
+        .or_else(|| unsafe { egl.get_display(egl::DEFAULT_DISPLAY) })
+        .ok_or("no EGL display")?;
+        egl.initialize(display)
+            .map_err(|e| format!("unable to initialize EGL: {}", e))?;
+        egl.bind_api(egl::OPENGL_ES_API)
+            .map_err(|e| format!("no OpenGL ES: {}", e))?;
+        // Drawing into textures only, the context needs no config where
+        // EGL_KHR_no_config_context is supported, as on Mesa's surfaceless
+        // displays, which may offer none.
+        let attributes = [egl::RENDERABLE_TYPE, egl::OPENGL_ES3_BIT, egl::NONE];
+        let config = match egl.choose_first_config(display, &attributes) {
+            Ok(Some(config)) => config,
+            _ => unsafe { egl::Config::from_ptr(std::ptr::null_mut()) },
+        };
+        let context = egl
+            .create_context(
+                display,
+                config,
+                None,
+                &[egl::CONTEXT_MAJOR_VERSION, 3, egl::NONE],
+            )
+            .map_err(|e| format!("unable to create a context: {}", e))?;
+        egl.make_current(display, None, None, Some(context))
+            .map_err(|e| format!("unable to use a context without surfaces: {}", e))?;
+        let gl = unsafe {
+            glow::Context::from_loader_function(|name| {
+                egl.get_proc_address(name)
+                    .map_or(std::ptr::null(), |f| f as *const _)
+            })
+        };
+        unsafe {
+            // Mesa's software renderers blur slower than the CPU does.
+            let renderer = gl.get_parameter_string(glow::RENDERER);
+            if ["llvmpipe", "softpipe", "swrast"]
+                .iter()
+                .any(|name| renderer.contains(name))
+            {
+                return Err(format!("{} renders in software", renderer));
+            }
+            let program = gl.create_program()?;
+            for (kind, source) in [
+                (glow::VERTEX_SHADER, VERTEX),
+                (glow::FRAGMENT_SHADER, FRAGMENT),
+            ] {
+                let shader = gl.create_shader(kind)?;
+                gl.shader_source(shader, source);
+                gl.compile_shader(shader);
+                if !gl.get_shader_compile_status(shader) {
+                    return Err(gl.get_shader_info_log(shader));
+                }
+                gl.attach_shader(program, shader);
+                gl.delete_shader(shader);
+            }
+            gl.link_program(program);
+            if !gl.get_program_link_status(program) {
+                return Err(gl.get_program_info_log(program));
+            }
+            let vertex_array = gl.create_vertex_array()?;
+            let framebuffer = gl.create_framebuffer()?;
+            Ok(Gpu {
+                gl,
+                program,
+                vertex_array,
+                framebuffer,
+                _egl: egl,
+            })
+        }
+    }
+
+    // Makes a texture of size holding pixels, or nothing to draw into.
+    unsafe fn texture(
+        &self,
+        size: (i32, i32),
+        pixels: Option<&[u8]>,
+    ) -> Result<glow::Texture, String> {
+        let gl = &self.gl;
+        let texture = gl.create_texture()?;
+        gl.bind_texture(glow::TEXTURE_2D, Some(texture));
+        gl.tex_parameter_i32(
+            glow::TEXTURE_2D,
+            glow::TEXTURE_MIN_FILTER,
+            glow::NEAREST as i32,
+        );
+        gl.tex_parameter_i32(
+            glow::TEXTURE_2D,
+            glow::TEXTURE_MAG_FILTER,
+            glow::NEAREST as i32,
+        );
+        gl.tex_image_2d(
+            glow::TEXTURE_2D,
+            0,
+            glow::RGBA8 as i32,
+            size.0,
+            size.1,
+            0,
+            glow::RGBA,
+            glow::UNSIGNED_BYTE,
+            pixels,
+        );
+        Ok(texture)
+    }
+
+    // Draws from into target, averaged over half texels to either side
+    // along step.
+    unsafe fn pass(
+        &self,
+        from: glow::Texture,
+        target: glow::Texture,
+        step: (i32, i32),
+        half: usize,
+    ) {
+        let gl = &self.gl;
+        gl.framebuffer_texture_2d(
+            glow::FRAMEBUFFER,
+            glow::COLOR_ATTACHMENT0,
+            glow::TEXTURE_2D,
+            Some(target),
+            0,
+        );
+        gl.bind_texture(glow::TEXTURE_2D, Some(from));
+        let uniform = |name| gl.get_uniform_location(self.program, name);
+        gl.uniform_1_i32(uniform("image").as_ref(), 0);
+        gl.uniform_2_i32(uniform("step").as_ref(), step.0, step.1);
+        gl.uniform_1_i32(uniform("reach").as_ref(), half as i32);
+        gl.draw_arrays(glow::TRIANGLES, 0, 3);
+    }
+
+    // Blurs pixels in three passes along rows and then three along columns,
+    // drawing back and forth between two textures.
+    fn blur(&self, pixels: &mut [[f32; 4]], size: (u32, u32), half: usize) -> Result<(), String> {
+        let gl = &self.gl;
+        let size = (size.0 as i32, size.1 as i32);
+        let mut data: Vec<u8> = pixels
+            .iter()
+            .flat_map(|p| p.map(|c| (c * 255.0).round() as u8))
+            .collect();
+        unsafe {
+            let mut image = self.texture(size, Some(&data))?;
+            let mut other = self.texture(size, None)?;
+            gl.use_program(Some(self.program));
+            gl.bind_vertex_array(Some(self.vertex_array));
+            gl.bind_framebuffer(glow::FRAMEBUFFER, Some(self.framebuffer));
+            gl.viewport(0, 0, size.0, size.1);
+            for step in [(1, 0), (1, 0), (1, 0), (0, 1), (0, 1), (0, 1)] {
+                self.pass(image, other, step, half);
+                std::mem::swap(&mut image, &mut other);
+            }
+            gl.read_pixels(
+                0,
+                0,
+                size.0,
+                size.1,
+                glow::RGBA,
+                glow::UNSIGNED_BYTE,
+                glow::PixelPackData::Slice(&mut data),
+            );
+            gl.delete_texture(image);
+            gl.delete_texture(other);
+            match gl.get_error() {
+                glow::NO_ERROR => (),
+                e => return Err(format!("OpenGL error {:#x}", e)),
+            }
+        }
+        for (p, c) in pixels.iter_mut().zip(data.chunks_exact(4)) {
+            *p = [0, 1, 2, 3].map(|i| c[i] as f32 / 255.0);
+        }
+        Ok(())
+    }
+}
+
+thread_local! {
+    // Opened when first needed, and None once that failed.
+    static GPU: RefCell<Option<Option<Gpu>>> = const { RefCell::new(None) };
+}
+
+/// Blurs pixels of an image of size in three passes of a box blur over half
+/// pixels to either side. Returns false, leaving pixels as they were, when
+/// no GPU can be used.
+pub fn blur(pixels: &mut [[f32; 4]], size: (u32, u32), half: usize) -> bool {
+    GPU.with(|gpu| {
+        let mut gpu = gpu.borrow_mut();
+        let gpu = gpu.get_or_insert_with(|| match Gpu::open() {
+            Ok(gpu) => {
+                debug!("Blurring on the GPU");
+                Some(gpu)
+            }
+            Err(e) => {
+                warn!("Blurring on the CPU, as the GPU cannot be used: {}", e);
+                None
+            }
+        });
+        match gpu.as_ref().map(|gpu| gpu.blur(pixels, size, half)) {
+            Some(Ok(())) => true,
+            Some(Err(e)) => {
+                warn!("Unable to blur on the GPU: {}", e);
+                false
+            }
+            None => false,
+        }
+    })
+}
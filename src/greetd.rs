@@ -0,0 +1,215 @@
+//! Communication with greetd on a thread of its own, so that the UI stays
+//! responsive while PAM does its thing.
+
+use crate::cmd::Cmd;
+use crate::pam;
+use crate::secret::Zeroize;
+
+use std::collections::VecDeque;
+use std::env;
+use std::error::Error;
+use std::io::{self, Write};
+use std::net::Shutdown;
+use std::os::unix::net::UnixStream;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+
+use calloop::channel::Sender as WakeSender;
+use greetd_ipc::{codec::SyncCodec, Request, Response};
+use log::debug;
+
+/// The kind of request a response answers.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Pending {
+    Auth,
+    Start,
+    Cancel,
+}
+
+// Counts the bytes written to it.
+struct Counter(usize);
+
+impl Write for Counter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0 += buf.len();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// Writes req like greetd_ipc does, but serialized into a buffer of exactly
+// the right size that is wiped afterwards, as requests carry passwords.
+fn write_request(stream: &mut UnixStream, req: &Request) -> Result<(), Box<dyn Error>> {
+    let mut counter = Counter(0);
+    serde_json::to_writer(&mut counter, req)?;
+    let mut buf = Vec::with_capacity(4 + counter.0);
+    buf.extend_from_slice(&(counter.0 as u32).to_ne_bytes());
+    let res = serde_json::to_writer(&mut buf, req)
+        .map_err(|e| e.into())
+        .and_then(|()| stream.write_all(&buf).map_err(|e| e.into()));
+    buf.zeroize();
+    res
+}
+
+fn roundtrip(
+    stream: &mut Option<UnixStream>,
+    shared: &Mutex<Option<UnixStream>>,
+    req: &Request,
+) -> Result<Response, Box<dyn Error>> {
+    if stream.is_none() {
+        let connection = UnixStream::connect(env::var("GREETD_SOCK")?)?;
+        *shared.lock().unwrap() = Some(connection.try_clone()?);
+        *stream = Some(connection);
+    }
+    let stream = stream.as_mut().unwrap();
+    write_request(stream, req)?;
+    Ok(Response::read_from(stream)?)
+}
+
+// Performs greetd requests in order. Each response is followed by a draw to
+// wake up the main loop.
+fn worker(
+    requests: Receiver<Request>,
+    responses: Sender<Result<Response, String>>,
+    shared: Arc<Mutex<Option<UnixStream>>>,
+    wake: WakeSender<Cmd>,
+) {
+    let mut stream = None;
+    for mut req in requests.iter() {
+        let res = roundtrip(&mut stream, &shared, &req);
+        if let Request::PostAuthMessageResponse {
+            response: Some(ref mut response),
+        } = req
+        {
+            response.zeroize();
+        }
+        if let Err(ref e) = res {
+            debug!("greetd request failed: {}", e);
+            // Close the connection for good, so that greetd drops the
+            // session along with it.
+            stream = None;
+            *shared.lock().unwrap() = None;
+        }
+        if responses.send(res.map_err(|e| e.to_string())).is_err() {
+            return;
+        }
+        let _ = wake.send(Cmd::Draw);
+    }
+}
+
+pub struct Greetd {
+    requests: Sender<Request>,
+    responses: Receiver<Result<Response, String>>,
+    // The connection of the worker, to abort a request in flight.
+    stream: Arc<Mutex<Option<UnixStream>>>,
+    // Requests awaiting a response, along with whether the response is
+    // stale because the session was cancelled since.
+    in_flight: VecDeque<(Pending, bool)>,
+    wake: WakeSender<Cmd>,
+}
+
+impl Greetd {
+    /// Starts the worker thread, which sends wake a draw after every
+    /// response.
+    pub fn spawn(wake: WakeSender<Cmd>) -> Greetd {
+        let stream = Arc::new(Mutex::new(None));
+        let shared = stream.clone();
+        Greetd::start(stream, wake, move |req_rx, res_tx, wake| {
+            worker(req_rx, res_tx, shared, wake)
+        })
+    }
+
+    /// Starts a worker thread that authenticates against the given PAM
+    /// service itself, in place of greetd, for locking the session.
+    pub fn spawn_pam(service: &'static str, wake: WakeSender<Cmd>) -> Greetd {
+        Greetd::start(
+            Arc::new(Mutex::new(None)),
+            wake,
+            move |req_rx, res_tx, wake| pam::worker(service, req_rx, res_tx, wake),
+        )
+    }
+
+    // Runs work on the worker thread. Failing to start it, or it going
+    // away, is reported to the main loop through wake.
+    fn start<F>(stream: Arc<Mutex<Option<UnixStream>>>, wake: WakeSender<Cmd>, work: F) -> Greetd
+    where
+        F: FnOnce(Receiver<Request>, Sender<Result<Response, String>>, WakeSender<Cmd>)
+            + Send
+            + 'static,
+    {
+        let (req_tx, req_rx) = channel();
+        let (res_tx, res_rx) = channel();
+        let worker_wake = wake.clone();
+        let res = std::thread::Builder::new()
+            .name("greetd".to_string())
+            .spawn(move || work(req_rx, res_tx, worker_wake));
+        if let Err(e) = res {
+            let _ = wake.send(Cmd::GreetdFailed(format!("unable to start worker: {}", e)));
+        }
+        Greetd {
+            requests: req_tx,
+            responses: res_rx,
+            stream,
+            in_flight: VecDeque::new(),
+            wake,
+        }
+    }
+
+    pub fn send(&mut self, req: Request) {
+        let pending = match req {
+            Request::CancelSession => {
+                for entry in self.in_flight.iter_mut() {
+                    entry.1 = true;
+                }
+                Pending::Cancel
+            }
+            Request::StartSession { .. } => Pending::Start,
+            _ => Pending::Auth,
+        };
+        if self.requests.send(req).is_err() {
+            let _ = self
+                .wake
+                .send(Cmd::GreetdFailed("worker is gone".to_string()));
+            return;
+        }
+        self.in_flight.push_back((pending, false));
+    }
+
+    /// Cancels the session. A request in flight is aborted by closing the
+    /// connection, rather than waiting for PAM to get back to it.
+    pub fn cancel(&mut self) {
+        if self.busy() {
+            if let Some(stream) = self.stream.lock().unwrap().take() {
+                let _ = stream.shutdown(Shutdown::Both);
+            }
+        }
+        self.send(Request::CancelSession);
+    }
+
+    /// Returns true while waiting on anything but a cancellation.
+    pub fn busy(&self) -> bool {
+        self.in_flight
+            .iter()
+            .any(|(pending, stale)| *pending != Pending::Cancel && !stale)
+    }
+
+    /// Returns true if no request is awaiting a response.
+    pub fn idle(&self) -> bool {
+        self.in_flight.is_empty()
+    }
+
+    /// Returns the next response that is not stale, along with the kind of
+    /// request it answers.
+    pub fn try_recv(&mut self) -> Option<(Pending, Result<Response, String>)> {
+        while let Ok(res) = self.responses.try_recv() {
+            let (pending, stale) = self.in_flight.pop_front()?;
+            if !stale {
+                return Some((pending, res));
+            }
+        }
+        None
+    }
+}
@@ -0,0 +1,78 @@
+//! Remembers the session each user last started, so that it can be picked
+//! again when they next log in. The cache holds a line per user, with the
+//! username and the command separated by a tab.
+
+use std::collections::BTreeMap;
+use std::fs::{self, read_to_string};
+use std::io;
+use std::path::{Path, PathBuf};
+
+use log::{debug, warn};
+
+pub struct SessionHistory {
+    path: Option<PathBuf>,
+    sessions: BTreeMap<String, String>,
+}
+
+impl SessionHistory {
+    /// Reads the cache at path, or remembers nothing if path is empty.
+    pub fn load(path: &str) -> SessionHistory {
+        if path.is_empty() {
+            return SessionHistory {
+                path: None,
+                sessions: BTreeMap::new(),
+            };
+        }
+        let sessions = match read_to_string(path) {
+            Ok(contents) => contents
+                .lines()
+                .filter_map(|line| line.split_once('\t'))
+                .map(|(user, cmd)| (user.to_string(), cmd.to_string()))
+                .collect(),
+            Err(e) => {
+                debug!("Unable to read session cache {}: {}", path, e);
+                BTreeMap::new()
+            }
+        };
+        SessionHistory {
+            path: Some(PathBuf::from(path)),
+            sessions,
+        }
+    }
+
+    /// Returns the session user last started, if known.
+    pub fn get(&self, user: &str) -> Option<&str> {
+        self.sessions.get(user).map(|cmd| cmd.as_str())
+    }
+
+    /// Records that user started cmd, and writes the cache.
+    pub fn remember(&mut self, user: &str, cmd: &str) {
+        let path = match self.path {
+            Some(ref path) => path.clone(),
+            None => return,
+        };
+        if user.is_empty() || user.contains(['\t', '\n']) || cmd.contains('\n') {
+            return;
+        }
+        if self.get(user) == Some(cmd) {
+            return;
+        }
+        self.sessions.insert(user.to_string(), cmd.to_string());
+        if let Err(e) = self.write(&path) {
+            warn!("Unable to write session cache {}: {}", path.display(), e);
+        }
+    }
+
+    // Writes the cache next to path first, so that a crash cannot leave
+    // it half written.
+    fn write(&self, path: &Path) -> io::Result<()> {
+        let contents: String = self
+            .sessions
+            .iter()
+            .map(|(user, cmd)| format!("{}\t{}\n", user, cmd))
+            .collect();
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, contents)?;
+        fs::rename(&tmp, path)
+    }
+}
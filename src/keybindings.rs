@@ -0,0 +1,261 @@
+//! Maps keys to what the greeter does with them. Built-in bindings can be
+//! replaced or turned off, and more added, in the [keybindings] table of the
+//! config, such as "Ctrl+Alt+BackSpace" = "reset" or "Ctrl+c" = "none".
+
+use std::collections::BTreeMap;
+
+use log::warn;
+use serde::{Deserialize, Serialize};
+use xkbcommon::xkb;
+
+use crate::widget::{Keysym, Modifiers};
+
+/// What a key binding does.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum Action {
+    /// Turns a built-in binding off, leaving the key to be typed.
+    None,
+    /// Quits the greeter, or starts over when locking the session.
+    Exit,
+    /// Clears what was typed and starts over at the first prompt.
+    Reset,
+    /// Answers the prompt, or presses whatever else has the focus.
+    Submit,
+    /// Moves the keyboard focus on to the next widget taking it, such as
+    /// from the login prompt to the session chooser.
+    FocusNext,
+    FocusPrev,
+    Paste,
+    EditCommand,
+    HighContrast,
+    TextLarger,
+    TextSmaller,
+    TextReset,
+    /// Makes the screen brighter, as the backlight allows.
+    BrightnessUp,
+    BrightnessDown,
+    /// Makes the sound louder, or quieter, or mutes it, with volumeCommand.
+    VolumeUp,
+    VolumeDown,
+    Mute,
+}
+
+// The bindings in place unless replaced in the config.
+const DEFAULT_BINDINGS: [(&str, Action); 22] = [
+    ("Ctrl+c", Action::Exit),
+    ("Ctrl+u", Action::Reset),
+    ("Escape", Action::Reset),
+    ("Return", Action::Submit),
+    ("KP_Enter", Action::Submit),
+    ("Tab", Action::FocusNext),
+    ("ISO_Left_Tab", Action::FocusPrev),
+    ("Ctrl+v", Action::Paste),
+    ("Ctrl+Alt+e", Action::EditCommand),
+    ("Ctrl+Alt+h", Action::HighContrast),
+    ("Ctrl+plus", Action::TextLarger),
+    ("Ctrl+equal", Action::TextLarger),
+    ("Ctrl+KP_Add", Action::TextLarger),
+    ("Ctrl+minus", Action::TextSmaller),
+    ("Ctrl+KP_Subtract", Action::TextSmaller),
+    ("Ctrl+0", Action::TextReset),
+    ("Ctrl+KP_0", Action::TextReset),
+    ("XF86MonBrightnessUp", Action::BrightnessUp),
+    ("XF86MonBrightnessDown", Action::BrightnessDown),
+    ("XF86AudioRaiseVolume", Action::VolumeUp),
+    ("XF86AudioLowerVolume", Action::VolumeDown),
+    ("XF86AudioMute", Action::Mute),
+];
+
+/// A key with the modifiers that have to be held for it, written as the
+/// modifiers and the XKB keysym name joined by +, as in Ctrl+Alt+h.
+/// Shift is usually left out, as the keysym already tells apart keys
+/// typed with it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Binding {
+    key: Keysym,
+    ctrl: bool,
+    alt: bool,
+    shift: bool,
+    logo: bool,
+}
+
+impl Binding {
+    pub fn parse(s: &str) -> Result<Binding, String> {
+        let (mods, name) = match s.rsplit_once('+') {
+            // A trailing + is the plus key itself, as in Ctrl++.
+            Some((mods, "")) => (mods.strip_suffix('+').unwrap_or(mods), "plus"),
+            Some((mods, name)) => (mods, name),
+            None => ("", s),
+        };
+        let mut binding = Binding {
+            key: Keysym::NoSymbol,
+            ctrl: false,
+            alt: false,
+            shift: false,
+            logo: false,
+        };
+        for m in mods.split('+').filter(|m| !m.is_empty()) {
+            match m.to_ascii_lowercase().as_str() {
+                "ctrl" | "control" => binding.ctrl = true,
+                "alt" => binding.alt = true,
+                "shift" => binding.shift = true,
+                "super" | "logo" => binding.logo = true,
+                _ => return Err(format!("unknown modifier {}", m)),
+            }
+        }
+        if !name.contains('\0') {
+            binding.key = xkb::keysym_from_name(name, xkb::KEYSYM_NO_FLAGS);
+        }
+        if binding.key == Keysym::NoSymbol {
+            return Err(format!("unknown key {}", name));
+        }
+        Ok(binding)
+    }
+
+    // The number of modifiers to hold, for picking the most specific of
+    // several matching bindings.
+    fn modifiers(&self) -> usize {
+        [self.ctrl, self.alt, self.shift, self.logo]
+            .iter()
+            .filter(|m| **m)
+            .count()
+    }
+
+    // Whether key pressed with modifiers held triggers the binding. Held
+    // modifiers that the binding does not name are ignored.
+    fn matches(&self, key: Keysym, modifiers: &Modifiers) -> bool {
+        self.key == key
+            && (!self.ctrl || modifiers.ctrl)
+            && (!self.alt || modifiers.alt)
+            && (!self.shift || modifiers.shift)
+            && (!self.logo || modifiers.logo)
+    }
+}
+
+pub struct Keybindings {
+    bindings: Vec<(Binding, Action)>,
+}
+
+impl Keybindings {
+    /// Builds the built-in bindings with those of the config on top.
+    /// Bindings that cannot be parsed are skipped with a warning.
+    pub fn new(config: &BTreeMap<String, Action>) -> Keybindings {
+        let mut keybindings = Keybindings {
+            bindings: Vec::new(),
+        };
+        for (key, action) in DEFAULT_BINDINGS.iter() {
+            keybindings.bind(Binding::parse(key).unwrap(), *action);
+        }
+        for (key, action) in config.iter() {
+            match Binding::parse(key) {
+                Ok(binding) => keybindings.bind(binding, *action),
+                Err(e) => warn!("Ignoring key binding {}: {}", key, e),
+            }
+        }
+        keybindings
+    }
+
+    fn bind(&mut self, binding: Binding, action: Action) {
+        self.bindings.retain(|(b, _)| *b != binding);
+        if action != Action::None {
+            self.bindings.push((binding, action));
+        }
+    }
+
+    /// Returns the action of the most specific binding matching key pressed
+    /// with modifiers held, if any.
+    pub fn lookup(&self, key: Keysym, modifiers: &Modifiers) -> Option<Action> {
+        self.bindings
+            .iter()
+            .filter(|(binding, _)| binding.matches(key, modifiers))
+            .max_by_key(|(binding, _)| binding.modifiers())
+            .map(|(_, action)| *action)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn held(ctrl: bool, alt: bool) -> Modifiers {
+        Modifiers {
+            ctrl,
+            alt,
+            ..Default::default()
+        }
+    }
+
+    fn keybindings(config: &[(&str, Action)]) -> Keybindings {
+        let config = config
+            .iter()
+            .map(|(key, action)| (key.to_string(), *action))
+            .collect();
+        Keybindings::new(&config)
+    }
+
+    #[test]
+    fn modifier_combinations_have_to_be_held() {
+        let binding = Binding::parse("Ctrl+Alt+BackSpace").unwrap();
+        assert!(binding.ctrl && binding.alt && !binding.shift && !binding.logo);
+        assert_eq!(binding.key, Keysym::BackSpace);
+        assert_eq!(Binding::parse("control+ALT+BackSpace"), Ok(binding));
+
+        let keybindings = keybindings(&[("Ctrl+Alt+BackSpace", Action::Exit)]);
+        let lookup = |modifiers| keybindings.lookup(Keysym::BackSpace, &modifiers);
+        assert_eq!(lookup(held(true, true)), Some(Action::Exit));
+        assert_eq!(lookup(held(true, false)), None);
+        assert_eq!(lookup(held(false, false)), None);
+    }
+
+    #[test]
+    fn the_most_specific_binding_wins() {
+        let keybindings = keybindings(&[("Ctrl+Alt+c", Action::Reset)]);
+        assert_eq!(
+            keybindings.lookup(Keysym::c, &held(true, true)),
+            Some(Action::Reset)
+        );
+        assert_eq!(
+            keybindings.lookup(Keysym::c, &held(true, false)),
+            Some(Action::Exit)
+        );
+    }
+
+    #[test]
+    fn plus_can_be_bound() {
+        assert_eq!(Binding::parse("Ctrl++").unwrap().key, Keysym::plus);
+    }
+
+    #[test]
+    fn unknown_names_are_rejected() {
+        assert!(Binding::parse("Ctrl+NoSuchKey").is_err());
+        assert!(Binding::parse("Hyper+a").is_err());
+        assert!(Binding::parse("").is_err());
+
+        // The built-in binding stays in place of one that does not parse.
+        let keybindings = keybindings(&[("Ctrl+NoSuchKey", Action::Exit)]);
+        assert_eq!(
+            keybindings.lookup(Keysym::Return, &held(false, false)),
+            Some(Action::Submit)
+        );
+    }
+
+    #[test]
+    fn duplicate_bindings_replace_the_earlier_one() {
+        let keybindings = keybindings(&[("Control+c", Action::Reset), ("Return", Action::None)]);
+        assert_eq!(
+            keybindings.lookup(Keysym::c, &held(true, false)),
+            Some(Action::Reset)
+        );
+        assert_eq!(
+            keybindings.lookup(Keysym::Return, &held(false, false)),
+            None
+        );
+        let count = keybindings
+            .bindings
+            .iter()
+            .filter(|(binding, _)| binding.key == Keysym::c)
+            .count();
+        assert_eq!(count, 1);
+    }
+}
@@ -0,0 +1,68 @@
+//! Changes the brightness and the volume on a thread of its own, as the
+//! commands doing so may take a while, and posts the level each ends up at
+//! back to the main loop to show. Changes are made one after the other, so
+//! that quick key presses all count.
+
+use std::sync::mpsc::{channel, Receiver, Sender};
+
+use calloop::channel::Sender as WakeSender;
+use log::{debug, warn};
+
+use crate::backlight::Backlight;
+use crate::cmd::Cmd;
+use crate::volume;
+
+enum Change {
+    // The backlight to change, and the step to change it by.
+    Brightness(String, i32),
+    // The command changing the volume, and the step to change it by.
+    Volume(String, i32),
+}
+
+fn run(changes: Receiver<Change>, tx: WakeSender<Cmd>) {
+    for change in changes.iter() {
+        let (what, res) = match change {
+            Change::Brightness(name, step) => (
+                "brightness",
+                Backlight::find(&name)
+                    .and_then(|backlight| backlight.step(step))
+                    .map(Some),
+            ),
+            Change::Volume(command, step) => ("volume", volume::change(&command, step)),
+        };
+        match res {
+            Ok(Some(level)) => {
+                debug!("The {} is now {}", what, level);
+                let _ = tx.send(Cmd::Level(level));
+            }
+            Ok(None) => (),
+            Err(e) => warn!("Unable to change the {}: {}", what, e),
+        }
+    }
+}
+
+pub struct Levels {
+    tx: Sender<Change>,
+}
+
+impl Levels {
+    pub fn spawn(tx: WakeSender<Cmd>) -> Levels {
+        let (changes_tx, changes_rx) = channel();
+        let _ = std::thread::Builder::new()
+            .name("levels".to_string())
+            .spawn(move || run(changes_rx, tx));
+        Levels { tx: changes_tx }
+    }
+
+    /// Makes the screen a step brighter, or dimmer if step is negative,
+    /// with the backlight called name, or the first one if name is empty.
+    pub fn brightness(&self, name: &str, step: i32) {
+        let _ = self.tx.send(Change::Brightness(name.to_string(), step));
+    }
+
+    /// Makes the sound a step louder, or quieter if step is negative, or
+    /// turns muting on or off if step is 0, with the given command.
+    pub fn volume(&self, command: &str, step: i32) {
+        let _ = self.tx.send(Change::Volume(command.to_string(), step));
+    }
+}
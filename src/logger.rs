@@ -0,0 +1,82 @@
+//! A logger for the log facade, writing to stderr or to the systemd journal.
+
+use std::io::Write;
+use std::os::unix::net::UnixDatagram;
+
+use log::{Level, LevelFilter, Log, Metadata, Record};
+
+const JOURNAL_SOCKET: &str = "/run/systemd/journal/socket";
+
+struct Logger {
+    level: LevelFilter,
+    journal: Option<UnixDatagram>,
+}
+
+// Appends a field in the journal native protocol. Values containing a
+// newline are length-prefixed instead of newline-terminated.
+fn push_field(buf: &mut Vec<u8>, name: &str, value: &str) {
+    buf.extend_from_slice(name.as_bytes());
+    if value.contains('\n') {
+        buf.push(b'\n');
+        buf.extend_from_slice(&(value.len() as u64).to_le_bytes());
+    } else {
+        buf.push(b'=');
+    }
+    buf.extend_from_slice(value.as_bytes());
+    buf.push(b'\n');
+}
+
+// The syslog priority of a level, as used by the journal.
+fn priority(level: Level) -> &'static str {
+    match level {
+        Level::Error => "3",
+        Level::Warn => "4",
+        Level::Info => "6",
+        Level::Debug | Level::Trace => "7",
+    }
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let message = record.args().to_string();
+        if let Some(ref socket) = self.journal {
+            let mut buf = Vec::new();
+            push_field(&mut buf, "PRIORITY", priority(record.level()));
+            push_field(&mut buf, "SYSLOG_IDENTIFIER", "wlgreet");
+            push_field(&mut buf, "CODE_MODULE", record.target());
+            push_field(&mut buf, "MESSAGE", &message);
+            if socket.send_to(&buf, JOURNAL_SOCKET).is_ok() {
+                return;
+            }
+        }
+        let _ = writeln!(
+            std::io::stderr(),
+            "wlgreet: {}: {}",
+            record.level().as_str().to_lowercase(),
+            message
+        );
+    }
+
+    fn flush(&self) {}
+}
+
+/// Installs the logger, dropping messages less severe than level. With
+/// journal set, messages are sent to the systemd journal, falling back to
+/// stderr if it cannot be reached.
+pub fn init(level: LevelFilter, journal: bool) {
+    let journal = if journal {
+        UnixDatagram::unbound().ok()
+    } else {
+        None
+    };
+    if log::set_boxed_logger(Box::new(Logger { level, journal })).is_ok() {
+        log::set_max_level(level);
+    }
+}
@@ -1,110 +1,533 @@
-use std::io::{Read, Write};
-use std::os::unix::io::AsRawFd;
-use std::sync::mpsc::channel;
+use std::time::{Duration, Instant};
 
-use nix::poll::{poll, PollFd, PollFlags};
-use os_pipe::pipe;
+use calloop::channel::{channel, Channel, Event, Sender};
+use calloop::generic::Generic;
+use calloop::timer::{TimeoutAction, Timer};
+use calloop::{EventLoop, Interest, LoopHandle, Mode, PostAction, RegistrationToken};
+use log::{debug, error, info, warn};
 
+#[cfg(feature = "dbus")]
+mod accounts;
+mod animation;
 mod app;
+mod avatar;
+mod backdrop;
+mod backlight;
 mod buffer;
+#[cfg(feature = "dbus")]
+mod bus;
+mod check;
 mod cmd;
 mod color;
+mod colorfont;
 mod config;
+mod control;
 mod doublemempool;
 mod draw;
+mod error;
+#[cfg(feature = "gpu")]
+mod gpu;
+mod greetd;
+mod history;
+mod keybindings;
+mod levels;
+mod logger;
+mod notify;
+mod pam;
+mod reload;
+mod render;
+mod secret;
+mod slideshow;
+#[cfg(feature = "speech")]
+mod speech;
+mod volume;
 mod widget;
 mod widgets;
 
 use app::App;
-use cmd::Cmd;
+use cmd::{Cmd, SeatId};
+use config::Config;
+use error::Error;
+use keybindings::Action;
+use rusttype::Font as RustFont;
+use widget::{Keysym, Modifiers, TICK};
+use widgets::login::LoginState;
+
+// Only typing and editing repeat. Shortcuts, such as those of the power
+// menu, must not fire again while held down.
+fn repeats(key: Keysym, modifiers: &Modifiers, interpreted: &Option<String>) -> bool {
+    match key {
+        Keysym::BackSpace
+        | Keysym::Delete
+        | Keysym::Left
+        | Keysym::Right
+        | Keysym::Up
+        | Keysym::Down => true,
+        _ => interpreted.is_some() && !modifiers.alt && !modifiers.ctrl && !modifiers.logo,
+    }
+}
+
+fn load_font(config: &Config) -> RustFont<'static> {
+    match config.font {
+        Some(ref path) => match draw::load_font(path) {
+            Ok(font) => font,
+            Err(e) => {
+                warn!("Unable to load font {}: {}", path, e);
+                draw::DEJAVUSANS_MONO.clone()
+            }
+        },
+        None => draw::DEJAVUSANS_MONO.clone(),
+    }
+}
+
+// Usual locations of Noto Color Emoji, used when emojiFont is unset.
+const EMOJI_FONTS: [&str; 4] = [
+    "/usr/share/fonts/noto/NotoColorEmoji.ttf",
+    "/usr/share/fonts/truetype/noto/NotoColorEmoji.ttf",
+    "/usr/share/fonts/google-noto-emoji/NotoColorEmoji.ttf",
+    "/usr/share/fonts/noto-emoji/NotoColorEmoji.ttf",
+];
+
+fn load_emoji_font(config: &Config) -> Option<RustFont<'static>> {
+    match config.emoji_font {
+        Some(ref path) => match draw::load_font(path) {
+            Ok(font) => Some(font),
+            Err(e) => {
+                warn!("Unable to load emoji font {}: {}", path, e);
+                None
+            }
+        },
+        None => EMOJI_FONTS
+            .iter()
+            .find_map(|path| draw::load_font(path).ok()),
+    }
+}
+
+// Returns the key repeat delay and interval, if repeat is enabled.
+fn repeat_timing(config: &Config) -> (Duration, Option<Duration>) {
+    let delay = Duration::from_millis(config.repeat_delay as u64);
+    match config.repeat_rate {
+        0 => (delay, None),
+        rate => (delay, Some(Duration::from_secs(1) / rate)),
+    }
+}
+
+// Steps and bounds of the text scale changed with Ctrl+Plus and Ctrl+Minus.
+const TEXT_SCALE_STEP: f32 = 0.25;
+const TEXT_SCALE_RANGE: (f32, f32) = (0.5, 3.0);
+
+// Stops repeating the key being held down, if any.
+fn stop_repeat(handle: &LoopHandle<App>, repeat: &mut Option<(SeatId, Keysym, RegistrationToken)>) {
+    if let Some((_, _, token)) = repeat.take() {
+        handle.remove(token);
+    }
+}
+
+// Keeps the login prompt to the seat that started typing into it, so that on
+// machines with several seats one cannot type into a login that someone on
+// another seat is in the middle of. The prompt is up for grabs again once it
+// is idle.
+struct InputSeat {
+    seat: Option<SeatId>,
+    idle: bool,
+}
+
+impl InputSeat {
+    // Returns whether input from seat goes to the greeter, taking the prompt
+    // for seat if it is idle.
+    fn accept(&mut self, seat: SeatId) -> bool {
+        if self.idle || self.seat.is_none() {
+            self.seat = Some(seat);
+        }
+        if self.seat == Some(seat) {
+            return true;
+        }
+        debug!(
+            "Ignoring input from seat {} while seat {} is logging in",
+            seat,
+            self.seat.unwrap()
+        );
+        false
+    }
+}
+
+// Shows a fatal error in place of the greeter for a few seconds, so that
+// the user can tell why it went away. This is best effort, as the error may
+// well be that the compositor is gone.
+fn show_fatal(app: &mut App, font: &RustFont<'static>, size: f32, error: &Error) {
+    let widget = widgets::fatal::Fatal::new(font, format!("wlgreet: {}", error), size);
+    let shown = app.set_widget(widget).and_then(|()| {
+        // Give the compositor a chance to configure the surfaces first.
+        app.roundtrip()?;
+        app.redraw(true)?;
+        app.flush_display()
+    });
+    if shown.is_ok() {
+        std::thread::sleep(Duration::from_secs(5));
+    }
+}
 
 fn main() {
-    let config = config::read_config();
+    reload::block_signals();
+    let loaded = config::read_config();
+    let config = loaded.with_high_contrast(loaded.high_contrast);
+    logger::init(config.log_level, config.journal);
+    info!(
+        "wlgreet {} starting with configuration {}",
+        env!("CARGO_PKG_VERSION"),
+        config.path
+    );
+
+    let font = load_font(&config);
+    draw::set_fallback_font(load_emoji_font(&config));
+    draw::set_text_scale(config.text_scale);
+    let size = config.prompt_size;
+
+    if let Some(ref path) = config.render_to {
+        if let Err(e) = render::render_to(path, &config, &font, config.render_size) {
+            error!("Unable to render {}: {}", path, e);
+            std::process::exit(1);
+        }
+        std::process::exit(0);
+    }
 
     let (tx_draw, rx_draw) = channel();
-    let mut app = App::new(tx_draw, config.clone());
-    app.set_widget(widgets::login::Login::new(config.command))
-        .unwrap();
+    let mut app = match App::new(tx_draw.clone(), config.clone()) {
+        Ok(app) => app,
+        Err(e) => {
+            error!("{}", e);
+            std::process::exit(e.exit_code());
+        }
+    };
+    if let Err(e) = run(&mut app, loaded, &font, tx_draw, rx_draw) {
+        error!("{}", e);
+        show_fatal(&mut app, &font, size, &e);
+        std::process::exit(e.exit_code());
+    }
+}
 
-    let (mut rx_pipe, mut tx_pipe) = pipe().unwrap();
+fn run(
+    app: &mut App,
+    loaded: Config,
+    font: &RustFont<'static>,
+    tx_draw: Sender<Cmd>,
+    rx_draw: Channel<Cmd>,
+) -> Result<(), Error> {
+    // The config as loaded, which the high contrast palette applies to.
+    let mut loaded = loaded;
+    let mut high_contrast = loaded.high_contrast;
+    let config = loaded.with_high_contrast(high_contrast);
 
-    let worker_queue = app.cmd_queue();
-    let _ = std::thread::Builder::new()
-        .name("cmd_proxy".to_string())
-        .spawn(move || loop {
-            let cmd = rx_draw.recv().unwrap();
-            worker_queue.lock().unwrap().push_back(cmd);
-            tx_pipe.write_all(&[0x1]).unwrap();
-        });
+    let (mut repeat_delay, mut repeat_interval) = repeat_timing(&config);
+    let mut configured_text_scale = config.text_scale;
+    let mut text_scale = config.text_scale;
 
-    let mut fds = [
-        PollFd::new(app.display().get_connection_fd(), PollFlags::POLLIN),
-        PollFd::new(rx_pipe.as_raw_fd(), PollFlags::POLLIN),
-    ];
+    let mut event_loop: EventLoop<App> = EventLoop::try_new()?;
+    let handle = event_loop.handle();
 
-    app.cmd_queue().lock().unwrap().push_back(Cmd::Draw);
+    reload::watch(&config.path, &handle, tx_draw.clone())?;
+    let _control = control::ControlSocket::spawn(tx_draw.clone());
+    let levels = levels::Levels::spawn(tx_draw.clone());
+    #[cfg(feature = "dbus")]
+    let bus = bus::Bus::spawn(config.lock, tx_draw.clone());
+    #[cfg(feature = "speech")]
+    let speech = speech::Speech::spawn();
+    #[cfg(not(feature = "speech"))]
+    if config.speech {
+        warn!("speech is set, but wlgreet was built without the speech feature");
+    }
+    let tx_reload = tx_draw.clone();
+    let root = widgets::build(&config, font, tx_draw);
+    app.set_widget(root)?;
 
     let q = app.cmd_queue();
+    let queue = q.clone();
+    handle.insert_source(rx_draw, move |event, _, _| {
+        if let Event::Msg(cmd) = event {
+            queue.lock().unwrap().push_back(cmd);
+        }
+    })?;
+
+    handle.insert_source(
+        Generic::new(app.connection_fd(), Interest::READ, Mode::Level),
+        |_, _, app| {
+            if let Err(e) = app.dispatch_events() {
+                app.fail(e);
+            }
+            Ok(PostAction::Continue)
+        },
+    )?;
+
+    if let Some(watchdog) = notify::Watchdog::new() {
+        handle.insert_source(Timer::immediate(), move |_, _, _| {
+            watchdog.ping();
+            TimeoutAction::ToDuration(watchdog.interval())
+        })?;
+    }
+
+    // Lets widgets update themselves as time passes.
+    handle.insert_source(Timer::from_duration(TICK), |_, _, app| {
+        let now = Instant::now();
+        if app.expire_level(now) {
+            app.cmd_queue().lock().unwrap().push_back(Cmd::ForceDraw);
+        } else if app.get_widget().tick(now) {
+            app.cmd_queue().lock().unwrap().push_back(Cmd::Draw);
+        }
+        TimeoutAction::ToDuration(TICK)
+    })?;
+
+    q.lock().unwrap().push_back(Cmd::Draw);
+
+    // The seat and key being held down, and the timer repeating it
+    let mut repeat: Option<(SeatId, Keysym, RegistrationToken)> = None;
+
+    let mut input_seat = InputSeat {
+        seat: None,
+        idle: true,
+    };
+
+    // When the timer drawing the next frame of an animation fires
+    let mut wake: Option<(Instant, RegistrationToken)> = None;
+
+    let mut ready = false;
+
     loop {
+        app.take_error()?;
         let cmd = q.lock().unwrap().pop_front();
         match cmd {
             Some(cmd) => match cmd {
                 Cmd::Draw => {
-                    app.redraw(false).expect("Failed to draw");
-                    app.flush_display();
+                    app.redraw(false)?;
+                    app.flush_display()?;
                 }
                 Cmd::ForceDraw => {
-                    app.redraw(true).expect("Failed to draw");
-                    app.flush_display();
+                    app.redraw(true)?;
+                    app.flush_display()?;
+                }
+                Cmd::Wake(at) => {
+                    // Only the earliest wake up counts, as everything is
+                    // drawn then.
+                    let now = Instant::now();
+                    match wake.filter(|(next, _)| *next > now) {
+                        Some((next, _)) if next <= at => (),
+                        pending => {
+                            if let Some((_, token)) = pending {
+                                handle.remove(token);
+                            }
+                            let token =
+                                handle.insert_source(Timer::from_deadline(at), |_, _, app| {
+                                    app.cmd_queue().lock().unwrap().push_back(Cmd::Draw);
+                                    TimeoutAction::Drop
+                                })?;
+                            wake = Some((at, token));
+                        }
+                    }
                 }
-                Cmd::MouseClick { btn, pos } => {
+                Cmd::Keyboard { seat, .. }
+                | Cmd::Action(seat, _)
+                | Cmd::Paste(seat, _)
+                | Cmd::MouseClick { seat, .. }
+                | Cmd::MouseScroll { seat, .. }
+                | Cmd::MouseMove { seat, .. }
+                | Cmd::MouseLeave { seat }
+                | Cmd::TouchDown { seat, .. }
+                | Cmd::TouchMotion { seat, .. }
+                | Cmd::TouchUp { seat, .. }
+                | Cmd::TouchCancel { seat }
+                    if !input_seat.accept(seat) => {}
+                Cmd::MouseClick { btn, pos, .. } => {
                     app.get_widget().mouse_click(btn, pos);
                     q.lock().unwrap().push_back(Cmd::Draw);
                 }
-                Cmd::MouseScroll { scroll, pos } => {
+                Cmd::MouseScroll { scroll, pos, .. } => {
                     app.get_widget().mouse_scroll(scroll, pos);
                     q.lock().unwrap().push_back(Cmd::Draw);
                 }
+                Cmd::MouseMove { pos, .. } => {
+                    app.get_widget().mouse_move(pos);
+                    q.lock().unwrap().push_back(Cmd::Draw);
+                }
+                Cmd::MouseLeave { .. } => {
+                    app.get_widget().mouse_leave();
+                    q.lock().unwrap().push_back(Cmd::Draw);
+                }
+                Cmd::TouchDown { id, pos, .. } => {
+                    app.get_widget().touch_down(id, pos);
+                    q.lock().unwrap().push_back(Cmd::Draw);
+                }
+                Cmd::TouchMotion { id, pos, .. } => {
+                    app.get_widget().touch_motion(id, pos);
+                    q.lock().unwrap().push_back(Cmd::Draw);
+                }
+                Cmd::TouchUp { id, .. } => {
+                    app.get_widget().touch_up(id);
+                    q.lock().unwrap().push_back(Cmd::Draw);
+                }
+                Cmd::TouchCancel { .. } => {
+                    app.get_widget().touch_cancel();
+                    q.lock().unwrap().push_back(Cmd::Draw);
+                }
                 Cmd::Keyboard {
+                    seat,
                     key,
                     key_state,
                     modifiers_state,
                     interpreted,
                 } => {
+                    stop_repeat(&handle, &mut repeat);
+                    app.set_seat(seat);
+                    if let Some(interval) =
+                        repeat_interval.filter(|_| repeats(key, &modifiers_state, &interpreted))
+                    {
+                        let interpreted = interpreted.clone();
+                        let timer = Timer::from_duration(repeat_delay);
+                        let token = handle.insert_source(timer, move |_, _, app| {
+                            app.get_widget().keyboard_input(
+                                key,
+                                modifiers_state,
+                                key_state,
+                                interpreted.clone(),
+                            );
+                            app.cmd_queue().lock().unwrap().push_back(Cmd::Draw);
+                            TimeoutAction::ToDuration(interval)
+                        })?;
+                        repeat = Some((seat, key, token));
+                    }
                     app.get_widget()
                         .keyboard_input(key, modifiers_state, key_state, interpreted);
                     q.lock().unwrap().push_back(Cmd::Draw);
                 }
-                Cmd::Exit => {
-                    return;
+                Cmd::KeyRelease { seat, key } => {
+                    if matches!(repeat, Some((s, held, _)) if s == seat && held == key) {
+                        stop_repeat(&handle, &mut repeat);
+                    }
                 }
-            },
-            None => {
-                app.flush_display();
-
-                poll(&mut fds, -1).unwrap();
-
-                if fds[0].revents().unwrap().contains(PollFlags::POLLIN) {
-                    if let Some(guard) = app.event_queue().prepare_read() {
-                        if let Err(e) = guard.read_events() {
-                            if e.kind() != ::std::io::ErrorKind::WouldBlock {
-                                eprintln!(
-                                    "Error while trying to read from the wayland socket: {:?}",
-                                    e
-                                );
-                            }
+                Cmd::KeyboardLeave { seat } => {
+                    if matches!(repeat, Some((s, _, _)) if s == seat) {
+                        stop_repeat(&handle, &mut repeat);
+                    }
+                }
+                Cmd::Modifiers {
+                    seat,
+                    modifiers_state,
+                } => {
+                    app.set_modifiers(seat, modifiers_state);
+                    q.lock().unwrap().push_back(Cmd::Draw);
+                }
+                Cmd::Action(_, action @ (Action::FocusNext | Action::FocusPrev)) => {
+                    stop_repeat(&handle, &mut repeat);
+                    let widget = app.get_widget();
+                    let forward = action == Action::FocusNext;
+                    let mut step = || {
+                        if forward {
+                            widget.focus_next()
+                        } else {
+                            widget.focus_prev()
                         }
+                    };
+                    // Moving on from the last widget wraps around to the first.
+                    if !step() {
+                        step();
                     }
-
-                    app.event_queue()
-                        .dispatch_pending(&mut (), |_, _, _| {})
-                        .expect("Failed to dispatch all messages.");
+                    q.lock().unwrap().push_back(Cmd::Draw);
                 }
-
-                if fds[1].revents().unwrap().contains(PollFlags::POLLIN) {
-                    let mut v = [0x00];
-                    rx_pipe.read_exact(&mut v).unwrap();
+                Cmd::Action(_, action) => {
+                    stop_repeat(&handle, &mut repeat);
+                    app.get_widget().action(action);
+                    q.lock().unwrap().push_back(Cmd::Draw);
+                }
+                Cmd::Paste(_, text) => {
+                    app.get_widget().paste(&text);
+                    q.lock().unwrap().push_back(Cmd::Draw);
+                }
+                Cmd::SwitchLayout => {
+                    stop_repeat(&handle, &mut repeat);
+                    app.next_layout();
+                    q.lock().unwrap().push_back(Cmd::Draw);
+                }
+                Cmd::ScaleText(step) => {
+                    text_scale = match step {
+                        0 => configured_text_scale,
+                        step => (text_scale + step as f32 * TEXT_SCALE_STEP)
+                            .clamp(TEXT_SCALE_RANGE.0, TEXT_SCALE_RANGE.1),
+                    };
+                    debug!("Text scale is now {}", text_scale);
+                    draw::set_text_scale(text_scale);
+                    q.lock().unwrap().push_back(Cmd::ForceDraw);
+                }
+                Cmd::ToggleHighContrast => {
+                    high_contrast = !high_contrast;
+                    debug!("High contrast is now {}", high_contrast);
+                    let config = loaded.with_high_contrast(high_contrast);
+                    configured_text_scale = config.text_scale;
+                    text_scale = config.text_scale;
+                    draw::set_text_scale(text_scale);
+                    app.set_config(config);
+                    q.lock().unwrap().push_back(Cmd::ForceDraw);
+                }
+                Cmd::Brightness(step) => levels.brightness(&loaded.backlight, step),
+                Cmd::Volume(step) => levels.volume(&loaded.volume_command, step),
+                Cmd::Level(level) => {
+                    app.show_level(level);
+                    q.lock().unwrap().push_back(Cmd::Draw);
+                }
+                Cmd::Reload => match config::load_config() {
+                    Ok(reloaded) => {
+                        high_contrast = reloaded.high_contrast;
+                        let config = reloaded.with_high_contrast(high_contrast);
+                        loaded = reloaded;
+                        let font = load_font(&config);
+                        draw::set_fallback_font(load_emoji_font(&config));
+                        configured_text_scale = config.text_scale;
+                        text_scale = config.text_scale;
+                        draw::set_text_scale(text_scale);
+                        let timing = repeat_timing(&config);
+                        repeat_delay = timing.0;
+                        repeat_interval = timing.1;
+                        stop_repeat(&handle, &mut repeat);
+                        let root = widgets::build(&config, &font, tx_reload.clone());
+                        app.set_config(config);
+                        app.set_widget(root)?;
+                        q.lock().unwrap().push_back(Cmd::ForceDraw);
+                    }
+                    Err(e) => error!("Unable to reload configuration: {}", e),
+                },
+                Cmd::State(state) => {
+                    debug!("Login state is now {}", state.as_str());
+                    input_seat.idle = state == LoginState::Idle;
+                    #[cfg(feature = "dbus")]
+                    bus.set_state(state);
+                }
+                Cmd::Speak(text) => {
+                    debug!("Speaking {:?}", text);
+                    #[cfg(feature = "speech")]
+                    speech.say(&text);
+                }
+                Cmd::Configure(size) => {
+                    app.get_widget().on_configure(size);
+                    q.lock().unwrap().push_back(Cmd::ForceDraw);
+                }
+                Cmd::Hide => app.set_visible(false),
+                Cmd::Show => app.set_visible(true),
+                Cmd::Message(text) => {
+                    app.get_widget().message(&text);
+                    q.lock().unwrap().push_back(Cmd::Draw);
+                }
+                Cmd::Exit => {
+                    return app.close();
+                }
+                Cmd::Unlock => {
+                    return app.unlock();
+                }
+                Cmd::GreetdFailed(e) => return Err(Error::Greetd(e)),
+            },
+            None => {
+                app.flush_display()?;
+                if !ready && app.committed() {
+                    notify::ready();
+                    ready = true;
                 }
+                event_loop.dispatch(None, app)?;
             }
         }
     }
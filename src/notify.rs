@@ -0,0 +1,47 @@
+//! Readiness and watchdog notifications for supervision by systemd. These
+//! do nothing unless the greeter is run as a service that asks for them.
+
+use std::time::Duration;
+
+use log::{debug, warn};
+use sd_notify::NotifyState;
+
+/// Tells the service manager that the greeter is up.
+pub fn ready() {
+    debug!("Notifying readiness");
+    if let Err(e) = sd_notify::notify(false, &[NotifyState::Ready]) {
+        warn!("Unable to notify readiness: {}", e);
+    }
+}
+
+/// Keeps the service manager watchdog from restarting the greeter while the
+/// main loop is running.
+pub struct Watchdog {
+    interval: Duration,
+}
+
+impl Watchdog {
+    /// Returns a watchdog if the service manager expects keep-alive pings.
+    pub fn new() -> Option<Watchdog> {
+        let mut usec = 0;
+        if !sd_notify::watchdog_enabled(false, &mut usec) || usec == 0 {
+            return None;
+        }
+        // Ping twice per timeout, so a slow iteration does not trip it.
+        let interval = Duration::from_micros(usec) / 2;
+        debug!("Watchdog enabled, pinging every {:?}", interval);
+        Some(Watchdog { interval })
+    }
+
+    /// Returns the time between pings.
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    /// Pings the service manager.
+    pub fn ping(&self) {
+        if let Err(e) = sd_notify::notify(false, &[NotifyState::Watchdog]) {
+            warn!("Unable to ping watchdog: {}", e);
+        }
+    }
+}
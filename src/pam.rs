@@ -0,0 +1,293 @@
+//! Minimal libpam bindings, used to authenticate the current user when
+//! running as a screen locker without greetd. PAM runs on a thread of its
+//! own that answers the same requests greetd does, so that locking asks the
+//! same questions the same way as logging in.
+
+use crate::cmd::Cmd;
+use crate::secret::{SecretBytes, Zeroize};
+
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_int, c_void};
+use std::ptr;
+use std::sync::mpsc::{Receiver, Sender};
+
+use calloop::channel::Sender as WakeSender;
+use greetd_ipc::{AuthMessageType, ErrorType, Request, Response};
+use nix::libc::{calloc, free, strdup, strlen};
+
+const PAM_SUCCESS: c_int = 0;
+const PAM_BUF_ERR: c_int = 5;
+const PAM_CONV_ERR: c_int = 19;
+
+const PAM_PROMPT_ECHO_OFF: c_int = 1;
+const PAM_PROMPT_ECHO_ON: c_int = 2;
+const PAM_ERROR_MSG: c_int = 3;
+const PAM_TEXT_INFO: c_int = 4;
+
+#[repr(C)]
+struct PamMessage {
+    msg_style: c_int,
+    msg: *const c_char,
+}
+
+#[repr(C)]
+struct PamResponse {
+    resp: *mut c_char,
+    resp_retcode: c_int,
+}
+
+#[repr(C)]
+struct PamConv {
+    conv: extern "C" fn(
+        num_msg: c_int,
+        msg: *mut *const PamMessage,
+        resp: *mut *mut PamResponse,
+        appdata_ptr: *mut c_void,
+    ) -> c_int,
+    appdata_ptr: *mut c_void,
+}
+
+#[repr(C)]
+struct PamHandle {
+    _private: [u8; 0],
+}
+
+#[link(name = "pam")]
+extern "C" {
+    fn pam_start(
+        service_name: *const c_char,
+        user: *const c_char,
+        pam_conversation: *const PamConv,
+        pamh: *mut *mut PamHandle,
+    ) -> c_int;
+    fn pam_authenticate(pamh: *mut PamHandle, flags: c_int) -> c_int;
+    fn pam_end(pamh: *mut PamHandle, pam_status: c_int) -> c_int;
+    fn pam_strerror(pamh: *mut PamHandle, errnum: c_int) -> *const c_char;
+}
+
+// What a conversation needs to pass messages on and wait for answers,
+// reached through appdata_ptr.
+struct Conversation<'a> {
+    requests: &'a Receiver<Request>,
+    responses: &'a Sender<Result<Response, String>>,
+    wake: &'a WakeSender<Cmd>,
+    // Whether the session was cancelled while waiting for an answer, which
+    // has been answered already.
+    cancelled: bool,
+}
+
+impl Conversation<'_> {
+    fn respond(&self, res: Response) {
+        let _ = self.responses.send(Ok(res));
+        let _ = self.wake.send(Cmd::Draw);
+    }
+
+    // Passes a message on, and waits for the answer to it. Returns None if
+    // the session is cancelled instead.
+    fn ask(
+        &mut self,
+        auth_message_type: AuthMessageType,
+        auth_message: String,
+    ) -> Option<Option<String>> {
+        self.respond(Response::AuthMessage {
+            auth_message_type,
+            auth_message,
+        });
+        loop {
+            match self.requests.recv() {
+                Ok(Request::PostAuthMessageResponse { response }) => return Some(response),
+                Ok(Request::CancelSession) => {
+                    self.cancelled = true;
+                    self.respond(Response::Success);
+                    return None;
+                }
+                Ok(_) => self.respond(Response::Error {
+                    error_type: ErrorType::Error,
+                    description: "authentication in progress".to_string(),
+                }),
+                Err(_) => {
+                    self.cancelled = true;
+                    return None;
+                }
+            }
+        }
+    }
+}
+
+// Copies s into a NUL-terminated buffer that is wiped afterwards, or returns
+// None if s holds a NUL itself.
+fn secret_cstr(s: &str) -> Option<SecretBytes> {
+    if s.contains('\0') {
+        return None;
+    }
+    let mut bytes = SecretBytes::new();
+    bytes.extend_from_slice(s.as_bytes());
+    bytes.extend_from_slice(b"\0");
+    Some(bytes)
+}
+
+// Wipes and frees the first count responses, and the array holding them.
+unsafe fn free_responses(responses: *mut PamResponse, count: isize) {
+    for i in 0..count {
+        let r = (*responses.offset(i)).resp;
+        if !r.is_null() {
+            std::slice::from_raw_parts_mut(r as *mut u8, strlen(r)).zeroize();
+            free(r as *mut c_void);
+        }
+    }
+    free(responses as *mut c_void);
+}
+
+// Passes every message on to the Conversation behind appdata_ptr, and
+// answers prompts with what comes back.
+extern "C" fn conversation(
+    num_msg: c_int,
+    msg: *mut *const PamMessage,
+    resp: *mut *mut PamResponse,
+    appdata_ptr: *mut c_void,
+) -> c_int {
+    if num_msg <= 0 || appdata_ptr.is_null() {
+        return PAM_CONV_ERR;
+    }
+    unsafe {
+        let conv = &mut *(appdata_ptr as *mut Conversation);
+        // Later modules may still ask after the session was cancelled.
+        if conv.cancelled {
+            return PAM_CONV_ERR;
+        }
+        let responses =
+            calloc(num_msg as usize, std::mem::size_of::<PamResponse>()) as *mut PamResponse;
+        if responses.is_null() {
+            return PAM_BUF_ERR;
+        }
+        for i in 0..num_msg as isize {
+            let m = &**msg.offset(i);
+            let kind = match m.msg_style {
+                PAM_PROMPT_ECHO_OFF => AuthMessageType::Secret,
+                PAM_PROMPT_ECHO_ON => AuthMessageType::Visible,
+                PAM_ERROR_MSG => AuthMessageType::Error,
+                PAM_TEXT_INFO => AuthMessageType::Info,
+                _ => {
+                    free_responses(responses, i);
+                    return PAM_CONV_ERR;
+                }
+            };
+            let prompt = matches!(kind, AuthMessageType::Secret | AuthMessageType::Visible);
+            let text = if m.msg.is_null() {
+                String::new()
+            } else {
+                CStr::from_ptr(m.msg).to_string_lossy().into_owned()
+            };
+            let mut answer = match conv.ask(kind, text) {
+                Some(answer) => answer.unwrap_or_default(),
+                None => {
+                    free_responses(responses, i);
+                    return PAM_CONV_ERR;
+                }
+            };
+            if prompt {
+                let r =
+                    secret_cstr(&answer).map(|a| strdup(a.as_slice().as_ptr() as *const c_char));
+                answer.zeroize();
+                match r {
+                    Some(r) if !r.is_null() => (*responses.offset(i)).resp = r,
+                    _ => {
+                        free_responses(responses, i);
+                        return PAM_CONV_ERR;
+                    }
+                }
+            } else {
+                answer.zeroize();
+            }
+        }
+        *resp = responses;
+    }
+    PAM_SUCCESS
+}
+
+fn error(error_type: ErrorType, description: String) -> Response {
+    Response::Error {
+        error_type,
+        description,
+    }
+}
+
+// Authenticates user against the given PAM service, passing the
+// conversation on to conv.
+fn authenticate(service: &str, user: &str, conv: &mut Conversation) -> Response {
+    let (service, user) = match (CString::new(service), CString::new(user)) {
+        (Ok(service), Ok(user)) => (service, user),
+        _ => return error(ErrorType::Error, "invalid service or user".to_string()),
+    };
+    let pam_conv = PamConv {
+        conv: conversation,
+        appdata_ptr: conv as *mut Conversation as *mut c_void,
+    };
+    let mut handle: *mut PamHandle = ptr::null_mut();
+
+    unsafe {
+        let res = pam_start(service.as_ptr(), user.as_ptr(), &pam_conv, &mut handle);
+        if res != PAM_SUCCESS {
+            return error(ErrorType::Error, format!("pam_start failed: {}", res));
+        }
+        let res = pam_authenticate(handle, 0);
+        let ret = if res == PAM_SUCCESS {
+            Response::Success
+        } else {
+            error(
+                ErrorType::AuthError,
+                CStr::from_ptr(pam_strerror(handle, res))
+                    .to_string_lossy()
+                    .into_owned(),
+            )
+        };
+        pam_end(handle, res);
+        ret
+    }
+}
+
+/// Answers requests in order the way greetd would, authenticating against
+/// the given PAM service, but never starting a session: a successful
+/// authentication is for the caller to act on. Each response is followed by
+/// a draw to wake up the main loop.
+pub fn worker(
+    service: &str,
+    requests: Receiver<Request>,
+    responses: Sender<Result<Response, String>>,
+    wake: WakeSender<Cmd>,
+) {
+    for req in requests.iter() {
+        let res = match req {
+            Request::CreateSession { ref username } if username.is_empty() => {
+                error(ErrorType::Error, "no user to authenticate".to_string())
+            }
+            Request::CreateSession { username } => {
+                let mut conv = Conversation {
+                    requests: &requests,
+                    responses: &responses,
+                    wake: &wake,
+                    cancelled: false,
+                };
+                let res = authenticate(service, &username, &mut conv);
+                if conv.cancelled {
+                    continue;
+                }
+                res
+            }
+            Request::PostAuthMessageResponse { response } => {
+                if let Some(mut response) = response {
+                    response.zeroize();
+                }
+                error(
+                    ErrorType::Error,
+                    "no authentication in progress".to_string(),
+                )
+            }
+            Request::StartSession { .. } | Request::CancelSession => Response::Success,
+        };
+        if responses.send(Ok(res)).is_err() {
+            return;
+        }
+        let _ = wake.send(Cmd::Draw);
+    }
+}
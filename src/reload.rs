@@ -0,0 +1,106 @@
+//! Watches for signals and changes to the config file from the event loop,
+//! passing them on as commands. SIGHUP and changes to the file reload the configuration,
+//! SIGTERM and SIGINT exit, and SIGUSR1 forces a redraw.
+
+use std::convert::TryFrom;
+use std::ffi::OsStr;
+use std::path::Path;
+
+use calloop::channel::Sender;
+use calloop::generic::{FdWrapper, Generic};
+use calloop::{Interest, LoopHandle, Mode, PostAction};
+use log::{debug, warn};
+use nix::sys::inotify::{AddWatchFlags, InitFlags, Inotify};
+use nix::sys::signal::{SigSet, Signal};
+use nix::sys::signalfd::SignalFd;
+
+use crate::cmd::Cmd;
+use crate::error::Error;
+
+fn signal_mask() -> SigSet {
+    let mut mask = SigSet::empty();
+    mask.add(Signal::SIGHUP);
+    mask.add(Signal::SIGTERM);
+    mask.add(Signal::SIGINT);
+    mask.add(Signal::SIGUSR1);
+    mask
+}
+
+/// Blocks the handled signals so that they can be read from a signalfd
+/// instead. Must be called before any other thread is started, as threads
+/// inherit the mask.
+pub fn block_signals() {
+    signal_mask()
+        .thread_block()
+        .expect("unable to block signals");
+}
+
+// Returns the command for a signal read from the signalfd.
+fn signal_cmd(signo: u32) -> Option<Cmd> {
+    match Signal::try_from(signo as i32).ok()? {
+        Signal::SIGHUP => Some(Cmd::Reload),
+        Signal::SIGTERM | Signal::SIGINT => Some(Cmd::Exit),
+        Signal::SIGUSR1 => Some(Cmd::ForceDraw),
+        _ => None,
+    }
+}
+
+// Watches the directory of the config file, as editors tend to replace the
+// file rather than write to it.
+fn watch_dir(path: &str) -> Option<Inotify> {
+    let dir = match Path::new(path).parent() {
+        Some(dir) if dir.as_os_str().is_empty() => Path::new("."),
+        Some(dir) => dir,
+        None => return None,
+    };
+    let inotify = Inotify::init(InitFlags::IN_CLOEXEC).ok()?;
+    match inotify.add_watch(
+        dir,
+        AddWatchFlags::IN_CLOSE_WRITE | AddWatchFlags::IN_MOVED_TO | AddWatchFlags::IN_CREATE,
+    ) {
+        Ok(_) => Some(inotify),
+        Err(e) => {
+            warn!("Unable to watch {}: {}", dir.display(), e);
+            None
+        }
+    }
+}
+
+/// Adds event sources sending Cmd::Reload when the config file at path
+/// changes, and the command for any handled signal.
+pub fn watch<D>(path: &str, handle: &LoopHandle<'_, D>, tx: Sender<Cmd>) -> Result<(), Error> {
+    // SAFETY: The signalfd stays open for as long as the source.
+    let signals = unsafe { FdWrapper::new(SignalFd::new(&signal_mask())?) };
+    let signal_tx = tx.clone();
+    handle.insert_source(
+        Generic::new(signals, Interest::READ, Mode::Level),
+        move |_, signals, _| {
+            // SAFETY: The signalfd is read from, not replaced.
+            if let Ok(Some(info)) = unsafe { signals.get_mut() }.read_signal() {
+                if let Some(cmd) = signal_cmd(info.ssi_signo) {
+                    debug!("Received signal {}", info.ssi_signo);
+                    let _ = signal_tx.send(cmd);
+                }
+            }
+            Ok(PostAction::Continue)
+        },
+    )?;
+
+    if let Some(inotify) = watch_dir(path) {
+        let name = Path::new(path).file_name().map(OsStr::to_os_string);
+        // SAFETY: The inotify descriptor is never closed.
+        let inotify = unsafe { FdWrapper::new(inotify) };
+        handle.insert_source(
+            Generic::new(inotify, Interest::READ, Mode::Level),
+            move |_, inotify, _| {
+                if let Ok(events) = inotify.read_events() {
+                    if events.iter().any(|e| e.name == name) {
+                        let _ = tx.send(Cmd::Reload);
+                    }
+                }
+                Ok(PostAction::Continue)
+            },
+        )?;
+    }
+    Ok(())
+}
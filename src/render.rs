@@ -0,0 +1,158 @@
+//! Renders a single frame of the greeter to a PNG file without a
+//! compositor, for previewing themes.
+
+use calloop::channel::channel;
+use std::fs::File;
+use std::io::{self, BufWriter};
+use std::time::Instant;
+
+use chrono::Local;
+use memmap2::MmapMut;
+use rusttype::Font as RustFont;
+
+use crate::backdrop::Backdrop;
+use crate::buffer::Buffer;
+use crate::config::{Anchor, Config};
+use crate::widget::{scaled, DrawContext, Modifiers, DEFAULT_WIDTH};
+use crate::widgets;
+
+// Returns the offset of a surface of the given length along one axis of the
+// output, placed like a layer surface anchored to the start and/or end edge.
+// Surfaces larger than the output get a negative offset, and are clipped.
+fn place(anchored: (bool, bool), margins: (u32, u32), length: u32, surface: u32) -> i64 {
+    let (length, surface) = (length as i64, surface as i64);
+    match anchored {
+        (true, false) => margins.0 as i64,
+        (false, true) => length - surface - margins.1 as i64,
+        _ => (length - surface) / 2,
+    }
+}
+
+// Converts a native endian ARGB pixel to RGBA bytes.
+fn rgba(argb: u32) -> [u8; 4] {
+    [
+        (argb >> 16) as u8,
+        (argb >> 8) as u8,
+        argb as u8,
+        (argb >> 24) as u8,
+    ]
+}
+
+/// Draws the greeter as it would appear on an output of size pixels, and
+/// writes the image to the PNG file at path.
+pub fn render_to(
+    path: &str,
+    config: &Config,
+    font: &RustFont<'static>,
+    size: (u32, u32),
+) -> io::Result<()> {
+    // Widgets may wake up the main loop, which is not there to listen.
+    let (tx, _rx) = channel();
+    let mut widget = widgets::build(config, font, tx);
+
+    let settings = config.output_settings(None, 1.0);
+    let scale = settings.scale;
+    let anchored = |edge| config.anchor.contains(&edge);
+    let horizontal = (anchored(Anchor::Left), anchored(Anchor::Right));
+    let vertical = (anchored(Anchor::Top), anchored(Anchor::Bottom));
+    let margin = |m: i32| scaled(m.max(0) as u32, scale);
+    let margins = (
+        (margin(config.margin.left), margin(config.margin.right)),
+        (margin(config.margin.top), margin(config.margin.bottom)),
+    );
+
+    let available = (
+        if horizontal == (true, true) {
+            size.0.saturating_sub(margins.0 .0 + margins.0 .1)
+        } else if (size.0 as f32 / scale) < DEFAULT_WIDTH as f32 {
+            size.0
+        } else {
+            0
+        },
+        if vertical == (true, true) {
+            size.1.saturating_sub(margins.1 .0 + margins.1 .1)
+        } else {
+            0
+        },
+    );
+    widget.resize(available, scale);
+    let widget_size = widget.size();
+    let surface = (
+        widget_size.0.max(available.0),
+        widget_size.1.max(available.1),
+    );
+    let origin = (
+        place(horizontal, margins.0, size.0, surface.0),
+        place(vertical, margins.1, size.1, surface.1),
+    );
+
+    let mut mmap = MmapMut::map_anon((4 * surface.0 * surface.1) as usize)?;
+    {
+        let mut buf = Buffer::new(&mut mmap, surface);
+        buf.memset(&settings.background);
+        widget.draw(
+            &mut DrawContext {
+                buf: &mut buf,
+                bg: &settings.background,
+                time: &Local::now(),
+                force: true,
+                config,
+                modifiers: &Modifiers::default(),
+                layout: config.keyboard_layouts.first().map(|l| l.as_str()),
+                output: None,
+                focused: true,
+            },
+            (
+                (surface.0 - widget_size.0) / 2,
+                (surface.1 - widget_size.1) / 2,
+            ),
+        )?;
+    }
+
+    if let Some(mut backdrop) = Backdrop::new(config) {
+        let widgets = (
+            (surface.0 - widget_size.0) / 2,
+            (surface.1 - widget_size.1) / 2,
+            widget_size.0,
+            widget_size.1,
+        );
+        backdrop.draw_behind(
+            &mut Buffer::new(&mut mmap, surface),
+            &settings.background,
+            widgets,
+            None,
+            scale,
+            Instant::now(),
+        )?;
+        backdrop.draw(
+            &mut Buffer::new(&mut mmap, surface),
+            &settings.background,
+            Some(&config.headline),
+            widgets,
+            scale,
+            Instant::now(),
+        )?;
+    }
+
+    // Place the surface on the background of the output.
+    let background = rgba(settings.background.as_argb8888());
+    let mut pixels = Vec::with_capacity((4 * size.0 * size.1) as usize);
+    for y in 0..size.1 as i64 {
+        for x in 0..size.0 as i64 {
+            let (sx, sy) = (x - origin.0, y - origin.1);
+            if sx < 0 || sy < 0 || sx >= surface.0 as i64 || sy >= surface.1 as i64 {
+                pixels.extend_from_slice(&background);
+                continue;
+            }
+            let offset = 4 * (sx + sy * surface.0 as i64) as usize;
+            let p = &mmap[offset..offset + 4];
+            pixels.extend_from_slice(&rgba(u32::from_ne_bytes([p[0], p[1], p[2], p[3]])));
+        }
+    }
+    let mut encoder = png::Encoder::new(BufWriter::new(File::create(path)?), size.0, size.1);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(&pixels)?;
+    Ok(())
+}
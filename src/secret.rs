@@ -0,0 +1,286 @@
+//! Storage for passwords and other secrets. Secrets are wiped with volatile
+//! writes that the compiler cannot optimize away, both when done with and
+//! when growing would otherwise leave a copy behind in freed memory, and
+//! are locked into memory where allowed so that they never reach swap.
+
+use std::ops::{Deref, Range};
+use std::os::raw::c_void;
+use std::ptr;
+use std::sync::atomic::{compiler_fence, AtomicBool, Ordering};
+
+use log::debug;
+use nix::sys::mman::mlock;
+
+// Room for most passwords without growing.
+const INITIAL_CAPACITY: usize = 256;
+
+/// Wipes the whole allocation of a buffer, including what lies beyond its
+/// length, and empties it.
+pub trait Zeroize {
+    fn zeroize(&mut self);
+}
+
+impl Zeroize for [u8] {
+    fn zeroize(&mut self) {
+        for b in self.iter_mut() {
+            unsafe { ptr::write_volatile(b, 0) };
+        }
+        compiler_fence(Ordering::SeqCst);
+    }
+}
+
+// Wipes the allocation of buf beyond its length.
+fn wipe_spare(buf: &mut Vec<u8>) {
+    for b in buf.spare_capacity_mut() {
+        unsafe { ptr::write_volatile(b.as_mut_ptr(), 0) };
+    }
+    compiler_fence(Ordering::SeqCst);
+}
+
+impl Zeroize for Vec<u8> {
+    fn zeroize(&mut self) {
+        self.clear();
+        wipe_spare(self);
+    }
+}
+
+impl Zeroize for String {
+    fn zeroize(&mut self) {
+        unsafe { self.as_mut_vec() }.zeroize();
+    }
+}
+
+// Keeps the pages of buf out of swap. Pages are never unlocked again, as
+// they may hold other secrets too, and what is freed has been wiped anyway.
+fn lock(buf: &Vec<u8>) {
+    static WARNED: AtomicBool = AtomicBool::new(false);
+    if buf.capacity() == 0 {
+        return;
+    }
+    let res = unsafe { mlock(buf.as_ptr() as *const c_void, buf.capacity()) };
+    if let Err(e) = res {
+        // Usually RLIMIT_MEMLOCK, which is not worth repeating.
+        if !WARNED.swap(true, Ordering::Relaxed) {
+            debug!("Unable to lock secrets into memory: {}", e);
+        }
+    }
+}
+
+/// Bytes of a secret, growing without leaving copies behind.
+pub struct SecretBytes {
+    buf: Vec<u8>,
+}
+
+impl SecretBytes {
+    pub fn new() -> SecretBytes {
+        SecretBytes::with_capacity(INITIAL_CAPACITY)
+    }
+
+    fn with_capacity(capacity: usize) -> SecretBytes {
+        let buf = Vec::with_capacity(capacity);
+        lock(&buf);
+        SecretBytes { buf }
+    }
+
+    // Makes room for additional bytes by moving to a larger buffer, rather
+    // than letting the allocator copy the secret and free the old one as is.
+    fn reserve(&mut self, additional: usize) {
+        let needed = self.buf.len() + additional;
+        if needed <= self.buf.capacity() {
+            return;
+        }
+        let mut larger = SecretBytes::with_capacity(needed.next_power_of_two());
+        larger.buf.extend_from_slice(&self.buf);
+        std::mem::swap(self, &mut larger);
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        &self.buf
+    }
+
+    pub fn extend_from_slice(&mut self, bytes: &[u8]) {
+        self.reserve(bytes.len());
+        self.buf.extend_from_slice(bytes);
+    }
+
+    fn insert(&mut self, idx: usize, bytes: &[u8]) {
+        self.reserve(bytes.len());
+        self.buf.splice(idx..idx, bytes.iter().copied());
+    }
+
+    fn remove(&mut self, range: Range<usize>) {
+        self.buf.drain(range);
+        // The tail moved down, leaving a copy of its end behind.
+        wipe_spare(&mut self.buf);
+    }
+
+    pub fn clear(&mut self) {
+        self.buf.zeroize();
+    }
+}
+
+impl Drop for SecretBytes {
+    fn drop(&mut self) {
+        self.buf.zeroize();
+    }
+}
+
+/// A secret string, such as the answer to a prompt.
+pub struct SecretString {
+    bytes: SecretBytes,
+}
+
+impl SecretString {
+    pub fn new() -> SecretString {
+        SecretString {
+            bytes: SecretBytes::new(),
+        }
+    }
+
+    /// Takes over bytes holding UTF-8, or hands them back if they do not.
+    pub fn from_utf8(bytes: SecretBytes) -> Result<SecretString, SecretBytes> {
+        match std::str::from_utf8(bytes.as_slice()) {
+            Ok(_) => Ok(SecretString { bytes }),
+            Err(_) => Err(bytes),
+        }
+    }
+
+    /// Inserts s at byte offset idx, which must be on a char boundary.
+    pub fn insert_str(&mut self, idx: usize, s: &str) {
+        assert!(self.is_char_boundary(idx));
+        self.bytes.insert(idx, s.as_bytes());
+    }
+
+    /// Removes the given range of bytes, which must start and end on char
+    /// boundaries.
+    pub fn remove(&mut self, range: Range<usize>) {
+        assert!(self.is_char_boundary(range.start) && self.is_char_boundary(range.end));
+        self.bytes.remove(range);
+    }
+
+    /// Replaces the contents with s.
+    pub fn set(&mut self, s: &str) {
+        self.bytes.clear();
+        self.bytes.extend_from_slice(s.as_bytes());
+    }
+
+    pub fn clear(&mut self) {
+        self.bytes.clear();
+    }
+}
+
+impl Deref for SecretString {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        // Only ever built from UTF-8, and only changed at char boundaries.
+        unsafe { std::str::from_utf8_unchecked(self.bytes.as_slice()) }
+    }
+}
+
+impl Clone for SecretString {
+    fn clone(&self) -> SecretString {
+        let mut copy = SecretString::new();
+        copy.set(self);
+        copy
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::alloc::{GlobalAlloc, Layout, System};
+    use std::cell::Cell;
+
+    const SECRET: &str = "correct horse battery staple";
+
+    thread_local! {
+        static WATCHING: Cell<bool> = const { Cell::new(false) };
+        static FOUND: Cell<bool> = const { Cell::new(false) };
+    }
+
+    // Looks for SECRET in every block freed on a thread that is watching.
+    // Blocks are zeroed when allocated, so that all of them can be read.
+    struct Inspector;
+
+    unsafe impl GlobalAlloc for Inspector {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            System.alloc_zeroed(layout)
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            if WATCHING.try_with(Cell::get).unwrap_or(false) {
+                let block = std::slice::from_raw_parts(ptr, layout.size());
+                if block.windows(SECRET.len()).any(|w| w == SECRET.as_bytes()) {
+                    FOUND.with(|found| found.set(true));
+                }
+            }
+            System.dealloc(ptr, layout)
+        }
+    }
+
+    #[global_allocator]
+    static INSPECTOR: Inspector = Inspector;
+
+    // Whether f frees memory with SECRET still in it.
+    fn leaves_secret(f: impl FnOnce()) -> bool {
+        FOUND.with(|found| found.set(false));
+        WATCHING.with(|watching| watching.set(true));
+        f();
+        WATCHING.with(|watching| watching.set(false));
+        FOUND.with(Cell::get)
+    }
+
+    // The whole allocation of s, beyond its length too.
+    fn storage(s: &SecretString) -> &[u8] {
+        let buf = &s.bytes.buf;
+        unsafe { std::slice::from_raw_parts(buf.as_ptr(), buf.capacity()) }
+    }
+
+    #[test]
+    fn freed_secrets_are_found() {
+        assert!(leaves_secret(|| drop(SECRET.to_string())));
+    }
+
+    #[test]
+    fn dropping_wipes_the_secret() {
+        assert!(!leaves_secret(|| {
+            let mut s = SecretString::new();
+            s.set(SECRET);
+            drop(s);
+        }));
+    }
+
+    #[test]
+    fn clearing_wipes_the_secret() {
+        let mut s = SecretString::new();
+        s.set(SECRET);
+        s.clear();
+        assert!(s.is_empty());
+        assert!(storage(&s).iter().all(|b| *b == 0));
+    }
+
+    #[test]
+    fn truncating_wipes_what_was_cut() {
+        let mut s = SecretString::new();
+        s.set(SECRET);
+        s.insert_str(0, "xyz ");
+        s.remove(4..s.len());
+        assert_eq!(&*s, "xyz ");
+        assert!(storage(&s)[4..].iter().all(|b| *b == 0));
+    }
+
+    #[test]
+    fn growing_leaves_no_copy_behind() {
+        let mut s = SecretString::new();
+        assert!(!leaves_secret(|| {
+            s.set(SECRET);
+            let capacity = s.bytes.buf.capacity();
+            while s.bytes.buf.capacity() == capacity {
+                s.insert_str(0, "more ");
+            }
+        }));
+        assert!(s.ends_with(SECRET));
+        assert!(!leaves_secret(|| drop(s)));
+    }
+}
@@ -0,0 +1,370 @@
+//! Wallpapers shown around the widgets, one after another, fading over to
+//! the next when animations are on. Only PNG pictures are shown, and only
+//! the one shown and the one fading out are kept in memory. Behind the
+//! widgets, the pictures can be shown blurred.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use log::warn;
+
+use crate::animation::{Animation, Easing, FRAME};
+use crate::buffer::Buffer;
+use crate::color::Color;
+use crate::colorfont::{decode_png, scale};
+
+// How long fading over to the next picture takes.
+const CROSSFADE: Duration = Duration::from_secs(2);
+
+// How much of the background color is mixed into the blurred pictures, for
+// text drawn for the background color to stay readable.
+const TINT: f32 = 0.5;
+
+/// Lists the pictures named by backgrounds, where a directory stands for
+/// the PNG files in it, in order of their names.
+pub fn pictures(backgrounds: &[String]) -> Vec<PathBuf> {
+    let mut paths = Vec::new();
+    for background in backgrounds {
+        let path = Path::new(background);
+        if !path.is_dir() {
+            paths.push(path.to_path_buf());
+            continue;
+        }
+        let mut entries: Vec<PathBuf> = match fs::read_dir(path) {
+            Ok(entries) => entries
+                .filter_map(|entry| entry.ok())
+                .map(|entry| entry.path())
+                .filter(|path| {
+                    path.extension()
+                        .is_some_and(|ext| ext.eq_ignore_ascii_case("png"))
+                })
+                .collect(),
+            Err(e) => {
+                warn!("Unable to list {}: {}", background, e);
+                continue;
+            }
+        };
+        entries.sort();
+        paths.extend(entries);
+    }
+    paths
+}
+
+// The channels of c, opacity first.
+fn channels(c: &Color) -> [f32; 4] {
+    let argb = c.as_argb8888();
+    [24, 16, 8, 0].map(|shift| (argb >> shift & 0xff) as f32 / 255.0)
+}
+
+// Averages the pixels of an image of size over half pixels to either side,
+// first along rows and then along columns, taking the edges to go on.
+fn box_blur(pixels: &mut [[f32; 4]], size: (u32, u32), half: usize) {
+    let (width, height) = (size.0 as usize, size.1 as usize);
+    let span = (2 * half + 1) as f32;
+    let mut line = Vec::new();
+    // The length of each line, the step along it and from one to the next.
+    for (length, step, lines, next) in [(width, 1, height, width), (height, width, width, 1)] {
+        for start in (0..lines).map(|l| l * next) {
+            line.clear();
+            line.extend((0..length).map(|i| pixels[start + i * step]));
+            let at = |i: isize| line[i.clamp(0, length as isize - 1) as usize];
+            let mut sum = [0.0; 4];
+            for i in -(half as isize)..=half as isize {
+                sum.iter_mut().zip(at(i)).for_each(|(s, c)| *s += c);
+            }
+            for i in 0..length as isize {
+                pixels[start + i as usize * step] = sum.map(|s| s / span);
+                let (gone, added) = (at(i - half as isize), at(i + half as isize + 1));
+                for (s, (g, a)) in sum.iter_mut().zip(gone.iter().zip(added)) {
+                    *s += a - g;
+                }
+            }
+        }
+    }
+}
+
+// Blurs pixels of an image of size, on the GPU when built with it and one
+// can be used.
+fn blur(pixels: &mut [[f32; 4]], size: (u32, u32), half: usize) {
+    #[cfg(feature = "gpu")]
+    if crate::gpu::blur(pixels, size, half) {
+        return;
+    }
+    // Three passes of a box blur come close to a gaussian one.
+    for _ in 0..3 {
+        box_blur(pixels, size, half);
+    }
+}
+
+// A picture, as last scaled to cover a surface and as last blurred.
+struct Picture {
+    size: (u32, u32),
+    pixels: Vec<[u8; 4]>,
+    // The size last scaled to, with no pixels until then.
+    scaled_size: (u32, u32),
+    scaled: Vec<(Color, f32)>,
+    // The area last blurred, with the radius and background color, and no
+    // pixels until then.
+    blurred_area: (u32, u32, u32, u32),
+    blurred_with: (u32, Color),
+    blurred: Vec<Color>,
+}
+
+impl Picture {
+    fn load(path: &Path) -> Option<Picture> {
+        let image = fs::read(path).ok().and_then(|data| decode_png(&data));
+        let (width, height, pixels) = match image {
+            Some(image) if image.0 > 0 && image.1 > 0 => image,
+            _ => {
+                warn!("Unable to load {} as PNG", path.display());
+                return None;
+            }
+        };
+        Some(Picture {
+            size: (width, height),
+            pixels,
+            scaled_size: (0, 0),
+            scaled: Vec::new(),
+            blurred_area: (0, 0, 0, 0),
+            blurred_with: (0, Color::default()),
+            blurred: Vec::new(),
+        })
+    }
+
+    // Scales the picture to cover size, cutting off what sticks out on
+    // either side.
+    fn cover(&mut self, size: (u32, u32)) {
+        if self.scaled_size == size && !self.scaled.is_empty() {
+            return;
+        }
+        let (width, height) = self.size;
+        let ratio = (size.0 as f32 / width as f32).max(size.1 as f32 / height as f32);
+        let crop = (
+            ((size.0 as f32 / ratio).round() as u32).clamp(1, width),
+            ((size.1 as f32 / ratio).round() as u32).clamp(1, height),
+        );
+        let (left, top) = ((width - crop.0) / 2, (height - crop.1) / 2);
+        let pixels: Vec<[u8; 4]> = (top..top + crop.1)
+            .flat_map(|y| {
+                let row = (y * width + left) as usize;
+                self.pixels[row..row + crop.0 as usize].iter().copied()
+            })
+            .collect();
+        self.scaled_size = size;
+        self.scaled = scale(&pixels, crop, size);
+        self.blurred.clear();
+    }
+
+    // Blurs the part given by area of the picture as last scaled, over bg,
+    // taking in the pixels up to radius away.
+    fn blur(&mut self, bg: &Color, area: (u32, u32, u32, u32), radius: u32) {
+        let blurred = self.blurred_area == area && self.blurred_with == (radius, *bg);
+        if (blurred && !self.blurred.is_empty()) || self.scaled.is_empty() {
+            return;
+        }
+        let width = self.scaled_size.0;
+        let (left, top) = (area.0.saturating_sub(radius), area.1.saturating_sub(radius));
+        let right = (area.0 + area.2 + radius).min(width);
+        let bottom = (area.1 + area.3 + radius).min(self.scaled_size.1);
+        let mut pixels: Vec<[f32; 4]> = (top..bottom)
+            .flat_map(|y| (left..right).map(move |x| (y * width + x) as usize))
+            .map(|idx| channels(&bg.blend(&self.scaled[idx].0, self.scaled[idx].1)))
+            .collect();
+        blur(
+            &mut pixels,
+            (right - left, bottom - top),
+            (radius / 3).max(1) as usize,
+        );
+        self.blurred = (area.1..area.1 + area.3)
+            .flat_map(|y| (area.0..area.0 + area.2).map(move |x| (y, x)))
+            .map(|(y, x)| {
+                let p = pixels[((y - top) * (right - left) + x - left) as usize];
+                Color::new(p[1], p[2], p[3], p[0])
+            })
+            .collect();
+        self.blurred_area = area;
+        self.blurred_with = (radius, *bg);
+    }
+}
+
+/// Pictures shown in turn, each for a while.
+pub struct Slideshow {
+    paths: Vec<PathBuf>,
+    interval: Duration,
+    crossfade: bool,
+    // The picture shown, since when, and the one fading out.
+    shown: usize,
+    since: Instant,
+    fade: Option<(usize, Animation)>,
+    // Whether there is something new to draw.
+    dirty: bool,
+    // The pictures loaded, or that failed to load, by index into paths.
+    pictures: HashMap<usize, Option<Picture>>,
+}
+
+impl Slideshow {
+    /// Shows the pictures at paths, each for interval, unless there are
+    /// none.
+    pub fn new(paths: Vec<PathBuf>, interval: Duration, crossfade: bool) -> Option<Slideshow> {
+        if paths.is_empty() {
+            return None;
+        }
+        Some(Slideshow {
+            paths,
+            interval,
+            crossfade,
+            shown: 0,
+            since: Instant::now(),
+            fade: None,
+            dirty: true,
+            pictures: HashMap::new(),
+        })
+    }
+
+    /// Moves on to the next picture once it is time to, and returns true if
+    /// there is something new to draw since it was last drawn.
+    pub fn advance(&mut self, now: Instant) -> bool {
+        if self.paths.len() > 1 && now.saturating_duration_since(self.since) >= self.interval {
+            let previous = self.shown;
+            self.shown = (self.shown + 1) % self.paths.len();
+            self.since = now;
+            self.fade = Some((previous, Animation::new(CROSSFADE, Easing::Linear)))
+                .filter(|_| self.crossfade);
+            let (shown, fade) = (self.shown, self.fade.as_ref().map(|(p, _)| *p));
+            self.pictures
+                .retain(|idx, _| *idx == shown || Some(*idx) == fade);
+            self.dirty = true;
+        } else if let Some(done) = self.fade.as_ref().map(|(_, fade)| fade.done(now)) {
+            // The last frame of the fade is drawn after it is dropped.
+            if done {
+                self.fade = None;
+            }
+            self.dirty = true;
+        }
+        self.dirty
+    }
+
+    /// Returns true if the pictures changed since they were last drawn.
+    pub fn changed(&self) -> bool {
+        self.dirty
+    }
+
+    /// Returns the time to draw the next frame at.
+    pub fn next_frame(&self, now: Instant) -> Option<Instant> {
+        match self.fade {
+            Some(_) => Some(now + FRAME),
+            None if self.paths.len() > 1 => Some(self.since + self.interval),
+            None => None,
+        }
+    }
+
+    // Loads the picture at idx if it has yet to be, scaled to cover size.
+    fn prepare(&mut self, idx: usize, size: (u32, u32)) {
+        let path = &self.paths[idx];
+        let picture = self
+            .pictures
+            .entry(idx)
+            .or_insert_with(|| Picture::load(path));
+        if let Some(picture) = picture {
+            picture.cover(size);
+        }
+    }
+
+    // The pixels of the picture at idx, as last scaled.
+    fn pixels(&self, idx: usize) -> Option<&[(Color, f32)]> {
+        match self.pictures.get(&idx) {
+            Some(Some(picture)) if !picture.scaled.is_empty() => Some(&picture.scaled),
+            _ => None,
+        }
+    }
+
+    // The pixels of the picture at idx, as last blurred.
+    fn blurred(&self, idx: usize) -> Option<&[Color]> {
+        match self.pictures.get(&idx) {
+            Some(Some(picture)) if !picture.blurred.is_empty() => Some(&picture.blurred),
+            _ => None,
+        }
+    }
+
+    /// Draws the part given by area of the pictures covering buf, over bg.
+    pub fn draw(
+        &mut self,
+        buf: &mut Buffer,
+        bg: &Color,
+        area: (u32, u32, u32, u32),
+        now: Instant,
+    ) -> Result<(), io::Error> {
+        let (_, _, width, height) = buf.get_bounds();
+        self.prepare(self.shown, (width, height));
+        let fade = self
+            .fade
+            .as_ref()
+            .map(|(idx, fade)| (*idx, fade.value(now)));
+        if let Some((idx, _)) = fade {
+            self.prepare(idx, (width, height));
+        }
+
+        let pixel = |pixels: Option<&[(Color, f32)]>, idx: usize| match pixels {
+            Some(pixels) => bg.blend(&pixels[idx].0, pixels[idx].1),
+            None => *bg,
+        };
+        let shown = self.pixels(self.shown);
+        let faded = fade.map(|(idx, value)| (self.pixels(idx), value));
+        for y in area.1..area.1 + area.3 {
+            for x in area.0..area.0 + area.2 {
+                let idx = (y * width + x) as usize;
+                let color = match faded {
+                    Some((pixels, value)) => pixel(pixels, idx).blend(&pixel(shown, idx), value),
+                    None => pixel(shown, idx),
+                };
+                buf.put((x, y), &color)?;
+            }
+        }
+        self.dirty = false;
+        Ok(())
+    }
+
+    /// Puts the pictures covering buf, blurred over radius pixels, behind
+    /// what is drawn over bg in the part given by area, where set in mask.
+    pub fn draw_blurred(
+        &mut self,
+        buf: &mut Buffer,
+        bg: &Color,
+        area: (u32, u32, u32, u32),
+        radius: u32,
+        mask: &[bool],
+        now: Instant,
+    ) -> Result<(), io::Error> {
+        let (_, _, width, height) = buf.get_bounds();
+        let fade = self
+            .fade
+            .as_ref()
+            .map(|(idx, fade)| (*idx, fade.value(now)));
+        for idx in Some(self.shown).into_iter().chain(fade.map(|(idx, _)| idx)) {
+            self.prepare(idx, (width, height));
+            if let Some(Some(picture)) = self.pictures.get_mut(&idx) {
+                picture.blur(bg, area, radius);
+            }
+        }
+
+        let pixel = |pixels: Option<&[Color]>, idx: usize| match pixels {
+            Some(pixels) => pixels[idx],
+            None => *bg,
+        };
+        let shown = self.blurred(self.shown);
+        let faded = fade.map(|(idx, value)| (self.blurred(idx), value));
+        for (idx, _) in mask.iter().enumerate().filter(|(_, set)| **set) {
+            let pos = (area.0 + idx as u32 % area.2, area.1 + idx as u32 / area.2);
+            let behind = match faded {
+                Some((pixels, value)) => pixel(pixels, idx).blend(&pixel(shown, idx), value),
+                None => pixel(shown, idx),
+            };
+            let drawn = buf.get(pos)?;
+            buf.put(pos, &drawn.rebase(bg, &behind.blend(bg, TINT)))?;
+        }
+        Ok(())
+    }
+}
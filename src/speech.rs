@@ -0,0 +1,140 @@
+//! Announces prompts and errors through speech-dispatcher, so that the
+//! greeter can be used without seeing it. speech-dispatcher is spoken to
+//! in SSIP over its Unix socket, at $SPEECHD_ADDRESS or in
+//! $XDG_RUNTIME_DIR/speech-dispatcher.
+
+use std::env;
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::sync::mpsc::{channel, Receiver, Sender};
+
+use log::{debug, warn};
+
+fn socket_path() -> io::Result<PathBuf> {
+    match env::var("SPEECHD_ADDRESS") {
+        Ok(address) => match address.strip_prefix("unix_socket:") {
+            Some(path) => Ok(PathBuf::from(path)),
+            None => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!("unsupported address {}", address),
+            )),
+        },
+        Err(_) => match env::var_os("XDG_RUNTIME_DIR") {
+            Some(dir) => Ok(PathBuf::from(dir).join("speech-dispatcher/speechd.sock")),
+            None => Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                "XDG_RUNTIME_DIR is unset",
+            )),
+        },
+    }
+}
+
+// A connection to speech-dispatcher.
+struct Connection {
+    reader: BufReader<UnixStream>,
+    writer: UnixStream,
+}
+
+impl Connection {
+    fn open() -> io::Result<Connection> {
+        let stream = UnixStream::connect(socket_path()?)?;
+        let mut conn = Connection {
+            reader: BufReader::new(stream.try_clone()?),
+            writer: stream,
+        };
+        let user = env::var("USER").unwrap_or_else(|_| "greeter".to_string());
+        conn.command(&format!("SET self CLIENT_NAME {}:wlgreet:main", user))?;
+        conn.command("SET self PRIORITY message")?;
+        Ok(conn)
+    }
+
+    // Reads a reply, which may span several lines, and fails unless it
+    // reports success.
+    fn reply(&mut self) -> io::Result<String> {
+        loop {
+            let mut line = String::new();
+            if self.reader.read_line(&mut line)? == 0 {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            let line = line.trim_end();
+            match line.as_bytes().get(3) {
+                // More lines follow.
+                Some(b'-') => continue,
+                _ if line.starts_with('2') => return Ok(line.to_string()),
+                _ => return Err(io::Error::new(io::ErrorKind::Other, line.to_string())),
+            }
+        }
+    }
+
+    fn command(&mut self, line: &str) -> io::Result<String> {
+        write!(self.writer, "{}\r\n", line)?;
+        self.reply()
+    }
+
+    fn speak(&mut self, text: &str) -> io::Result<()> {
+        self.command("SPEAK")?;
+        // The message ends with a line holding a single dot, so dots
+        // starting a line are doubled.
+        let mut data = String::new();
+        for line in text.lines() {
+            if line.starts_with('.') {
+                data.push('.');
+            }
+            data.push_str(line);
+            data.push_str("\r\n");
+        }
+        data.push_str(".\r\n");
+        self.writer.write_all(data.as_bytes())?;
+        self.reply()?;
+        Ok(())
+    }
+}
+
+fn run(rx: Receiver<String>) {
+    let mut conn = None;
+    // Only the first failure is worth a warning, as speech-dispatcher is
+    // not likely to come along later.
+    let mut warned = false;
+    for text in rx {
+        if conn.is_none() {
+            match Connection::open() {
+                Ok(c) => {
+                    debug!("Connected to speech-dispatcher");
+                    conn = Some(c);
+                }
+                Err(e) if !warned => {
+                    warn!("Unable to connect to speech-dispatcher: {}", e);
+                    warned = true;
+                }
+                Err(e) => debug!("Unable to connect to speech-dispatcher: {}", e),
+            }
+        }
+        if let Some(ref mut c) = conn {
+            if let Err(e) = c.speak(&text) {
+                warn!("Unable to speak through speech-dispatcher: {}", e);
+                conn = None;
+            }
+        }
+    }
+}
+
+/// Speaks text through speech-dispatcher on a thread of its own, so that
+/// the greeter does not wait on it.
+pub struct Speech {
+    tx: Sender<String>,
+}
+
+impl Speech {
+    pub fn spawn() -> Speech {
+        let (tx, rx) = channel();
+        let _ = std::thread::Builder::new()
+            .name("speech".to_string())
+            .spawn(move || run(rx));
+        Speech { tx }
+    }
+
+    pub fn say(&self, text: &str) {
+        let _ = self.tx.send(text.to_string());
+    }
+}
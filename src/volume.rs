@@ -0,0 +1,59 @@
+//! Changes the volume for the volume keys, with amixer unless the config
+//! names a command of its own, such as one for wpctl or pactl.
+
+use std::io;
+use std::process::Command;
+
+use crate::config::session_argv;
+
+// The volume changed by each key press, in amixer terms.
+const STEP: &str = "5%";
+
+/// Finds the volume, in percent, and whether it is muted in what amixer
+/// printed, as in "Front Left: Playback 39 [60%] [-20.00dB] [on]".
+fn parse_status(output: &str) -> Option<(u32, bool)> {
+    let level = output.split_whitespace().find_map(|word| {
+        word.trim_matches(|c| c == '[' || c == ']')
+            .strip_suffix('%')?
+            .parse()
+            .ok()
+    })?;
+    Some((level, output.contains("[off]")))
+}
+
+/// Makes the sound a step louder, or quieter if step is negative, or turns
+/// muting on or off if step is 0. Returns the volume as a fraction, which
+/// is 0 when muted, if the command printed it.
+pub fn change(command: &str, step: i32) -> io::Result<Option<f32>> {
+    let action = match step {
+        0 => "mute",
+        step if step > 0 => "up",
+        _ => "down",
+    };
+    let argv = if command.is_empty() {
+        let change = match action {
+            "mute" => "toggle".to_string(),
+            "up" => format!("{}+", STEP),
+            _ => format!("{}-", STEP),
+        };
+        vec![
+            "amixer".to_string(),
+            "-M".to_string(),
+            "sset".to_string(),
+            "Master".to_string(),
+            change,
+        ]
+    } else {
+        session_argv(command, "", &[("action", action)])
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?
+    };
+    let output = Command::new(&argv[0]).args(&argv[1..]).output()?;
+    if !output.status.success() {
+        return Err(io::Error::new(
+            io::ErrorKind::Other,
+            format!("{} failed: {}", argv[0], output.status),
+        ));
+    }
+    let status = parse_status(&String::from_utf8_lossy(&output.stdout));
+    Ok(status.map(|(level, muted)| if muted { 0.0 } else { level as f32 / 100.0 }))
+}
@@ -1,8 +1,11 @@
 use crate::buffer::Buffer;
 use crate::color::Color;
 use crate::config::Config;
+use crate::keybindings::Action;
 use chrono::{DateTime, Local};
-pub use smithay_client_toolkit::seat::keyboard::{KeyState, ModifiersState};
+pub use smithay_client_toolkit::seat::keyboard::{Keysym, Modifiers};
+use std::time::{Duration, Instant};
+pub use wayland_client::protocol::wl_keyboard::KeyState;
 
 pub struct DrawContext<'a> {
     pub buf: &'a mut Buffer<'a>,
@@ -10,6 +13,14 @@ pub struct DrawContext<'a> {
     pub time: &'a DateTime<Local>,
     pub force: bool,
     pub config: &'a Config,
+    pub modifiers: &'a Modifiers,
+    /// The keyboard layout in use, if the greeter picks it.
+    pub layout: Option<&'a str>,
+    /// Name of the output the keyboard is focused on, if known.
+    pub output: Option<&'a str>,
+    /// Whether the widget being drawn has the keyboard focus. Containers
+    /// set it for each child in turn.
+    pub focused: bool,
 }
 
 #[derive(Debug)]
@@ -31,7 +42,32 @@ impl DrawReport {
     }
 }
 
+/// How often widgets are ticked, fast enough for the spinner to turn.
+pub const TICK: Duration = Duration::from_millis(100);
+
+/// Width used by widgets when the compositor leaves the choice to us.
+pub const DEFAULT_WIDTH: u32 = 512;
+
+/// Multiplies a size in logical pixels by scale, giving buffer pixels.
+pub fn scaled(size: u32, scale: f32) -> u32 {
+    (size as f32 * scale).round() as u32
+}
+
+/// Picks the width to lay out to for the given available width.
+pub fn layout_width(available: u32, scale: f32) -> u32 {
+    if available == 0 {
+        scaled(DEFAULT_WIDTH, scale)
+    } else {
+        available
+    }
+}
+
 pub trait Widget {
+    /// Tells the widget how much space it has, as configured by the
+    /// compositor. A dimension of 0 means the widget picks its own size.
+    /// Sizes are in buffer pixels, and the widget is expected to multiply
+    /// its own sizes by scale.
+    fn resize(&mut self, available: (u32, u32), scale: f32);
     fn size(&self) -> (u32, u32);
     fn draw(
         &mut self,
@@ -41,11 +77,733 @@ pub trait Widget {
 
     fn keyboard_input(
         &mut self,
-        keysym: u32,
-        modifier_state: ModifiersState,
+        keysym: Keysym,
+        modifier_state: Modifiers,
         key_state: KeyState,
         interpreted: Option<String>,
     );
     fn mouse_click(&mut self, button: u32, pos: (u32, u32));
     fn mouse_scroll(&mut self, scroll: (f64, f64), pos: (u32, u32));
+    /// Tells the widget that the pointer moved to pos over it, or entered
+    /// it there. Widgets without anything to highlight ignore it.
+    fn mouse_move(&mut self, _pos: (u32, u32)) {}
+    /// Tells the widget that the pointer left it.
+    fn mouse_leave(&mut self) {}
+    /// Tells the widget that the touch point id went down at pos over it.
+    /// The later events of the point go to the same widget, even once it
+    /// moves off it, with positions left of or above the widget reading as
+    /// 0. Widgets ignore the events of points they did not see go down.
+    fn touch_down(&mut self, _id: i32, _pos: (u32, u32)) {}
+    fn touch_motion(&mut self, _id: i32, _pos: (u32, u32)) {}
+    fn touch_up(&mut self, _id: i32) {}
+    /// Tells the widget that its touch points are gone without going up.
+    fn touch_cancel(&mut self) {}
+    /// Moves the keyboard focus to the next part of the widget that takes
+    /// it, or to the first if the widget does not have the focus yet.
+    /// Returns false if there is none left, in which case the widget gives
+    /// up the focus for it to move on to the next widget.
+    fn focus_next(&mut self) -> bool {
+        false
+    }
+    /// Like focus_next, but moving backwards.
+    fn focus_prev(&mut self) -> bool {
+        false
+    }
+    /// Lets the widget update itself as time passes, such as a clock or a
+    /// countdown. Called every TICK, returning whether the widget needs to
+    /// be drawn again.
+    fn tick(&mut self, _now: Instant) -> bool {
+        false
+    }
+    /// Tells the widget that the greeter is being shown, such as on start
+    /// or through the control socket, for it to set up what it only needs
+    /// while visible.
+    fn on_show(&mut self) {}
+    /// Tells the widget that the greeter was hidden, for it to stop what it
+    /// only needs while visible.
+    fn on_hide(&mut self) {}
+    /// Tells the widget that a surface showing it was configured to size,
+    /// in buffer pixels, for it to lay itself out again.
+    fn on_configure(&mut self, _size: (u32, u32)) {}
+    fn paste(&mut self, _text: &str) {}
+    /// Performs the action of a key binding. Widgets ignore actions that
+    /// are not theirs.
+    fn action(&mut self, _action: Action) {}
+    /// Shows a status message sent over the control socket, or clears it if
+    /// text is empty. Widgets without room for one ignore it.
+    fn message(&mut self, _text: &str) {}
+}
+
+// Translates pos to be relative to a child of the given size at offset,
+// if it lies over the child.
+fn within(pos: (u32, u32), offset: (u32, u32), size: (u32, u32)) -> Option<(u32, u32)> {
+    if pos.0 >= offset.0
+        && pos.0 < offset.0 + size.0
+        && pos.1 >= offset.1
+        && pos.1 < offset.1 + size.1
+    {
+        Some((pos.0 - offset.0, pos.1 - offset.1))
+    } else {
+        None
+    }
+}
+
+// Merges the report of a child drawn at pos into the damage of its parent.
+fn merge_report(
+    report: DrawReport,
+    pos: (u32, u32),
+    damage: &mut Vec<(i32, i32, i32, i32)>,
+    full_damage: &mut bool,
+) {
+    if report.full_damage {
+        damage.push((
+            pos.0 as i32,
+            pos.1 as i32,
+            report.width as i32,
+            report.height as i32,
+        ));
+        *full_damage = true;
+    } else {
+        damage.extend(report.damage);
+    }
+}
+
+// The child under a position, and the position relative to that child.
+type ChildAt<'a> = Option<(&'a mut Box<dyn Widget + Send>, (u32, u32))>;
+
+/// Decides where a Container places its children.
+pub trait Layout {
+    /// Passes the space available to the container on to its children.
+    fn resize(&mut self, widgets: &mut [Box<dyn Widget + Send>], available: (u32, u32), scale: f32);
+    fn size(&self, widgets: &[Box<dyn Widget + Send>]) -> (u32, u32);
+    /// Offsets of the children relative to the container.
+    fn offsets(&self, widgets: &[Box<dyn Widget + Send>]) -> Vec<(u32, u32)>;
+}
+
+/// Holds widgets placed by a Layout. Pointer and touch input go to the
+/// child under it, the keyboard focus moves from child to child, and
+/// everything else goes to all of them.
+pub struct Container<L: Layout> {
+    widgets: Vec<Box<dyn Widget + Send>>,
+    layout: L,
+    // The child holding the keyboard focus, if any.
+    focus: Option<usize>,
+    // The child each touch point down went to.
+    touches: Vec<(i32, usize)>,
+}
+
+impl<L: Layout> Container<L> {
+    fn with_layout(widgets: Vec<Box<dyn Widget + Send>>, layout: L) -> Box<Container<L>> {
+        Box::new(Container {
+            widgets,
+            layout,
+            focus: None,
+            touches: Vec::new(),
+        })
+    }
+
+    fn child_at(&mut self, pos: (u32, u32)) -> ChildAt<'_> {
+        let offsets = self.layout.offsets(&self.widgets);
+        for (widget, offset) in self.widgets.iter_mut().zip(offsets) {
+            if let Some(pos) = within(pos, offset, widget.size()) {
+                return Some((widget, pos));
+            }
+        }
+        None
+    }
+}
+
+impl<L: Layout> Widget for Container<L> {
+    fn resize(&mut self, available: (u32, u32), scale: f32) {
+        self.layout.resize(&mut self.widgets, available, scale);
+    }
+
+    fn size(&self) -> (u32, u32) {
+        self.layout.size(&self.widgets)
+    }
+
+    fn draw(
+        &mut self,
+        ctx: &mut DrawContext,
+        pos: (u32, u32),
+    ) -> Result<DrawReport, ::std::io::Error> {
+        let (width, height) = self.size();
+        let offsets = self.layout.offsets(&self.widgets);
+        let mut damage = Vec::new();
+        let mut full_damage = false;
+        let focused = ctx.focused;
+        for (idx, (widget, offset)) in self.widgets.iter_mut().zip(offsets).enumerate() {
+            let child_pos = (pos.0 + offset.0, pos.1 + offset.1);
+            ctx.focused = focused && self.focus == Some(idx);
+            let report = widget.draw(ctx, child_pos);
+            ctx.focused = focused;
+            merge_report(report?, child_pos, &mut damage, &mut full_damage);
+        }
+        Ok(DrawReport {
+            width,
+            height,
+            damage,
+            full_damage,
+        })
+    }
+
+    fn keyboard_input(
+        &mut self,
+        keysym: Keysym,
+        modifier_state: Modifiers,
+        key_state: KeyState,
+        interpreted: Option<String>,
+    ) {
+        for widget in self.widgets.iter_mut() {
+            widget.keyboard_input(keysym, modifier_state, key_state, interpreted.clone());
+        }
+    }
+
+    fn paste(&mut self, text: &str) {
+        for widget in self.widgets.iter_mut() {
+            widget.paste(text);
+        }
+    }
+
+    fn action(&mut self, action: Action) {
+        for widget in self.widgets.iter_mut() {
+            widget.action(action);
+        }
+    }
+
+    fn message(&mut self, text: &str) {
+        for widget in self.widgets.iter_mut() {
+            widget.message(text);
+        }
+    }
+
+    fn mouse_click(&mut self, button: u32, pos: (u32, u32)) {
+        if let Some((widget, pos)) = self.child_at(pos) {
+            widget.mouse_click(button, pos);
+        }
+    }
+
+    fn mouse_scroll(&mut self, scroll: (f64, f64), pos: (u32, u32)) {
+        if let Some((widget, pos)) = self.child_at(pos) {
+            widget.mouse_scroll(scroll, pos);
+        }
+    }
+
+    fn mouse_move(&mut self, pos: (u32, u32)) {
+        let offsets = self.layout.offsets(&self.widgets);
+        for (widget, offset) in self.widgets.iter_mut().zip(offsets) {
+            match within(pos, offset, widget.size()) {
+                Some(pos) => widget.mouse_move(pos),
+                None => widget.mouse_leave(),
+            }
+        }
+    }
+
+    fn mouse_leave(&mut self) {
+        for widget in self.widgets.iter_mut() {
+            widget.mouse_leave();
+        }
+    }
+
+    fn touch_down(&mut self, id: i32, pos: (u32, u32)) {
+        let offsets = self.layout.offsets(&self.widgets);
+        for (idx, (widget, offset)) in self.widgets.iter_mut().zip(offsets).enumerate() {
+            if let Some(pos) = within(pos, offset, widget.size()) {
+                self.touches.push((id, idx));
+                widget.touch_down(id, pos);
+                return;
+            }
+        }
+    }
+
+    fn touch_motion(&mut self, id: i32, pos: (u32, u32)) {
+        if let Some(&(_, idx)) = self.touches.iter().find(|(touch, _)| *touch == id) {
+            let offset = self.layout.offsets(&self.widgets)[idx];
+            self.widgets[idx].touch_motion(
+                id,
+                (
+                    pos.0.saturating_sub(offset.0),
+                    pos.1.saturating_sub(offset.1),
+                ),
+            );
+        }
+    }
+
+    fn touch_up(&mut self, id: i32) {
+        if let Some(idx) = self.touches.iter().position(|(touch, _)| *touch == id) {
+            let (_, idx) = self.touches.remove(idx);
+            self.widgets[idx].touch_up(id);
+        }
+    }
+
+    fn touch_cancel(&mut self) {
+        self.touches.clear();
+        for widget in self.widgets.iter_mut() {
+            widget.touch_cancel();
+        }
+    }
+
+    fn focus_next(&mut self) -> bool {
+        let start = self.focus.unwrap_or(0);
+        self.focus = (start..self.widgets.len()).find(|idx| self.widgets[*idx].focus_next());
+        self.focus.is_some()
+    }
+
+    fn focus_prev(&mut self) -> bool {
+        let end = self.focus.map_or(self.widgets.len(), |idx| idx + 1);
+        self.focus = (0..end).rev().find(|idx| self.widgets[*idx].focus_prev());
+        self.focus.is_some()
+    }
+
+    fn tick(&mut self, now: Instant) -> bool {
+        let mut changed = false;
+        for widget in self.widgets.iter_mut() {
+            changed |= widget.tick(now);
+        }
+        changed
+    }
+
+    fn on_show(&mut self) {
+        for widget in self.widgets.iter_mut() {
+            widget.on_show();
+        }
+    }
+
+    fn on_hide(&mut self) {
+        for widget in self.widgets.iter_mut() {
+            widget.on_hide();
+        }
+    }
+
+    fn on_configure(&mut self, size: (u32, u32)) {
+        for widget in self.widgets.iter_mut() {
+            widget.on_configure(size);
+        }
+    }
+}
+
+/// Stacks widgets vertically, centering them horizontally.
+pub type VBox = Container<Column>;
+
+/// The Layout of a VBox.
+pub struct Column;
+
+impl VBox {
+    pub fn new(widgets: Vec<Box<dyn Widget + Send>>) -> Box<VBox> {
+        Container::with_layout(widgets, Column)
+    }
+}
+
+impl Layout for Column {
+    fn resize(
+        &mut self,
+        widgets: &mut [Box<dyn Widget + Send>],
+        available: (u32, u32),
+        scale: f32,
+    ) {
+        for widget in widgets.iter_mut() {
+            widget.resize((available.0, 0), scale);
+        }
+    }
+
+    fn size(&self, widgets: &[Box<dyn Widget + Send>]) -> (u32, u32) {
+        let mut width = 0;
+        let mut height = 0;
+        for widget in widgets {
+            let (w, h) = widget.size();
+            width = width.max(w);
+            height += h;
+        }
+        (width, height)
+    }
+
+    fn offsets(&self, widgets: &[Box<dyn Widget + Send>]) -> Vec<(u32, u32)> {
+        let (width, _) = self.size(widgets);
+        let mut y = 0;
+        widgets
+            .iter()
+            .map(|widget| {
+                let (w, h) = widget.size();
+                let offset = ((width - w) / 2, y);
+                y += h;
+                offset
+            })
+            .collect()
+    }
+}
+
+/// Places widgets side by side, centering them vertically.
+pub type HBox = Container<Row>;
+
+/// The Layout of an HBox.
+pub struct Row;
+
+impl HBox {
+    pub fn new(widgets: Vec<Box<dyn Widget + Send>>) -> Box<HBox> {
+        Container::with_layout(widgets, Row)
+    }
+}
+
+impl Layout for Row {
+    fn resize(
+        &mut self,
+        widgets: &mut [Box<dyn Widget + Send>],
+        available: (u32, u32),
+        scale: f32,
+    ) {
+        // Split the available width evenly, or let every child pick its own.
+        let count = widgets.len().max(1) as u32;
+        for widget in widgets.iter_mut() {
+            widget.resize((available.0 / count, available.1), scale);
+        }
+    }
+
+    fn size(&self, widgets: &[Box<dyn Widget + Send>]) -> (u32, u32) {
+        let mut width = 0;
+        let mut height = 0;
+        for widget in widgets {
+            let (w, h) = widget.size();
+            width += w;
+            height = height.max(h);
+        }
+        (width, height)
+    }
+
+    fn offsets(&self, widgets: &[Box<dyn Widget + Send>]) -> Vec<(u32, u32)> {
+        let (_, height) = self.size(widgets);
+        let mut x = 0;
+        widgets
+            .iter()
+            .map(|widget| {
+                let (w, h) = widget.size();
+                let offset = (x, (height - h) / 2);
+                x += w;
+                offset
+            })
+            .collect()
+    }
+}
+
+/// Surrounds a widget with empty space, given as (top, right, bottom, left).
+pub struct Margin {
+    widget: Box<dyn Widget + Send>,
+    margin: (u32, u32, u32, u32),
+    scale: f32,
+}
+
+impl Margin {
+    pub fn new(widget: Box<dyn Widget + Send>, margin: (u32, u32, u32, u32)) -> Box<Margin> {
+        Box::new(Margin {
+            widget,
+            margin,
+            scale: 1.0,
+        })
+    }
+
+    // The margin in buffer pixels.
+    fn scaled(&self) -> (u32, u32, u32, u32) {
+        let (top, right, bottom, left) = self.margin;
+        let s = self.scale;
+        (
+            scaled(top, s),
+            scaled(right, s),
+            scaled(bottom, s),
+            scaled(left, s),
+        )
+    }
+}
+
+impl Widget for Margin {
+    fn resize(&mut self, available: (u32, u32), scale: f32) {
+        self.scale = scale;
+        let (top, right, bottom, left) = self.scaled();
+        let shrink = |size: u32, by: u32| {
+            if size == 0 {
+                0
+            } else {
+                size.saturating_sub(by).max(1)
+            }
+        };
+        self.widget.resize(
+            (
+                shrink(available.0, left + right),
+                shrink(available.1, top + bottom),
+            ),
+            scale,
+        );
+    }
+
+    fn size(&self) -> (u32, u32) {
+        let (top, right, bottom, left) = self.scaled();
+        let (w, h) = self.widget.size();
+        (w + left + right, h + top + bottom)
+    }
+
+    fn draw(
+        &mut self,
+        ctx: &mut DrawContext,
+        pos: (u32, u32),
+    ) -> Result<DrawReport, ::std::io::Error> {
+        let (width, height) = self.size();
+        let (top, _, _, left) = self.scaled();
+        let child_pos = (pos.0 + left, pos.1 + top);
+        let report = self.widget.draw(ctx, child_pos)?;
+        let mut damage = Vec::new();
+        let mut full_damage = false;
+        merge_report(report, child_pos, &mut damage, &mut full_damage);
+        Ok(DrawReport {
+            width,
+            height,
+            damage,
+            full_damage,
+        })
+    }
+
+    fn keyboard_input(
+        &mut self,
+        keysym: Keysym,
+        modifier_state: Modifiers,
+        key_state: KeyState,
+        interpreted: Option<String>,
+    ) {
+        self.widget
+            .keyboard_input(keysym, modifier_state, key_state, interpreted);
+    }
+
+    fn paste(&mut self, text: &str) {
+        self.widget.paste(text);
+    }
+
+    fn action(&mut self, action: Action) {
+        self.widget.action(action);
+    }
+
+    fn message(&mut self, text: &str) {
+        self.widget.message(text);
+    }
+
+    fn mouse_click(&mut self, button: u32, pos: (u32, u32)) {
+        let (w, h) = self.widget.size();
+        let (top, _, _, left) = self.scaled();
+        if pos.0 >= left && pos.0 < left + w && pos.1 >= top && pos.1 < top + h {
+            self.widget.mouse_click(button, (pos.0 - left, pos.1 - top));
+        }
+    }
+
+    fn mouse_scroll(&mut self, scroll: (f64, f64), pos: (u32, u32)) {
+        let (w, h) = self.widget.size();
+        let (top, _, _, left) = self.scaled();
+        if pos.0 >= left && pos.0 < left + w && pos.1 >= top && pos.1 < top + h {
+            self.widget
+                .mouse_scroll(scroll, (pos.0 - left, pos.1 - top));
+        }
+    }
+
+    fn mouse_move(&mut self, pos: (u32, u32)) {
+        let (top, _, _, left) = self.scaled();
+        match within(pos, (left, top), self.widget.size()) {
+            Some(pos) => self.widget.mouse_move(pos),
+            None => self.widget.mouse_leave(),
+        }
+    }
+
+    fn mouse_leave(&mut self) {
+        self.widget.mouse_leave();
+    }
+
+    fn touch_down(&mut self, id: i32, pos: (u32, u32)) {
+        let (top, _, _, left) = self.scaled();
+        if let Some(pos) = within(pos, (left, top), self.widget.size()) {
+            self.widget.touch_down(id, pos);
+        }
+    }
+
+    fn touch_motion(&mut self, id: i32, pos: (u32, u32)) {
+        let (top, _, _, left) = self.scaled();
+        self.widget
+            .touch_motion(id, (pos.0.saturating_sub(left), pos.1.saturating_sub(top)));
+    }
+
+    fn touch_up(&mut self, id: i32) {
+        self.widget.touch_up(id);
+    }
+
+    fn touch_cancel(&mut self) {
+        self.widget.touch_cancel();
+    }
+
+    fn focus_next(&mut self) -> bool {
+        self.widget.focus_next()
+    }
+
+    fn focus_prev(&mut self) -> bool {
+        self.widget.focus_prev()
+    }
+
+    fn tick(&mut self, now: Instant) -> bool {
+        self.widget.tick(now)
+    }
+
+    fn on_show(&mut self) {
+        self.widget.on_show();
+    }
+
+    fn on_hide(&mut self) {
+        self.widget.on_hide();
+    }
+
+    fn on_configure(&mut self, size: (u32, u32)) {
+        self.widget.on_configure(size);
+    }
+}
+
+/// Places three widgets at the top, center and bottom of the available
+/// height, centering them horizontally.
+pub type AnchorLayout = Container<Anchored>;
+
+/// The Layout of an AnchorLayout.
+pub struct Anchored {
+    available_height: u32,
+}
+
+impl AnchorLayout {
+    pub fn new(
+        top: Box<dyn Widget + Send>,
+        center: Box<dyn Widget + Send>,
+        bottom: Box<dyn Widget + Send>,
+    ) -> Box<AnchorLayout> {
+        Container::with_layout(
+            vec![top, center, bottom],
+            Anchored {
+                available_height: 0,
+            },
+        )
+    }
+}
+
+impl Layout for Anchored {
+    fn resize(
+        &mut self,
+        widgets: &mut [Box<dyn Widget + Send>],
+        available: (u32, u32),
+        scale: f32,
+    ) {
+        self.available_height = available.1;
+        for widget in widgets.iter_mut() {
+            widget.resize((available.0, 0), scale);
+        }
+    }
+
+    fn size(&self, widgets: &[Box<dyn Widget + Send>]) -> (u32, u32) {
+        let mut width = 0;
+        let mut height = 0;
+        for widget in widgets {
+            let (w, h) = widget.size();
+            width = width.max(w);
+            height += h;
+        }
+        (width, height.max(self.available_height))
+    }
+
+    fn offsets(&self, widgets: &[Box<dyn Widget + Send>]) -> Vec<(u32, u32)> {
+        let (width, height) = self.size(widgets);
+        let sizes = [widgets[0].size(), widgets[1].size(), widgets[2].size()];
+        let center_y = ((height - sizes[1].1) / 2)
+            .max(sizes[0].1)
+            .min(height - sizes[2].1 - sizes[1].1);
+        vec![
+            ((width - sizes[0].0) / 2, 0),
+            ((width - sizes[1].0) / 2, center_y),
+            ((width - sizes[2].0) / 2, height - sizes[2].1),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    // A widget of a fixed size, noting down the touch events it gets.
+    struct Probe {
+        name: &'static str,
+        events: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl Widget for Probe {
+        fn resize(&mut self, _available: (u32, u32), _scale: f32) {}
+
+        fn size(&self) -> (u32, u32) {
+            (10, 10)
+        }
+
+        fn draw(
+            &mut self,
+            _ctx: &mut DrawContext,
+            _pos: (u32, u32),
+        ) -> Result<DrawReport, ::std::io::Error> {
+            Ok(DrawReport::empty(10, 10))
+        }
+
+        fn keyboard_input(
+            &mut self,
+            _keysym: Keysym,
+            _modifier_state: Modifiers,
+            _key_state: KeyState,
+            _interpreted: Option<String>,
+        ) {
+        }
+
+        fn mouse_click(&mut self, _button: u32, _pos: (u32, u32)) {}
+
+        fn mouse_scroll(&mut self, _scroll: (f64, f64), _pos: (u32, u32)) {}
+
+        fn touch_down(&mut self, id: i32, pos: (u32, u32)) {
+            self.note(format!("down {} {:?}", id, pos));
+        }
+
+        fn touch_motion(&mut self, id: i32, pos: (u32, u32)) {
+            self.note(format!("motion {} {:?}", id, pos));
+        }
+
+        fn touch_up(&mut self, id: i32) {
+            self.note(format!("up {}", id));
+        }
+    }
+
+    impl Probe {
+        fn note(&self, event: String) {
+            let mut events = self.events.lock().unwrap();
+            events.push(format!("{}: {}", self.name, event));
+        }
+    }
+
+    #[test]
+    fn touch_points_stay_with_the_child_they_went_down_on() {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let probe = |name| -> Box<dyn Widget + Send> {
+            Box::new(Probe {
+                name,
+                events: events.clone(),
+            })
+        };
+        let mut vbox = VBox::new(vec![probe("top"), probe("bottom")]);
+        vbox.touch_down(1, (5, 5));
+        vbox.touch_motion(1, (5, 15));
+        vbox.touch_motion(1, (25, 3));
+        vbox.touch_up(1);
+        vbox.touch_down(2, (5, 15));
+        vbox.touch_up(2);
+        assert_eq!(
+            *events.lock().unwrap(),
+            [
+                "top: down 1 (5, 5)",
+                "top: motion 1 (5, 15)",
+                "top: motion 1 (25, 3)",
+                "top: up 1",
+                "bottom: down 2 (5, 5)",
+                "bottom: up 2",
+            ]
+        );
+    }
 }
@@ -0,0 +1,136 @@
+use crate::draw::{draw_circle, draw_line};
+use crate::widget::{
+    layout_width, scaled, DrawContext, DrawReport, KeyState, Keysym, Modifiers, Widget,
+    DEFAULT_WIDTH,
+};
+
+use std::f32::consts::PI;
+use std::time::Instant;
+
+use chrono::{Local, Timelike};
+
+/// A clock face with hour and minute hands, as an alternative to the text
+/// of Clock. The face is three times as wide as the clock font size, so
+/// that clockSize sizes either.
+pub struct AnalogClock {
+    font_size: f32,
+    // The hour and minute as last drawn.
+    time: Option<(u32, u32)>,
+    width: u32,
+    scale: f32,
+    // Whether the greeter is shown, for the clock to tick.
+    shown: bool,
+}
+
+impl AnalogClock {
+    pub fn new(font_size: f32) -> Box<AnalogClock> {
+        Box::new(AnalogClock {
+            font_size,
+            time: None,
+            width: DEFAULT_WIDTH,
+            scale: 1.0,
+            shown: false,
+        })
+    }
+
+    fn diameter(&self) -> u32 {
+        scaled((self.font_size * 3.0) as u32, self.scale)
+    }
+}
+
+// The point at length from center in the direction of the hand of a clock
+// showing fraction of a turn.
+fn hand(center: (f32, f32), fraction: f32, length: f32) -> (f32, f32) {
+    let angle = fraction * 2.0 * PI;
+    (
+        center.0 + length * angle.sin(),
+        center.1 - length * angle.cos(),
+    )
+}
+
+impl Widget for AnalogClock {
+    fn resize(&mut self, available: (u32, u32), scale: f32) {
+        self.scale = scale;
+        self.width = layout_width(available.0, scale);
+    }
+
+    fn size(&self) -> (u32, u32) {
+        (self.width, self.diameter() + scaled(16, self.scale))
+    }
+
+    fn draw(
+        &mut self,
+        ctx: &mut DrawContext,
+        pos: (u32, u32),
+    ) -> Result<DrawReport, ::std::io::Error> {
+        let (width, height) = self.size();
+        let time = (ctx.time.hour() % 12, ctx.time.minute());
+        if self.time == Some(time) && !ctx.force {
+            return Ok(DrawReport::empty(width, height));
+        }
+        let mut buf = ctx.buf.subdimensions((pos.0, pos.1, width, height))?;
+        buf.memset(ctx.bg);
+
+        let c = &ctx.config.headline;
+        let stroke = scaled(2, self.scale).max(1) as f32;
+        let diameter = self.diameter() as f32;
+        let radius = diameter / 2.0 - stroke;
+        let center = (
+            scaled(24, self.scale) as f32 + diameter / 2.0,
+            scaled(8, self.scale) as f32 + diameter / 2.0,
+        );
+        draw_circle(&mut buf, c, center, radius, stroke);
+        for mark in 0..12 {
+            let fraction = mark as f32 / 12.0;
+            let inner = if mark % 3 == 0 { 0.75 } else { 0.85 };
+            draw_line(
+                &mut buf,
+                c,
+                hand(center, fraction, radius * inner),
+                hand(center, fraction, radius * 0.92),
+                stroke,
+            );
+        }
+        let (hour, minute) = time;
+        let hours = (hour as f32 + minute as f32 / 60.0) / 12.0;
+        draw_line(
+            &mut buf,
+            c,
+            center,
+            hand(center, hours, radius * 0.5),
+            stroke * 2.5,
+        );
+        draw_line(
+            &mut buf,
+            c,
+            center,
+            hand(center, minute as f32 / 60.0, radius * 0.75),
+            stroke * 1.5,
+        );
+        draw_line(&mut buf, c, center, center, stroke * 4.0);
+        self.time = Some(time);
+
+        Ok(DrawReport {
+            width,
+            height,
+            damage: vec![buf.get_signed_bounds()],
+            full_damage: false,
+        })
+    }
+
+    fn keyboard_input(&mut self, _: Keysym, _: Modifiers, _: KeyState, _: Option<String>) {}
+    fn mouse_click(&mut self, _: u32, _: (u32, u32)) {}
+    fn mouse_scroll(&mut self, _: (f64, f64), _: (u32, u32)) {}
+    fn tick(&mut self, _: Instant) -> bool {
+        let now = Local::now();
+        self.shown && self.time != Some((now.hour() % 12, now.minute()))
+    }
+    fn on_show(&mut self) {
+        self.shown = true;
+        // The time shown before hiding is long gone.
+        self.time = None;
+    }
+    fn on_hide(&mut self) {
+        self.shown = false;
+    }
+}
@@ -0,0 +1,150 @@
+use crate::buffer::Buffer;
+use crate::color::Color;
+use crate::draw::Font;
+use crate::widget::{
+    layout_width, scaled, DrawContext, DrawReport, KeyState, Keysym, Modifiers, Widget,
+    DEFAULT_WIDTH,
+};
+
+use std::time::Instant;
+
+use chrono::{Datelike, Local, NaiveDate};
+use rusttype::Font as RustFont;
+
+const WEEKDAYS: [&str; 7] = ["Mo", "Tu", "We", "Th", "Fr", "Sa", "Su"];
+
+// The month name, the weekdays and the most weeks a month spans.
+const ROWS: u32 = 8;
+
+// Width of the column of each weekday.
+const CELL: u32 = 32;
+
+/// Shows the current month, weeks starting on Monday, with today
+/// highlighted. The widget is as high as a month spanning six weeks, so
+/// that the layout stays put from month to month.
+pub struct Calendar {
+    // The day as last drawn.
+    date: Option<NaiveDate>,
+    font: Font,
+    width: u32,
+    scale: f32,
+    // Whether the greeter is shown, for the calendar to turn over at
+    // midnight.
+    shown: bool,
+}
+
+impl Calendar {
+    pub fn new(font: &RustFont<'static>) -> Box<Calendar> {
+        Box::new(Calendar {
+            date: None,
+            font: Font::new(font, 16.0),
+            width: DEFAULT_WIDTH,
+            scale: 1.0,
+            shown: false,
+        })
+    }
+
+    // Draws text right-aligned, as numbers are, in the cell at pos, filled
+    // with bg.
+    fn draw_cell(
+        &mut self,
+        buf: &mut Buffer,
+        (bg, c): (&Color, &Color),
+        pos: (u32, u32),
+        text: &str,
+    ) -> Result<(), ::std::io::Error> {
+        let (cell, height) = (scaled(CELL, self.scale), self.font.size() as u32);
+        buf.subdimensions((pos.0, pos.1, cell, height))?.memset(bg);
+        let inset = cell.saturating_sub(self.font.measure_text(text).0 + scaled(4, self.scale));
+        self.font
+            .auto_draw_text(&mut buf.offset((pos.0 + inset, pos.1))?, bg, c, text)?;
+        Ok(())
+    }
+}
+
+impl Widget for Calendar {
+    fn resize(&mut self, available: (u32, u32), scale: f32) {
+        self.scale = scale;
+        self.font.set_scale(scale);
+        self.width = layout_width(available.0, scale);
+    }
+
+    fn size(&self) -> (u32, u32) {
+        (
+            self.width,
+            ROWS * self.font.size() as u32 + scaled(16, self.scale),
+        )
+    }
+
+    fn draw(
+        &mut self,
+        ctx: &mut DrawContext,
+        pos: (u32, u32),
+    ) -> Result<DrawReport, ::std::io::Error> {
+        let (width, height) = self.size();
+        let today = ctx.time.date_naive();
+        if self.date == Some(today) && !ctx.force {
+            return Ok(DrawReport::empty(width, height));
+        }
+        let mut buf = ctx.buf.subdimensions((pos.0, pos.1, width, height))?;
+        buf.memset(ctx.bg);
+
+        let s = self.scale;
+        let (left, top) = (scaled(24, s), scaled(8, s));
+        let line_height = self.font.size() as u32;
+        let cell = scaled(CELL, s);
+        self.font.auto_draw_text(
+            &mut buf.offset((left, top))?,
+            ctx.bg,
+            &ctx.config.headline,
+            &ctx.time.format("%B %Y").to_string(),
+        )?;
+
+        // Columns are left out where the widget is too narrow for them.
+        let columns = (width.saturating_sub(left) / cell).min(7);
+        let plain = (ctx.bg, &ctx.config.prompt);
+        for (column, weekday) in WEEKDAYS.iter().enumerate().take(columns as usize) {
+            let pos = (left + column as u32 * cell, top + line_height);
+            self.draw_cell(&mut buf, plain, pos, weekday)?;
+        }
+        let first = today.with_day(1).unwrap_or(today);
+        let offset = first.weekday().num_days_from_monday();
+        let mut day = Some(first);
+        while let Some(date) = day.filter(|date| date.month() == today.month()) {
+            let idx = offset + date.day0();
+            if idx % 7 < columns {
+                let pos = (left + idx % 7 * cell, top + (2 + idx / 7) * line_height);
+                let colors = if date == today {
+                    (&ctx.config.headline, ctx.bg)
+                } else {
+                    plain
+                };
+                self.draw_cell(&mut buf, colors, pos, &date.day().to_string())?;
+            }
+            day = date.succ_opt();
+        }
+        self.date = Some(today);
+
+        Ok(DrawReport {
+            width,
+            height,
+            damage: vec![buf.get_signed_bounds()],
+            full_damage: false,
+        })
+    }
+
+    fn keyboard_input(&mut self, _: Keysym, _: Modifiers, _: KeyState, _: Option<String>) {}
+    fn mouse_click(&mut self, _: u32, _: (u32, u32)) {}
+    fn mouse_scroll(&mut self, _: (f64, f64), _: (u32, u32)) {}
+    fn tick(&mut self, _: Instant) -> bool {
+        self.shown && self.date != Some(Local::now().date_naive())
+    }
+    fn on_show(&mut self) {
+        self.shown = true;
+        // The day shown before hiding may be long gone.
+        self.date = None;
+    }
+    fn on_hide(&mut self) {
+        self.shown = false;
+    }
+}
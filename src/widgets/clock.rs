@@ -0,0 +1,97 @@
+use crate::draw::Font;
+use crate::widget::{
+    layout_width, scaled, DrawContext, DrawReport, KeyState, Keysym, Modifiers, Widget,
+    DEFAULT_WIDTH,
+};
+
+use std::time::Instant;
+
+use chrono::format::{Item, StrftimeItems};
+use chrono::Local;
+use log::warn;
+use rusttype::Font as RustFont;
+
+pub struct Clock {
+    format: String,
+    font: Font,
+    text: String,
+    width: u32,
+    scale: f32,
+    // Whether the greeter is shown, for the clock to tick.
+    shown: bool,
+}
+
+impl Clock {
+    pub fn new(font: &RustFont<'static>, format: String, font_size: f32) -> Box<Clock> {
+        let format = if StrftimeItems::new(&format).any(|i| i == Item::Error) {
+            warn!("invalid clock format {:?}, using default", format);
+            "%H:%M".to_string()
+        } else {
+            format
+        };
+        Box::new(Clock {
+            format,
+            font: Font::new(font, font_size),
+            text: String::new(),
+            width: DEFAULT_WIDTH,
+            scale: 1.0,
+            shown: false,
+        })
+    }
+}
+
+impl Widget for Clock {
+    fn resize(&mut self, available: (u32, u32), scale: f32) {
+        self.scale = scale;
+        self.font.set_scale(scale);
+        self.width = layout_width(available.0, scale);
+    }
+
+    fn size(&self) -> (u32, u32) {
+        (self.width, self.font.size() as u32 + scaled(16, self.scale))
+    }
+
+    fn draw(
+        &mut self,
+        ctx: &mut DrawContext,
+        pos: (u32, u32),
+    ) -> Result<DrawReport, ::std::io::Error> {
+        let (width, height) = self.size();
+        let text = ctx.time.format(&self.format).to_string();
+        if text == self.text && !ctx.force {
+            return Ok(DrawReport::empty(width, height));
+        }
+        let mut buf = ctx.buf.subdimensions((pos.0, pos.1, width, height))?;
+        buf.memset(ctx.bg);
+
+        self.font.auto_draw_text(
+            &mut buf.offset((scaled(24, self.scale), scaled(8, self.scale)))?,
+            ctx.bg,
+            &ctx.config.headline,
+            &text,
+        )?;
+        self.text = text;
+
+        Ok(DrawReport {
+            width,
+            height,
+            damage: vec![buf.get_signed_bounds()],
+            full_damage: false,
+        })
+    }
+
+    fn keyboard_input(&mut self, _: Keysym, _: Modifiers, _: KeyState, _: Option<String>) {}
+    fn mouse_click(&mut self, _: u32, _: (u32, u32)) {}
+    fn mouse_scroll(&mut self, _: (f64, f64), _: (u32, u32)) {}
+    fn tick(&mut self, _: Instant) -> bool {
+        self.shown && Local::now().format(&self.format).to_string() != self.text
+    }
+    fn on_show(&mut self) {
+        self.shown = true;
+        // The time shown before hiding is long gone.
+        self.text.clear();
+    }
+    fn on_hide(&mut self) {
+        self.shown = false;
+    }
+}
@@ -0,0 +1,78 @@
+use crate::draw::Font;
+use crate::widget::{
+    layout_width, scaled, DrawContext, DrawReport, KeyState, Keysym, Modifiers, Widget,
+    DEFAULT_WIDTH,
+};
+
+use rusttype::Font as RustFont;
+
+/// Shows the error that stopped the greeter, in place of the usual widgets.
+pub struct Fatal {
+    message: String,
+    lines: Vec<String>,
+    font: Font,
+    width: u32,
+    scale: f32,
+}
+
+impl Fatal {
+    pub fn new(font: &RustFont<'static>, message: String, font_size: f32) -> Box<Fatal> {
+        Box::new(Fatal {
+            message,
+            lines: Vec::new(),
+            font: Font::new(font, font_size),
+            width: DEFAULT_WIDTH,
+            scale: 1.0,
+        })
+    }
+}
+
+impl Widget for Fatal {
+    fn resize(&mut self, available: (u32, u32), scale: f32) {
+        self.scale = scale;
+        self.font.set_scale(scale);
+        self.width = layout_width(available.0, scale);
+        self.lines = self
+            .font
+            .wrap(&self.message, self.width.saturating_sub(scaled(48, scale)));
+    }
+
+    fn size(&self) -> (u32, u32) {
+        (
+            self.width,
+            self.lines.len() as u32 * self.font.size() as u32 + scaled(24, self.scale),
+        )
+    }
+
+    fn draw(
+        &mut self,
+        ctx: &mut DrawContext,
+        pos: (u32, u32),
+    ) -> Result<DrawReport, ::std::io::Error> {
+        let (width, height) = self.size();
+        let mut buf = ctx.buf.subdimensions((pos.0, pos.1, width, height))?;
+        buf.memset(ctx.bg);
+
+        let s = self.scale;
+        let line_height = self.font.size() as u32;
+        for (idx, line) in self.lines.iter().enumerate() {
+            self.font.auto_draw_text(
+                &mut buf.offset((scaled(24, s), scaled(12, s) + idx as u32 * line_height))?,
+                ctx.bg,
+                &ctx.config.prompt_err,
+                line,
+            )?;
+        }
+
+        Ok(DrawReport {
+            width,
+            height,
+            damage: vec![buf.get_signed_bounds()],
+            full_damage: false,
+        })
+    }
+
+    fn keyboard_input(&mut self, _: Keysym, _: Modifiers, _: KeyState, _: Option<String>) {}
+    fn mouse_click(&mut self, _: u32, _: (u32, u32)) {}
+    fn mouse_scroll(&mut self, _: (f64, f64), _: (u32, u32)) {}
+}
@@ -0,0 +1,114 @@
+use crate::draw::Font;
+use crate::widget::{
+    layout_width, scaled, DrawContext, DrawReport, KeyState, Keysym, Modifiers, Widget,
+    DEFAULT_WIDTH,
+};
+
+use std::fs::read_to_string;
+
+use nix::sys::utsname::uname;
+use nix::unistd::gethostname;
+use rusttype::Font as RustFont;
+
+// Where the distribution is described, in order of preference.
+const OS_RELEASE: [&str; 2] = ["/etc/os-release", "/usr/lib/os-release"];
+
+// PRETTY_NAME from os-release, without its quotes.
+fn pretty_name() -> Option<String> {
+    let contents = OS_RELEASE
+        .iter()
+        .find_map(|path| read_to_string(path).ok())?;
+    contents.lines().find_map(|line| {
+        let value = line.strip_prefix("PRETTY_NAME=")?.trim();
+        let value = value
+            .strip_prefix('"')
+            .and_then(|v| v.strip_suffix('"'))
+            .or_else(|| value.strip_prefix('\'').and_then(|v| v.strip_suffix('\'')))
+            .unwrap_or(value);
+        Some(value.to_string()).filter(|v| !v.is_empty())
+    })
+}
+
+// The hostname, distribution and kernel, leaving out what is unknown.
+fn host_info() -> String {
+    let mut parts = Vec::new();
+    if let Ok(hostname) = gethostname() {
+        parts.push(hostname.to_string_lossy().into_owned());
+    }
+    parts.extend(pretty_name());
+    if let Ok(uts) = uname() {
+        parts.push(format!(
+            "{} {}",
+            uts.sysname().to_string_lossy(),
+            uts.release().to_string_lossy()
+        ));
+    }
+    parts.join(" · ")
+}
+
+/// Shows the hostname, the distribution from os-release and the kernel
+/// version on a single line, to tell apart machines that look the same.
+pub struct HostInfo {
+    text: String,
+    font: Font,
+    width: u32,
+    scale: f32,
+}
+
+impl HostInfo {
+    pub fn new(font: &RustFont<'static>) -> Box<HostInfo> {
+        Box::new(HostInfo {
+            text: host_info(),
+            font: Font::new(font, 16.0),
+            width: DEFAULT_WIDTH,
+            scale: 1.0,
+        })
+    }
+}
+
+impl Widget for HostInfo {
+    fn resize(&mut self, available: (u32, u32), scale: f32) {
+        self.scale = scale;
+        self.font.set_scale(scale);
+        self.width = layout_width(available.0, scale);
+    }
+
+    fn size(&self) -> (u32, u32) {
+        (self.width, self.font.size() as u32 + scaled(16, self.scale))
+    }
+
+    fn draw(
+        &mut self,
+        ctx: &mut DrawContext,
+        pos: (u32, u32),
+    ) -> Result<DrawReport, ::std::io::Error> {
+        let (width, height) = self.size();
+        if !ctx.force {
+            return Ok(DrawReport::empty(width, height));
+        }
+        let mut buf = ctx.buf.subdimensions((pos.0, pos.1, width, height))?;
+        buf.memset(ctx.bg);
+
+        let s = self.scale;
+        let text = self
+            .font
+            .ellipsize(&self.text, width.saturating_sub(scaled(48, s)));
+        self.font.auto_draw_text(
+            &mut buf.offset((scaled(24, s), scaled(8, s)))?,
+            ctx.bg,
+            &ctx.config.prompt,
+            &text,
+        )?;
+
+        Ok(DrawReport {
+            width,
+            height,
+            damage: vec![buf.get_signed_bounds()],
+            full_damage: false,
+        })
+    }
+
+    fn keyboard_input(&mut self, _: Keysym, _: Modifiers, _: KeyState, _: Option<String>) {}
+    fn mouse_click(&mut self, _: u32, _: (u32, u32)) {}
+    fn mouse_scroll(&mut self, _: (f64, f64), _: (u32, u32)) {}
+}
@@ -1,307 +1,1495 @@
-use crate::draw::{draw_box, Font, DEJAVUSANS_MONO};
-use crate::widget::{DrawContext, DrawReport, KeyState, ModifiersState, Widget};
+#[cfg(feature = "dbus")]
+use crate::accounts;
+use crate::animation::{Animation, Blink, Easing};
+use crate::avatar::Avatar;
+use crate::cmd::Cmd;
+use crate::color::Color;
+use crate::config::{
+    session_argv, session_env, BorderStyle, Config, SecretEcho, SessionConfig, UserSource,
+};
+use crate::draw::{draw_rounded_box, draw_shadow, draw_spinner, Font};
+use crate::greetd::{Greetd, Pending};
+use crate::history::SessionHistory;
+use crate::keybindings::Action;
+use crate::secret::{SecretString, Zeroize};
+use crate::widget::{
+    layout_width, scaled, DrawContext, DrawReport, KeyState, Keysym, Modifiers, Widget,
+    DEFAULT_WIDTH,
+};
+use crate::widgets::session::session_name;
 
-use std::env;
-use std::error::Error;
-use std::os::unix::net::UnixStream;
+use calloop::channel::Sender;
+use std::f32::consts::PI;
+use std::fs::read_to_string;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
-use smithay_client_toolkit::seat::keyboard::keysyms;
+use rusttype::Font as RustFont;
 
-use greetd_ipc::{codec::SyncCodec, AuthMessageType, ErrorType, Request, Response};
+use greetd_ipc::{AuthMessageType, ErrorType, Request, Response};
 
-pub trait Scrambler {
-    fn scramble(&mut self);
+use log::{error, warn};
+use nix::unistd::{getuid, User};
+use unicode_segmentation::{GraphemeCursor, UnicodeSegmentation};
+
+const PAM_SERVICE: &str = "wlgreet";
+
+// How long and how many times the prompt shakes after a failed login.
+const SHAKE: Duration = Duration::from_millis(400);
+const SHAKE_CYCLES: f32 = 3.0;
+
+// Dots shown for a password of any length with secretEcho = "fixed".
+const FIXED_ECHO: usize = 8;
+
+// How long the last character typed into a password shows with revealLast.
+const REVEAL: Duration = Duration::from_secs(1);
+
+// How long the border and the error message stay in the error color after a
+// failed login, before turning back.
+const FLASH: Duration = Duration::from_millis(1500);
+
+/// A user offered in the user list.
+#[derive(Clone, Debug)]
+pub struct Account {
+    pub name: String,
+    /// Full name shown in place of the login name, if not empty.
+    pub real_name: String,
+    /// Picture of the user, if known to the source of the list.
+    pub icon: Option<PathBuf>,
 }
 
-impl<T: Default> Scrambler for Vec<T> {
-    fn scramble(&mut self) {
-        let cap = self.capacity();
-        self.truncate(0);
-        for _ in 0..cap {
-            self.push(Default::default())
+impl Account {
+    fn label(&self) -> &str {
+        if self.real_name.is_empty() {
+            &self.name
+        } else {
+            &self.real_name
         }
-        self.truncate(0);
     }
 }
 
-impl Scrambler for String {
-    fn scramble(&mut self) {
-        let cap = self.capacity();
-        self.truncate(0);
-        for _ in 0..cap {
-            self.push(Default::default())
+/// Returns the users in /etc/passwd with a UID within the given range and a
+/// shell that permits logging in.
+pub fn local_users(min_uid: u32, max_uid: u32) -> Vec<Account> {
+    let passwd = match read_to_string("/etc/passwd") {
+        Ok(s) => s,
+        Err(_) => return Vec::new(),
+    };
+    let mut users: Vec<Account> = passwd
+        .lines()
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split(':').collect();
+            if fields.len() < 7 {
+                return None;
+            }
+            let uid: u32 = fields[2].parse().ok()?;
+            let shell = fields[6];
+            if uid < min_uid
+                || uid > max_uid
+                || shell.ends_with("/nologin")
+                || shell.ends_with("/false")
+            {
+                return None;
+            }
+            Some(Account {
+                name: fields[0].to_string(),
+                real_name: String::new(),
+                icon: None,
+            })
+        })
+        .collect();
+    users.sort_by(|a, b| a.name.cmp(&b.name));
+    users
+}
+
+// The users offered in the user list, from the configured source, falling
+// back to /etc/passwd.
+fn list_users(config: &Config) -> Vec<Account> {
+    match config.user_source {
+        #[cfg(feature = "dbus")]
+        UserSource::AccountsService => match accounts::list() {
+            Ok(accounts) => return accounts,
+            Err(e) => warn!("Unable to list users with AccountsService: {}", e),
+        },
+        #[cfg(not(feature = "dbus"))]
+        UserSource::AccountsService => {
+            warn!("userSource is accountsService, but wlgreet was built without the dbus feature")
         }
-        self.truncate(0);
+        UserSource::Passwd => (),
     }
+    local_users(config.min_uid, config.max_uid)
+}
+
+/// What the login widget is doing, as reported over D-Bus.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum LoginState {
+    /// Waiting for someone to start typing a username.
+    Idle,
+    /// Answering a question, be it the username or one from PAM.
+    Prompting,
+    /// Waiting on greetd.
+    Authenticating,
+}
+
+impl LoginState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LoginState::Idle => "idle",
+            LoginState::Prompting => "prompting",
+            LoginState::Authenticating => "authenticating",
+        }
+    }
+}
+
+// Returns the digit on a keypad key pressed with NumLock off, so that PINs
+// can be typed on the keypad either way.
+fn keypad_digit(key: Keysym) -> Option<char> {
+    Some(match key {
+        Keysym::KP_Insert => '0',
+        Keysym::KP_End => '1',
+        Keysym::KP_Down => '2',
+        Keysym::KP_Next => '3',
+        Keysym::KP_Left => '4',
+        Keysym::KP_Begin => '5',
+        Keysym::KP_Right => '6',
+        Keysym::KP_Home => '7',
+        Keysym::KP_Up => '8',
+        Keysym::KP_Prior => '9',
+        _ => return None,
+    })
+}
+
+// Editing the session command in place of the username, opened with
+// Ctrl+Alt+E.
+#[derive(PartialEq)]
+enum CommandEdit {
+    Editing,
+    // Waiting for the edited command to be confirmed.
+    Confirming,
+}
+
+// The start of the grapheme cluster before cursor in text, or cursor if
+// there is none.
+fn prev_grapheme(text: &str, cursor: usize) -> usize {
+    GraphemeCursor::new(cursor, text.len(), true)
+        .prev_boundary(text, 0)
+        .ok()
+        .flatten()
+        .unwrap_or(cursor)
+}
+
+// The end of the grapheme cluster after cursor in text, or cursor if there
+// is none.
+fn next_grapheme(text: &str, cursor: usize) -> usize {
+    GraphemeCursor::new(cursor, text.len(), true)
+        .next_boundary(text, 0)
+        .ok()
+        .flatten()
+        .unwrap_or(cursor)
 }
 
 pub struct Login {
     question: String,
-    answer: String,
-    command: String,
+    // The question split at newlines and wrapped to the width of the
+    // widget. The answer goes after the last line.
+    question_lines: Vec<String>,
+    answer: SecretString,
+    // Byte offset of the caret within answer.
+    cursor: usize,
+    blink: Blink,
+    // Whether the blinking caret was shown when last drawn.
+    caret_shown: bool,
+    // Shows the character before the caret while running, if it was just
+    // typed into a password.
+    reveal: Option<Animation>,
+    reveal_last: bool,
+    // Headline of the box, if any, and the question asking for the username.
+    greeting: String,
+    username_prompt: String,
+    // Shakes the prompt after a failed login.
+    shake: Option<Animation>,
+    // Turns the border and error message back from the error color after a
+    // failed login.
+    flash: Option<Animation>,
+    animations: bool,
+    // Color and unscaled width of the shadow around the box.
+    shadow: Option<(Color, u32)>,
+    command: Arc<Mutex<String>>,
     mode: Option<AuthMessageType>,
     error: String,
+    // Status message from the control socket, shown in place of the error
+    // while there is none.
+    message: String,
     headline_font: Font,
     prompt_font: Font,
+    hint_font: Font,
+    // Caps Lock and Num Lock state as last drawn.
+    locks: (bool, bool),
+    // Keyboard layout as last drawn.
+    layout: Option<String>,
+    // Whether typing goes to the prompt rather than another widget.
+    focused: bool,
+    // The touch point down on the widget and where it is, which taps it
+    // there when going up.
+    touch: Option<(i32, (u32, u32))>,
     dirty: bool,
-    stream: Option<UnixStream>,
+    greetd: Greetd,
+    // Spinner frame as last drawn.
+    frame: Option<u32>,
+    // State as last reported to the main loop.
+    state: LoginState,
+    users: Vec<Account>,
+    // Selected entry of the user list, with users.len() being "other…".
+    // None when entering the username as free text.
+    user_select: Option<usize>,
+    speech: bool,
+    // Whether to show pictures of users, and the user and their picture as
+    // last looked up.
+    avatars: bool,
+    avatar: Option<(String, Option<Avatar>)>,
+    // Question, selected user, error and message as last announced.
+    announced: [String; 4],
+    // Where the answer, or the user list entry in its place, was last
+    // drawn, as (x, y, width, height).
+    answer_rect: (u32, u32, u32, u32),
+    tx: Sender<Cmd>,
+    // The user to authenticate when locking the session instead of
+    // acting as a greeter, whether to start authenticating them once no
+    // longer waiting, and whether PAM asked anything since it last started.
+    lock_user: Option<String>,
+    start_unlock: bool,
+    asked: bool,
+    // The user a session was last created for, and the sessions users
+    // last started.
+    username: String,
+    history: SessionHistory,
+    // Environment of the started session, and that of configured sessions.
+    env: Vec<String>,
+    sessions: Vec<SessionConfig>,
+    command_shell: String,
+    command_override: bool,
+    command_edit: Option<CommandEdit>,
+    // Output the keyboard was focused on when last drawn, if known.
+    output: Option<String>,
+    autologin: Option<Autologin>,
+    // How long a half-entered login is kept without input, and when the
+    // last input came.
+    input_timeout: Option<Duration>,
+    last_input: Instant,
+    width: u32,
+    scale: f32,
+}
+
+struct Autologin {
+    user: String,
+    session: Option<String>,
+    deadline: Instant,
+    // Seconds left as last drawn.
+    remaining: u64,
+}
+
+impl Autologin {
+    fn remaining(&self) -> u64 {
+        let left = self.deadline.saturating_duration_since(Instant::now());
+        left.as_secs() + if left.subsec_nanos() > 0 { 1 } else { 0 }
+    }
 }
 
 impl Login {
-    pub fn new(cmd: String) -> Box<Login> {
+    pub fn new(
+        font: &RustFont<'static>,
+        cmd: Arc<Mutex<String>>,
+        tx: Sender<Cmd>,
+        config: &Config,
+    ) -> Box<Login> {
+        // When locking, PAM takes the place of greetd.
+        let greetd = if config.lock {
+            Greetd::spawn_pam(PAM_SERVICE, tx.clone())
+        } else {
+            Greetd::spawn(tx.clone())
+        };
+        let autologin = match config.autologin_user {
+            Some(ref user) if !config.lock => {
+                let delay = config.autologin_delay;
+                Some(Autologin {
+                    user: user.to_string(),
+                    session: config.autologin_session.clone(),
+                    deadline: Instant::now() + Duration::from_secs(delay as u64),
+                    remaining: delay as u64,
+                })
+            }
+            _ => None,
+        };
         let mut l = Login {
             question: String::new(),
-            answer: String::new(),
+            question_lines: Vec::new(),
+            answer: SecretString::new(),
+            cursor: 0,
+            blink: Blink::new(config.animations),
+            caret_shown: true,
+            reveal: None,
+            reveal_last: config.reveal_last,
+            greeting: config.greeting.clone(),
+            username_prompt: config.username_prompt.clone(),
+            shake: None,
+            flash: None,
+            animations: config.animations,
+            shadow: match config.shadow_size {
+                0 => None,
+                size => Some((config.shadow, size)),
+            },
             command: cmd,
             mode: None,
             error: "".to_string(),
-            headline_font: Font::new(&DEJAVUSANS_MONO, 72.0),
-            prompt_font: Font::new(&DEJAVUSANS_MONO, 32.0),
+            message: "".to_string(),
+            headline_font: Font::new(font, config.headline_size),
+            prompt_font: Font::new(font, config.prompt_size),
+            hint_font: Font::new(font, config.prompt_size / 2.0),
+            locks: (false, false),
+            layout: None,
+            focused: false,
+            touch: None,
             dirty: false,
-            stream: None,
+            greetd,
+            frame: None,
+            state: LoginState::Idle,
+            users: if config.user_list && !config.lock {
+                list_users(config)
+            } else {
+                Vec::new()
+            },
+            user_select: None,
+            speech: config.speech,
+            avatars: config.avatar,
+            avatar: None,
+            announced: Default::default(),
+            answer_rect: (0, 0, 0, 0),
+            tx,
+            lock_user: if config.lock {
+                // Without a name, PAM turns the user down with an error
+                // shown on the prompt.
+                Some(match User::from_uid(getuid()) {
+                    Ok(Some(user)) => user.name,
+                    _ => {
+                        error!("Unable to look up the current user");
+                        String::new()
+                    }
+                })
+            } else {
+                None
+            },
+            start_unlock: config.lock,
+            asked: false,
+            autologin,
+            username: String::new(),
+            history: SessionHistory::load(if config.lock {
+                ""
+            } else {
+                &config.session_cache
+            }),
+            env: config.env.clone(),
+            sessions: config.sessions.clone(),
+            command_shell: config.command_shell.clone(),
+            command_override: config.command_override,
+            command_edit: None,
+            output: None,
+            input_timeout: match config.input_timeout {
+                0 => None,
+                secs => Some(Duration::from_secs(secs as u64)),
+            },
+            last_input: Instant::now(),
+            width: DEFAULT_WIDTH,
+            scale: 1.0,
         };
         l.reset();
         Box::new(l)
     }
 
+    // Space around the box for the shadow.
+    fn shadow_pad(&self) -> u32 {
+        self.shadow.map_or(0, |(_, size)| scaled(size, self.scale))
+    }
+
+    fn prompt_y(&self) -> u32 {
+        // Without a headline, there is still room for the error message.
+        let headline = if self.greeting.is_empty() {
+            self.prompt_font.size()
+        } else {
+            self.headline_font.size()
+        };
+        scaled(16, self.scale) + headline as u32 + scaled(24, self.scale)
+    }
+
+    // The vertical position of the last line of the question, which the
+    // answer is drawn next to.
+    fn answer_y(&self) -> u32 {
+        let lines = self.question_lines.len().max(1) as u32;
+        self.prompt_y() + (lines - 1) * self.prompt_font.size() as u32
+    }
+
+    fn wrap_question(&mut self) {
+        let width = self.width.saturating_sub(scaled(48, self.scale));
+        let font = &self.prompt_font;
+        self.question_lines = self
+            .question
+            .trim_end()
+            .lines()
+            .flat_map(|line| font.wrap(line, width))
+            .collect();
+    }
+
     fn reset(&mut self) {
-        self.question = "username:".to_string();
-        self.answer = String::new();
+        self.command_edit = None;
+        self.question = self.username_prompt.clone();
+        self.answer.clear();
+        self.cursor = 0;
+        self.user_select = if self.users.is_empty() { None } else { Some(0) };
+        if self.lock_user.is_some() {
+            // PAM asks its own questions.
+            self.question.clear();
+        }
+        self.wrap_question();
     }
 
-    fn cancel(&mut self) -> Result<(), Box<dyn Error>> {
-        let stream = match self.stream {
-            Some(ref mut s) => s,
-            None => {
-                self.stream = Some(UnixStream::connect(
-                    env::var("GREETD_SOCK").expect("GREETD_SOCK not set"),
-                )?);
-                self.stream.as_mut().unwrap()
+    // Starts authenticating the locking user when asked to, once done
+    // with the last attempt.
+    fn poll_unlock(&mut self) {
+        if !self.start_unlock || self.waiting() {
+            return;
+        }
+        self.start_unlock = false;
+        self.asked = false;
+        self.send(Request::CreateSession {
+            username: self.lock_user.clone().unwrap_or_default(),
+        });
+        self.dirty = true;
+    }
+
+    // The user known to be logging in, either picked from the list or
+    // being authenticated.
+    fn known_user(&self) -> Option<&str> {
+        if let Some(ref user) = self.lock_user {
+            return Some(user);
+        }
+        if self.mode.is_some() || self.waiting() {
+            return Some(self.username.as_str()).filter(|u| !u.is_empty());
+        }
+        match self.user_select {
+            Some(idx) => self.users.get(idx).map(|u| u.name.as_str()),
+            None => None,
+        }
+    }
+
+    // The entry of the user list to log in as, if picking from it.
+    fn selected_user(&self) -> Option<&str> {
+        match self.user_select {
+            Some(idx) if self.mode.is_none() => {
+                Some(self.users.get(idx).map(|u| u.label()).unwrap_or("other…"))
             }
-        };
-        Request::CancelSession.write_to(stream)?;
-        match Response::read_from(stream)? {
-            Response::AuthMessage { .. } => panic!("unexpected message"),
-            Response::Success => Ok(()),
-            Response::Error {
-                error_type,
-                description,
-            } => {
-                eprintln!("err: {:?}: {}", error_type, description);
-                std::process::exit(-1);
+            _ => None,
+        }
+    }
+
+    // Asks for what changed since last announced to be spoken.
+    fn announce(&mut self) {
+        let current = [
+            self.question.clone(),
+            self.selected_user().unwrap_or("").to_string(),
+            self.error.clone(),
+            self.message.clone(),
+        ];
+        for (text, last) in current.iter().zip(self.announced.iter_mut()) {
+            if text != last {
+                if !text.is_empty() {
+                    let _ = self.tx.send(Cmd::Speak(text.clone()));
+                }
+                *last = text.clone();
             }
         }
     }
 
-    fn communicate(&mut self) -> Result<(), Box<dyn Error>> {
-        let req = match self.mode {
-            None => Request::CreateSession {
-                username: self.answer.to_string(),
+    // Whether the answer is being edited, showing a caret.
+    fn editing(&self) -> bool {
+        match self.mode {
+            None => self.user_select.is_none() && self.lock_user.is_none(),
+            Some(AuthMessageType::Visible) | Some(AuthMessageType::Secret) => true,
+            _ => false,
+        }
+    }
+
+    fn start_shake(&mut self) {
+        if self.animations {
+            self.shake = Some(Animation::new(SHAKE, Easing::Linear));
+        }
+    }
+
+    fn start_flash(&mut self) {
+        self.flash = Some(Animation::new(FLASH, Easing::EaseIn));
+    }
+
+    // How far the border and error message have turned back from the error
+    // color, from 0 to 1. Without animations they turn back all at once.
+    fn flash_level(&self, now: Instant) -> f32 {
+        match self.flash {
+            Some(ref flash) if self.animations => flash.value(now),
+            Some(ref flash) if !flash.done(now) => 0.0,
+            _ => 1.0,
+        }
+    }
+
+    // Horizontal offset of the prompt while it shakes, dying down towards
+    // the end.
+    fn shake_offset(&self, now: Instant) -> i32 {
+        match self.shake {
+            Some(ref shake) => {
+                let t = shake.value(now);
+                let amplitude = scaled(12, self.scale) as f32 * (1.0 - t);
+                (amplitude * (t * SHAKE_CYCLES * 2.0 * PI).sin()).round() as i32
+            }
+            None => 0,
+        }
+    }
+
+    // Starts editing the session command, while asking for the username.
+    fn edit_command(&mut self) {
+        if !self.command_override
+            || self.lock_user.is_some()
+            || self.mode.is_some()
+            || self.waiting()
+            || self.command_edit.is_some()
+        {
+            return;
+        }
+        let cmd = self.command.lock().unwrap().to_string();
+        self.command_edit = Some(CommandEdit::Editing);
+        self.user_select = None;
+        self.question = "session command:".to_string();
+        self.answer.set(&cmd);
+        self.cursor = self.answer.len();
+        self.error.clear();
+        self.wrap_question();
+        self.dirty = true;
+    }
+
+    // Confirms or leaves the session command being edited.
+    fn command_edit_action(&mut self, action: Action) {
+        match (action, &self.command_edit) {
+            (Action::Reset, Some(CommandEdit::Confirming)) => {
+                self.command_edit = Some(CommandEdit::Editing);
+                self.question = "session command:".to_string();
+            }
+            (Action::Reset, _) => self.reset(),
+            (Action::Submit, Some(CommandEdit::Editing)) => {
+                if self.answer.trim().is_empty() {
+                    return;
+                }
+                self.command_edit = Some(CommandEdit::Confirming);
+                self.question =
+                    "start sessions with this command? enter to confirm, escape to edit:"
+                        .to_string();
+            }
+            (Action::Submit, _) => {
+                *self.command.lock().unwrap() = self.answer.trim().to_string();
+                self.reset();
+            }
+            _ => return,
+        }
+        self.wrap_question();
+        self.dirty = true;
+    }
+
+    // Answers the prompt, or picks the selected entry of the user list.
+    fn submit(&mut self) {
+        if let Some(idx) = self.user_select.filter(|_| self.mode.is_none()) {
+            self.user_select = None;
+            self.dirty = true;
+            match self.users.get(idx) {
+                Some(user) => {
+                    self.answer.set(&user.name);
+                    self.cursor = self.answer.len();
+                }
+                // "other…" starts free text entry.
+                None => return,
+            }
+        }
+        if self.lock_user.is_some() && self.mode.is_none() {
+            // Tries again once PAM gave up without asking anything.
+            self.start_unlock = true;
+            return;
+        }
+        if self.waiting() {
+            return;
+        }
+        if self.mode.is_none() {
+            // Pick the session the user last started, which can still be
+            // changed before logging in.
+            if let Some(cmd) = self.history.get(&self.answer) {
+                *self.command.lock().unwrap() = cmd.to_string();
+            }
+        }
+        self.communicate();
+        self.dirty = true;
+        self.clear_answer();
+        self.error.clear();
+    }
+
+    // Drops what was typed and any error, cancelling the session being
+    // authenticated, and goes back to the first prompt.
+    fn start_over(&mut self) {
+        self.cancel();
+        self.clear_answer();
+        self.error.clear();
+        self.reset();
+        self.start_unlock = self.lock_user.is_some();
+        self.dirty = true;
+    }
+
+    fn clear_answer(&mut self) {
+        self.answer.clear();
+        self.cursor = 0;
+    }
+
+    fn insert(&mut self, s: &str) {
+        // Nothing is typed when locked until PAM asks for it, as it might not
+        // be something to hide.
+        if self.lock_user.is_some() && self.mode.is_none() {
+            return;
+        }
+        self.answer.insert_str(self.cursor, s);
+        self.cursor += s.len();
+    }
+
+    fn user_list_input(&mut self, key: Keysym, interpreted: &Option<String>) -> bool {
+        let idx = match self.user_select {
+            Some(idx) if self.mode.is_none() => idx,
+            _ => return false,
+        };
+        let entries = self.users.len() + 1;
+        match key {
+            Keysym::Up => self.user_select = Some((idx + entries - 1) % entries),
+            Keysym::Down => self.user_select = Some((idx + 1) % entries),
+            _ => match interpreted {
+                Some(v) if !v.chars().any(char::is_control) => {
+                    // Typing starts free text entry
+                    self.user_select = None;
+                    return false;
+                }
+                _ => return true,
             },
+        }
+        self.dirty = true;
+        true
+    }
+
+    fn send(&mut self, req: Request) {
+        self.greetd.send(req);
+    }
+
+    // Returns true while waiting on greetd for anything but a cancellation.
+    fn authenticating(&self) -> bool {
+        self.greetd.busy()
+    }
+
+    fn state(&self) -> LoginState {
+        if self.authenticating() {
+            LoginState::Authenticating
+        } else if self.mode.is_some() || !self.answer.is_empty() {
+            LoginState::Prompting
+        } else {
+            LoginState::Idle
+        }
+    }
+
+    // Returns true while waiting on greetd for anything at all.
+    fn waiting(&self) -> bool {
+        !self.greetd.idle()
+    }
+
+    fn cancel(&mut self) {
+        if self.mode.is_some() || self.waiting() {
+            self.greetd.cancel();
+        }
+        self.mode = None;
+    }
+
+    fn fail(&mut self, error: String) {
+        self.mode = None;
+        self.reset();
+        self.error = error;
+        self.start_shake();
+        self.start_flash();
+        self.send(Request::CancelSession);
+    }
+
+    fn communicate(&mut self) {
+        let req = match self.mode {
+            None => {
+                self.username = self.answer.to_string();
+                Request::CreateSession {
+                    username: self.answer.to_string(),
+                }
+            }
             Some(_) => Request::PostAuthMessageResponse {
                 response: Some(self.answer.to_string()),
             },
         };
-        let stream = match self.stream {
-            Some(ref mut s) => s,
-            None => {
-                self.stream = Some(UnixStream::connect(
-                    env::var("GREETD_SOCK").expect("GREETD_SOCK not set"),
-                )?);
-                self.stream.as_mut().unwrap()
+        self.send(req);
+    }
+
+    fn poll_autologin(&mut self) {
+        let remaining = match self.autologin {
+            Some(ref autologin) => autologin.remaining(),
+            None => return,
+        };
+        if remaining > 0 {
+            let autologin = self.autologin.as_mut().unwrap();
+            if autologin.remaining != remaining {
+                autologin.remaining = remaining;
+                self.dirty = true;
             }
+            return;
+        }
+        let autologin = self.autologin.take().unwrap();
+        if let Some(session) = autologin.session {
+            *self.command.lock().unwrap() = session;
+        }
+        self.user_select = None;
+        self.answer.set(&autologin.user);
+        self.cursor = self.answer.len();
+        self.communicate();
+        self.clear_answer();
+        self.dirty = true;
+    }
+
+    fn cancel_autologin(&mut self) -> bool {
+        if self.autologin.take().is_some() {
+            self.dirty = true;
+            return true;
+        }
+        false
+    }
+
+    fn poll_responses(&mut self) {
+        while let Some((pending, res)) = self.greetd.try_recv() {
+            self.handle_response(pending, res);
+            // A new question deserves the full time to answer it.
+            self.last_input = Instant::now();
+            self.dirty = true;
+        }
+    }
+
+    // Starts over once a login has been left half-entered for inputTimeout,
+    // so that nobody else can pick it up.
+    fn poll_input_timeout(&mut self, now: Instant) {
+        let timeout = match self.input_timeout {
+            Some(timeout) => timeout,
+            None => return,
         };
-        req.write_to(stream)?;
+        let started = !self.answer.is_empty() || (self.mode.is_some() && self.lock_user.is_none());
+        if !started || self.authenticating() || now < self.last_input + timeout {
+            return;
+        }
+        self.cancel();
+        self.clear_answer();
+        self.error.clear();
+        self.reset();
+        self.start_unlock = self.lock_user.is_some();
+        self.dirty = true;
+    }
 
-        match Response::read_from(stream)? {
-            Response::AuthMessage {
-                auth_message,
-                auth_message_type,
-            } => {
+    fn handle_response(&mut self, pending: Pending, res: Result<Response, String>) {
+        let res = match res {
+            Ok(res) => res,
+            Err(e) => {
+                if pending != Pending::Cancel {
+                    self.fail(e);
+                }
+                return;
+            }
+        };
+        match (pending, res) {
+            // The session is gone either way.
+            (Pending::Cancel, _) => (),
+            (
+                _,
+                Response::AuthMessage {
+                    auth_message,
+                    auth_message_type,
+                },
+            ) => {
                 self.question = auth_message;
-                self.question.make_ascii_lowercase();
+                self.wrap_question();
                 self.mode = Some(auth_message_type);
+                self.asked = true;
             }
-            Response::Success => {
-                Request::StartSession {
-                    cmd: vec![self.command.to_string()],
-                    env: Vec::new(),
-                }
-                .write_to(stream)?;
-
-                match Response::read_from(stream)? {
-                    Response::Success => std::process::exit(0),
-                    Response::Error {
-                        error_type,
-                        description,
-                    } => match error_type {
-                        ErrorType::AuthError => return Err("Login failed".into()),
-                        ErrorType::Error => {
-                            eprintln!("err: {}", description);
-                            std::process::exit(-1);
-                        }
-                    },
-                    _ => panic!("unexpected message"),
-                }
+            (Pending::Auth, Response::Success) if self.lock_user.is_some() => {
+                self.mode = None;
+                self.error.clear();
+                let _ = self.tx.send(Cmd::Unlock);
             }
-            Response::Error {
-                error_type,
-                description,
-            } => match error_type {
-                ErrorType::AuthError => return Err("Login failed".into()),
-                ErrorType::Error => {
-                    eprintln!("err: {}", description);
-                    std::process::exit(-1);
+            (Pending::Auth, Response::Success) => {
+                let cmd = self.command.lock().unwrap().to_string();
+                let session = session_name(&cmd, &self.sessions);
+                let vars = [
+                    ("username", self.username.as_str()),
+                    ("session", session.as_str()),
+                    ("output", self.output.as_deref().unwrap_or("")),
+                ];
+                match session_argv(&cmd, &self.command_shell, &vars) {
+                    Ok(argv) => {
+                        let env = session_env(&self.env, &self.sessions, &cmd);
+                        self.send(Request::StartSession { cmd: argv, env });
+                    }
+                    Err(e) => {
+                        error!("Unable to start session {:?}: {}", cmd, e);
+                        self.fail(format!("Invalid session command: {}", e));
+                    }
                 }
-            },
+            }
+            (Pending::Start, Response::Success) => {
+                let cmd = self.command.lock().unwrap().to_string();
+                self.history.remember(&self.username, &cmd);
+                std::process::exit(0)
+            }
+            (
+                _,
+                Response::Error {
+                    error_type: ErrorType::AuthError,
+                    description,
+                },
+            ) if self.lock_user.is_some() => {
+                self.fail(description);
+                // Asking again right away is pointless if PAM turned the
+                // user down without asking anything.
+                self.start_unlock = self.asked;
+            }
+            (
+                _,
+                Response::Error {
+                    error_type: ErrorType::AuthError,
+                    ..
+                },
+            ) => {
+                self.fail("Login failed".to_string());
+            }
+            (_, Response::Error { description, .. }) if self.lock_user.is_some() => {
+                error!("PAM error: {}", description);
+                self.fail(description);
+            }
+            (_, Response::Error { description, .. }) => {
+                let _ = self.tx.send(Cmd::GreetdFailed(description));
+            }
         }
-        Ok(())
     }
 }
 
 impl Widget for Login {
+    fn resize(&mut self, available: (u32, u32), scale: f32) {
+        self.scale = scale;
+        self.headline_font.set_scale(scale);
+        self.prompt_font.set_scale(scale);
+        self.hint_font.set_scale(scale);
+        self.width = layout_width(available.0, scale);
+        if available.0 > 0 {
+            // Make room for the shadow within the given width.
+            self.width = self.width.saturating_sub(2 * self.shadow_pad());
+        }
+        self.wrap_question();
+    }
+
     fn size(&self) -> (u32, u32) {
-        (512, 176)
+        let pad = self.shadow_pad();
+        (
+            self.width + 2 * pad,
+            self.answer_y() + 2 * self.prompt_font.size() as u32 + 2 * pad,
+        )
     }
 
     fn draw(
         &mut self,
         ctx: &mut DrawContext,
-        _pos: (u32, u32),
+        pos: (u32, u32),
     ) -> Result<DrawReport, ::std::io::Error> {
-        let (width, height) = self.size();
+        let (outer_width, outer_height) = self.size();
+        let pad = self.shadow_pad();
+        let (width, height) = (outer_width - 2 * pad, outer_height - 2 * pad);
+        let s = self.scale;
+        let prompt_height = 2 * self.prompt_font.size() as u32;
+        let lines = self.question_lines.len();
+        if self.output.as_deref() != ctx.output {
+            self.output = ctx.output.map(|o| o.to_string());
+        }
+        self.poll_responses();
+        self.poll_unlock();
+        if self.question_lines.len() != lines {
+            // The new question changes our height, so get laid out again
+            // before drawing it.
+            let _ = self.tx.send(Cmd::Draw);
+            return Ok(DrawReport::empty(outer_width, outer_height));
+        }
+        let state = self.state();
+        if state != self.state {
+            self.state = state;
+            let _ = self.tx.send(Cmd::State(state));
+        }
+        if self.speech {
+            self.announce();
+        }
+        let prompt_y = self.answer_y();
+        let frame = if self.authenticating() {
+            Some((ctx.time.timestamp_millis() / 100 % 8) as u32)
+        } else {
+            None
+        };
+        if frame != self.frame {
+            self.frame = frame;
+            self.dirty = true;
+        }
+        let locks = (ctx.modifiers.caps_lock, ctx.modifiers.num_lock);
+        if locks != self.locks {
+            self.locks = locks;
+            self.dirty = true;
+        }
+        if ctx.layout != self.layout.as_deref() {
+            self.layout = ctx.layout.map(|l| l.to_string());
+            self.dirty = true;
+        }
+        let now = Instant::now();
+        let (caret_shown, blink_at) = if !ctx.focused {
+            (false, None)
+        } else if self.editing() {
+            self.blink.state(now)
+        } else {
+            (true, None)
+        };
+        if caret_shown != self.caret_shown {
+            self.caret_shown = caret_shown;
+            self.dirty = true;
+        }
+        let shake = self.shake_offset(now);
+        let shake_at = self.shake.as_ref().and_then(|a| a.next_frame(now));
+        if self.shake.is_some() {
+            self.dirty = true;
+            if shake_at.is_none() {
+                self.shake = None;
+            }
+        }
+        let flash = self.flash_level(now);
+        let flash_at = match self.flash {
+            Some(ref flash) if self.animations => flash.next_frame(now),
+            Some(ref flash) => Some(flash.end()).filter(|end| *end > now),
+            None => None,
+        };
+        if self.flash.is_some() {
+            self.dirty = true;
+            if flash_at.is_none() {
+                self.flash = None;
+            }
+        }
+        let reveal_at = self
+            .reveal
+            .as_ref()
+            .map(|r| r.end())
+            .filter(|end| *end > now);
+        if self.reveal.is_some() && reveal_at.is_none() {
+            // Time to mask the revealed character.
+            self.reveal = None;
+            self.dirty = true;
+        }
+        let user = self.known_user().filter(|_| self.avatars);
+        if user != self.avatar.as_ref().map(|(u, _)| u.as_str()) {
+            let icon = |user: &str| {
+                self.users
+                    .iter()
+                    .find(|u| u.name == user)
+                    .and_then(|u| u.icon.clone())
+            };
+            self.avatar = user.map(|user| (user.to_string(), Avatar::load(user, icon(user))));
+            self.dirty = true;
+        }
+        if let Some(at) = blink_at
+            .into_iter()
+            .chain(reveal_at)
+            .chain(shake_at)
+            .chain(flash_at)
+            .min()
+        {
+            let _ = self.tx.send(Cmd::Wake(at));
+        }
         if !self.dirty && !ctx.force {
-            return Ok(DrawReport::empty(width, height));
+            return Ok(DrawReport::empty(outer_width, outer_height));
         }
         self.dirty = false;
-        let mut buf = ctx.buf.subdimensions((0, 0, width, height))?;
-        buf.memset(&ctx.bg);
-        draw_box(&mut buf, &ctx.config.border, (width, height))?;
-
-        self.headline_font.auto_draw_text(
-            &mut buf.offset((168, 16))?,
-            &ctx.bg,
-            &ctx.config.headline,
-            "Login",
-        )?;
-
-        let (w, _) = self.prompt_font.auto_draw_text(
-            &mut buf.offset((24, 112))?,
-            &ctx.bg,
-            &ctx.config.prompt,
-            &self.question,
-        )?;
+        let radius = scaled(ctx.config.border_radius, s);
+        let mut outer = ctx
+            .buf
+            .subdimensions((pos.0, pos.1, outer_width, outer_height))?;
+        let damage = outer.get_signed_bounds();
+        outer.memset(ctx.bg);
+        if let Some((color, _)) = self.shadow {
+            draw_shadow(
+                &mut outer,
+                ctx.bg,
+                &color,
+                (outer_width, outer_height),
+                pad,
+                radius,
+            )?;
+        }
+        let mut buf = outer.subdimensions((pad, pad, width, height))?;
+        if ctx.config.border_style == BorderStyle::Solid {
+            draw_rounded_box(
+                &mut buf,
+                ctx.bg,
+                &ctx.config.prompt_err.blend(&ctx.config.border, flash),
+                (width, height),
+                scaled(ctx.config.border_width, s),
+                radius,
+            )?;
+        }
+
+        if !self.greeting.is_empty() {
+            let (headline_width, _) = self.headline_font.measure_text(&self.greeting);
+            self.headline_font.auto_draw_text(
+                &mut buf.offset((width.saturating_sub(headline_width) / 2, scaled(16, s)))?,
+                ctx.bg,
+                &ctx.config.headline,
+                &self.greeting,
+            )?;
+        }
+
+        // The picture takes the height of the headline, at its left.
+        let size = self
+            .prompt_y()
+            .saturating_sub(scaled(16, s) + scaled(24, s));
+        if let Some((_, Some(ref mut avatar))) = self.avatar {
+            if size > 0 {
+                avatar.draw(
+                    &mut buf.subdimensions((scaled(24, s), scaled(16, s), size, size))?,
+                    ctx.bg,
+                    size,
+                )?;
+            }
+        }
 
+        let mut w = 0;
+        let left = (scaled(24, s) as i32 + shake) as u32;
+        let line_height = self.prompt_font.size() as u32;
+        for (idx, line) in self.question_lines.iter().enumerate() {
+            let (line_width, _) = self.prompt_font.auto_draw_text(
+                &mut buf.offset((left, self.prompt_y() + idx as u32 * line_height))?,
+                ctx.bg,
+                &ctx.config.prompt,
+                line,
+            )?;
+            w = line_width;
+        }
+
+        // Horizontal position of the caret, if the answer is being edited
+        let mut caret = None;
         match self.mode {
+            // Waiting for PAM to ask something.
+            None if self.lock_user.is_some() => (),
             None | Some(AuthMessageType::Visible) => {
-                self.prompt_font.auto_draw_text(
-                    &mut buf.subdimensions((24 + w + 16, 112, width - (24 + w + 16) - 24, 64))?,
-                    &ctx.bg,
+                let x = left + w + scaled(16, s);
+                let text = match self.selected_user() {
+                    Some(user) => format!("< {} >", user),
+                    _ => {
+                        let (cw, _) = self.prompt_font.measure_text(&self.answer[..self.cursor]);
+                        caret = Some(x + cw);
+                        self.answer.to_string()
+                    }
+                };
+                let (text_width, text_height) = self.prompt_font.auto_draw_text(
+                    &mut buf.subdimensions((
+                        x,
+                        prompt_y,
+                        width.saturating_sub(x + scaled(24, s)),
+                        prompt_height,
+                    ))?,
+                    ctx.bg,
                     &ctx.config.prompt,
-                    &format!("{}", self.answer),
+                    &text,
                 )?;
+                self.answer_rect = (x, prompt_y, text_width, text_height);
             }
             Some(AuthMessageType::Secret) => {
-                let mut stars = "".to_string();
-                for _ in 0..self.answer.len() {
-                    stars += "*";
-                }
+                let x = left + w + scaled(8, s);
+                let mask = ctx.config.secret_mask.to_string();
+                let revealed = match self.reveal {
+                    Some(_) if self.cursor > 0 => Some(prev_grapheme(&self.answer, self.cursor)),
+                    _ => None,
+                };
+                // The answer up to end, masked but for the revealed character
+                let echo = |end: usize| -> String {
+                    self.answer[..end]
+                        .grapheme_indices(true)
+                        .map(|(idx, g)| if Some(idx) == revealed { g } else { &mask })
+                        .collect()
+                };
+                // Unless every character gets a dot, the caret stays at the
+                // end so that it does not give away the length either.
+                let (mut stars, mut before) = match ctx.config.secret_echo {
+                    SecretEcho::Dots => (echo(self.answer.len()), echo(self.cursor)),
+                    SecretEcho::Fixed if !self.answer.is_empty() => {
+                        (mask.repeat(FIXED_ECHO), mask.repeat(FIXED_ECHO))
+                    }
+                    _ => (String::new(), String::new()),
+                };
+                let (cw, _) = self.prompt_font.measure_text(&before);
+                caret = Some(x + cw);
                 self.prompt_font.auto_draw_text(
-                    &mut buf.subdimensions((24 + w + 8, 112, width - (24 + w + 8) - 24, 64))?,
-                    &ctx.bg,
+                    &mut buf.subdimensions((
+                        x,
+                        prompt_y,
+                        width.saturating_sub(x + scaled(24, s)),
+                        prompt_height,
+                    ))?,
+                    ctx.bg,
                     &ctx.config.prompt,
                     &stars,
                 )?;
+                stars.zeroize();
+                before.zeroize();
             }
             _ => (),
         }
 
-        if self.error.len() > 0 {
+        if let Some(frame) = frame {
+            let size = self.prompt_font.size() as u32;
+            draw_spinner(
+                &mut buf.subdimensions((
+                    width.saturating_sub(size + scaled(24, s)),
+                    prompt_y,
+                    size,
+                    size,
+                ))?,
+                ctx.bg,
+                &ctx.config.prompt,
+                size,
+                frame,
+            );
+        }
+
+        if let Some(x) = caret.filter(|_| self.caret_shown) {
+            if x + scaled(2, s) + scaled(24, s) < width {
+                for y in prompt_y..prompt_y + self.prompt_font.size() as u32 {
+                    for dx in 0..scaled(2, s) {
+                        buf.put((x + dx, y), &ctx.config.prompt)?;
+                    }
+                }
+            }
+        }
+
+        if let Some(ref autologin) = self.autologin {
+            self.hint_font.auto_draw_text(
+                &mut buf.offset((
+                    scaled(24, s),
+                    prompt_y + self.prompt_font.size() as u32 + self.hint_font.size() as u32 / 2,
+                ))?,
+                ctx.bg,
+                &ctx.config.prompt,
+                &format!(
+                    "Logging in as {} in {}s, press any key to cancel",
+                    autologin.user, autologin.remaining
+                ),
+            )?;
+        }
+
+        if let Some(AuthMessageType::Secret) = self.mode {
+            let mut hint_buf = buf.offset((
+                scaled(24, s),
+                prompt_y + self.prompt_font.size() as u32 + self.hint_font.size() as u32 / 2,
+            ))?;
+            let mut x = 0;
+            if self.locks.0 {
+                let (w, _) = self.hint_font.auto_draw_text(
+                    &mut hint_buf,
+                    ctx.bg,
+                    &ctx.config.prompt_err,
+                    "Caps Lock is on",
+                )?;
+                x += w + scaled(16, s);
+            }
+            if self.locks.1 {
+                self.hint_font.auto_draw_text(
+                    &mut hint_buf.offset((x, 0))?,
+                    ctx.bg,
+                    &ctx.config.prompt,
+                    "Num Lock is on",
+                )?;
+            }
+        }
+
+        if let Some(ref layout) = self.layout {
+            let (w, _) = self.hint_font.measure_text(layout);
+            self.hint_font.auto_draw_text(
+                &mut buf.offset((
+                    width.saturating_sub(w + scaled(24, s)),
+                    prompt_y + self.prompt_font.size() as u32 + self.hint_font.size() as u32 / 2,
+                ))?,
+                ctx.bg,
+                &ctx.config.prompt,
+                layout,
+            )?;
+        }
+
+        let status = if self.error.len() > 0 {
+            // Errors settle on a color between the error and prompt colors.
+            let settled = ctx.config.prompt_err.blend(&ctx.config.prompt, 0.5);
+            Some((&self.error, ctx.config.prompt_err.blend(&settled, flash)))
+        } else if !self.message.is_empty() {
+            Some((&self.message, ctx.config.prompt))
+        } else {
+            None
+        };
+        if let Some((text, color)) = status {
+            let text = self
+                .prompt_font
+                .ellipsize(text, width.saturating_sub(scaled(48, s)));
+            let (text_width, _) = self.prompt_font.measure_text(&text);
             self.prompt_font.auto_draw_text(
-                &mut buf.offset((256, 64))?,
-                &ctx.bg,
-                &ctx.config.prompt_err,
-                &self.error,
+                &mut buf.offset((
+                    width.saturating_sub(text_width) / 2,
+                    self.prompt_y()
+                        .saturating_sub(self.prompt_font.size() as u32 + scaled(16, s)),
+                ))?,
+                ctx.bg,
+                &color,
+                &text,
             )?;
         }
 
         Ok(DrawReport {
-            width,
-            height,
-            damage: vec![buf.get_signed_bounds()],
+            width: outer_width,
+            height: outer_height,
+            damage: vec![damage],
             full_damage: false,
         })
     }
 
     fn keyboard_input(
         &mut self,
-        key: u32,
-        modifiers: ModifiersState,
+        key: Keysym,
+        modifiers: Modifiers,
         _: KeyState,
         interpreted: Option<String>,
     ) {
+        self.blink.reset();
+        self.last_input = Instant::now();
+        self.reveal = None;
+        if self.cancel_autologin() {
+            return;
+        }
+        // Typing goes to the focused widget.
+        if !self.focused {
+            return;
+        }
+        // Only confirming or going back does anything while confirming.
+        if self.command_edit == Some(CommandEdit::Confirming) {
+            return;
+        }
+        let interpreted = match keypad_digit(key) {
+            Some(digit) if !modifiers.ctrl => Some(digit.to_string()),
+            _ => interpreted,
+        };
+        if !modifiers.ctrl && self.user_list_input(key, &interpreted) {
+            return;
+        }
         match key {
-            keysyms::XKB_KEY_u if modifiers.ctrl => {
-                if self.mode.is_some() {
-                    self.cancel().expect("unable to cancel");
-                    self.mode = None;
+            Keysym::BackSpace => {
+                if modifiers.ctrl {
+                    self.clear_answer();
+                } else {
+                    let start = prev_grapheme(&self.answer, self.cursor);
+                    self.answer.remove(start..self.cursor);
+                    self.cursor = start;
                 }
-                self.answer.clear();
-                self.error.clear();
-                self.reset();
                 self.dirty = true;
             }
-            keysyms::XKB_KEY_c if modifiers.ctrl => {
-                if self.mode.is_some() {
-                    self.cancel().expect("unable to cancel");
-                    self.mode = None;
-                }
-                self.answer.clear();
-                self.error.clear();
-                self.reset();
+            Keysym::Delete => {
+                let end = next_grapheme(&self.answer, self.cursor);
+                self.answer.remove(self.cursor..end);
                 self.dirty = true;
             }
-            keysyms::XKB_KEY_BackSpace => {
-                if modifiers.ctrl {
-                    self.answer.clear();
-                } else {
-                    self.answer.truncate(self.answer.len().saturating_sub(1));
-                }
+            Keysym::Left => {
+                self.cursor = prev_grapheme(&self.answer, self.cursor);
                 self.dirty = true;
             }
-            keysyms::XKB_KEY_Return | keysyms::XKB_KEY_Tab => match self.answer.chars().next() {
-                Some('!') => {
-                    self.error =
-                        format!("Command set to: {}", self.answer[1..].to_string()).to_string();
-                    self.command = self.answer[1..].to_string();
-                    self.answer.clear();
-                    self.dirty = true;
-                    self.mode = None;
-                }
-                _ => {
-                    let res = self.communicate();
-                    self.dirty = true;
-                    self.answer.clear();
-                    self.error.clear();
-                    if let Err(e) = res {
-                        self.reset();
-                        self.error = format!("{}", e);
-                        self.mode = None;
-                        if let Err(e) = self.cancel() {
-                            self.error = format!("{}", e);
-                        };
-                    }
-                }
-            },
+            Keysym::Right => {
+                self.cursor = next_grapheme(&self.answer, self.cursor);
+                self.dirty = true;
+            }
+            Keysym::Home => {
+                self.cursor = 0;
+                self.dirty = true;
+            }
+            Keysym::End => {
+                self.cursor = self.answer.len();
+                self.dirty = true;
+            }
+            // Keys without a binding, such as Return with it turned off,
+            // come with control characters that are not to be typed.
             _ => match interpreted {
-                Some(v) => {
-                    self.answer += &v;
+                Some(v) if !modifiers.alt && !v.chars().any(char::is_control) => {
+                    self.insert(&v);
+                    if self.reveal_last && matches!(self.mode, Some(AuthMessageType::Secret)) {
+                        self.reveal = Some(Animation::new(REVEAL, Easing::Linear));
+                    }
                     self.dirty = true;
                 }
-                None => {}
+                _ => {}
             },
         }
     }
-    fn mouse_click(&mut self, _: u32, _: (u32, u32)) {}
-    fn mouse_scroll(&mut self, _: (f64, f64), _: (u32, u32)) {}
+    fn action(&mut self, action: Action) {
+        self.blink.reset();
+        self.last_input = Instant::now();
+        self.reveal = None;
+        if self.cancel_autologin() {
+            return;
+        }
+        // Submitting belongs to the focused widget.
+        if action == Action::Submit && !self.focused {
+            return;
+        }
+        if self.command_edit.is_some() {
+            self.command_edit_action(action);
+            return;
+        }
+        match action {
+            Action::Submit => self.submit(),
+            // Locking the session cannot be quit, so exiting starts over.
+            Action::Reset | Action::Exit => self.start_over(),
+            Action::EditCommand => self.edit_command(),
+            _ => (),
+        }
+    }
+    fn mouse_click(&mut self, _: u32, pos: (u32, u32)) {
+        if self.cancel_autologin() {
+            return;
+        }
+        // Clicking the user list entry moves on to the next one.
+        let (x, y, width, height) = self.answer_rect;
+        if pos.0 >= x && pos.0 < x + width && pos.1 >= y && pos.1 < y + height {
+            self.user_list_input(Keysym::Down, &None);
+        }
+    }
+    fn mouse_scroll(&mut self, scroll: (f64, f64), _: (u32, u32)) {
+        if scroll.1 > 0.0 {
+            self.user_list_input(Keysym::Down, &None);
+        } else if scroll.1 < 0.0 {
+            self.user_list_input(Keysym::Up, &None);
+        }
+    }
+    fn touch_down(&mut self, id: i32, pos: (u32, u32)) {
+        self.touch = Some((id, pos));
+    }
+    fn touch_motion(&mut self, id: i32, pos: (u32, u32)) {
+        if matches!(self.touch, Some((touch, _)) if touch == id) {
+            self.touch = Some((id, pos));
+        }
+    }
+    fn touch_up(&mut self, id: i32) {
+        if let Some((_, pos)) = self.touch.filter(|(touch, _)| *touch == id) {
+            self.touch = None;
+            self.mouse_click(0, pos);
+        }
+    }
+    fn touch_cancel(&mut self) {
+        self.touch = None;
+    }
+    fn focus_next(&mut self) -> bool {
+        self.focused = !self.focused;
+        self.dirty = true;
+        self.focused
+    }
+    fn focus_prev(&mut self) -> bool {
+        self.focus_next()
+    }
+    fn tick(&mut self, now: Instant) -> bool {
+        self.poll_autologin();
+        self.poll_input_timeout(now);
+        self.poll_unlock();
+        // The spinner turns while waiting on greetd.
+        self.dirty || self.authenticating()
+    }
+    fn on_show(&mut self) {
+        self.dirty = true;
+    }
+    fn on_hide(&mut self) {
+        // Nothing of the password is to show when the greeter comes back.
+        self.reveal = None;
+    }
+    fn paste(&mut self, text: &str) {
+        if !self.focused
+            || self.user_select.is_some()
+            || self.command_edit == Some(CommandEdit::Confirming)
+        {
+            return;
+        }
+        self.last_input = Instant::now();
+        self.reveal = None;
+        // Only the first line, so that a trailing newline does not end up
+        // in the password.
+        if let Some(line) = text.lines().next() {
+            self.insert(line);
+            self.blink.reset();
+            self.dirty = true;
+        }
+    }
+    fn message(&mut self, text: &str) {
+        self.message = text.to_string();
+        self.dirty = true;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn combining_marks_move_with_their_letter() {
+        let text = "ae\u{301}b";
+        assert_eq!(next_grapheme(text, 1), 4);
+        assert_eq!(prev_grapheme(text, 4), 1);
+    }
+
+    #[test]
+    fn emoji_sequences_are_one_grapheme() {
+        let family = "\u{1f469}\u{200d}\u{1f469}\u{200d}\u{1f467}";
+        let text = format!("a{}", family);
+        assert_eq!(next_grapheme(&text, 1), text.len());
+        assert_eq!(prev_grapheme(&text, text.len()), 1);
+    }
+
+    #[test]
+    fn backspace_at_the_start_removes_nothing() {
+        assert_eq!(prev_grapheme("abc", 0), 0);
+        assert_eq!(prev_grapheme("", 0), 0);
+    }
+
+    #[test]
+    fn delete_at_the_end_removes_nothing() {
+        assert_eq!(next_grapheme("abc", 3), 3);
+        assert_eq!(next_grapheme("", 0), 0);
+    }
+
+    #[test]
+    fn cursor_steps_over_the_whole_text() {
+        let text = "e\u{301}\u{1f44d}\u{1f3fd}x";
+        let mut cursor = 0;
+        let mut stops = vec![cursor];
+        while next_grapheme(text, cursor) != cursor {
+            cursor = next_grapheme(text, cursor);
+            stops.push(cursor);
+        }
+        assert_eq!(stops, [0, 3, 11, 12]);
+        while prev_grapheme(text, cursor) != cursor {
+            cursor = prev_grapheme(text, cursor);
+            stops.pop();
+            assert_eq!(stops.last(), Some(&cursor));
+        }
+    }
 }
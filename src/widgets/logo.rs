@@ -0,0 +1,183 @@
+use crate::color::Color;
+use crate::colorfont::{decode_png, scale};
+use crate::widget::{scaled, DrawContext, DrawReport, KeyState, Keysym, Modifiers, Widget};
+
+use std::fs;
+use std::path::Path;
+
+// The picture as read from its file.
+enum Source {
+    Png(Vec<[u8; 4]>),
+    // Parsed again whenever it is drawn at a new size, as it is only ever
+    // drawn at a few.
+    #[cfg(feature = "svg")]
+    Svg(Vec<u8>),
+}
+
+#[cfg(feature = "svg")]
+fn parse_svg(data: &[u8]) -> Result<resvg::usvg::Tree, String> {
+    resvg::usvg::Tree::from_data(data, &resvg::usvg::Options::default()).map_err(|e| e.to_string())
+}
+
+// The SVG data as a source, with its size.
+#[cfg(feature = "svg")]
+fn svg_source(data: Vec<u8>) -> Result<(Source, (f32, f32)), String> {
+    let size = parse_svg(&data)?.size();
+    Ok((Source::Svg(data), (size.width(), size.height())))
+}
+
+#[cfg(not(feature = "svg"))]
+fn svg_source(_: Vec<u8>) -> Result<(Source, (f32, f32)), String> {
+    Err("SVG logos need wlgreet built with the svg feature".to_string())
+}
+
+// Renders the SVG data to size, taking the colors back out of the
+// premultiplied pixels.
+#[cfg(feature = "svg")]
+fn render_svg(data: &[u8], size: (u32, u32)) -> Vec<(Color, f32)> {
+    let tree = match parse_svg(data) {
+        Ok(tree) => tree,
+        Err(_) => return Vec::new(),
+    };
+    let mut pixmap = match resvg::tiny_skia::Pixmap::new(size.0, size.1) {
+        Some(pixmap) => pixmap,
+        None => return Vec::new(),
+    };
+    let natural = tree.size();
+    let transform = resvg::tiny_skia::Transform::from_scale(
+        size.0 as f32 / natural.width(),
+        size.1 as f32 / natural.height(),
+    );
+    resvg::render(&tree, transform, &mut pixmap.as_mut());
+    pixmap
+        .data()
+        .chunks(4)
+        .map(|p| {
+            let alpha = p[3] as f32 / 255.0;
+            if p[3] == 0 {
+                return (Color::default(), 0.0);
+            }
+            let channel = |c: u8| (c as f32 / 255.0 / alpha).min(1.0);
+            (
+                Color::new(channel(p[0]), channel(p[1]), channel(p[2]), 1.0),
+                alpha,
+            )
+        })
+        .collect()
+}
+
+/// Shows the logo of a distribution or company, from a PNG file or, when
+/// built with the svg feature, an SVG file. The logo is scaled to a fixed
+/// height, keeping its aspect ratio.
+pub struct Logo {
+    source: Source,
+    // The size of the picture as drawn at a scale of 1.
+    natural: (f32, f32),
+    height: u32,
+    width: u32,
+    scale: f32,
+    // The pixels as last scaled, with the size they were scaled to.
+    scaled: Vec<(Color, f32)>,
+    scaled_size: (u32, u32),
+}
+
+impl Logo {
+    /// Loads the logo at path, to be shown height pixels high.
+    pub fn new(path: &str, height: u32) -> Result<Box<Logo>, String> {
+        let data = fs::read(path).map_err(|e| e.to_string())?;
+        let svg = Path::new(path)
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("svg"));
+        let (source, natural) = if svg {
+            svg_source(data)?
+        } else {
+            let (width, height, pixels) =
+                decode_png(&data).ok_or_else(|| "not a PNG or SVG file".to_string())?;
+            (Source::Png(pixels), (width as f32, height as f32))
+        };
+        if natural.0 < 1.0 || natural.1 < 1.0 {
+            return Err("the picture is empty".to_string());
+        }
+        Ok(Box::new(Logo {
+            source,
+            natural,
+            height,
+            width: 0,
+            scale: 1.0,
+            scaled: Vec::new(),
+            scaled_size: (0, 0),
+        }))
+    }
+
+    // The size the logo is drawn at.
+    fn logo_size(&self) -> (u32, u32) {
+        let height = scaled(self.height, self.scale).max(1);
+        let width = (height as f32 * self.natural.0 / self.natural.1).round() as u32;
+        (width.max(1), height)
+    }
+
+    // Scales the logo to size if it was last scaled to another.
+    fn prepare(&mut self, size: (u32, u32)) {
+        if self.scaled_size == size && !self.scaled.is_empty() {
+            return;
+        }
+        self.scaled = match self.source {
+            Source::Png(ref pixels) => {
+                scale(pixels, (self.natural.0 as u32, self.natural.1 as u32), size)
+            }
+            #[cfg(feature = "svg")]
+            Source::Svg(ref data) => render_svg(data, size),
+        };
+        self.scaled_size = size;
+    }
+}
+
+impl Widget for Logo {
+    fn resize(&mut self, available: (u32, u32), scale: f32) {
+        self.scale = scale;
+        self.width = available.0;
+    }
+
+    fn size(&self) -> (u32, u32) {
+        let (width, height) = self.logo_size();
+        (
+            self.width.max(width + scaled(48, self.scale)),
+            height + scaled(16, self.scale),
+        )
+    }
+
+    fn draw(
+        &mut self,
+        ctx: &mut DrawContext,
+        pos: (u32, u32),
+    ) -> Result<DrawReport, ::std::io::Error> {
+        let (width, height) = self.size();
+        if !ctx.force {
+            return Ok(DrawReport::empty(width, height));
+        }
+        let mut buf = ctx.buf.subdimensions((pos.0, pos.1, width, height))?;
+        buf.memset(ctx.bg);
+
+        let size = self.logo_size();
+        self.prepare(size);
+        let mut logo =
+            buf.subdimensions(((width - size.0) / 2, scaled(8, self.scale), size.0, size.1))?;
+        for (idx, (color, coverage)) in self.scaled.iter().enumerate() {
+            if *coverage > 0.0 {
+                let (x, y) = (idx as u32 % size.0, idx as u32 / size.0);
+                logo.put((x, y), &ctx.bg.blend(color, *coverage))?;
+            }
+        }
+
+        Ok(DrawReport {
+            width,
+            height,
+            damage: vec![buf.get_signed_bounds()],
+            full_damage: false,
+        })
+    }
+
+    fn keyboard_input(&mut self, _: Keysym, _: Modifiers, _: KeyState, _: Option<String>) {}
+    fn mouse_click(&mut self, _: u32, _: (u32, u32)) {}
+    fn mouse_scroll(&mut self, _: (f64, f64), _: (u32, u32)) {}
+}
@@ -1 +1,178 @@
+pub mod analog;
+pub mod calendar;
+pub mod clock;
+pub mod fatal;
+pub mod hostinfo;
 pub mod login;
+pub mod logo;
+pub mod network;
+pub mod power;
+pub mod qr;
+pub mod script;
+pub mod session;
+pub mod sysinfo;
+
+use calloop::channel::Sender;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use log::warn;
+use rusttype::Font as RustFont;
+
+use crate::cmd::Cmd;
+use crate::config::{ClockStyle, Config, LogoPosition, WidgetAnchor, WidgetKind};
+use crate::widget::{AnchorLayout, HBox, Margin, VBox, Widget};
+
+/// Creates a single widget of the given kind, or None if it is disabled in
+/// the current mode.
+fn create(
+    kind: WidgetKind,
+    config: &Config,
+    font: &RustFont<'static>,
+    command: &Arc<Mutex<String>>,
+    tx: &Sender<Cmd>,
+) -> Option<Box<dyn Widget + Send>> {
+    match kind {
+        WidgetKind::Clock if config.clock_format.is_empty() => None,
+        WidgetKind::Clock if config.clock_style == ClockStyle::Analog => {
+            Some(analog::AnalogClock::new(config.clock_size))
+        }
+        WidgetKind::Clock => Some(clock::Clock::new(
+            font,
+            config.clock_format.clone(),
+            config.clock_size,
+        )),
+        WidgetKind::Login => Some(login::Login::new(font, command.clone(), tx.clone(), config)),
+        WidgetKind::Session if !config.lock => Some(session::Session::new(
+            font,
+            command.clone(),
+            &config.sessions,
+        )),
+        WidgetKind::Power if !config.lock => Some(power::Power::new(font, tx.clone())),
+        WidgetKind::Session | WidgetKind::Power => None,
+        WidgetKind::HostInfo => Some(hostinfo::HostInfo::new(font)),
+        WidgetKind::Network => Some(network::Network::new(font, config.network_command.clone())),
+        WidgetKind::QrCode if !config.qr_code.is_empty() => {
+            match qr::QrCode::new(&config.qr_code) {
+                Ok(qr) => Some(qr),
+                Err(e) => {
+                    warn!("Unable to encode qrCode: {}", e);
+                    None
+                }
+            }
+        }
+        WidgetKind::QrCode => None,
+        WidgetKind::Logo if !config.logo.is_empty() => {
+            match logo::Logo::new(&config.logo, config.logo_size) {
+                Ok(logo) => Some(logo),
+                Err(e) => {
+                    warn!("Unable to load logo {}: {}", config.logo, e);
+                    None
+                }
+            }
+        }
+        WidgetKind::Logo => None,
+        WidgetKind::Calendar => Some(calendar::Calendar::new(font)),
+        WidgetKind::SystemInfo => Some(sysinfo::SystemInfo::new(font)),
+        // Scripts are made from their widget entries in layout.
+        WidgetKind::Script => None,
+    }
+}
+
+// The layout used when the config does not list any widgets.
+fn default_layout(
+    config: &Config,
+    font: &RustFont<'static>,
+    command: &Arc<Mutex<String>>,
+    tx: &Sender<Cmd>,
+) -> Box<dyn Widget + Send> {
+    let mut children: Vec<Box<dyn Widget + Send>> = Vec::new();
+    children.extend(create(WidgetKind::Clock, config, font, command, tx));
+    let logo = create(WidgetKind::Logo, config, font, command, tx);
+    let (above, beside) = match config.logo_position {
+        LogoPosition::Above => (logo, None),
+        LogoPosition::Left | LogoPosition::Right => (None, logo),
+    };
+    children.extend(above);
+    // The QR code and a logo beside it go next to the login box
+    let mut login: Vec<Box<dyn Widget + Send>> = Vec::new();
+    login.extend(create(WidgetKind::Login, config, font, command, tx));
+    if let Some(logo) = beside {
+        if config.logo_position == LogoPosition::Left {
+            login.insert(0, Margin::new(logo, (0, 16, 0, 0)));
+        } else {
+            login.push(Margin::new(logo, (0, 0, 0, 16)));
+        }
+    }
+    if let Some(qr) = create(WidgetKind::QrCode, config, font, command, tx) {
+        login.push(Margin::new(qr, (0, 0, 0, 16)));
+    }
+    if login.len() > 1 {
+        children.push(HBox::new(login));
+    } else {
+        children.extend(login);
+    }
+
+    // Session chooser and power menu share a row below the login box
+    let mut row: Vec<Box<dyn Widget + Send>> = Vec::new();
+    if config.session_chooser {
+        row.extend(create(WidgetKind::Session, config, font, command, tx));
+    }
+    if config.power_menu {
+        row.extend(create(WidgetKind::Power, config, font, command, tx));
+    }
+    if !row.is_empty() {
+        children.push(Margin::new(HBox::new(row), (16, 0, 0, 0)));
+    }
+    if config.host_info {
+        children.extend(create(WidgetKind::HostInfo, config, font, command, tx));
+    }
+    if config.network_status {
+        children.extend(create(WidgetKind::Network, config, font, command, tx));
+    }
+    if config.system_info {
+        children.extend(create(WidgetKind::SystemInfo, config, font, command, tx));
+    }
+    VBox::new(children)
+}
+
+/// Builds the widget tree from the widget list in the config, with the
+/// keyboard focus on the first widget taking it.
+pub fn build(config: &Config, font: &RustFont<'static>, tx: Sender<Cmd>) -> Box<dyn Widget + Send> {
+    let mut widget = layout(config, font, tx);
+    widget.focus_next();
+    widget
+}
+
+fn layout(config: &Config, font: &RustFont<'static>, tx: Sender<Cmd>) -> Box<dyn Widget + Send> {
+    let command = Arc::new(Mutex::new(config.command.clone()));
+    if config.widgets.is_empty() {
+        return default_layout(config, font, &command, &tx);
+    }
+
+    let mut top: Vec<Box<dyn Widget + Send>> = Vec::new();
+    let mut center: Vec<Box<dyn Widget + Send>> = Vec::new();
+    let mut bottom: Vec<Box<dyn Widget + Send>> = Vec::new();
+    for widget in config.widgets.iter() {
+        let group = match widget.anchor {
+            WidgetAnchor::Top => &mut top,
+            WidgetAnchor::Center => &mut center,
+            WidgetAnchor::Bottom => &mut bottom,
+        };
+        if widget.kind == WidgetKind::Script {
+            group.push(script::Script::new(
+                font,
+                widget.command.clone(),
+                Duration::from_secs(widget.interval.max(1)),
+                widget.lines as usize,
+            ));
+            continue;
+        }
+        group.extend(create(widget.kind, config, font, &command, &tx));
+    }
+    if !config.widgets.iter().any(|w| w.kind == WidgetKind::Login) {
+        warn!("No login widget configured, adding one");
+        center.extend(create(WidgetKind::Login, config, font, &command, &tx));
+    }
+    AnchorLayout::new(VBox::new(top), VBox::new(center), VBox::new(bottom))
+}
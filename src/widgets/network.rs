@@ -0,0 +1,214 @@
+use crate::draw::Font;
+use crate::widget::{
+    layout_width, scaled, DrawContext, DrawReport, KeyState, Keysym, Modifiers, Widget,
+    DEFAULT_WIDTH,
+};
+
+use std::fs::{read_dir, read_to_string};
+use std::net::IpAddr;
+use std::process::Command;
+use std::sync::{Arc, Mutex, Weak};
+use std::time::{Duration, Instant};
+
+use log::warn;
+use nix::ifaddrs::getifaddrs;
+use rusttype::Font as RustFont;
+
+const NET: &str = "/sys/class/net";
+
+// How often the status is looked up again.
+const INTERVAL: Duration = Duration::from_secs(5);
+
+// The first address of each interface, preferring IPv4 and leaving out
+// link-local IPv6 addresses, which are of no use to tell.
+fn addresses() -> Vec<(String, IpAddr)> {
+    let mut addresses: Vec<(String, IpAddr)> = Vec::new();
+    let ifaddrs = match getifaddrs() {
+        Ok(ifaddrs) => ifaddrs,
+        Err(_) => return addresses,
+    };
+    for ifaddr in ifaddrs {
+        let address = match ifaddr.address {
+            Some(address) => address,
+            None => continue,
+        };
+        let ip = if let Some(sin) = address.as_sockaddr_in() {
+            IpAddr::V4(sin.ip().into())
+        } else if let Some(sin6) = address.as_sockaddr_in6() {
+            let ip = sin6.ip();
+            if ip.segments()[0] & 0xffc0 == 0xfe80 {
+                continue;
+            }
+            IpAddr::V6(ip)
+        } else {
+            continue;
+        };
+        match addresses
+            .iter_mut()
+            .find(|(name, _)| *name == ifaddr.interface_name)
+        {
+            Some((_, existing)) if existing.is_ipv6() && ip.is_ipv4() => *existing = ip,
+            Some(_) => (),
+            None => addresses.push((ifaddr.interface_name, ip)),
+        }
+    }
+    addresses
+}
+
+// The interfaces that are up, from sysfs, with their addresses.
+fn link_status() -> String {
+    let mut names: Vec<String> = match read_dir(NET) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.file_name().to_string_lossy().into_owned())
+            .filter(|name| name != "lo")
+            .filter(|name| {
+                read_to_string(format!("{}/{}/operstate", NET, name))
+                    .is_ok_and(|state| state.trim() == "up")
+            })
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+    if names.is_empty() {
+        return "network down".to_string();
+    }
+    names.sort();
+    let addresses = addresses();
+    names
+        .iter()
+        .map(
+            |name| match addresses.iter().find(|(iface, _)| iface == name) {
+                Some((_, ip)) => format!("{} {}", name, ip),
+                None => format!("{} up, no address", name),
+            },
+        )
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+// The first line printed by the configured command.
+fn command_status(command: &str) -> String {
+    match Command::new("/bin/sh").arg("-c").arg(command).output() {
+        Ok(output) => String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .next()
+            .unwrap_or("")
+            .trim()
+            .to_string(),
+        Err(e) => {
+            warn!("Unable to run network status command: {}", e);
+            String::new()
+        }
+    }
+}
+
+// Looks up the status every INTERVAL until the widget is hidden or gone.
+fn poller(alive: Weak<()>, command: String, status: Arc<Mutex<String>>) {
+    while alive.upgrade().is_some() {
+        let text = if command.is_empty() {
+            link_status()
+        } else {
+            command_status(&command)
+        };
+        *status.lock().unwrap() = text;
+        std::thread::sleep(INTERVAL);
+    }
+}
+
+/// Shows whether the network is up, with the interfaces that are and their
+/// addresses, or the first line printed by a command. The status is looked
+/// up on a thread of its own while the greeter is shown, so that a slow
+/// command does not hold up the greeter.
+pub struct Network {
+    command: String,
+    // The status as last looked up, and as last drawn.
+    status: Arc<Mutex<String>>,
+    text: String,
+    // Keeps the poller running while the greeter is shown.
+    alive: Option<Arc<()>>,
+    font: Font,
+    width: u32,
+    scale: f32,
+}
+
+impl Network {
+    pub fn new(font: &RustFont<'static>, command: String) -> Box<Network> {
+        Box::new(Network {
+            command,
+            status: Arc::new(Mutex::new(String::new())),
+            text: String::new(),
+            alive: None,
+            font: Font::new(font, 16.0),
+            width: DEFAULT_WIDTH,
+            scale: 1.0,
+        })
+    }
+}
+
+impl Widget for Network {
+    fn resize(&mut self, available: (u32, u32), scale: f32) {
+        self.scale = scale;
+        self.font.set_scale(scale);
+        self.width = layout_width(available.0, scale);
+    }
+
+    fn size(&self) -> (u32, u32) {
+        (self.width, self.font.size() as u32 + scaled(16, self.scale))
+    }
+
+    fn draw(
+        &mut self,
+        ctx: &mut DrawContext,
+        pos: (u32, u32),
+    ) -> Result<DrawReport, ::std::io::Error> {
+        let (width, height) = self.size();
+        let text = self.status.lock().unwrap().to_string();
+        if text == self.text && !ctx.force {
+            return Ok(DrawReport::empty(width, height));
+        }
+        let mut buf = ctx.buf.subdimensions((pos.0, pos.1, width, height))?;
+        buf.memset(ctx.bg);
+
+        let s = self.scale;
+        let shown = self
+            .font
+            .ellipsize(&text, width.saturating_sub(scaled(48, s)));
+        self.font.auto_draw_text(
+            &mut buf.offset((scaled(24, s), scaled(8, s)))?,
+            ctx.bg,
+            &ctx.config.prompt,
+            &shown,
+        )?;
+        self.text = text;
+
+        Ok(DrawReport {
+            width,
+            height,
+            damage: vec![buf.get_signed_bounds()],
+            full_damage: false,
+        })
+    }
+
+    fn keyboard_input(&mut self, _: Keysym, _: Modifiers, _: KeyState, _: Option<String>) {}
+    fn mouse_click(&mut self, _: u32, _: (u32, u32)) {}
+    fn mouse_scroll(&mut self, _: (f64, f64), _: (u32, u32)) {}
+    fn tick(&mut self, _: Instant) -> bool {
+        *self.status.lock().unwrap() != self.text
+    }
+    fn on_show(&mut self) {
+        if self.alive.is_some() {
+            return;
+        }
+        let alive = Arc::new(());
+        let weak = Arc::downgrade(&alive);
+        let command = self.command.clone();
+        let status = self.status.clone();
+        let _ = std::thread::Builder::new()
+            .name("network".to_string())
+            .spawn(move || poller(weak, command, status));
+        self.alive = Some(alive);
+    }
+    fn on_hide(&mut self) {
+        self.alive = None;
+    }
+}
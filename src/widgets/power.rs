@@ -0,0 +1,377 @@
+use crate::cmd::Cmd;
+use crate::draw::Font;
+use crate::keybindings;
+use crate::widget::{
+    layout_width, scaled, DrawContext, DrawReport, KeyState, Keysym, Modifiers, Widget,
+    DEFAULT_WIDTH,
+};
+
+use std::convert::TryFrom;
+use std::process::Command;
+use std::sync::mpsc::{channel, Receiver, TryRecvError};
+
+use calloop::channel::Sender;
+use rusttype::Font as RustFont;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Action {
+    Poweroff,
+    Reboot,
+    Suspend,
+}
+
+impl Action {
+    fn label(&self) -> &'static str {
+        match self {
+            Action::Poweroff => "[p]oweroff",
+            Action::Reboot => "[r]eboot",
+            Action::Suspend => "[s]uspend",
+        }
+    }
+
+    fn verb(&self) -> &'static str {
+        match self {
+            Action::Poweroff => "poweroff",
+            Action::Reboot => "reboot",
+            Action::Suspend => "suspend",
+        }
+    }
+}
+
+const ACTIONS: [Action; 3] = [Action::Poweroff, Action::Reboot, Action::Suspend];
+
+pub struct Power {
+    font: Font,
+    // (start, end, action) ranges of the labels as last drawn.
+    regions: Vec<(u32, u32, Action)>,
+    // The label under the pointer, underlined to show it can be clicked.
+    hover: Option<Action>,
+    // The label with the keyboard focus, underlined too.
+    focus: Option<Action>,
+    // The touch point down on the widget, and the label under it, which
+    // is pressed when the point goes up there.
+    touch: Option<(i32, Option<Action>)>,
+    // The action asked to be confirmed by choosing it again.
+    confirm: Option<Action>,
+    // The error of the action being run, once it is done.
+    running: Option<Receiver<String>>,
+    error: String,
+    tx: Sender<Cmd>,
+    dirty: bool,
+    width: u32,
+    scale: f32,
+}
+
+impl Power {
+    pub fn new(font: &RustFont<'static>, tx: Sender<Cmd>) -> Box<Power> {
+        Box::new(Power {
+            font: Font::new(font, 24.0),
+            regions: Vec::new(),
+            hover: None,
+            focus: None,
+            touch: None,
+            confirm: None,
+            running: None,
+            error: String::new(),
+            tx,
+            dirty: false,
+            width: DEFAULT_WIDTH,
+            scale: 1.0,
+        })
+    }
+
+    // Asks to confirm action, or runs it if that was asked already.
+    fn choose(&mut self, action: Action) {
+        if self.running.is_some() {
+            return;
+        }
+        if self.confirm == Some(action) {
+            self.confirm = None;
+            self.run(action);
+        } else {
+            self.confirm = Some(action);
+        }
+        self.dirty = true;
+    }
+
+    // The action of the label drawn at pos, if any.
+    fn action_at(&self, pos: (u32, u32)) -> Option<Action> {
+        if !self.error.is_empty() {
+            return None;
+        }
+        self.regions
+            .iter()
+            .find(|(start, end, _)| pos.0 >= *start && pos.0 < *end)
+            .map(|(_, _, action)| *action)
+    }
+
+    // The label step labels away from the focused one, or the first or
+    // last one when the focus comes in from another widget.
+    fn focus_step(&self, step: isize) -> Option<Action> {
+        let idx = match self.focus {
+            Some(focus) => ACTIONS.iter().position(|a| *a == focus).unwrap() as isize + step,
+            None if step > 0 => 0,
+            None => ACTIONS.len() as isize - 1,
+        };
+        usize::try_from(idx)
+            .ok()
+            .and_then(|idx| ACTIONS.get(idx))
+            .copied()
+    }
+
+    fn set_focus(&mut self, focus: Option<Action>) -> bool {
+        if self.focus != focus {
+            self.focus = focus;
+            self.confirm = None;
+            self.dirty = true;
+        }
+        focus.is_some()
+    }
+
+    fn set_hover(&mut self, hover: Option<Action>) {
+        if self.hover != hover {
+            self.hover = hover;
+            self.dirty = true;
+        }
+    }
+
+    fn cancel_confirm(&mut self) {
+        if self.confirm.take().is_some() {
+            self.dirty = true;
+        }
+    }
+
+    // Runs action, waiting for it on a thread of its own that wakes up the
+    // main loop once done.
+    fn run(&mut self, action: Action) {
+        let verb = action.verb();
+        let mut child = match Command::new("loginctl").arg(verb).spawn() {
+            Ok(child) => child,
+            Err(e) => {
+                self.error = format!("{} failed: {}", verb, e);
+                return;
+            }
+        };
+        let (done_tx, done_rx) = channel();
+        let wake = self.tx.clone();
+        let waiter = std::thread::Builder::new()
+            .name("power".to_string())
+            .spawn(move || {
+                let error = match child.wait() {
+                    Ok(status) if status.success() => String::new(),
+                    Ok(status) => format!("{} failed: {}", verb, status),
+                    Err(e) => format!("{} failed: {}", verb, e),
+                };
+                let _ = done_tx.send(error);
+                let _ = wake.send(Cmd::Draw);
+            });
+        if waiter.is_ok() {
+            self.running = Some(done_rx);
+        }
+    }
+
+    // Shows the error of the action run, once it is done.
+    fn poll_running(&mut self) {
+        let res = match self.running {
+            Some(ref done) => done.try_recv(),
+            None => return,
+        };
+        match res {
+            Ok(error) => {
+                self.error = error;
+                self.running = None;
+                self.dirty = true;
+            }
+            Err(TryRecvError::Disconnected) => self.running = None,
+            Err(TryRecvError::Empty) => (),
+        }
+    }
+}
+
+impl Widget for Power {
+    fn resize(&mut self, available: (u32, u32), scale: f32) {
+        self.scale = scale;
+        self.font.set_scale(scale);
+        self.width = layout_width(available.0, scale);
+    }
+
+    fn size(&self) -> (u32, u32) {
+        (self.width, scaled(48, self.scale))
+    }
+
+    fn draw(
+        &mut self,
+        ctx: &mut DrawContext,
+        pos: (u32, u32),
+    ) -> Result<DrawReport, ::std::io::Error> {
+        let (width, height) = self.size();
+        self.poll_running();
+        if !self.dirty && !ctx.force {
+            return Ok(DrawReport::empty(width, height));
+        }
+        self.dirty = false;
+        let mut buf = ctx.buf.subdimensions((pos.0, pos.1, width, height))?;
+        buf.memset(ctx.bg);
+
+        let s = self.scale;
+        if !self.error.is_empty() {
+            let error = self
+                .font
+                .ellipsize(&self.error, width.saturating_sub(scaled(48, s)));
+            self.font.auto_draw_text(
+                &mut buf.offset((scaled(24, s), scaled(12, s)))?,
+                ctx.bg,
+                &ctx.config.prompt_err,
+                &error,
+            )?;
+        } else {
+            self.regions.clear();
+            let mut x = scaled(24, s);
+            for action in ACTIONS.iter() {
+                let label = if self.confirm == Some(*action) {
+                    format!("{}? again to confirm", action.verb())
+                } else {
+                    action.label().to_string()
+                };
+                let (w, h) = self.font.auto_draw_text(
+                    &mut buf.offset((x, scaled(12, s)))?,
+                    ctx.bg,
+                    &ctx.config.prompt,
+                    &label,
+                )?;
+                if self.hover == Some(*action) || self.focus == Some(*action) {
+                    buf.subdimensions((x, scaled(14, s) + h, w, scaled(2, s).max(1)))?
+                        .memset(&ctx.config.prompt);
+                }
+                self.regions.push((x, x + w, *action));
+                x += w + scaled(24, s);
+            }
+        }
+
+        Ok(DrawReport {
+            width,
+            height,
+            damage: vec![buf.get_signed_bounds()],
+            full_damage: false,
+        })
+    }
+
+    fn keyboard_input(
+        &mut self,
+        key: Keysym,
+        modifiers: Modifiers,
+        _: KeyState,
+        _: Option<String>,
+    ) {
+        if key.is_modifier_key() {
+            return;
+        }
+        // The shortcuts only work with the labels focused, so that they
+        // never shut down by accident while typing elsewhere.
+        let focused = self.focus.is_some();
+        match key {
+            Keysym::p if modifiers.alt && focused => self.choose(Action::Poweroff),
+            Keysym::r if modifiers.alt && focused => self.choose(Action::Reboot),
+            Keysym::s if modifiers.alt && focused => self.choose(Action::Suspend),
+            Keysym::XF86_PowerOff => self.choose(Action::Poweroff),
+            Keysym::XF86_Sleep => self.choose(Action::Suspend),
+            Keysym::Left | Keysym::Right if focused => {
+                let step = if key == Keysym::Left { -1 } else { 1 };
+                if let Some(focus) = self.focus_step(step) {
+                    self.set_focus(Some(focus));
+                }
+            }
+            _ => {
+                self.cancel_confirm();
+                if !self.error.is_empty() {
+                    self.error.clear();
+                    self.dirty = true;
+                }
+            }
+        }
+    }
+
+    fn mouse_click(&mut self, _: u32, pos: (u32, u32)) {
+        if !self.error.is_empty() {
+            self.error.clear();
+            self.dirty = true;
+            return;
+        }
+        match self.action_at(pos) {
+            Some(action) => self.choose(action),
+            None => self.cancel_confirm(),
+        }
+    }
+
+    fn mouse_scroll(&mut self, _: (f64, f64), _: (u32, u32)) {}
+
+    fn mouse_move(&mut self, pos: (u32, u32)) {
+        let hover = self.action_at(pos);
+        self.set_hover(hover);
+    }
+
+    fn mouse_leave(&mut self) {
+        self.set_hover(None);
+    }
+
+    fn touch_down(&mut self, id: i32, pos: (u32, u32)) {
+        let action = self.action_at(pos);
+        self.touch = Some((id, action));
+        self.set_hover(action);
+    }
+
+    fn touch_motion(&mut self, id: i32, pos: (u32, u32)) {
+        if matches!(self.touch, Some((touch, _)) if touch == id) {
+            let action = self.action_at(pos);
+            self.touch = Some((id, action));
+            self.set_hover(action);
+        }
+    }
+
+    fn touch_up(&mut self, id: i32) {
+        let action = match self.touch {
+            Some((touch, action)) if touch == id => action,
+            _ => return,
+        };
+        self.touch = None;
+        self.set_hover(None);
+        if !self.error.is_empty() {
+            self.error.clear();
+            self.dirty = true;
+        } else if let Some(action) = action {
+            self.choose(action);
+        }
+    }
+
+    fn touch_cancel(&mut self) {
+        if self.touch.take().is_some() {
+            self.set_hover(None);
+        }
+    }
+
+    fn focus_next(&mut self) -> bool {
+        let focus = self.focus_step(1);
+        self.set_focus(focus)
+    }
+
+    fn focus_prev(&mut self) -> bool {
+        let focus = self.focus_step(-1);
+        self.set_focus(focus)
+    }
+
+    fn on_hide(&mut self) {
+        self.confirm = None;
+    }
+
+    fn action(&mut self, action: keybindings::Action) {
+        match action {
+            keybindings::Action::Submit => {
+                if let Some(focus) = self.focus {
+                    self.choose(focus);
+                }
+            }
+            keybindings::Action::Reset | keybindings::Action::Exit => self.cancel_confirm(),
+            _ => (),
+        }
+    }
+}
@@ -0,0 +1,90 @@
+use crate::color::Color;
+use crate::widget::{scaled, DrawContext, DrawReport, KeyState, Keysym, Modifiers, Widget};
+
+use qrcode::{Color as Module, QrCode as Code};
+
+// Width of a module, the square each bit is drawn as, and of the light
+// border around the code that scanners need, in modules.
+const MODULE: u32 = 4;
+const QUIET_ZONE: u32 = 4;
+
+/// Shows a URL as a QR code, such as that of a helpdesk or of a portal to
+/// reset passwords at, to be opened on a phone. The code is always drawn
+/// black on white, as not every scanner reads it the other way around.
+pub struct QrCode {
+    // Whether each module is dark, row by row.
+    modules: Vec<bool>,
+    modules_wide: u32,
+    width: u32,
+    scale: f32,
+}
+
+impl QrCode {
+    /// Encodes url, failing if it is too long to fit in a QR code.
+    pub fn new(url: &str) -> Result<Box<QrCode>, String> {
+        let code = Code::new(url.as_bytes()).map_err(|e| e.to_string())?;
+        Ok(Box::new(QrCode {
+            modules: code
+                .to_colors()
+                .into_iter()
+                .map(|m| m == Module::Dark)
+                .collect(),
+            modules_wide: code.width() as u32,
+            width: 0,
+            scale: 1.0,
+        }))
+    }
+
+    // The size of the code with its quiet zone.
+    fn code_size(&self) -> u32 {
+        (self.modules_wide + 2 * QUIET_ZONE) * scaled(MODULE, self.scale).max(1)
+    }
+}
+
+impl Widget for QrCode {
+    fn resize(&mut self, available: (u32, u32), scale: f32) {
+        self.scale = scale;
+        self.width = available.0;
+    }
+
+    fn size(&self) -> (u32, u32) {
+        let size = self.code_size();
+        (self.width.max(size), size + scaled(16, self.scale))
+    }
+
+    fn draw(
+        &mut self,
+        ctx: &mut DrawContext,
+        pos: (u32, u32),
+    ) -> Result<DrawReport, ::std::io::Error> {
+        let (width, height) = self.size();
+        if !ctx.force {
+            return Ok(DrawReport::empty(width, height));
+        }
+        let mut buf = ctx.buf.subdimensions((pos.0, pos.1, width, height))?;
+        buf.memset(ctx.bg);
+
+        let size = self.code_size();
+        let mut code =
+            buf.subdimensions(((width - size) / 2, scaled(8, self.scale), size, size))?;
+        code.memset(&Color::new(1.0, 1.0, 1.0, 1.0));
+        let module = scaled(MODULE, self.scale).max(1);
+        let dark = Color::new(0.0, 0.0, 0.0, 1.0);
+        for (idx, _) in self.modules.iter().enumerate().filter(|(_, m)| **m) {
+            let x = (idx as u32 % self.modules_wide + QUIET_ZONE) * module;
+            let y = (idx as u32 / self.modules_wide + QUIET_ZONE) * module;
+            code.subdimensions((x, y, module, module))?.memset(&dark);
+        }
+
+        Ok(DrawReport {
+            width,
+            height,
+            damage: vec![buf.get_signed_bounds()],
+            full_damage: false,
+        })
+    }
+
+    fn keyboard_input(&mut self, _: Keysym, _: Modifiers, _: KeyState, _: Option<String>) {}
+    fn mouse_click(&mut self, _: u32, _: (u32, u32)) {}
+    fn mouse_scroll(&mut self, _: (f64, f64), _: (u32, u32)) {}
+}
@@ -0,0 +1,156 @@
+use crate::draw::Font;
+use crate::widget::{
+    layout_width, scaled, DrawContext, DrawReport, KeyState, Keysym, Modifiers, Widget,
+    DEFAULT_WIDTH,
+};
+
+use std::process::Command;
+use std::sync::{Arc, Mutex, Weak};
+use std::time::{Duration, Instant};
+
+use log::warn;
+use rusttype::Font as RustFont;
+
+// The first lines printed by command.
+fn run(command: &str, lines: usize) -> Vec<String> {
+    match Command::new("/bin/sh").arg("-c").arg(command).output() {
+        Ok(output) => String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .take(lines)
+            .map(|line| line.trim_end().to_string())
+            .collect(),
+        Err(e) => {
+            warn!("Unable to run {}: {}", command, e);
+            Vec::new()
+        }
+    }
+}
+
+// Runs the command every interval until the widget is hidden or gone.
+fn poller(
+    alive: Weak<()>,
+    command: String,
+    interval: Duration,
+    lines: usize,
+    output: Arc<Mutex<Vec<String>>>,
+) {
+    while alive.upgrade().is_some() {
+        let text = run(&command, lines);
+        *output.lock().unwrap() = text;
+        std::thread::sleep(interval);
+    }
+}
+
+/// Shows what a command prints, run again every so often, for things such
+/// as the weather or the bookings of a room. The widget is as high as the
+/// number of lines it is set to show, whatever the command prints, so that
+/// the layout stays put.
+pub struct Script {
+    command: String,
+    interval: Duration,
+    lines: usize,
+    // The output as last read, and as last drawn.
+    output: Arc<Mutex<Vec<String>>>,
+    text: Vec<String>,
+    // Keeps the poller running while the greeter is shown.
+    alive: Option<Arc<()>>,
+    font: Font,
+    width: u32,
+    scale: f32,
+}
+
+impl Script {
+    pub fn new(
+        font: &RustFont<'static>,
+        command: String,
+        interval: Duration,
+        lines: usize,
+    ) -> Box<Script> {
+        Box::new(Script {
+            command,
+            interval,
+            lines,
+            output: Arc::new(Mutex::new(Vec::new())),
+            text: Vec::new(),
+            alive: None,
+            font: Font::new(font, 16.0),
+            width: DEFAULT_WIDTH,
+            scale: 1.0,
+        })
+    }
+}
+
+impl Widget for Script {
+    fn resize(&mut self, available: (u32, u32), scale: f32) {
+        self.scale = scale;
+        self.font.set_scale(scale);
+        self.width = layout_width(available.0, scale);
+    }
+
+    fn size(&self) -> (u32, u32) {
+        (
+            self.width,
+            self.lines as u32 * self.font.size() as u32 + scaled(16, self.scale),
+        )
+    }
+
+    fn draw(
+        &mut self,
+        ctx: &mut DrawContext,
+        pos: (u32, u32),
+    ) -> Result<DrawReport, ::std::io::Error> {
+        let (width, height) = self.size();
+        let text = self.output.lock().unwrap().clone();
+        if text == self.text && !ctx.force {
+            return Ok(DrawReport::empty(width, height));
+        }
+        let mut buf = ctx.buf.subdimensions((pos.0, pos.1, width, height))?;
+        buf.memset(ctx.bg);
+
+        let s = self.scale;
+        let line_height = self.font.size() as u32;
+        for (idx, line) in text.iter().enumerate() {
+            let shown = self
+                .font
+                .ellipsize(line, width.saturating_sub(scaled(48, s)));
+            self.font.auto_draw_text(
+                &mut buf.offset((scaled(24, s), scaled(8, s) + idx as u32 * line_height))?,
+                ctx.bg,
+                &ctx.config.prompt,
+                &shown,
+            )?;
+        }
+        self.text = text;
+
+        Ok(DrawReport {
+            width,
+            height,
+            damage: vec![buf.get_signed_bounds()],
+            full_damage: false,
+        })
+    }
+
+    fn keyboard_input(&mut self, _: Keysym, _: Modifiers, _: KeyState, _: Option<String>) {}
+    fn mouse_click(&mut self, _: u32, _: (u32, u32)) {}
+    fn mouse_scroll(&mut self, _: (f64, f64), _: (u32, u32)) {}
+    fn tick(&mut self, _: Instant) -> bool {
+        *self.output.lock().unwrap() != self.text
+    }
+    fn on_show(&mut self) {
+        if self.alive.is_some() {
+            return;
+        }
+        let alive = Arc::new(());
+        let weak = Arc::downgrade(&alive);
+        let command = self.command.clone();
+        let (interval, lines) = (self.interval, self.lines);
+        let output = self.output.clone();
+        let _ = std::thread::Builder::new()
+            .name("script".to_string())
+            .spawn(move || poller(weak, command, interval, lines, output));
+        self.alive = Some(alive);
+    }
+    fn on_hide(&mut self) {
+        self.alive = None;
+    }
+}
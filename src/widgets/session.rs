@@ -0,0 +1,451 @@
+use crate::config::SessionConfig;
+use crate::draw::Font;
+use crate::keybindings::Action;
+use crate::widget::{
+    layout_width, scaled, DrawContext, DrawReport, KeyState, Keysym, Modifiers, Widget,
+    DEFAULT_WIDTH,
+};
+
+use std::fs::{read_dir, read_to_string};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use rusttype::Font as RustFont;
+
+const WAYLAND_SESSIONS: &str = "/usr/share/wayland-sessions";
+const X_SESSIONS: &str = "/usr/share/xsessions";
+const X_SESSION_WRAPPER: &str = "startx /usr/bin/env";
+const ENVIRONMENTS: &str = "/etc/greetd/environments";
+
+// Sessions beyond this many in the config have no F-key.
+const SHORTCUTS: usize = 12;
+
+// How far a touch point has to move sideways to swipe, in logical pixels.
+const SWIPE: u32 = 48;
+
+pub struct SessionEntry {
+    pub name: String,
+    pub exec: String,
+}
+
+impl SessionEntry {
+    fn from_desktop_file(path: &Path, xsession: bool) -> Option<SessionEntry> {
+        SessionEntry::from_desktop_entry(&read_to_string(path).ok()?, xsession)
+    }
+
+    fn from_desktop_entry(contents: &str, xsession: bool) -> Option<SessionEntry> {
+        let mut in_entry = false;
+        let mut name = None;
+        let mut exec = None;
+        for line in contents.lines() {
+            let line = line.trim();
+            if line.starts_with('[') {
+                in_entry = line == "[Desktop Entry]";
+                continue;
+            }
+            if !in_entry {
+                continue;
+            }
+            let (key, value) = match line.find('=') {
+                Some(idx) => (line[..idx].trim(), line[idx + 1..].trim()),
+                None => continue,
+            };
+            match key {
+                "Name" => name = Some(value.to_string()),
+                "Exec" => exec = Some(value.to_string()),
+                "Hidden" | "NoDisplay" if value == "true" => return None,
+                _ => (),
+            }
+        }
+
+        // Drop field codes such as %f or %U, which make no sense here.
+        let exec = exec?
+            .split_whitespace()
+            .filter(|arg| !arg.starts_with('%'))
+            .collect::<Vec<&str>>()
+            .join(" ");
+        if exec.is_empty() {
+            return None;
+        }
+        Some(SessionEntry {
+            name: name.unwrap_or_else(|| exec.clone()),
+            exec: if xsession {
+                format!("{} {}", X_SESSION_WRAPPER, exec)
+            } else {
+                exec
+            },
+        })
+    }
+}
+
+fn read_sessions(dir: &str, xsession: bool) -> Vec<SessionEntry> {
+    let mut entries: Vec<SessionEntry> = match read_dir(dir) {
+        Ok(dir) => dir
+            .filter_map(|e| e.ok())
+            .map(|e| e.path())
+            .filter(|p| p.extension().map(|ext| ext == "desktop").unwrap_or(false))
+            .filter_map(|p| SessionEntry::from_desktop_file(&p, xsession))
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+    entries.sort_by(|a, b| a.name.cmp(&b.name));
+    entries
+}
+
+/// Returns the name the session chooser lists cmd under, or cmd itself if
+/// it is not listed by name.
+pub fn session_name(cmd: &str, sessions: &[SessionConfig]) -> String {
+    if let Some(session) = sessions.iter().find(|s| s.command == cmd) {
+        return session.name.to_string();
+    }
+    read_sessions(WAYLAND_SESSIONS, false)
+        .into_iter()
+        .chain(read_sessions(X_SESSIONS, true))
+        .find(|e| e.exec == cmd)
+        .map(|e| e.name)
+        .unwrap_or_else(|| cmd.to_string())
+}
+
+// Reads a gtkgreet-style environments file, with one command per line.
+fn read_environments(path: &str) -> Vec<SessionEntry> {
+    match read_to_string(path) {
+        Ok(contents) => parse_environments(&contents),
+        Err(_) => Vec::new(),
+    }
+}
+
+fn parse_environments(contents: &str) -> Vec<SessionEntry> {
+    contents
+        .lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| SessionEntry {
+            name: line.to_string(),
+            exec: line.to_string(),
+        })
+        .collect()
+}
+
+/// Lists the configured sessions, the configured command, the commands from
+/// /etc/greetd/environments and the installed wayland and X sessions, with
+/// the configured command (if any) as the initial entry. The selected
+/// command is written to the shared command string used by the login
+/// widget.
+///
+/// F1 to F12 pick the configured sessions in order. Without any, F1 and F2
+/// step through the list instead. With the focus, the arrow keys and
+/// Return step through it too.
+pub struct Session {
+    entries: Vec<SessionEntry>,
+    current: usize,
+    // How many of the first entries have an F-key.
+    shortcuts: usize,
+    command: Arc<Mutex<String>>,
+    font: Font,
+    label: String,
+    // Whether the pointer is over the widget, and whether the label was
+    // last drawn underlined for it or the focus.
+    hover: bool,
+    drawn_highlight: bool,
+    // Whether the arrow keys and Return step through the sessions.
+    focused: bool,
+    // The touch point down on the widget, with where it went down and
+    // where it is now horizontally.
+    touch: Option<(i32, u32, u32)>,
+    width: u32,
+    scale: f32,
+}
+
+impl Session {
+    pub fn new(
+        font: &RustFont<'static>,
+        command: Arc<Mutex<String>>,
+        sessions: &[SessionConfig],
+    ) -> Box<Session> {
+        let mut entries: Vec<SessionEntry> = sessions
+            .iter()
+            .map(|s| SessionEntry {
+                name: s.name.to_string(),
+                exec: s.command.to_string(),
+            })
+            .collect();
+        let shortcuts = entries.len().min(SHORTCUTS);
+        let cmd = command.lock().unwrap().to_string();
+        let mut found = entries.iter().position(|e| e.exec == cmd);
+        if !cmd.is_empty() && found.is_none() {
+            found = Some(entries.len());
+            entries.push(SessionEntry {
+                name: cmd.to_string(),
+                exec: cmd.to_string(),
+            });
+        }
+        for entry in read_environments(ENVIRONMENTS)
+            .into_iter()
+            .chain(read_sessions(WAYLAND_SESSIONS, false))
+            .chain(read_sessions(X_SESSIONS, true))
+        {
+            if !entries.iter().any(|e| e.exec == entry.exec) {
+                entries.push(entry);
+            }
+        }
+
+        let mut s = Session {
+            entries,
+            current: 0,
+            shortcuts,
+            command,
+            font: Font::new(font, 24.0),
+            label: String::new(),
+            hover: false,
+            drawn_highlight: false,
+            focused: false,
+            touch: None,
+            width: DEFAULT_WIDTH,
+            scale: 1.0,
+        };
+        s.select(found.unwrap_or(0));
+        Box::new(s)
+    }
+
+    fn select(&mut self, idx: usize) {
+        if self.entries.is_empty() {
+            return;
+        }
+        self.current = idx % self.entries.len();
+        *self.command.lock().unwrap() = self.entries[self.current].exec.to_string();
+    }
+
+    fn next(&mut self) {
+        self.select(self.current + 1);
+    }
+
+    fn prev(&mut self) {
+        self.select(self.current + self.entries.len().saturating_sub(1));
+    }
+
+    fn label(&mut self) -> String {
+        let cmd = self.command.lock().unwrap();
+        // The command may have been set from elsewhere, such as to the
+        // session the user last started.
+        if !matches!(self.entries.get(self.current), Some(entry) if entry.exec == *cmd) {
+            if let Some(idx) = self.entries.iter().position(|e| e.exec == *cmd) {
+                self.current = idx;
+            }
+        }
+        match self.entries.get(self.current) {
+            Some(entry) if entry.exec == *cmd && self.current < self.shortcuts => {
+                format!("session: {} [F{}]", entry.name, self.current + 1)
+            }
+            Some(entry) if entry.exec == *cmd => format!("session: {}", entry.name),
+            _ => format!("session: {}", cmd),
+        }
+    }
+}
+
+impl Widget for Session {
+    fn resize(&mut self, available: (u32, u32), scale: f32) {
+        self.scale = scale;
+        self.font.set_scale(scale);
+        self.width = layout_width(available.0, scale);
+    }
+
+    fn size(&self) -> (u32, u32) {
+        (self.width, scaled(48, self.scale))
+    }
+
+    fn draw(
+        &mut self,
+        ctx: &mut DrawContext,
+        pos: (u32, u32),
+    ) -> Result<DrawReport, ::std::io::Error> {
+        let (width, height) = self.size();
+        let label = self.label();
+        let highlight = self.hover || ctx.focused;
+        if label == self.label && highlight == self.drawn_highlight && !ctx.force {
+            return Ok(DrawReport::empty(width, height));
+        }
+        let mut buf = ctx.buf.subdimensions((pos.0, pos.1, width, height))?;
+        buf.memset(ctx.bg);
+
+        let s = self.scale;
+        let text = self
+            .font
+            .ellipsize(&label, width.saturating_sub(scaled(48, s)));
+        let (w, h) = self.font.auto_draw_text(
+            &mut buf.subdimensions((
+                scaled(24, s),
+                scaled(12, s),
+                width.saturating_sub(scaled(48, s)),
+                height - scaled(12, s),
+            ))?,
+            ctx.bg,
+            &ctx.config.prompt,
+            &text,
+        )?;
+        if highlight {
+            let w = w.min(width.saturating_sub(scaled(48, s)));
+            buf.subdimensions((scaled(24, s), scaled(14, s) + h, w, scaled(2, s).max(1)))?
+                .memset(&ctx.config.prompt);
+        }
+        self.label = label;
+        self.drawn_highlight = highlight;
+
+        Ok(DrawReport {
+            width,
+            height,
+            damage: vec![buf.get_signed_bounds()],
+            full_damage: false,
+        })
+    }
+
+    fn keyboard_input(&mut self, key: Keysym, _: Modifiers, _: KeyState, _: Option<String>) {
+        let fkey = key.raw().wrapping_sub(Keysym::F1.raw()) as usize;
+        match key {
+            _ if fkey < self.shortcuts => self.select(fkey),
+            Keysym::Left | Keysym::Up if self.focused => self.prev(),
+            Keysym::Right | Keysym::Down if self.focused => self.next(),
+            _ if self.shortcuts > 0 => (),
+            Keysym::F1 => self.prev(),
+            Keysym::F2 => self.next(),
+            _ => (),
+        }
+    }
+
+    fn mouse_click(&mut self, _: u32, _: (u32, u32)) {
+        self.next();
+    }
+
+    fn mouse_scroll(&mut self, scroll: (f64, f64), _: (u32, u32)) {
+        if scroll.1 > 0.0 {
+            self.next();
+        } else if scroll.1 < 0.0 {
+            self.prev();
+        }
+    }
+
+    fn mouse_move(&mut self, _: (u32, u32)) {
+        self.hover = true;
+    }
+
+    fn mouse_leave(&mut self) {
+        self.hover = false;
+    }
+
+    fn touch_down(&mut self, id: i32, pos: (u32, u32)) {
+        self.touch = Some((id, pos.0, pos.0));
+    }
+
+    fn touch_motion(&mut self, id: i32, pos: (u32, u32)) {
+        if let Some((touch, _, ref mut x)) = self.touch {
+            if touch == id {
+                *x = pos.0;
+            }
+        }
+    }
+
+    // Swiping right picks the previous session, while swiping left or
+    // tapping picks the next like a click.
+    fn touch_up(&mut self, id: i32) {
+        match self.touch {
+            Some((touch, start, end)) if touch == id => {
+                self.touch = None;
+                if start + scaled(SWIPE, self.scale) < end {
+                    self.prev();
+                } else {
+                    self.next();
+                }
+            }
+            _ => (),
+        }
+    }
+
+    fn touch_cancel(&mut self) {
+        self.touch = None;
+    }
+
+    fn focus_next(&mut self) -> bool {
+        self.focused = !self.focused;
+        self.focused
+    }
+
+    fn focus_prev(&mut self) -> bool {
+        self.focus_next()
+    }
+
+    fn action(&mut self, action: Action) {
+        if action == Action::Submit && self.focused {
+            self.next();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(contents: &str, xsession: bool) -> Option<(String, String)> {
+        SessionEntry::from_desktop_entry(contents, xsession).map(|e| (e.name, e.exec))
+    }
+
+    #[test]
+    fn field_codes_are_dropped() {
+        let contents = "[Desktop Entry]\nName=Sway\nExec=sway %f --debug %U\n";
+        assert_eq!(
+            entry(contents, false),
+            Some(("Sway".to_string(), "sway --debug".to_string()))
+        );
+    }
+
+    #[test]
+    fn x_sessions_are_wrapped() {
+        let contents = "[Desktop Entry]\nName=i3\nExec=i3\n";
+        assert_eq!(
+            entry(contents, true),
+            Some(("i3".to_string(), format!("{} i3", X_SESSION_WRAPPER)))
+        );
+    }
+
+    #[test]
+    fn hidden_entries_are_left_out() {
+        let hidden = "[Desktop Entry]\nName=Sway\nExec=sway\nHidden=true\n";
+        let no_display = "[Desktop Entry]\nNoDisplay=true\nName=Sway\nExec=sway\n";
+        let shown = "[Desktop Entry]\nName=Sway\nExec=sway\nNoDisplay=false\n";
+        assert_eq!(entry(hidden, false), None);
+        assert_eq!(entry(no_display, false), None);
+        assert!(entry(shown, false).is_some());
+    }
+
+    #[test]
+    fn localized_names_are_ignored() {
+        let contents =
+            "[Desktop Entry]\nName[de]=Fenster\nName=Window\nName[fr]=Fenêtre\nExec=win\n";
+        assert_eq!(entry(contents, false).unwrap().0, "Window");
+    }
+
+    #[test]
+    fn entries_need_a_command() {
+        assert_eq!(entry("[Desktop Entry]\nName=Nothing\n", false), None);
+        assert_eq!(
+            entry("[Desktop Entry]\nName=Nothing\nExec=%U\n", false),
+            None
+        );
+        let elsewhere = "[Desktop Entry]\nName=Sway\n[Desktop Action new]\nExec=sway\n";
+        assert_eq!(entry(elsewhere, false), None);
+    }
+
+    #[test]
+    fn names_default_to_the_command() {
+        let contents = "[Desktop Entry]\nExec=sway\n";
+        assert_eq!(
+            entry(contents, false),
+            Some(("sway".to_string(), "sway".to_string()))
+        );
+    }
+
+    #[test]
+    fn environments_skip_comments_and_blank_lines() {
+        let entries = parse_environments("# shells\nbash\n\n  sway --unsupported-gpu \n");
+        let commands: Vec<&str> = entries.iter().map(|e| e.exec.as_str()).collect();
+        assert_eq!(commands, ["bash", "sway --unsupported-gpu"]);
+    }
+}
@@ -0,0 +1,163 @@
+use crate::draw::Font;
+use crate::widget::{
+    layout_width, scaled, DrawContext, DrawReport, KeyState, Keysym, Modifiers, Widget,
+    DEFAULT_WIDTH,
+};
+
+use std::fs::read_to_string;
+use std::time::{Duration, Instant};
+
+use rusttype::Font as RustFont;
+
+// How often the figures are read again. Reading them from /proc is cheap,
+// so unlike the network status this needs no thread of its own.
+const INTERVAL: Duration = Duration::from_secs(5);
+
+// Time since boot, as in "up 3 days, 4:05".
+fn uptime() -> Option<String> {
+    let contents = read_to_string("/proc/uptime").ok()?;
+    let seconds = contents.split_whitespace().next()?.parse::<f64>().ok()? as u64;
+    let (days, hours, minutes) = (seconds / 86400, seconds / 3600 % 24, seconds / 60 % 60);
+    Some(match days {
+        0 => format!("up {}:{:02}", hours, minutes),
+        1 => format!("up 1 day, {}:{:02}", hours, minutes),
+        _ => format!("up {} days, {}:{:02}", days, hours, minutes),
+    })
+}
+
+// The load averages over 1, 5 and 15 minutes.
+fn load() -> Option<String> {
+    let contents = read_to_string("/proc/loadavg").ok()?;
+    let averages: Vec<&str> = contents.split_whitespace().take(3).collect();
+    if averages.len() < 3 {
+        return None;
+    }
+    Some(format!("load {}", averages.join(" ")))
+}
+
+// Memory in use, that is not available to be handed out, out of the total.
+fn memory() -> Option<String> {
+    let contents = read_to_string("/proc/meminfo").ok()?;
+    let field = |key: &str| -> Option<u64> {
+        contents.lines().find_map(|line| {
+            line.strip_prefix(key)?
+                .strip_prefix(':')?
+                .trim()
+                .strip_suffix("kB")?
+                .trim()
+                .parse()
+                .ok()
+        })
+    };
+    let (total, available) = (field("MemTotal")?, field("MemAvailable")?);
+    let gib = |kib: u64| kib as f64 / (1024.0 * 1024.0);
+    Some(format!(
+        "memory {:.1}/{:.1} GiB",
+        gib(total.saturating_sub(available)),
+        gib(total)
+    ))
+}
+
+// The uptime, load and memory use, leaving out what is unknown.
+fn system_info() -> String {
+    [uptime(), load(), memory()]
+        .iter()
+        .flatten()
+        .cloned()
+        .collect::<Vec<_>>()
+        .join(" · ")
+}
+
+/// Shows the uptime, load averages and memory use on a single line, for
+/// consoles of labs and servers where the greeter doubles as a status
+/// display. The figures are read again every few seconds while the greeter
+/// is shown.
+pub struct SystemInfo {
+    // The figures as last read, when, and as last drawn.
+    status: String,
+    read: Option<Instant>,
+    text: String,
+    font: Font,
+    width: u32,
+    scale: f32,
+}
+
+impl SystemInfo {
+    pub fn new(font: &RustFont<'static>) -> Box<SystemInfo> {
+        Box::new(SystemInfo {
+            status: system_info(),
+            read: None,
+            text: String::new(),
+            font: Font::new(font, 16.0),
+            width: DEFAULT_WIDTH,
+            scale: 1.0,
+        })
+    }
+}
+
+impl Widget for SystemInfo {
+    fn resize(&mut self, available: (u32, u32), scale: f32) {
+        self.scale = scale;
+        self.font.set_scale(scale);
+        self.width = layout_width(available.0, scale);
+    }
+
+    fn size(&self) -> (u32, u32) {
+        (self.width, self.font.size() as u32 + scaled(16, self.scale))
+    }
+
+    fn draw(
+        &mut self,
+        ctx: &mut DrawContext,
+        pos: (u32, u32),
+    ) -> Result<DrawReport, ::std::io::Error> {
+        let (width, height) = self.size();
+        if self.status == self.text && !ctx.force {
+            return Ok(DrawReport::empty(width, height));
+        }
+        let mut buf = ctx.buf.subdimensions((pos.0, pos.1, width, height))?;
+        buf.memset(ctx.bg);
+
+        let s = self.scale;
+        let shown = self
+            .font
+            .ellipsize(&self.status, width.saturating_sub(scaled(48, s)));
+        self.font.auto_draw_text(
+            &mut buf.offset((scaled(24, s), scaled(8, s)))?,
+            ctx.bg,
+            &ctx.config.prompt,
+            &shown,
+        )?;
+        self.text = self.status.clone();
+
+        Ok(DrawReport {
+            width,
+            height,
+            damage: vec![buf.get_signed_bounds()],
+            full_damage: false,
+        })
+    }
+
+    fn keyboard_input(&mut self, _: Keysym, _: Modifiers, _: KeyState, _: Option<String>) {}
+    fn mouse_click(&mut self, _: u32, _: (u32, u32)) {}
+    fn mouse_scroll(&mut self, _: (f64, f64), _: (u32, u32)) {}
+    fn tick(&mut self, now: Instant) -> bool {
+        // The figures are only read while the greeter is shown.
+        let read = match self.read {
+            Some(read) => read,
+            None => return false,
+        };
+        if now.duration_since(read) >= INTERVAL {
+            self.status = system_info();
+            self.read = Some(now);
+        }
+        self.status != self.text
+    }
+    fn on_show(&mut self) {
+        self.status = system_info();
+        self.read = Some(Instant::now());
+    }
+    fn on_hide(&mut self) {
+        self.read = None;
+    }
+}
@@ -0,0 +1,648 @@
+//! Runs wlgreet against a minimal headless compositor, which records the
+//! buffers and damage committed in response to configure and keyboard
+//! events.
+
+use std::fs::{self, File};
+use std::io::Read;
+use std::os::fd::AsFd;
+use std::os::unix::fs::FileExt;
+use std::os::unix::net::UnixListener;
+use std::path::PathBuf;
+use std::process::{Child, Command};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use wayland_protocols_wlr::layer_shell::v1::server::{
+    zwlr_layer_shell_v1::{self, ZwlrLayerShellV1},
+    zwlr_layer_surface_v1::{self, ZwlrLayerSurfaceV1},
+};
+use wayland_server::backend::{ClientData, ClientId, DisconnectReason};
+use wayland_server::protocol::{
+    wl_buffer::{self, WlBuffer},
+    wl_callback::WlCallback,
+    wl_compositor::{self, WlCompositor},
+    wl_keyboard::{self, WlKeyboard},
+    wl_region::{self, WlRegion},
+    wl_seat::{self, WlSeat},
+    wl_shm::{self, WlShm},
+    wl_shm_pool::{self, WlShmPool},
+    wl_surface::{self, WlSurface},
+};
+use wayland_server::{
+    Client, DataInit, Dispatch, Display, DisplayHandle, GlobalDispatch, ListeningSocket, New,
+    Resource,
+};
+
+const CONFIG: &str = r##"
+outputMode = "active"
+clockFormat = ""
+animations = false
+background = "#336699"
+"##;
+
+// The background color above, as an ARGB8888 pixel.
+const BACKGROUND: u32 = 0xff336699;
+
+// A keymap with just the keys typed below, so that the tests do not depend on
+// the XKB data of the host. Key codes are evdev codes offset by 8.
+const KEYMAP: &str = r#"xkb_keymap {
+    xkb_keycodes "test" {
+        minimum = 8;
+        maximum = 255;
+        <RTRN> = 36;
+        <AD04> = 27;
+        <AD05> = 28;
+        <AD09> = 32;
+        <AC01> = 38;
+    };
+    xkb_types "test" {
+        type "ONE_LEVEL" {
+            modifiers = none;
+            level_name[Level1] = "Any";
+        };
+    };
+    xkb_compatibility "test" {
+    };
+    xkb_symbols "test" {
+        key <RTRN> { [ Return ] };
+        key <AD04> { [ r ] };
+        key <AD05> { [ t ] };
+        key <AD09> { [ o ] };
+        key <AC01> { [ a ] };
+    };
+};
+"#;
+
+// Evdev key codes.
+const KEY_A: u32 = 30;
+const KEY_ENTER: u32 = 28;
+const KEY_O: u32 = 24;
+const KEY_R: u32 = 19;
+const KEY_T: u32 = 20;
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A buffer committed by the client, copied out of its pool.
+struct Frame {
+    size: (i32, i32),
+    pixels: Vec<u32>,
+    damage: Vec<(i32, i32, i32, i32)>,
+}
+
+impl Frame {
+    fn pixel(&self, x: i32, y: i32) -> u32 {
+        self.pixels[(x + y * self.size.0) as usize]
+    }
+
+    fn full_damage(&self) -> bool {
+        self.damage
+            .iter()
+            .any(|d| d.0 <= 0 && d.1 <= 0 && d.2 >= self.size.0 && d.3 >= self.size.1)
+    }
+}
+
+#[derive(Default)]
+struct SurfaceState {
+    buffer: Option<WlBuffer>,
+    damage: Vec<(i32, i32, i32, i32)>,
+    callbacks: Vec<WlCallback>,
+    layer: Option<ZwlrLayerSurfaceV1>,
+    configured: bool,
+}
+
+struct BufferData {
+    pool: Arc<File>,
+    offset: i32,
+    width: i32,
+    height: i32,
+    stride: i32,
+}
+
+#[derive(Default)]
+struct State {
+    frames: Vec<Frame>,
+    requested_size: Option<(u32, u32)>,
+    surface: Option<WlSurface>,
+    keyboards: Vec<WlKeyboard>,
+    keymap: Option<File>,
+    serial: u32,
+}
+
+impl State {
+    fn next_serial(&mut self) -> u32 {
+        self.serial += 1;
+        self.serial
+    }
+
+    fn commit(&mut self, surface: &WlSurface) {
+        let mut pending = surface
+            .data::<Mutex<SurfaceState>>()
+            .unwrap()
+            .lock()
+            .unwrap();
+
+        // The first commit of a layer surface asks for a configure.
+        if !pending.configured {
+            if let Some(layer) = pending.layer.clone() {
+                pending.configured = true;
+                let (width, height) = self.requested_size.unwrap_or((0, 0));
+                let (width, height) = (
+                    if width == 0 { 1280 } else { width },
+                    if height == 0 { 720 } else { height },
+                );
+                layer.configure(self.next_serial(), width, height);
+            }
+        }
+
+        if let Some(buffer) = pending.buffer.take() {
+            let data = buffer.data::<BufferData>().unwrap();
+            let mut row = vec![0u8; (data.width * 4) as usize];
+            let mut pixels = Vec::with_capacity((data.width * data.height) as usize);
+            for y in 0..data.height {
+                data.pool
+                    .read_exact_at(&mut row, (data.offset + y * data.stride) as u64)
+                    .unwrap();
+                pixels.extend(
+                    row.chunks_exact(4)
+                        .map(|p| u32::from_ne_bytes([p[0], p[1], p[2], p[3]])),
+                );
+            }
+            self.frames.push(Frame {
+                size: (data.width, data.height),
+                pixels,
+                damage: std::mem::take(&mut pending.damage),
+            });
+            // The pixels are copied, so the client may have the buffer back.
+            buffer.release();
+        }
+        for callback in pending.callbacks.drain(..) {
+            callback.done(0);
+        }
+        self.surface = Some(surface.clone());
+    }
+}
+
+struct ClientState;
+
+impl ClientData for ClientState {
+    fn initialized(&self, _: ClientId) {}
+    fn disconnected(&self, _: ClientId, _: DisconnectReason) {}
+}
+
+impl GlobalDispatch<WlCompositor, ()> for State {
+    fn bind(
+        _: &mut State,
+        _: &DisplayHandle,
+        _: &Client,
+        resource: New<WlCompositor>,
+        _: &(),
+        data_init: &mut DataInit<'_, State>,
+    ) {
+        data_init.init(resource, ());
+    }
+}
+
+impl Dispatch<WlCompositor, ()> for State {
+    fn request(
+        _: &mut State,
+        _: &Client,
+        _: &WlCompositor,
+        request: wl_compositor::Request,
+        _: &(),
+        _: &DisplayHandle,
+        data_init: &mut DataInit<'_, State>,
+    ) {
+        match request {
+            wl_compositor::Request::CreateSurface { id } => {
+                data_init.init(id, Mutex::new(SurfaceState::default()));
+            }
+            wl_compositor::Request::CreateRegion { id } => {
+                data_init.init(id, ());
+            }
+            _ => (),
+        }
+    }
+}
+
+impl Dispatch<WlRegion, ()> for State {
+    fn request(
+        _: &mut State,
+        _: &Client,
+        _: &WlRegion,
+        _: wl_region::Request,
+        _: &(),
+        _: &DisplayHandle,
+        _: &mut DataInit<'_, State>,
+    ) {
+    }
+}
+
+impl Dispatch<WlSurface, Mutex<SurfaceState>> for State {
+    fn request(
+        state: &mut State,
+        _: &Client,
+        surface: &WlSurface,
+        request: wl_surface::Request,
+        data: &Mutex<SurfaceState>,
+        _: &DisplayHandle,
+        data_init: &mut DataInit<'_, State>,
+    ) {
+        match request {
+            wl_surface::Request::Attach { buffer, .. } => data.lock().unwrap().buffer = buffer,
+            wl_surface::Request::Damage {
+                x,
+                y,
+                width,
+                height,
+            }
+            | wl_surface::Request::DamageBuffer {
+                x,
+                y,
+                width,
+                height,
+            } => data.lock().unwrap().damage.push((x, y, width, height)),
+            wl_surface::Request::Frame { callback } => {
+                let callback = data_init.init(callback, ());
+                data.lock().unwrap().callbacks.push(callback);
+            }
+            wl_surface::Request::Commit => state.commit(surface),
+            _ => (),
+        }
+    }
+}
+
+impl Dispatch<WlCallback, ()> for State {
+    fn request(
+        _: &mut State,
+        _: &Client,
+        _: &WlCallback,
+        _: <WlCallback as Resource>::Request,
+        _: &(),
+        _: &DisplayHandle,
+        _: &mut DataInit<'_, State>,
+    ) {
+    }
+}
+
+impl GlobalDispatch<WlShm, ()> for State {
+    fn bind(
+        _: &mut State,
+        _: &DisplayHandle,
+        _: &Client,
+        resource: New<WlShm>,
+        _: &(),
+        data_init: &mut DataInit<'_, State>,
+    ) {
+        let shm = data_init.init(resource, ());
+        shm.format(wl_shm::Format::Argb8888);
+        shm.format(wl_shm::Format::Xrgb8888);
+    }
+}
+
+impl Dispatch<WlShm, ()> for State {
+    fn request(
+        _: &mut State,
+        _: &Client,
+        _: &WlShm,
+        request: wl_shm::Request,
+        _: &(),
+        _: &DisplayHandle,
+        data_init: &mut DataInit<'_, State>,
+    ) {
+        if let wl_shm::Request::CreatePool { id, fd, .. } = request {
+            data_init.init(id, Arc::new(File::from(fd)));
+        }
+    }
+}
+
+impl Dispatch<WlShmPool, Arc<File>> for State {
+    fn request(
+        _: &mut State,
+        _: &Client,
+        _: &WlShmPool,
+        request: wl_shm_pool::Request,
+        pool: &Arc<File>,
+        _: &DisplayHandle,
+        data_init: &mut DataInit<'_, State>,
+    ) {
+        if let wl_shm_pool::Request::CreateBuffer {
+            id,
+            offset,
+            width,
+            height,
+            stride,
+            ..
+        } = request
+        {
+            data_init.init(
+                id,
+                BufferData {
+                    pool: pool.clone(),
+                    offset,
+                    width,
+                    height,
+                    stride,
+                },
+            );
+        }
+    }
+}
+
+impl Dispatch<WlBuffer, BufferData> for State {
+    fn request(
+        _: &mut State,
+        _: &Client,
+        _: &WlBuffer,
+        _: wl_buffer::Request,
+        _: &BufferData,
+        _: &DisplayHandle,
+        _: &mut DataInit<'_, State>,
+    ) {
+    }
+}
+
+impl GlobalDispatch<WlSeat, ()> for State {
+    fn bind(
+        _: &mut State,
+        _: &DisplayHandle,
+        _: &Client,
+        resource: New<WlSeat>,
+        _: &(),
+        data_init: &mut DataInit<'_, State>,
+    ) {
+        let seat = data_init.init(resource, ());
+        seat.capabilities(wl_seat::Capability::Keyboard);
+        if seat.version() >= 2 {
+            seat.name("seat0".to_string());
+        }
+    }
+}
+
+impl Dispatch<WlSeat, ()> for State {
+    fn request(
+        state: &mut State,
+        _: &Client,
+        _: &WlSeat,
+        request: wl_seat::Request,
+        _: &(),
+        _: &DisplayHandle,
+        data_init: &mut DataInit<'_, State>,
+    ) {
+        if let wl_seat::Request::GetKeyboard { id } = request {
+            let keyboard = data_init.init(id, ());
+            let keymap = state.keymap.as_ref().unwrap();
+            keyboard.keymap(
+                wl_keyboard::KeymapFormat::XkbV1,
+                keymap.as_fd(),
+                KEYMAP.len() as u32 + 1,
+            );
+            if keyboard.version() >= 4 {
+                keyboard.repeat_info(0, 0);
+            }
+            state.keyboards.push(keyboard);
+        }
+    }
+}
+
+impl Dispatch<WlKeyboard, ()> for State {
+    fn request(
+        _: &mut State,
+        _: &Client,
+        _: &WlKeyboard,
+        _: wl_keyboard::Request,
+        _: &(),
+        _: &DisplayHandle,
+        _: &mut DataInit<'_, State>,
+    ) {
+    }
+}
+
+impl GlobalDispatch<ZwlrLayerShellV1, ()> for State {
+    fn bind(
+        _: &mut State,
+        _: &DisplayHandle,
+        _: &Client,
+        resource: New<ZwlrLayerShellV1>,
+        _: &(),
+        data_init: &mut DataInit<'_, State>,
+    ) {
+        data_init.init(resource, ());
+    }
+}
+
+impl Dispatch<ZwlrLayerShellV1, ()> for State {
+    fn request(
+        _: &mut State,
+        _: &Client,
+        _: &ZwlrLayerShellV1,
+        request: zwlr_layer_shell_v1::Request,
+        _: &(),
+        _: &DisplayHandle,
+        data_init: &mut DataInit<'_, State>,
+    ) {
+        if let zwlr_layer_shell_v1::Request::GetLayerSurface { id, surface, .. } = request {
+            let layer = data_init.init(id, ());
+            surface
+                .data::<Mutex<SurfaceState>>()
+                .unwrap()
+                .lock()
+                .unwrap()
+                .layer = Some(layer);
+        }
+    }
+}
+
+impl Dispatch<ZwlrLayerSurfaceV1, ()> for State {
+    fn request(
+        state: &mut State,
+        _: &Client,
+        _: &ZwlrLayerSurfaceV1,
+        request: zwlr_layer_surface_v1::Request,
+        _: &(),
+        _: &DisplayHandle,
+        _: &mut DataInit<'_, State>,
+    ) {
+        if let zwlr_layer_surface_v1::Request::SetSize { width, height } = request {
+            state.requested_size = Some((width, height));
+        }
+    }
+}
+
+/// A headless compositor with wlgreet connected to it.
+struct Harness {
+    display: Display<State>,
+    socket: ListeningSocket,
+    state: State,
+    child: Child,
+    dir: PathBuf,
+}
+
+impl Harness {
+    fn start(name: &str) -> Harness {
+        let dir = std::env::temp_dir().join(format!("wlgreet-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("config.toml"), CONFIG).unwrap();
+        fs::write(dir.join("keymap.xkb"), format!("{}\0", KEYMAP)).unwrap();
+
+        let display = Display::new().unwrap();
+        let handle = display.handle();
+        handle.create_global::<State, WlCompositor, ()>(4, ());
+        handle.create_global::<State, WlShm, ()>(1, ());
+        handle.create_global::<State, WlSeat, ()>(5, ());
+        handle.create_global::<State, ZwlrLayerShellV1, ()>(1, ());
+        let socket = ListeningSocket::bind_absolute(dir.join("wayland-0")).unwrap();
+
+        let child = Command::new(env!("CARGO_BIN_EXE_wlgreet"))
+            .arg("--config")
+            .arg(dir.join("config.toml"))
+            .env("XDG_RUNTIME_DIR", &dir)
+            .env("WAYLAND_DISPLAY", "wayland-0")
+            .env("GREETD_SOCK", dir.join("greetd.sock"))
+            .spawn()
+            .unwrap();
+
+        Harness {
+            display,
+            socket,
+            state: State {
+                keymap: Some(File::open(dir.join("keymap.xkb")).unwrap()),
+                ..State::default()
+            },
+            child,
+            dir,
+        }
+    }
+
+    fn dispatch(&mut self) {
+        if let Some(stream) = self.socket.accept().unwrap() {
+            self.display
+                .handle()
+                .insert_client(stream, Arc::new(ClientState))
+                .unwrap();
+        }
+        self.display.dispatch_clients(&mut self.state).unwrap();
+        self.display.flush_clients().unwrap();
+    }
+
+    fn wait_for(&mut self, what: &str, done: impl Fn(&State) -> bool) {
+        let deadline = Instant::now() + TIMEOUT;
+        loop {
+            self.dispatch();
+            if done(&self.state) {
+                return;
+            }
+            if let Some(status) = self.child.try_wait().unwrap() {
+                panic!("wlgreet exited with {} waiting for {}", status, what);
+            }
+            if Instant::now() > deadline {
+                panic!("timed out waiting for {}", what);
+            }
+            std::thread::sleep(Duration::from_millis(5));
+        }
+    }
+
+    fn wait_for_first_frame(&mut self) {
+        self.wait_for("the first frame", |state| !state.frames.is_empty());
+    }
+
+    /// Gives the keyboard focus to the greeter.
+    fn enter(&mut self) {
+        let surface = self.state.surface.clone().unwrap();
+        for keyboard in self.state.keyboards.clone() {
+            let serial = self.state.next_serial();
+            keyboard.enter(serial, &surface, vec![]);
+            let serial = self.state.next_serial();
+            keyboard.modifiers(serial, 0, 0, 0, 0);
+        }
+        self.dispatch();
+    }
+
+    /// Presses and releases each key in turn.
+    fn type_keys(&mut self, keys: &[u32]) {
+        for &key in keys {
+            for keyboard in self.state.keyboards.clone() {
+                for key_state in [
+                    wl_keyboard::KeyState::Pressed,
+                    wl_keyboard::KeyState::Released,
+                ] {
+                    let serial = self.state.next_serial();
+                    keyboard.key(serial, 0, key, key_state);
+                }
+            }
+        }
+        self.dispatch();
+    }
+}
+
+impl Drop for Harness {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+#[test]
+fn first_frame_fills_the_configured_size() {
+    let mut harness = Harness::start("first-frame");
+    harness.wait_for_first_frame();
+
+    let (width, height) = harness.state.requested_size.unwrap();
+    let frame = &harness.state.frames[0];
+    assert_eq!(frame.size, (width as i32, height as i32));
+    assert!(frame.full_damage(), "damage was {:?}", frame.damage);
+    assert_eq!(frame.pixel(0, 0), BACKGROUND);
+}
+
+#[test]
+fn typing_damages_only_the_prompt() {
+    let mut harness = Harness::start("typing");
+    harness.wait_for_first_frame();
+    harness.enter();
+    harness.type_keys(&[KEY_A]);
+
+    harness.wait_for("a redraw", |state| {
+        state
+            .frames
+            .iter()
+            .skip(1)
+            .any(|f| f.pixels != state.frames[0].pixels)
+    });
+    let frame = harness.state.frames.last().unwrap();
+    assert!(!frame.damage.is_empty());
+    assert!(!frame.full_damage(), "damage was {:?}", frame.damage);
+}
+
+#[test]
+fn enter_sends_the_username_to_greetd() {
+    let mut harness = Harness::start("greetd");
+    let greetd = UnixListener::bind(harness.dir.join("greetd.sock")).unwrap();
+    greetd.set_nonblocking(true).unwrap();
+    harness.wait_for_first_frame();
+    harness.enter();
+    harness.type_keys(&[KEY_R, KEY_O, KEY_O, KEY_T, KEY_ENTER]);
+
+    let deadline = Instant::now() + TIMEOUT;
+    let mut stream = loop {
+        harness.dispatch();
+        match greetd.accept() {
+            Ok((stream, _)) => break stream,
+            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                assert!(Instant::now() < deadline, "timed out waiting for greetd");
+                std::thread::sleep(Duration::from_millis(5));
+            }
+            Err(e) => panic!("accept failed: {}", e),
+        }
+    };
+    stream.set_nonblocking(false).unwrap();
+    stream.set_read_timeout(Some(TIMEOUT)).unwrap();
+
+    let mut len = [0u8; 4];
+    stream.read_exact(&mut len).unwrap();
+    let mut body = vec![0u8; u32::from_ne_bytes(len) as usize];
+    stream.read_exact(&mut body).unwrap();
+    let body = String::from_utf8(body).unwrap();
+    assert!(body.contains(r#""type":"create_session""#), "{}", body);
+    assert!(body.contains(r#""username":"root""#), "{}", body);
+}